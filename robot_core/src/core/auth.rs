@@ -0,0 +1,304 @@
+//! Authentication of console users and ownership of their sessions.
+//!
+//! An [`Auth`] without authenticators is disabled: every caller is anonymous
+//! and may use any session, as before. Once configured, the web and TCP
+//! consoles require a token, attach the [`Identity`] to each `InputEvent` and
+//! only let a user read or write sessions they own. A session belongs to the
+//! first user that creates or writes to it, except for the sessions of the
+//! channel tentacles, which no console user may claim or read.
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    ApiToken,
+    SessionToken,
+}
+
+/// An authenticated user
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    pub user_id: String,
+    pub method: AuthMethod,
+}
+
+pub trait Authenticator: Send + Sync {
+    /// The identity `token` stands for, if it is valid
+    fn authenticate(&self, token: &str) -> Option<Identity>;
+}
+
+/// Static API tokens, e.g. from `ROBOT_API_TOKENS=alice:s3cret,bob:t0ken`
+#[derive(Default)]
+pub struct ApiTokens {
+    /// token -> user id
+    tokens: HashMap<String, String>,
+}
+
+impl ApiTokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, user_id: &str, token: &str) -> Self {
+        self.tokens.insert(token.to_string(), user_id.to_string());
+        self
+    }
+
+    /// Parse comma-separated `user:token` pairs
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut tokens = Self::new();
+        for pair in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (user, token) = pair
+                .split_once(':')
+                .filter(|(u, t)| !u.is_empty() && !t.is_empty())
+                .ok_or_else(|| anyhow::anyhow!("invalid API token entry '{}', expected user:token", pair))?;
+            tokens = tokens.with(user, token);
+        }
+        Ok(tokens)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+}
+
+impl Authenticator for ApiTokens {
+    fn authenticate(&self, token: &str) -> Option<Identity> {
+        // Compare every entry in constant time rather than hashing the lookup
+        self.tokens
+            .iter()
+            .find(|(known, _)| constant_time_eq(known.as_bytes(), token.as_bytes()))
+            .map(|(_, user)| Identity {
+                user_id: user.clone(),
+                method: AuthMethod::ApiToken,
+            })
+    }
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Short-lived tokens signed with HMAC-SHA256:
+/// `<hex user id>.<expiry, unix seconds>.<hex signature>`
+pub struct SessionTokens {
+    secret: Vec<u8>,
+    ttl: Duration,
+}
+
+impl SessionTokens {
+    pub fn new(secret: impl Into<Vec<u8>>, ttl: Duration) -> Self {
+        Self {
+            secret: secret.into(),
+            ttl,
+        }
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts any key length");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    /// A token for `user_id` and its expiry (unix seconds)
+    pub fn issue(&self, user_id: &str) -> (String, u64) {
+        let expires_at = now_secs() + self.ttl.as_secs();
+        let payload = format!("{}.{}", hex::encode(user_id), expires_at);
+        let signature = hex::encode(self.mac(&payload).finalize().into_bytes());
+        (format!("{}.{}", payload, signature), expires_at)
+    }
+}
+
+impl Authenticator for SessionTokens {
+    fn authenticate(&self, token: &str) -> Option<Identity> {
+        let (payload, signature) = token.rsplit_once('.')?;
+        let (user_hex, expires_at) = payload.split_once('.')?;
+        self.mac(payload).verify_slice(&hex::decode(signature).ok()?).ok()?;
+        if expires_at.parse::<u64>().ok()? <= now_secs() {
+            return None;
+        }
+        Some(Identity {
+            user_id: String::from_utf8(hex::decode(user_hex).ok()?).ok()?,
+            method: AuthMethod::SessionToken,
+        })
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Session id prefixes of the channel tentacles: chat bots, email, webhooks
+/// and the local console. Their sessions belong to the channel.
+pub const CHANNEL_PREFIXES: &[&str] = &["bot:", "email:", "webhook:", "console:"];
+
+/// Whether `session_id` is the session of a channel tentacle
+pub fn is_channel_session(session_id: &str) -> bool {
+    CHANNEL_PREFIXES.iter().any(|p| session_id.starts_with(p))
+}
+
+/// Authenticators plus the session ownership registry, shared by all consoles
+#[derive(Default)]
+pub struct Auth {
+    authenticators: Vec<Box<dyn Authenticator>>,
+    session_tokens: Option<SessionTokens>,
+    /// session id -> owning user id
+    owners: RwLock<HashMap<String, String>>,
+}
+
+impl Auth {
+    /// No authentication: everyone is anonymous and may use every session
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    pub fn with_authenticator(mut self, authenticator: impl Authenticator + 'static) -> Self {
+        self.authenticators.push(Box::new(authenticator));
+        self
+    }
+
+    /// Accept session tokens and issue them on login
+    pub fn with_session_tokens(mut self, tokens: SessionTokens) -> Self {
+        self.session_tokens = Some(tokens);
+        self
+    }
+
+    /// `ROBOT_API_TOKENS` (`user:token,...`), `ROBOT_AUTH_SECRET` for session
+    /// tokens and `ROBOT_SESSION_TOKEN_TTL` (seconds, default 12h)
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut auth = Self::disabled();
+        if let Ok(spec) = std::env::var("ROBOT_API_TOKENS") {
+            let tokens = ApiTokens::parse(&spec)?;
+            if !tokens.is_empty() {
+                auth = auth.with_authenticator(tokens);
+            }
+        }
+        if let Ok(secret) = std::env::var("ROBOT_AUTH_SECRET")
+            && !secret.is_empty()
+        {
+            let ttl = std::env::var("ROBOT_SESSION_TOKEN_TTL")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(12 * 3600);
+            auth = auth.with_session_tokens(SessionTokens::new(secret, Duration::from_secs(ttl)));
+        }
+        Ok(auth)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.authenticators.is_empty() || self.session_tokens.is_some()
+    }
+
+    pub fn authenticate(&self, token: &str) -> Option<Identity> {
+        let token = token.trim();
+        self.authenticators
+            .iter()
+            .find_map(|a| a.authenticate(token))
+            .or_else(|| self.session_tokens.as_ref()?.authenticate(token))
+    }
+
+    /// A session token for `identity` and its expiry, if session tokens are
+    /// configured
+    pub fn issue_session_token(&self, identity: &Identity) -> Option<(String, u64)> {
+        Some(self.session_tokens.as_ref()?.issue(&identity.user_id))
+    }
+
+    /// Take ownership of `session_id` for `identity` unless another user owns
+    /// it already. Always true when authentication is disabled, and always
+    /// false for [channel sessions](is_channel_session).
+    pub fn claim(&self, session_id: &str, identity: Option<&Identity>) -> bool {
+        if is_channel_session(session_id) {
+            return false;
+        }
+        if !self.is_enabled() {
+            return true;
+        }
+        let Some(identity) = identity else {
+            return false;
+        };
+        let mut owners = self.owners.write().unwrap();
+        owners
+            .entry(session_id.to_string())
+            .or_insert_with(|| identity.user_id.clone())
+            == &identity.user_id
+    }
+
+    /// Whether `identity` may read `session_id`: it owns it, or
+    /// authentication is disabled. Channel sessions are never readable.
+    pub fn may_read(&self, session_id: &str, identity: Option<&Identity>) -> bool {
+        if is_channel_session(session_id) {
+            return false;
+        }
+        if !self.is_enabled() {
+            return true;
+        }
+        identity.is_some_and(|i| self.owner(session_id).as_deref() == Some(i.user_id.as_str()))
+    }
+
+    pub fn owner(&self, session_id: &str) -> Option<String> {
+        self.owners.read().unwrap().get(session_id).cloned()
+    }
+
+    /// Whether output of session `from` may be shown in session `to`.
+    /// Broadcasts stay within the sessions of one user.
+    pub fn shares_owner(&self, from: &str, to: &str) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        let owners = self.owners.read().unwrap();
+        matches!((owners.get(from), owners.get(to)), (Some(a), Some(b)) if a == b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_and_session_ownership() {
+        let auth = Auth::disabled()
+            .with_authenticator(ApiTokens::parse("alice:a-token, bob:b-token").unwrap())
+            .with_session_tokens(SessionTokens::new("secret", Duration::from_secs(60)));
+        assert!(auth.is_enabled());
+        assert!(auth.authenticate("nope").is_none());
+        let alice = auth.authenticate("a-token").unwrap();
+        assert_eq!(alice.user_id, "alice");
+        let bob = auth.authenticate("b-token").unwrap();
+
+        let (token, _) = auth.issue_session_token(&alice).unwrap();
+        let from_session = auth.authenticate(&token).unwrap();
+        assert_eq!(from_session.user_id, "alice");
+        assert_eq!(from_session.method, AuthMethod::SessionToken);
+        let forged = token.replacen(&hex::encode("alice"), &hex::encode("bob"), 1);
+        assert!(auth.authenticate(&forged).is_none());
+        let expired = SessionTokens::new("secret", Duration::ZERO).issue("alice").0;
+        assert!(auth.authenticate(&expired).is_none());
+
+        assert!(auth.claim("s1", Some(&alice)));
+        assert!(auth.claim("s1", Some(&from_session)));
+        assert!(!auth.claim("s1", Some(&bob)));
+        assert!(!auth.claim("s2", None));
+        assert!(auth.may_read("s1", Some(&alice)));
+        assert!(!auth.may_read("s1", Some(&bob)));
+        assert!(auth.claim("s2", Some(&bob)));
+        assert!(!auth.shares_owner("s1", "s2"));
+
+        // Channel sessions stay out of reach, signed in or not
+        assert!(!auth.claim("bot:42", Some(&alice)));
+        assert!(!auth.may_read("email:m1@example.com", Some(&alice)));
+        assert_eq!(auth.owner("bot:42"), None);
+
+        let open = Auth::disabled();
+        assert!(open.claim("s1", None) && open.may_read("s1", None));
+        assert!(!open.claim("webhook:alerts", None) && !open.may_read("webhook:alerts", None));
+        assert!(ApiTokens::parse("missing-colon").is_err());
+    }
+}
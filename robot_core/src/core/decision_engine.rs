@@ -1,15 +1,57 @@
+use crate::core::metrics;
 use crate::core::persona::Persona;
-use crate::llm::adapter::{ChatMessage, ChatRequest, LLMClient};
+use crate::core::uploads;
+use crate::llm::adapter::{ChatMessage, ChatOutput, ChatRequest, LLMClient};
 use crate::mcp::client::MCPClient;
 use crate::mcp::registry::ToolMeta;
-use crate::utils::{InputEvent, StepSpec, WorkflowPlan};
+use crate::utils::{InputEvent, RecoveryPolicy, StepSpec, WorkflowPlan};
+use crate::workflow_steps::bindings::has_refs;
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tracing::info;
+use tracing::{info, warn};
+
+/// Describes a workflow step that could not be recovered by its own policy
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StepFailure {
+    pub step_index: usize,
+    pub tool: Option<String>,
+    pub error: String,
+    pub input_text: String,
+    pub session_id: Option<String>,
+}
+
+impl StepFailure {
+    /// Plain fallback message used when no LLM is available to phrase the failure
+    pub fn default_message(&self) -> String {
+        match &self.tool {
+            Some(tool) => format!("抱歉，执行工具 {} 时失败：{}", tool, self.error),
+            None => format!("抱歉，处理请求时失败：{}", self.error),
+        }
+    }
+}
 
 #[async_trait]
 pub trait DecisionEngine {
     async fn decide(&self, persona: &Persona, input: &InputEvent, mcp_client: &dyn MCPClient) -> anyhow::Result<WorkflowPlan>;
+
+    /// Plan the remaining steps after `failure`, given the execution history
+    /// stored in `ctx.memory["workflow"]["history"]`. The returned steps are
+    /// appended after the failed step.
+    async fn replan(
+        &self,
+        _persona: &Persona,
+        _failure: &StepFailure,
+        _history: &Value,
+        _mcp_client: &dyn MCPClient,
+    ) -> anyhow::Result<WorkflowPlan> {
+        Err(anyhow::anyhow!("replanning is not supported by this decision engine"))
+    }
+
+    /// Phrase an unrecoverable failure for the user
+    async fn explain_failure(&self, _persona: &Persona, failure: &StepFailure) -> anyhow::Result<String> {
+        Ok(failure.default_message())
+    }
 }
 
 pub struct BasicDecisionEngine;
@@ -119,6 +161,12 @@ impl DecisionEngine for LLMDecisionEngine {
             - 'reasoning': Explain why you selected these tools and how you plan to extract parameters.
             - 'steps': The array of tool steps as described before. Each object must have 'tool' (string) and 'dependencies' (array of integers).
             'dependencies' should contain the 0-based indices of previous steps that the current step depends on. If independent, use [].
+            10. OPTIONAL 'args': when a parameter is exactly the output of an earlier step, bind it instead of describing it:
+            \"args\": {{ \"a\": {{ \"$ref\": \"steps[0].content[0].text\", \"as\": \"number\" }} }}. 'as' is one of string, number, integer, boolean, json.
+            Leave out parameters that are not a direct copy of an earlier result; they are extracted later.
+            11. OPTIONAL 'on_error': what to do when the step fails. One of \"Replan\" (the default: plan the remaining steps again), \"Abort\" (stop and tell the user), \"Skip\" (go on with the next step),
+            {{ \"Retry\": {{ \"max_attempts\": 3, \"backoff_ms\": 500 }} }} or {{ \"Substitute\": {{ \"tool\": \"other_tool\" }} }} (call another tool with the same args).
+            Use \"Abort\" for steps that must not be worked around, e.g. ones changing data; leave it out otherwise.
             Example:
             {{
               \"reasoning\": \"User wants to know time difference. I need to get current time twice (or user provided one?) and then subtract.\",
@@ -127,7 +175,10 @@ impl DecisionEngine for LLMDecisionEngine {
             No explanation.",
             source_context, tool_descriptions
         );
-        let user = format!("Input: {}\nReturn steps:", text);
+        let user = match uploads::describe_attachments(&input.attachments) {
+            Some(files) => format!("Input: {}\n{}Return steps:", text, files),
+            None => format!("Input: {}\nReturn steps:", text),
+        };
         let req = ChatRequest {
             model: self.model.clone(),
             messages: vec![
@@ -143,88 +194,197 @@ impl DecisionEngine for LLMDecisionEngine {
             temperature: Some(0.2),
             session_id: input.session_id.clone(),
         };
-        let out = self.llm.chat(req).await?;
-
-        let s = out.text.trim();
-        let mut planner_reasoning = out.thought.clone();
+        let out = metrics::observe_llm("planner", self.llm.chat(req)).await?;
+        let plan = parse_plan(&out, &tools);
+        info!("llm decision plan: {:?}", plan);
+        Ok(plan)
+    }
 
-        #[derive(serde::Deserialize)]
-        struct StepItem {
-            tool: String,
-            dependencies: Vec<usize>,
+    async fn replan(
+        &self,
+        persona: &Persona,
+        failure: &StepFailure,
+        history: &Value,
+        mcp_client: &dyn MCPClient,
+    ) -> anyhow::Result<WorkflowPlan> {
+        let tools: Vec<ToolMeta> = mcp_client.list_tools().await.unwrap_or_default();
+        if tools.is_empty() {
+            return Err(anyhow::anyhow!("NO_TOOLS_AVAILABLE"));
         }
+        let tool_descriptions: Vec<String> = tools
+            .iter()
+            .map(|t| format!("name={} description={}", t.name, t.description))
+            .collect();
 
-        #[derive(serde::Deserialize)]
-        struct PlanResponse {
-            reasoning: Option<String>,
-            steps: Vec<StepItem>,
+        let system = format!(
+            "You are '{}', a workflow planner recovering from a failed step.\n\
+            Available MCP Tools: {:?}.\n\
+            \n\
+            Step {} ({}) failed with error: {}\n\
+            Execution history so far (0-based step_index, args, result, error): {}\n\
+            \n\
+            Rules:
+            1. Plan ONLY the steps that still need to run. Completed steps keep their indices 0..={}; new steps are numbered from {}.
+            2. Do NOT repeat the failed call with the same arguments.
+            3. If the request cannot be fulfilled any more, return an empty 'steps' array.
+            4. Return a JSON object with 'reasoning' (string) and 'steps' (array of {{\"tool\": string, \"dependencies\": [int]}}).
+            No explanation.",
+            persona.name,
+            tool_descriptions,
+            failure.step_index,
+            failure.tool.as_deref().unwrap_or("system step"),
+            failure.error,
+            history,
+            failure.step_index,
+            failure.step_index + 1,
+        );
+        let user = format!("Input: {}\nReturn steps:", failure.input_text);
+        let req = ChatRequest {
+            model: self.model.clone(),
+            messages: vec![
+                ChatMessage {
+                    role: "system".into(),
+                    content: system,
+                },
+                ChatMessage {
+                    role: "user".into(),
+                    content: user,
+                },
+            ],
+            temperature: Some(0.2),
+            session_id: failure.session_id.clone(),
+        };
+        let out = metrics::observe_llm("replanner", self.llm.chat(req)).await?;
+        let plan = parse_plan(&out, &tools);
+        info!("llm replan: {:?}", plan);
+        Ok(plan)
+    }
+
+    async fn explain_failure(&self, persona: &Persona, failure: &StepFailure) -> anyhow::Result<String> {
+        let system = format!(
+            "You are '{}' with a {} style. A tool you used to answer the user failed.\n\
+            Tell the user briefly, in their language and in your style, what could not be done and why.\n\
+            If the error hints at a fix (e.g. a different input), suggest it. Do not mention internal step numbers.",
+            persona.name, persona.style
+        );
+        let user = format!(
+            "User input: {}\nFailed tool: {}\nError: {}",
+            failure.input_text,
+            failure.tool.as_deref().unwrap_or("-"),
+            failure.error
+        );
+        let req = ChatRequest {
+            model: self.model.clone(),
+            messages: vec![
+                ChatMessage {
+                    role: "system".into(),
+                    content: system,
+                },
+                ChatMessage {
+                    role: "user".into(),
+                    content: user,
+                },
+            ],
+            temperature: Some(0.5),
+            session_id: failure.session_id.clone(),
+        };
+        match metrics::observe_llm("failure", self.llm.chat(req)).await {
+            Ok(out) if !out.text.trim().is_empty() => Ok(out.text.trim().to_string()),
+            Ok(_) => Ok(failure.default_message()),
+            Err(e) => {
+                warn!("explain_failure LLM call failed: {}", e);
+                Ok(failure.default_message())
+            }
         }
-        
-        let mut step_items: Vec<StepItem> = Vec::new();
+    }
+}
 
-        // 1. Try to parse as JSON Object (New Format)
-        let starts_obj: Vec<usize> = s.match_indices('{').map(|(i, _)| i).collect();
-        for start in starts_obj {
-            if let Some(end_offset) = s[start..].rfind('}') {
-                let end = start + end_offset;
-                let candidate = &s[start..=end];
-                if let Ok(resp) = serde_json::from_str::<PlanResponse>(candidate) {
-                    step_items = resp.steps;
-                    if let Some(r) = resp.reasoning {
-                        if !r.trim().is_empty() {
-                            planner_reasoning = Some(r);
-                        }
-                    }
-                    if !step_items.is_empty() {
-                        break;
+/// Parse the planner's answer (object or legacy array format) into a plan
+fn parse_plan(out: &ChatOutput, tools: &[ToolMeta]) -> WorkflowPlan {
+    let s = out.text.trim();
+    let mut planner_reasoning = out.thought.clone();
+
+    #[derive(serde::Deserialize)]
+    struct StepItem {
+        tool: String,
+        dependencies: Vec<usize>,
+        #[serde(default)]
+        on_error: RecoveryPolicy,
+        /// Only `$ref` bindings are kept; other args are left to the resolver
+        #[serde(default)]
+        args: Value,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct PlanResponse {
+        reasoning: Option<String>,
+        steps: Vec<StepItem>,
+    }
+    
+    let mut step_items: Vec<StepItem> = Vec::new();
+
+    // 1. Try to parse as JSON Object (New Format)
+    let starts_obj: Vec<usize> = s.match_indices('{').map(|(i, _)| i).collect();
+    for start in starts_obj {
+        if let Some(end_offset) = s[start..].rfind('}') {
+            let end = start + end_offset;
+            let candidate = &s[start..=end];
+            if let Ok(resp) = serde_json::from_str::<PlanResponse>(candidate) {
+                step_items = resp.steps;
+                if let Some(r) = resp.reasoning {
+                    if !r.trim().is_empty() {
+                        planner_reasoning = Some(r);
                     }
                 }
+                if !step_items.is_empty() {
+                    break;
+                }
             }
         }
+    }
 
-        // 2. Fallback: Try to parse as JSON Array (Legacy Format)
-        if step_items.is_empty() {
-            let starts_arr: Vec<usize> = s.match_indices('[').map(|(i, _)| i).collect();
-            for start in starts_arr {
-                if let Some(end_offset) = s[start..].rfind(']') {
-                    let end = start + end_offset;
-                    let candidate = &s[start..=end];
-                    if let Ok(items) = serde_json::from_str::<Vec<StepItem>>(candidate) {
-                        step_items = items;
-                        break;
-                    }
+    // 2. Fallback: Try to parse as JSON Array (Legacy Format)
+    if step_items.is_empty() {
+        let starts_arr: Vec<usize> = s.match_indices('[').map(|(i, _)| i).collect();
+        for start in starts_arr {
+            if let Some(end_offset) = s[start..].rfind(']') {
+                let end = start + end_offset;
+                let candidate = &s[start..=end];
+                if let Ok(items) = serde_json::from_str::<Vec<StepItem>>(candidate) {
+                    step_items = items;
+                    break;
                 }
             }
         }
-        
-        let mut steps = Vec::new();
-        for item in step_items {
-            let n = item.tool;
-            let deps = item.dependencies;
-            let args: Value = Value::Null;
-            let lower = n.to_lowercase();
-            if lower == "memory" {
-                steps.push(StepSpec::Memory);
-            } else if lower == "profile" {
-                steps.push(StepSpec::Profile);
-            } else if lower == "relationship" {
-                steps.push(StepSpec::Relationship);
-            } else {
-                let is_background = tools
-                    .iter()
-                    .find(|t| t.name == n)
-                    .map(|t| t.is_long_running)
-                    .unwrap_or(false);
-                steps.push(StepSpec::Tool {
-                    name: n,
-                    args,
-                    is_background,
-                    dependencies: deps,
-                });
-            }
+    }
+    
+    let mut steps = Vec::new();
+    for item in step_items {
+        let n = item.tool;
+        let deps = item.dependencies;
+        let on_error = item.on_error;
+        let args: Value = if has_refs(&item.args) { item.args } else { Value::Null };
+        let lower = n.to_lowercase();
+        if lower == "memory" {
+            steps.push(StepSpec::Memory);
+        } else if lower == "profile" {
+            steps.push(StepSpec::Profile);
+        } else if lower == "relationship" {
+            steps.push(StepSpec::Relationship);
+        } else {
+            let is_background = tools
+                .iter()
+                .find(|t| t.name == n)
+                .map(|t| t.is_long_running)
+                .unwrap_or(false);
+            steps.push(StepSpec::Tool {
+                name: n,
+                args,
+                is_background,
+                dependencies: deps,
+                on_error,
+            });
         }
-        let plan = WorkflowPlan { steps, reasoning: planner_reasoning };
-        info!("llm decision plan: {:?}", plan);
-        Ok(plan)
     }
+    WorkflowPlan { steps, reasoning: planner_reasoning }
 }
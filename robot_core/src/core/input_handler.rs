@@ -1,3 +1,4 @@
+use crate::core::runtime::Runtime;
 use crate::utils::InputEvent;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
@@ -21,6 +22,10 @@ pub trait InputHandler {
     fn metadata(&self) -> Option<SourceMetadata> {
         None
     }
+
+    /// Called once when the handler is added to a core, for handlers that
+    /// publish side-channel outputs such as echoed user messages
+    fn bind(&self, _runtime: &Runtime) {}
 }
 
 #[async_trait]
@@ -1,3 +1,4 @@
+use crate::core::metrics;
 use crate::core::perception::PerceptionData;
 use crate::core::persona::Persona;
 use crate::llm::adapter::{ChatMessage, ChatRequest, LLMClient};
@@ -47,13 +48,15 @@ impl IntentModule for LLMIntentModule {
             Sentiment: {}\n\
             Urgency: {}\n\
             Context: {}\n\
+            Attachments: {}\n\
             \n\
             You are receiving a message. Your task is to decide whether to RESPOND or IGNORE.\n\
             \n\
             Guidelines:\n\
             1. If the message is a direct question, a command, or explicitly addressed to you, RESPOND.\n\
             2. If the message is ambiguous but likely requires an answer (e.g., 'How is the weather?'), RESPOND.\n\
-            3. If the message is just noise, irrelevant, or clearly addressed to someone else, IGNORE.\n\
+            3. If the message only sends files, they are meant to be looked at; RESPOND.\n\
+            4. If the message is just noise, irrelevant, or clearly addressed to someone else, IGNORE.\n\
             \n\
             Format your answer exactly like this:\n\
             Reason: [Short explanation of why]\n\
@@ -62,7 +65,8 @@ impl IntentModule for LLMIntentModule {
             persona.style,
             perception.sentiment,
             perception.urgency,
-            perception.context_summary
+            perception.context_summary,
+            perception.describe_attachments()
         );
 
         let user_prompt = format!("Message: {}", input_text);
@@ -83,7 +87,7 @@ impl IntentModule for LLMIntentModule {
             session_id: None, // Intent analysis is internal, usually no need to stream think?
         };
 
-        let out = self.llm.chat(req).await?;
+        let out = metrics::observe_llm("intent", self.llm.chat(req)).await?;
         let output_text = out.text.trim();
         
         info!("Intent analysis:\n{}", output_text);
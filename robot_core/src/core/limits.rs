@@ -0,0 +1,477 @@
+//! Token-bucket rate limits, so one user cannot starve a shared deployment.
+//!
+//! Limits are read from YAML; every entry is optional:
+//!
+//! ```yaml
+//! messages: 20/min        # inputs per user (per session when anonymous)
+//! llm_requests: 500/day   # LLM requests per session
+//! llm_tokens: 200000/day  # prompt + completion tokens per session
+//! tools:
+//!   "*": 60/min           # calls per session of tools without an entry
+//!   pusher_tool: 3/hour
+//! ```
+//!
+//! Inputs are checked by the `SessionManager`, tool calls by
+//! [`RateLimitedMcp`] in the session's client chain and LLM requests by
+//! [`RateLimitedLlm`], which finds the session through [`scope`].
+use crate::core::metrics;
+use crate::llm::adapter::{ChatOutput, ChatRequest, LLMClient};
+use crate::mcp::client::MCPClient;
+use crate::mcp::registry::ToolMeta;
+use async_trait::async_trait;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// `amount` per `per`, written `20/min`, `500/day`, `3/hour` or `1/s`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    pub amount: f64,
+    pub per: Duration,
+}
+
+impl std::str::FromStr for Rate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (amount, unit) = s
+            .split_once('/')
+            .ok_or_else(|| anyhow::anyhow!("invalid rate '{}', expected e.g. 20/min", s))?;
+        let amount: f64 = amount.trim().parse()?;
+        let per = match unit.trim() {
+            "s" | "sec" | "second" => 1,
+            "m" | "min" | "minute" => 60,
+            "h" | "hour" => 3600,
+            "d" | "day" => 86400,
+            other => anyhow::bail!("unknown rate unit '{}' in '{}'", other, s),
+        };
+        if amount <= 0.0 {
+            anyhow::bail!("rate '{}' must allow at least some requests", s);
+        }
+        Ok(Self {
+            amount,
+            per: Duration::from_secs(per),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+    pub messages: Option<Rate>,
+    pub llm_requests: Option<Rate>,
+    pub llm_tokens: Option<Rate>,
+    /// Per tool name; `*` covers the tools without their own entry
+    pub tools: HashMap<String, Rate>,
+}
+
+impl RateLimits {
+    pub fn from_yaml(text: &str) -> anyhow::Result<Self> {
+        Ok(serde_yaml::from_str(text)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("failed to read rate limits {:?}: {}", path, e))?;
+        Self::from_yaml(&text).map_err(|e| anyhow::anyhow!("invalid rate limits {:?}: {}", path, e))
+    }
+}
+
+/// Which limit was hit, for the message shown to the user
+#[derive(Debug, Clone)]
+pub struct LimitExceeded {
+    /// `input`, `llm` or `tool`
+    pub layer: &'static str,
+    pub subject: String,
+    pub retry_after: Duration,
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let wait = human(self.retry_after);
+        match self.layer {
+            "input" => write!(f, "You are sending messages too quickly. Please wait {} before the next one.", wait),
+            "llm" => write!(f, "This conversation has used up its model budget for now. Please try again in {}.", wait),
+            _ => write!(f, "'{}' has been used too often. Please try again in {}.", self.subject, wait),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+fn human(d: Duration) -> String {
+    let secs = d.as_secs().max(1);
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs.div_ceil(60)),
+        _ => format!("{}h {}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
+
+struct Bucket {
+    rate: Rate,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let per_sec = self.rate.amount / self.rate.per.as_secs_f64();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_sec).min(self.rate.amount);
+        self.updated = now;
+    }
+
+    /// Time until `n` tokens are available
+    fn wait_for(&self, n: f64) -> Duration {
+        let per_sec = self.rate.amount / self.rate.per.as_secs_f64();
+        Duration::from_secs_f64(((n - self.tokens) / per_sec).max(0.0))
+    }
+}
+
+/// How often buckets that refilled completely are dropped; a full bucket is
+/// created again as it was
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    pruned: Option<Instant>,
+}
+
+/// Buckets for every user, session and tool, shared by all sessions
+#[derive(Default)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// No limits at all
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Run `f` on the refilled bucket `key`, created full
+    fn with_bucket<R>(&self, key: String, rate: Rate, f: impl FnOnce(&mut Bucket) -> R) -> R {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.pruned.is_none_or(|at| now.duration_since(at) >= PRUNE_INTERVAL) {
+            buckets.by_key.retain(|_, b| {
+                let idle = now.duration_since(b.updated) >= PRUNE_INTERVAL;
+                b.refill(now);
+                !idle || b.tokens < b.rate.amount
+            });
+            buckets.pruned = Some(now);
+        }
+        let bucket = buckets.by_key.entry(key).or_insert(Bucket {
+            rate,
+            tokens: rate.amount,
+            updated: now,
+        });
+        bucket.refill(now);
+        f(bucket)
+    }
+
+    fn take(&self, key: String, rate: Rate) -> Result<(), Duration> {
+        self.with_bucket(key, rate, |b| {
+            if b.tokens >= 1.0 {
+                b.tokens -= 1.0;
+                Ok(())
+            } else {
+                Err(b.wait_for(1.0))
+            }
+        })
+    }
+
+    fn exceeded(layer: &'static str, subject: &str, retry_after: Duration) -> LimitExceeded {
+        metrics::record_rate_limited(layer);
+        LimitExceeded {
+            layer,
+            subject: subject.to_string(),
+            retry_after,
+        }
+    }
+
+    /// Count one input of `user`
+    pub fn check_message(&self, user: &str) -> Result<(), LimitExceeded> {
+        let Some(rate) = self.limits.messages else {
+            return Ok(());
+        };
+        self.take(format!("input:{}", user), rate)
+            .map_err(|wait| Self::exceeded("input", user, wait))
+    }
+
+    /// Whether `session` has LLM requests and tokens left, without using any
+    pub fn llm_available(&self, session: &str) -> Result<(), LimitExceeded> {
+        if let Some(rate) = self.limits.llm_requests {
+            self.with_bucket(format!("llm:{}", session), rate, |b| {
+                if b.tokens >= 1.0 { Ok(()) } else { Err(b.wait_for(1.0)) }
+            })
+            .map_err(|wait| Self::exceeded("llm", session, wait))?;
+        }
+        // Token costs are charged afterwards, so the budget may be overdrawn
+        if let Some(rate) = self.limits.llm_tokens {
+            self.with_bucket(format!("llm_tokens:{}", session), rate, |b| {
+                if b.tokens > 0.0 { Ok(()) } else { Err(b.wait_for(1.0)) }
+            })
+            .map_err(|wait| Self::exceeded("llm", session, wait))?;
+        }
+        Ok(())
+    }
+
+    /// Count one LLM request of `session`
+    pub fn check_llm(&self, session: &str) -> Result<(), LimitExceeded> {
+        self.llm_available(session)?;
+        if let Some(rate) = self.limits.llm_requests {
+            self.take(format!("llm:{}", session), rate)
+                .map_err(|wait| Self::exceeded("llm", session, wait))?;
+        }
+        Ok(())
+    }
+
+    /// Charge the tokens an LLM request of `session` used
+    pub fn charge_llm_tokens(&self, session: &str, tokens: u64) {
+        if let Some(rate) = self.limits.llm_tokens {
+            self.with_bucket(format!("llm_tokens:{}", session), rate, |b| b.tokens -= tokens as f64);
+        }
+    }
+
+    /// Count one call of `tool` in `session`
+    pub fn check_tool(&self, session: &str, tool: &str) -> Result<(), LimitExceeded> {
+        let Some(rate) = self.limits.tools.get(tool).or_else(|| self.limits.tools.get("*")) else {
+            return Ok(());
+        };
+        self.take(format!("tool:{}:{}", session, tool), *rate)
+            .map_err(|wait| Self::exceeded("tool", tool, wait))
+    }
+}
+
+struct SessionScope {
+    session_id: String,
+    limiter: Arc<RateLimiter>,
+}
+
+tokio::task_local! {
+    static SESSION: SessionScope;
+}
+
+/// Run `fut` with its LLM requests counted against `session_id`
+pub async fn scope<F: Future>(session_id: String, limiter: Arc<RateLimiter>, fut: F) -> F::Output {
+    SESSION.scope(SessionScope { session_id, limiter }, fut).await
+}
+
+/// Applies the LLM limits of the session in [`scope`]; requests outside of
+/// a session are not limited
+pub struct RateLimitedLlm {
+    inner: Arc<dyn LLMClient + Send + Sync>,
+}
+
+impl RateLimitedLlm {
+    pub fn new(inner: Arc<dyn LLMClient + Send + Sync>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl LLMClient for RateLimitedLlm {
+    async fn chat(&self, req: ChatRequest) -> anyhow::Result<ChatOutput> {
+        let session = SESSION.try_with(|s| (s.session_id.clone(), s.limiter.clone())).ok();
+        if let Some((id, limiter)) = &session {
+            limiter.check_llm(id)?;
+        }
+        let out = self.inner.chat(req).await?;
+        if let Some((id, limiter)) = &session {
+            let usage = &out.raw["usage"];
+            let tokens = usage["total_tokens"].as_u64().unwrap_or_else(|| {
+                usage["prompt_tokens"].as_u64().unwrap_or(0) + usage["completion_tokens"].as_u64().unwrap_or(0)
+            });
+            limiter.charge_llm_tokens(id, tokens);
+        }
+        Ok(out)
+    }
+}
+
+/// Applies the tool limits to the calls of one session
+pub struct RateLimitedMcp {
+    inner: Arc<dyn MCPClient + Send + Sync>,
+    limiter: Arc<RateLimiter>,
+    session_id: String,
+}
+
+impl RateLimitedMcp {
+    pub fn new(inner: Arc<dyn MCPClient + Send + Sync>, limiter: Arc<RateLimiter>, session_id: String) -> Self {
+        Self {
+            inner,
+            limiter,
+            session_id,
+        }
+    }
+}
+
+#[async_trait]
+impl MCPClient for RateLimitedMcp {
+    async fn call(&self, tool: &str, args: Value) -> anyhow::Result<Value> {
+        self.limiter.check_tool(&self.session_id, tool)?;
+        self.inner.call(tool, args).await
+    }
+
+    async fn list_tools(&self) -> anyhow::Result<Vec<ToolMeta>> {
+        self.inner.list_tools().await
+    }
+
+    async fn required_fields(&self, tool: &str) -> anyhow::Result<Vec<String>> {
+        self.inner.required_fields(tool).await
+    }
+
+    async fn tool_schema(&self, tool: &str) -> anyhow::Result<Option<Value>> {
+        self.inner.tool_schema(tool).await
+    }
+
+    async fn elicit_preview(&self, tool: &str) -> anyhow::Result<Option<Value>> {
+        self.inner.elicit_preview(tool).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: &str = r#"
+messages: 2/min
+llm_tokens: 100/day
+tools:
+  "*": 5/min
+  pusher_tool: 1/hour
+"#;
+
+    #[tokio::test]
+    async fn limits_inputs_llm_tokens_and_tools() {
+        let limiter = Arc::new(RateLimiter::new(RateLimits::from_yaml(LIMITS).unwrap()));
+        assert!(limiter.check_message("alice").is_ok());
+        assert!(limiter.check_message("alice").is_ok());
+        let hit = limiter.check_message("alice").unwrap_err();
+        assert!(hit.retry_after > Duration::from_secs(20), "{:?}", hit);
+        assert!(hit.to_string().contains("too quickly"));
+        assert!(limiter.check_message("bob").is_ok());
+
+        let mcp = RateLimitedMcp::new(Arc::new(crate::mcp::client::BasicMCPClient), limiter.clone(), "s1".into());
+        assert!(mcp.call("pusher_tool", Value::Null).await.is_ok());
+        let err = mcp.call("pusher_tool", Value::Null).await.unwrap_err();
+        assert!(err.downcast_ref::<LimitExceeded>().is_some());
+        assert!(mcp.call("echo", Value::Null).await.is_ok());
+
+        let llm = RateLimitedLlm::new(Arc::new(crate::testkit::ScriptedLlm::new().on(".*", "hi")));
+        let req = || ChatRequest {
+            model: "m".into(),
+            messages: Vec::new(),
+            temperature: None,
+            session_id: None,
+        };
+        // Outside a session nothing is limited
+        assert!(llm.chat(req()).await.is_ok());
+        scope("s1".into(), limiter.clone(), async {
+            assert!(llm.chat(req()).await.is_ok());
+            limiter.charge_llm_tokens("s1", 150);
+            let err = llm.chat(req()).await.unwrap_err();
+            assert!(err.to_string().contains("model budget"), "{}", err);
+        })
+        .await;
+        assert!(limiter.llm_available("s2").is_ok());
+        assert!("3/fortnight".parse::<Rate>().is_err());
+    }
+
+    #[test]
+    fn drops_buckets_that_refilled_while_idle() {
+        let limiter = RateLimiter::new(RateLimits::from_yaml(LIMITS).unwrap());
+        assert!(limiter.check_tool("s1", "echo").is_ok());
+        assert!(limiter.check_tool("s1", "pusher_tool").is_ok());
+        {
+            let mut buckets = limiter.buckets.lock().unwrap();
+            let earlier = Instant::now() - PRUNE_INTERVAL * 2;
+            for bucket in buckets.by_key.values_mut() {
+                bucket.updated = earlier;
+            }
+            buckets.pruned = Some(earlier);
+        }
+        assert!(limiter.check_message("alice").is_ok());
+        let buckets = limiter.buckets.lock().unwrap();
+        let mut keys: Vec<&String> = buckets.by_key.keys().collect();
+        keys.sort();
+        // The echo bucket refilled in two minutes, the hourly one did not
+        assert_eq!(keys, ["input:alice", "tool:s1:pusher_tool"]);
+    }
+
+    #[tokio::test]
+    async fn retries_stop_at_the_tool_limit() {
+        use crate::core::workflow_engine::{StepOutcome, WorkflowEngine};
+        use crate::testkit::{FakeMcp, FakeTool};
+        let fake = FakeMcp::new()
+            .tool(FakeTool::new("pusher_tool", "Push a stream").handler(|_| Err(anyhow::anyhow!("no route"))));
+        let limiter = Arc::new(RateLimiter::new(RateLimits::from_yaml(LIMITS).unwrap()));
+        let mcp = RateLimitedMcp::new(Arc::new(fake.clone()), limiter, "s1".into());
+        let spec: crate::utils::StepSpec = serde_json::from_value(serde_json::json!({"Tool": {
+            "name": "pusher_tool",
+            "args": {"ip": "10.0.0.5"},
+            "on_error": {"Retry": {"max_attempts": 5, "backoff_ms": 100}}
+        }}))
+        .unwrap();
+        let mut ctx = crate::utils::Context::new(Default::default(), "push".into(), None);
+        ctx.memory = serde_json::json!({"workflow": {"current_step_index": 0}});
+
+        let started = Instant::now();
+        let outcome = WorkflowEngine::new().run_step(&spec, &mut ctx, &mcp).await;
+        let StepOutcome::Failed(err) = outcome else {
+            panic!("the step should fail");
+        };
+        assert!(err.downcast_ref::<LimitExceeded>().is_some(), "{}", err);
+        // Only the first backoff was waited for
+        assert!(started.elapsed() < Duration::from_millis(500), "{:?}", started.elapsed());
+        assert_eq!(fake.calls_to("pusher_tool").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn tool_limits_stop_the_workflow_without_replanning() {
+        use crate::testkit::{FakeMcp, FakeTool, ScriptedLlm, TestRobot, prompts};
+        let plan = serde_json::json!({"reasoning": "push twice", "steps": [
+            {"tool": "pusher_tool", "dependencies": []},
+            {"tool": "pusher_tool", "dependencies": []}
+        ]});
+        let llm = ScriptedLlm::new()
+            .respond_to_all()
+            .plan("推流", plan)
+            .params("pusher_tool", serde_json::json!({"ip": "10.0.0.5"}));
+        let mcp = FakeMcp::new().tool(FakeTool::new("pusher_tool", "Push a stream").returns("pushed"));
+        let robot = TestRobot::builder(llm, mcp)
+            .limits(RateLimits::from_yaml(LIMITS).unwrap())
+            .build()
+            .await;
+        let mut chat = robot.conversation("dave");
+
+        chat.say("推流两次").unwrap();
+        let reply = chat.reply_text().await.unwrap();
+        assert!(reply.contains("'pusher_tool' has been used too often. Please try again in"), "{}", reply);
+        assert_eq!(robot.mcp.calls_to("pusher_tool").len(), 1);
+        assert!(robot.llm.prompts_matching(prompts::REPLANNER).is_empty());
+        assert!(robot.llm.prompts_matching(prompts::FAILURE).is_empty());
+    }
+}
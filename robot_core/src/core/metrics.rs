@@ -0,0 +1,251 @@
+//! Process-wide Prometheus metrics, served as text on `/metrics`.
+//!
+//! Components record through the helpers below; [`render`] encodes the
+//! registry in the Prometheus text format.
+use crate::llm::adapter::ChatOutput;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+pub struct Metrics {
+    registry: Registry,
+    inputs: IntCounterVec,
+    intent_decisions: IntCounterVec,
+    plan_steps: HistogramVec,
+    tool_calls: IntCounterVec,
+    tool_call_duration: HistogramVec,
+    llm_requests: IntCounterVec,
+    llm_request_duration: HistogramVec,
+    llm_tokens: IntCounterVec,
+    active_sessions: IntGauge,
+    background_tasks: IntGauge,
+    elicitations: IntCounterVec,
+    active_elicitations: IntGauge,
+    output_failures: IntCounterVec,
+    rate_limited: IntCounterVec,
+    input_queue_depth: IntGauge,
+    session_queue_depth: IntGaugeVec,
+    queue_overflows: IntCounterVec,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+fn counter(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let c = IntCounterVec::new(Opts::new(name, help), labels).expect("valid counter");
+    registry.register(Box::new(c.clone())).expect("unique metric");
+    c
+}
+
+fn histogram(registry: &Registry, name: &str, help: &str, labels: &[&str], buckets: &[f64]) -> HistogramVec {
+    let h = HistogramVec::new(HistogramOpts::new(name, help).buckets(buckets.to_vec()), labels)
+        .expect("valid histogram");
+    registry.register(Box::new(h.clone())).expect("unique metric");
+    h
+}
+
+fn gauge(registry: &Registry, name: &str, help: &str) -> IntGauge {
+    let g = IntGauge::new(name, help).expect("valid gauge");
+    registry.register(Box::new(g.clone())).expect("unique metric");
+    g
+}
+
+fn gauge_vec(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+    let g = IntGaugeVec::new(Opts::new(name, help), labels).expect("valid gauge");
+    registry.register(Box::new(g.clone())).expect("unique metric");
+    g
+}
+
+const LATENCY_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(|| {
+        let r = Registry::new();
+        Metrics {
+            inputs: counter(&r, "robot_inputs_total", "Inputs received, by source", &["source"]),
+            intent_decisions: counter(&r, "robot_intent_decisions_total", "Intent decisions", &["decision"]),
+            plan_steps: histogram(
+                &r,
+                "robot_plan_steps",
+                "Top-level steps per plan, by origin (planner, template)",
+                &["origin"],
+                &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 8.0, 13.0, 20.0],
+            ),
+            tool_calls: counter(&r, "robot_tool_calls_total", "Tool calls, by outcome (ok, error)", &["tool", "outcome"]),
+            tool_call_duration: histogram(
+                &r,
+                "robot_tool_call_duration_seconds",
+                "Tool call latency, including elicitation",
+                &["tool"],
+                LATENCY_BUCKETS,
+            ),
+            llm_requests: counter(&r, "robot_llm_requests_total", "LLM requests, by purpose and outcome", &["purpose", "outcome"]),
+            llm_request_duration: histogram(
+                &r,
+                "robot_llm_request_duration_seconds",
+                "LLM request latency, by purpose",
+                &["purpose"],
+                LATENCY_BUCKETS,
+            ),
+            llm_tokens: counter(
+                &r,
+                "robot_llm_tokens_total",
+                "Tokens reported by the LLM, by purpose and kind (prompt, completion)",
+                &["purpose", "kind"],
+            ),
+            active_sessions: gauge(&r, "robot_active_sessions", "Running session actors"),
+            background_tasks: gauge(&r, "robot_background_tasks", "Running background tool tasks"),
+            elicitations: counter(&r, "robot_elicitations_total", "Elicitations, by outcome (accepted, cancelled, failed)", &["outcome"]),
+            active_elicitations: gauge(&r, "robot_active_elicitations", "Sessions waiting on an elicitation answer"),
+            output_failures: counter(&r, "robot_output_failures_total", "Failed output handler emits", &["handler"]),
+            rate_limited: counter(&r, "robot_rate_limited_total", "Requests refused by a rate limit, by layer (input, llm, tool)", &["layer"]),
+            input_queue_depth: gauge(&r, "robot_input_queue_depth", "Inputs waiting to be dispatched to a session"),
+            session_queue_depth: gauge_vec(&r, "robot_session_queue_depth", "Inputs waiting in a session's inbox", &["session"]),
+            queue_overflows: counter(
+                &r,
+                "robot_queue_overflows_total",
+                "Items dropped or rejected by a full queue, by queue and policy",
+                &["queue", "policy"],
+            ),
+            registry: r,
+        }
+    })
+}
+
+/// Prometheus text exposition of all metrics
+pub fn render() -> String {
+    let mut buf = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&metrics().registry.gather(), &mut buf) {
+        tracing::error!("Failed to encode metrics: {}", e);
+    }
+    String::from_utf8(buf).unwrap_or_default()
+}
+
+fn outcome(ok: bool) -> &'static str {
+    if ok { "ok" } else { "error" }
+}
+
+pub fn record_input(source: &str) {
+    metrics().inputs.with_label_values(&[source]).inc();
+}
+
+pub fn record_intent(decision: &str) {
+    metrics().intent_decisions.with_label_values(&[decision]).inc();
+}
+
+pub fn record_plan(origin: &str, steps: usize) {
+    metrics().plan_steps.with_label_values(&[origin]).observe(steps as f64);
+}
+
+pub fn record_tool_call(tool: &str, ok: bool, duration: Duration) {
+    let m = metrics();
+    m.tool_calls.with_label_values(&[tool, outcome(ok)]).inc();
+    m.tool_call_duration
+        .with_label_values(&[tool])
+        .observe(duration.as_secs_f64());
+}
+
+pub fn record_elicitation(outcome: &str) {
+    metrics().elicitations.with_label_values(&[outcome]).inc();
+}
+
+pub fn elicitation_started() {
+    metrics().active_elicitations.inc();
+}
+
+pub fn elicitation_ended() {
+    metrics().active_elicitations.dec();
+}
+
+pub fn record_output_failure(handler: &str) {
+    metrics().output_failures.with_label_values(&[handler]).inc();
+}
+
+pub fn record_rate_limited(layer: &str) {
+    metrics().rate_limited.with_label_values(&[layer]).inc();
+}
+
+pub fn record_queue_overflow(queue: &str, policy: &str) {
+    metrics().queue_overflows.with_label_values(&[queue, policy]).inc();
+}
+
+pub fn input_queue_depth() -> IntGauge {
+    metrics().input_queue_depth.clone()
+}
+
+pub fn session_queue_depth(session: &str) -> IntGauge {
+    metrics().session_queue_depth.with_label_values(&[session])
+}
+
+/// Stop reporting the inbox of a session that ended
+pub fn remove_session_queue_depth(session: &str) {
+    let _ = metrics().session_queue_depth.remove_label_values(&[session]);
+}
+
+pub fn session_started() {
+    metrics().active_sessions.inc();
+}
+
+pub fn session_ended() {
+    metrics().active_sessions.dec();
+}
+
+pub fn task_started() {
+    metrics().background_tasks.inc();
+}
+
+pub fn task_finished() {
+    metrics().background_tasks.dec();
+}
+
+/// Time an LLM request made for `purpose` and count the tokens of its
+/// OpenAI-style `usage`
+pub async fn observe_llm(
+    purpose: &str,
+    request: impl Future<Output = anyhow::Result<ChatOutput>>,
+) -> anyhow::Result<ChatOutput> {
+    let started = Instant::now();
+    let res = request.await;
+    let m = metrics();
+    m.llm_request_duration
+        .with_label_values(&[purpose])
+        .observe(started.elapsed().as_secs_f64());
+    m.llm_requests.with_label_values(&[purpose, outcome(res.is_ok())]).inc();
+    if let Ok(out) = &res {
+        for kind in ["prompt", "completion"] {
+            if let Some(n) = out.raw["usage"][format!("{}_tokens", kind)].as_u64() {
+                m.llm_tokens.with_label_values(&[purpose, kind]).inc_by(n);
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn renders_llm_usage_and_tool_calls() {
+        let tokens = || metrics().llm_tokens.with_label_values(&["test", "prompt"]).get();
+        let before = tokens();
+        let out = observe_llm("test", async {
+            Ok(ChatOutput {
+                text: "ok".into(),
+                thought: None,
+                raw: serde_json::json!({"usage": {"prompt_tokens": 12, "completion_tokens": 3}}),
+            })
+        })
+        .await;
+        assert!(out.is_ok());
+        assert_eq!(tokens() - before, 12);
+
+        record_tool_call("metrics_test_tool", false, Duration::from_millis(20));
+        let text = render();
+        assert!(text.contains(r#"robot_llm_tokens_total{kind="completion",purpose="test"}"#));
+        assert!(text.contains(r#"robot_tool_calls_total{outcome="error",tool="metrics_test_tool"} 1"#));
+        assert!(text.contains("robot_tool_call_duration_seconds_bucket"));
+    }
+}
@@ -1,12 +1,23 @@
+pub mod auth;
 pub mod decision_engine;
 pub mod input_handler;
 pub mod intent;
+pub mod limits;
+pub mod metrics;
 pub mod output_handler;
 pub mod perception;
 pub mod persona;
+pub mod policy;
+pub mod queue;
 pub mod router;
+pub mod runtime;
 pub mod session;
 pub mod sessions;
+pub mod synthesis;
+pub mod replay;
+pub mod templates;
+pub mod trace;
+pub mod uploads;
 pub mod workflow_engine;
 pub mod tasks;
 
@@ -17,14 +28,14 @@ use crate::core::output_handler::OutputHandler;
 use crate::core::perception::PerceptionModule;
 use crate::core::persona::Persona;
 use crate::core::router::{EventRouter, HandlerId};
+use crate::core::runtime::Runtime;
 use crate::core::session::SessionManager;
 use crate::core::workflow_engine::WorkflowEngine;
 use crate::mcp::client::MCPClient;
 use crate::utils::InputEvent;
-use futures::future::{join_all, BoxFuture};
+use futures::future::{join_all, BoxFuture, FutureExt};
 use std::collections::HashMap;
-use tokio::sync::mpsc;
-use tracing::info;
+use tracing::{info, warn};
 
 use std::sync::{Arc, RwLock as StdRwLock};
 use tokio::sync::RwLock;
@@ -45,9 +56,10 @@ pub struct RobotCore {
     // mcp_clients is replaced by session_manager
     pub session_manager: Arc<SessionManager>,
     pub mcp_client_factory: Arc<McpClientFactory>,
-    pub input_receiver: mpsc::UnboundedReceiver<InputEvent>,
-    pub input_sender: mpsc::UnboundedSender<InputEvent>,
+    pub input_receiver: queue::Receiver<InputEvent>,
+    pub input_sender: queue::Sender<InputEvent>,
     pub router: Arc<StdRwLock<EventRouter>>,
+    pub runtime: Runtime,
 }
 
 impl RobotCore {
@@ -59,7 +71,30 @@ impl RobotCore {
         intent_module: Box<dyn IntentModule + Send + Sync>,
         mcp_client_factory: McpClientFactory,
     ) -> Self {
-        let (input_sender, input_receiver) = mpsc::unbounded_channel();
+        Self::with_runtime(
+            Runtime::new(),
+            persona,
+            decision_engine,
+            workflow_engine,
+            perception_module,
+            intent_module,
+            mcp_client_factory,
+        )
+    }
+
+    /// Like [`RobotCore::new`], sharing `runtime` with MCP clients and LLM
+    /// clients created beforehand
+    pub fn with_runtime(
+        runtime: Runtime,
+        persona: Persona,
+        decision_engine: Box<dyn DecisionEngine + Send + Sync>,
+        workflow_engine: WorkflowEngine,
+        perception_module: Box<dyn PerceptionModule + Send + Sync>,
+        intent_module: Box<dyn IntentModule + Send + Sync>,
+        mcp_client_factory: McpClientFactory,
+    ) -> Self {
+        let (input_sender, input_receiver) =
+            queue::channel("input", runtime.queues().input, Some(metrics::input_queue_depth()));
         let output_handlers: Arc<RwLock<HashMap<HandlerId, Box<dyn OutputHandler + Send + Sync>>>> =
             Arc::new(RwLock::new(HashMap::new()));
 
@@ -80,25 +115,27 @@ impl RobotCore {
             persona_arc.clone(),
             output_handlers.clone(),
             router_arc.clone(),
+            runtime.clone(),
         ));
 
         // Spawn background task for system output broadcasting
         let handlers_clone = output_handlers.clone();
+        let mut output_bus_receiver = runtime.subscribe_outputs();
         tokio::spawn(async move {
-            let mut output_bus_receiver = crate::utils::output_bus().subscribe();
             while let Ok(event) = output_bus_receiver.recv().await {
                 info!("Broadcasting system output from {}", event.source);
                 let handlers_guard = handlers_clone.read().await;
 
                 // Collect futures to await them
                 let futures = handlers_guard
-                    .values()
-                    .map(|handler| handler.emit(event.clone()))
+                    .iter()
+                    .map(|(id, handler)| handler.emit(event.clone()).map(move |res| (id, res)))
                     .collect::<Vec<_>>();
 
                 let results = join_all(futures).await;
-                for res in results {
+                for (id, res) in results {
                     if let Err(e) = res {
+                        metrics::record_output_failure(id.name());
                         info!("Error emitting system output: {}", e);
                     }
                 }
@@ -117,19 +154,30 @@ impl RobotCore {
             input_receiver,
             input_sender,
             router: router_arc,
+            runtime,
         }
     }
 
     pub fn add_input_handler(&self, handler: Box<dyn InputHandler + Send + Sync>) {
+        handler.bind(&self.runtime);
         let sender = self.input_sender.clone();
+        let runtime = self.runtime.clone();
         tokio::spawn(async move {
             loop {
                 match handler.poll().await {
                     Ok(Some(event)) => {
                         info!("Received event from {}", event.source);
-                        if sender.send(event).is_err() {
-                            info!("Input handler: main channel closed, stopping");
-                            break;
+                        // An elicitation may be waiting for this very input
+                        runtime.publish_input(&event);
+                        match sender.send(event).await {
+                            Ok(()) => {}
+                            Err(queue::SendError::Full(event)) => {
+                                warn!("Input queue full, dropping input {} from {}", event.id, event.source);
+                            }
+                            Err(queue::SendError::Closed(_)) => {
+                                info!("Input handler: main channel closed, stopping");
+                                break;
+                            }
                         }
                     }
                     Ok(None) => {
@@ -149,28 +197,37 @@ impl RobotCore {
         id: HandlerId,
         handler: Box<dyn OutputHandler + Send + Sync>,
     ) {
+        handler.bind(&self.runtime);
         self.output_handlers.write().await.insert(id, handler);
     }
 
+    /// Write a structured trace of every handled input to `sink`
+    pub fn set_trace_sink(&self, sink: Arc<dyn crate::core::trace::TraceSink>) {
+        self.session_manager.set_trace_sink(sink);
+    }
+
+    /// Enforce a tool authorization policy in new sessions
+    pub fn set_policy(&self, policy: Arc<crate::core::policy::Policy>) {
+        self.session_manager.set_policy(policy);
+    }
+
+    /// Rate-limit inputs per user, and LLM and tool use per session
+    pub fn set_rate_limits(&self, limits: crate::core::limits::RateLimits) {
+        self.session_manager
+            .set_rate_limiter(Arc::new(crate::core::limits::RateLimiter::new(limits)));
+    }
+
     pub fn route(&self) -> std::sync::RwLockWriteGuard<'_, EventRouter> {
         self.router.write().expect("Failed to lock router")
     }
 
+    /// Wait for the next input and hand it to its session
     pub async fn run_once(&mut self) -> anyhow::Result<()> {
-        tokio::select! {
-            res = self.input_receiver.recv() => {
-                if let Some(event) = res {
-                    info!("Dispatching event from {} to session manager", event.source);
-                    self.session_manager.dispatch(event).await;
-                } else {
-                    info!("Input channel closed");
-                }
-            }
-            _ = tokio::time::sleep(tokio::time::Duration::from_millis(100)) => {
-                // Idle
-            }
+        // The core keeps a sender itself, so the queue never closes
+        if let Some(event) = self.input_receiver.recv().await {
+            info!("Dispatching event from {} to session manager", event.source);
+            self.session_manager.dispatch(event).await;
         }
-
         Ok(())
     }
 }
@@ -1,4 +1,5 @@
 use crate::core::input_handler::SourceType;
+use crate::core::runtime::Runtime;
 use crate::utils::OutputEvent;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
@@ -18,6 +19,15 @@ pub trait OutputHandler {
     fn metadata(&self) -> Option<OutputMetadata> {
         None
     }
+
+    /// Whether this handler also wants intermediate tool results
+    /// (`type: "tool_result"`), not just the synthesized reply
+    fn show_intermediate(&self) -> bool {
+        false
+    }
+    /// Called once when the handler is added to a core, for handlers that
+    /// size their client queues from the runtime
+    fn bind(&self, _runtime: &Runtime) {}
 }
 
 #[async_trait]
@@ -1,4 +1,4 @@
-use crate::utils::InputEvent;
+use crate::utils::{Attachment, InputEvent};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
@@ -7,6 +7,23 @@ pub struct PerceptionData {
     pub sentiment: String,
     pub urgency: String,
     pub context_summary: String,
+    /// Files that came with the input
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+impl PerceptionData {
+    /// One line naming each attachment and its type, or `none`
+    pub fn describe_attachments(&self) -> String {
+        if self.attachments.is_empty() {
+            return "none".to_string();
+        }
+        self.attachments
+            .iter()
+            .map(|a| format!("{} ({}, {} bytes)", a.name, a.mime_type, a.size))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
 }
 
 #[async_trait]
@@ -18,12 +35,13 @@ pub struct BasicPerceptionModule;
 
 #[async_trait]
 impl PerceptionModule for BasicPerceptionModule {
-    async fn perceive(&self, _input: &InputEvent) -> anyhow::Result<PerceptionData> {
+    async fn perceive(&self, input: &InputEvent) -> anyhow::Result<PerceptionData> {
         // Placeholder: In a real system, this would analyze the input text/event
         Ok(PerceptionData {
             sentiment: "neutral".to_string(),
             urgency: "normal".to_string(),
             context_summary: "No deep analysis".to_string(),
+            attachments: input.attachments.clone(),
         })
     }
 }
@@ -0,0 +1,666 @@
+//! Authorization policy for tool calls.
+//!
+//! A [`Policy`] is an ordered list of rules loaded from YAML. The first rule
+//! whose tools, subject (users or roles) and argument constraints match the
+//! call decides: `allow`, `deny` or `confirm`. `confirm` pauses the workflow
+//! with `StepStatus::WaitUser` until the user approves the exact call, edits
+//! its args or rejects it. Allowed calls of tools the server annotates as
+//! destructive are confirmed too, unless `confirm_destructive` is off.
+//!
+//! ```yaml
+//! default: allow
+//! confirm_destructive: true
+//! roles:
+//!   admin: [alice]
+//! rules:
+//!   - tools: [gpuinfo]
+//!     roles: [admin]
+//!     effect: allow
+//!   - tools: [gpuinfo]
+//!     effect: deny
+//!   - tools: [pusher_tool]
+//!     effect: confirm
+//!     args:
+//!       ip: { cidr: ["10.0.0.0/8", "192.168.0.0/16"] }
+//!       port: { min: 1024, max: 65535 }
+//!   - tools: [pusher_tool]
+//!     effect: deny
+//!     message: pushing is limited to the device networks
+//! ```
+use crate::core::auth::Identity;
+use crate::mcp::client::MCPClient;
+use crate::mcp::registry::ToolMeta;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+/// User id that rules see for callers without an identity
+pub const ANONYMOUS: &str = "anonymous";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Effect {
+    #[default]
+    Allow,
+    Deny,
+    Confirm,
+}
+
+/// Conditions on one argument; all given ones must hold
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArgConstraint {
+    pub one_of: Option<Vec<Value>>,
+    /// Regex the whole string value must match
+    pub pattern: Option<String>,
+    /// CIDR ranges (or single addresses) an IP literal must fall in
+    pub cidr: Option<Vec<String>>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyRule {
+    /// Tool names; `*` matches every tool and `prefix_*` a prefix
+    pub tools: Vec<String>,
+    /// Users the rule applies to; empty with empty `roles` means everyone
+    pub users: Vec<String>,
+    pub roles: Vec<String>,
+    pub args: HashMap<String, ArgConstraint>,
+    pub effect: Effect,
+    /// Shown to the user when the rule denies or asks for confirmation
+    pub message: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Effect when no rule matches
+    pub default: Effect,
+    /// Ask before allowed calls of destructive tools
+    pub confirm_destructive: bool,
+    /// role -> user ids
+    pub roles: HashMap<String, Vec<String>>,
+    pub rules: Vec<PolicyRule>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            default: Effect::Allow,
+            confirm_destructive: true,
+            roles: HashMap::new(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Decision {
+    Allow,
+    Deny(String),
+    Confirm(String),
+}
+
+impl Policy {
+    /// Everything allowed, destructive tools after confirmation: the
+    /// behaviour without a policy file
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    pub fn from_yaml(text: &str) -> anyhow::Result<Self> {
+        let policy: Self = serde_yaml::from_str(text)?;
+        for rule in &policy.rules {
+            for (arg, c) in &rule.args {
+                if let Some(p) = &c.pattern {
+                    regex::Regex::new(p).map_err(|e| anyhow::anyhow!("invalid pattern for '{}': {}", arg, e))?;
+                }
+                for range in c.cidr.iter().flatten() {
+                    parse_cidr(range).ok_or_else(|| anyhow::anyhow!("invalid cidr '{}' for '{}'", range, arg))?;
+                }
+            }
+        }
+        Ok(policy)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("failed to read policy {:?}: {}", path, e))?;
+        Self::from_yaml(&text).map_err(|e| anyhow::anyhow!("invalid policy {:?}: {}", path, e))
+    }
+
+    fn user_roles<'a>(&'a self, user: &'a str) -> impl Iterator<Item = &'a String> + 'a {
+        self.roles
+            .iter()
+            .filter(move |(_, users)| users.iter().any(|u| u == user))
+            .map(|(role, _)| role)
+    }
+
+    fn applies_to(&self, rule: &PolicyRule, tool: &str, user: &str) -> bool {
+        let tool_matches = rule.tools.iter().any(|t| match t.strip_suffix('*') {
+            Some(prefix) => tool.starts_with(prefix),
+            None => t == tool,
+        });
+        let subject_matches = (rule.users.is_empty() && rule.roles.is_empty())
+            || rule.users.iter().any(|u| u == user)
+            || self.user_roles(user).any(|r| rule.roles.contains(r));
+        tool_matches && subject_matches
+    }
+
+    /// Decide a call of `tool` with `args` by `caller`
+    pub fn decide(&self, tool: &str, args: &Value, caller: Option<&Identity>) -> Decision {
+        let user = caller.map(|i| i.user_id.as_str()).unwrap_or(ANONYMOUS);
+        // Why the closest rule did not match, for the deny message
+        let mut violation = None;
+        for rule in self.rules.iter().filter(|r| self.applies_to(r, tool, user)) {
+            if let Err(e) = check_args(&rule.args, args) {
+                violation.get_or_insert(e);
+                continue;
+            }
+            return match rule.effect {
+                Effect::Allow => Decision::Allow,
+                Effect::Deny => Decision::Deny(rule.message.clone().or(violation).unwrap_or_else(|| {
+                    format!("'{}' is not allowed for {}", tool, user)
+                })),
+                Effect::Confirm => Decision::Confirm(rule.message.clone().unwrap_or_default()),
+            };
+        }
+        match self.default {
+            Effect::Allow => Decision::Allow,
+            Effect::Deny => Decision::Deny(violation.unwrap_or_else(|| format!("'{}' is not allowed for {}", tool, user))),
+            Effect::Confirm => Decision::Confirm(String::new()),
+        }
+    }
+
+    /// Whether `caller` may use `tool` at all: false when the first rule
+    /// that could apply denies regardless of arguments
+    pub fn offers(&self, tool: &str, caller: Option<&Identity>) -> bool {
+        let user = caller.map(|i| i.user_id.as_str()).unwrap_or(ANONYMOUS);
+        match self.rules.iter().find(|r| self.applies_to(r, tool, user)) {
+            Some(rule) => !(rule.effect == Effect::Deny && rule.args.is_empty()),
+            None => self.default != Effect::Deny,
+        }
+    }
+
+    /// Whether the first rule that could apply to `tool` asks for
+    /// confirmation, before the args are known
+    pub fn may_confirm(&self, tool: &str, caller: Option<&Identity>) -> bool {
+        let user = caller.map(|i| i.user_id.as_str()).unwrap_or(ANONYMOUS);
+        match self.rules.iter().find(|r| self.applies_to(r, tool, user)) {
+            Some(rule) => rule.effect == Effect::Confirm,
+            None => self.default == Effect::Confirm,
+        }
+    }
+}
+
+fn check_args(constraints: &HashMap<String, ArgConstraint>, args: &Value) -> Result<(), String> {
+    for (name, c) in constraints {
+        let value = args
+            .get(name)
+            .filter(|v| !v.is_null())
+            .ok_or_else(|| format!("'{}' must be given explicitly", name))?;
+        let shown = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        if let Some(allowed) = &c.one_of
+            && !allowed.contains(value)
+        {
+            return Err(format!("{} '{}' is not one of the allowed values", name, shown));
+        }
+        if let Some(pattern) = &c.pattern {
+            let re = regex::Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| e.to_string())?;
+            if !re.is_match(&shown) {
+                return Err(format!("{} '{}' is not allowed", name, shown));
+            }
+        }
+        if let Some(ranges) = &c.cidr {
+            let ip: IpAddr = shown
+                .parse()
+                .map_err(|_| format!("{} '{}' is not an IP address", name, shown))?;
+            if !ranges.iter().filter_map(|r| parse_cidr(r)).any(|(net, bits)| in_range(ip, net, bits)) {
+                return Err(format!("{} {} is outside the allowed ranges", name, ip));
+            }
+        }
+        if c.min.is_some() || c.max.is_some() {
+            let n = value
+                .as_f64()
+                .or_else(|| shown.parse().ok())
+                .ok_or_else(|| format!("{} '{}' is not a number", name, shown))?;
+            if c.min.is_some_and(|min| n < min) || c.max.is_some_and(|max| n > max) {
+                return Err(format!("{} {} is out of the allowed range", name, n));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_cidr(range: &str) -> Option<(IpAddr, u32)> {
+    let (addr, bits) = match range.split_once('/') {
+        Some((a, b)) => (a.parse::<IpAddr>().ok()?, Some(b.parse::<u32>().ok()?)),
+        None => (range.parse::<IpAddr>().ok()?, None),
+    };
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let bits = bits.unwrap_or(max);
+    (bits <= max).then_some((addr, bits))
+}
+
+fn in_range(ip: IpAddr, net: IpAddr, bits: u32) -> bool {
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - bits).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - bits).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Returned by [`PolicyMcpClient::call`] when the call needs the user's
+/// approval. `McpToolStep` turns it into `StepStatus::WaitUser`.
+#[derive(Debug, Clone)]
+pub struct ConfirmationRequired {
+    pub tool: String,
+    pub args: Value,
+    pub message: String,
+}
+
+impl ConfirmationRequired {
+    pub fn prompt(&self) -> String {
+        let mut prompt = format!("'{}' needs your confirmation before it runs", self.tool);
+        if !self.message.is_empty() {
+            prompt.push_str(&format!(": {}", self.message));
+        }
+        let shown: serde_json::Map<_, _> = self
+            .args
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(k, _)| !is_internal_arg(k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        if !shown.is_empty() {
+            prompt.push_str(&format!("\nArguments: {}", Value::Object(shown)));
+        }
+        prompt.push_str("\nReply 'yes' to run it, 'edit name=value ...' to change arguments, anything else to cancel.");
+        prompt
+    }
+}
+
+impl std::fmt::Display for ConfirmationRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "tool '{}' requires confirmation", self.tool)
+    }
+}
+
+impl std::error::Error for ConfirmationRequired {}
+
+/// The session id and hidden `__*` keys added for the server, which the
+/// user neither sees nor edits in a confirmation
+pub fn is_internal_arg(key: &str) -> bool {
+    key == "session_id" || key.starts_with("__")
+}
+
+/// The user's answer to a confirmation prompt
+#[derive(Debug, PartialEq)]
+pub enum ConfirmationReply {
+    Approve,
+    /// New values for some args: `edit port=7001 ip=10.0.0.6` or
+    /// `edit {"port": 7001}`
+    Edit(serde_json::Map<String, Value>),
+    Reject,
+}
+
+impl ConfirmationReply {
+    pub fn parse(reply: &str) -> Self {
+        let reply = reply.trim();
+        let word = reply.trim_end_matches(['.', '!', '。', '！']).to_lowercase();
+        if matches!(
+            word.as_str(),
+            "y" | "yes" | "ok" | "approve" | "confirm" | "是" | "是的" | "好" | "确认" | "同意"
+        ) {
+            return Self::Approve;
+        }
+        let edits = ["edit", "修改", "改"]
+            .iter()
+            .find_map(|kw| reply.strip_prefix(kw))
+            .map(str::trim)
+            .and_then(parse_edits);
+        match edits {
+            Some(edits) if !edits.is_empty() => Self::Edit(edits),
+            _ => Self::Reject,
+        }
+    }
+}
+
+/// A JSON object, or whitespace separated `name=value` pairs whose values
+/// are read as JSON when they parse and as strings otherwise
+fn parse_edits(text: &str) -> Option<serde_json::Map<String, Value>> {
+    if text.starts_with('{') {
+        return serde_json::from_str(text).ok();
+    }
+    text.split_whitespace()
+        .map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+            Some((name.to_string(), value))
+        })
+        .collect()
+}
+
+/// Enforces a [`Policy`] on the tool calls of one session, for the user the
+/// session belongs to
+pub struct PolicyMcpClient {
+    inner: Arc<dyn MCPClient + Send + Sync>,
+    policy: Arc<Policy>,
+    caller: RwLock<Option<Identity>>,
+    /// Calls approved by the user: exact args, or any args (`None`) once
+    approved: RwLock<Vec<(String, Option<Value>)>>,
+    /// Tools annotated as destructive, known after the first tool listing
+    destructive: RwLock<Option<HashSet<String>>>,
+}
+
+impl PolicyMcpClient {
+    pub fn new(inner: Arc<dyn MCPClient + Send + Sync>, policy: Arc<Policy>) -> Self {
+        Self {
+            inner,
+            policy,
+            caller: RwLock::new(None),
+            approved: RwLock::new(Vec::new()),
+            destructive: RwLock::new(None),
+        }
+    }
+
+    fn remember_destructive(&self, tools: &[ToolMeta]) {
+        let names = tools.iter().filter(|t| t.destructive).map(|t| t.name.clone()).collect();
+        *self.destructive.write().unwrap() = Some(names);
+    }
+
+    async fn is_destructive(&self, tool: &str) -> bool {
+        if self.destructive.read().unwrap().is_none() {
+            match self.inner.list_tools().await {
+                Ok(tools) => self.remember_destructive(&tools),
+                Err(e) => tracing::warn!("failed to list tools for their annotations: {}", e),
+            }
+        }
+        self.destructive
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|names| names.contains(tool))
+    }
+
+    /// The identity of the latest input of the session
+    pub fn set_caller(&self, caller: Option<Identity>) {
+        *self.caller.write().unwrap() = caller;
+    }
+
+    /// Let `tool` through confirmation for one call, with exactly `args` or
+    /// with any args
+    pub fn approve(&self, tool: &str, args: Option<Value>) {
+        self.approved.write().unwrap().push((tool.to_string(), args));
+    }
+
+    /// Forget approvals when a new workflow starts
+    pub fn clear_approvals(&self) {
+        self.approved.write().unwrap().clear();
+    }
+
+    /// Whether a background run of `tool` has to be confirmed up front, as
+    /// its call cannot pause the workflow
+    pub async fn needs_confirmation(&self, tool: &str) -> bool {
+        let caller = self.caller.read().unwrap().clone();
+        let confirm = self.policy.may_confirm(tool, caller.as_ref())
+            || (self.policy.confirm_destructive
+                && self.policy.offers(tool, caller.as_ref())
+                && self.is_destructive(tool).await);
+        confirm
+            && !self.approved.read().unwrap().iter().any(|(t, a)| t == tool && a.is_none())
+    }
+
+    fn take_approval(&self, tool: &str, args: &Value) -> bool {
+        let mut approved = self.approved.write().unwrap();
+        let exact = approved.iter().position(|(t, a)| t == tool && a.as_ref() == Some(args));
+        match exact.or_else(|| approved.iter().position(|(t, a)| t == tool && a.is_none())) {
+            Some(i) => {
+                approved.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[async_trait]
+impl MCPClient for PolicyMcpClient {
+    async fn call(&self, tool: &str, args: Value) -> anyhow::Result<Value> {
+        let caller = self.caller.read().unwrap().clone();
+        let mut decision = self.policy.decide(tool, &args, caller.as_ref());
+        if decision == Decision::Allow && self.policy.confirm_destructive && self.is_destructive(tool).await {
+            decision = Decision::Confirm("it may change things that cannot be undone".to_string());
+        }
+        match decision {
+            Decision::Allow => {}
+            Decision::Deny(reason) => {
+                tracing::warn!("policy denied '{}' for {:?}: {}", tool, caller, reason);
+                return Err(anyhow::anyhow!("'{}' denied by policy: {}", tool, reason));
+            }
+            Decision::Confirm(message) => {
+                if !self.take_approval(tool, &args) {
+                    return Err(ConfirmationRequired {
+                        tool: tool.to_string(),
+                        args,
+                        message,
+                    }
+                    .into());
+                }
+            }
+        }
+        self.inner.call(tool, args).await
+    }
+
+    async fn list_tools(&self) -> anyhow::Result<Vec<ToolMeta>> {
+        // Tools the caller can never use are not offered to the planner
+        let caller = self.caller.read().unwrap().clone();
+        let mut tools = self.inner.list_tools().await?;
+        self.remember_destructive(&tools);
+        tools.retain(|t| self.policy.offers(&t.name, caller.as_ref()));
+        Ok(tools)
+    }
+
+    async fn required_fields(&self, tool: &str) -> anyhow::Result<Vec<String>> {
+        self.inner.required_fields(tool).await
+    }
+
+    async fn tool_schema(&self, tool: &str) -> anyhow::Result<Option<Value>> {
+        self.inner.tool_schema(tool).await
+    }
+
+    async fn elicit_preview(&self, tool: &str) -> anyhow::Result<Option<Value>> {
+        self.inner.elicit_preview(tool).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::auth::AuthMethod;
+
+    const POLICY: &str = r#"
+roles:
+  admin: [alice]
+rules:
+  - tools: [gpuinfo]
+    roles: [admin]
+    effect: allow
+  - tools: [gpuinfo]
+    effect: deny
+  - tools: [pusher_tool]
+    effect: confirm
+    args:
+      ip: { cidr: ["10.0.0.0/8"] }
+      port: { min: 1024 }
+  - tools: [pusher_tool]
+    effect: deny
+"#;
+
+    fn user(id: &str) -> Identity {
+        Identity {
+            user_id: id.to_string(),
+            method: AuthMethod::ApiToken,
+        }
+    }
+
+    #[tokio::test]
+    async fn rules_constraints_and_confirmation() {
+        let policy = Arc::new(Policy::from_yaml(POLICY).unwrap());
+        let alice = user("alice");
+        let bob = user("bob");
+        let args = serde_json::json!({"ip": "10.1.2.3", "port": 7000});
+
+        assert_eq!(policy.decide("gpuinfo", &Value::Null, Some(&alice)), Decision::Allow);
+        assert!(matches!(policy.decide("gpuinfo", &Value::Null, Some(&bob)), Decision::Deny(_)));
+        assert!(!policy.offers("gpuinfo", None));
+        assert_eq!(policy.decide("echo", &Value::Null, None), Decision::Allow);
+        assert!(matches!(policy.decide("pusher_tool", &args, Some(&bob)), Decision::Confirm(_)));
+        match policy.decide("pusher_tool", &serde_json::json!({"ip": "8.8.8.8", "port": 7000}), Some(&bob)) {
+            Decision::Deny(reason) => assert!(reason.contains("outside the allowed ranges"), "{}", reason),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            policy.decide("pusher_tool", &serde_json::json!({"ip": "10.0.0.1"}), Some(&bob)),
+            Decision::Deny(_)
+        ));
+
+        let client = PolicyMcpClient::new(Arc::new(crate::mcp::client::BasicMCPClient), policy);
+        client.set_caller(Some(bob));
+        let err = client.call("pusher_tool", args.clone()).await.unwrap_err();
+        assert!(err.downcast_ref::<ConfirmationRequired>().is_some());
+        client.approve("pusher_tool", Some(args.clone()));
+        assert!(client.call("pusher_tool", args.clone()).await.is_ok());
+        assert!(client.call("pusher_tool", args.clone()).await.is_err());
+        assert!(client.call("gpuinfo", Value::Null).await.is_err());
+
+        assert!(client.needs_confirmation("pusher_tool").await);
+        client.clear_approvals();
+        client.approve("pusher_tool", None);
+        assert!(!client.needs_confirmation("pusher_tool").await);
+        assert!(client.call("pusher_tool", args.clone()).await.is_ok());
+        assert!(client.call("pusher_tool", args).await.is_err());
+        assert_eq!(ConfirmationReply::parse(" Yes "), ConfirmationReply::Approve);
+        assert_eq!(ConfirmationReply::parse("no"), ConfirmationReply::Reject);
+        let ConfirmationReply::Edit(edits) = ConfirmationReply::parse("edit port=7001 ip=10.0.0.6") else {
+            panic!("expected an edit");
+        };
+        assert_eq!(Value::Object(edits), serde_json::json!({"port": 7001, "ip": "10.0.0.6"}));
+    }
+
+    #[tokio::test]
+    async fn waits_for_approval_before_calling() {
+        use crate::testkit::{FakeMcp, FakeTool, ScriptedLlm, TestRobot};
+        let plan = serde_json::json!({"reasoning": "push", "steps": [{"tool": "pusher_tool", "dependencies": []}]});
+        let llm = ScriptedLlm::new()
+            .respond_to_all()
+            .plan("推流", plan)
+            .params("pusher_tool", serde_json::json!({"ip": "10.0.0.5", "port": 7000}))
+            .synthesize("pushed", "推流已开始");
+        let mcp = FakeMcp::new().tool(
+            FakeTool::new("pusher_tool", "Push a video")
+                .param("ip", "string", true)
+                .param("port", "integer", true)
+                .returns("pushed"),
+        );
+        let robot = TestRobot::builder(llm, mcp)
+            .policy(Policy::from_yaml(POLICY).unwrap())
+            .build()
+            .await;
+
+        let mut chat = robot.conversation("bob");
+        chat.say("推流到设备").unwrap();
+        let prompt = chat.reply_text().await.unwrap();
+        assert!(prompt.contains("needs your confirmation") && prompt.contains("10.0.0.5"), "{}", prompt);
+        assert!(robot.mcp.calls_to("pusher_tool").is_empty());
+        chat.say("no").unwrap();
+        assert_eq!(chat.reply_text().await.unwrap(), "Cancelled 'pusher_tool'.");
+
+        chat.say("推流到设备").unwrap();
+        chat.reply_text().await.unwrap();
+        chat.say("yes").unwrap();
+        assert_eq!(chat.reply_text().await.unwrap(), "推流已开始");
+        let calls = robot.mcp.calls_to("pusher_tool");
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].args["ip"], "10.0.0.5");
+    }
+
+    #[tokio::test]
+    async fn destructive_tools_accept_edits() {
+        use crate::testkit::{FakeMcp, FakeTool, ScriptedLlm, TestRobot};
+        let plan = serde_json::json!({"reasoning": "save", "steps": [{"tool": "profile_update", "dependencies": []}]});
+        let llm = ScriptedLlm::new()
+            .respond_to_all()
+            .plan("保存", plan)
+            .params("profile_update", serde_json::json!({"name": "Bob", "age": 30}))
+            .synthesize("saved", "已保存");
+        let mcp = FakeMcp::new().tool(
+            FakeTool::new("profile_update", "Update the profile")
+                .param("name", "string", true)
+                .param("age", "integer", true)
+                .destructive()
+                .returns("saved"),
+        );
+        let robot = TestRobot::builder(llm, mcp).build().await;
+
+        let mut chat = robot.conversation("bob");
+        chat.say("保存资料").unwrap();
+        assert!(chat.reply_text().await.unwrap().contains(r#""age":30"#));
+        chat.say("edit age=31").unwrap();
+        let prompt = chat.reply_text().await.unwrap();
+        assert!(prompt.contains(r#""age":31"#), "{}", prompt);
+        assert!(robot.mcp.calls_to("profile_update").is_empty());
+        chat.say("ok").unwrap();
+        assert_eq!(chat.reply_text().await.unwrap(), "已保存");
+        assert_eq!(robot.mcp.calls_to("profile_update")[0].args["age"], 31);
+    }
+
+    #[tokio::test]
+    async fn confirmation_edits_cannot_change_internal_args() {
+        use crate::testkit::{FakeMcp, FakeTool, ScriptedLlm, TestRobot};
+        let plan = serde_json::json!({"reasoning": "save", "steps": [{"tool": "profile_update", "dependencies": []}]});
+        let llm = ScriptedLlm::new()
+            .respond_to_all()
+            .plan("保存", plan)
+            .params("profile_update", serde_json::json!({"name": "Bob"}))
+            .synthesize("saved", "已保存");
+        let mcp = FakeMcp::new().tool(
+            FakeTool::new("profile_update", "Update the profile")
+                .param("name", "string", true)
+                .destructive()
+                .returns("saved"),
+        );
+        let robot = TestRobot::builder(llm, mcp).build().await;
+
+        let mut chat = robot.conversation("bob");
+        chat.say("保存资料").unwrap();
+        chat.reply_text().await.unwrap();
+        chat.say("edit session_id=alice-1 name=Eve").unwrap();
+        let refusal = chat.reply_text().await.unwrap();
+        assert!(refusal.contains("can't be edited: session_id"), "{}", refusal);
+        chat.say(r#"edit {"__elicitation": {"hint": "x"}}"#).unwrap();
+        assert!(chat.reply_text().await.unwrap().contains("can't be edited: __elicitation"));
+        chat.say("yes").unwrap();
+        assert_eq!(chat.reply_text().await.unwrap(), "已保存");
+        let calls = robot.mcp.calls_to("profile_update");
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].args["name"], "Bob");
+    }
+}
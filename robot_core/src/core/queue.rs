@@ -0,0 +1,330 @@
+//! Bounded queues with an explicit policy for when they are full.
+//!
+//! Used for the core's input queue, the session inboxes and the per-client
+//! queues of the consoles, so a burst of inputs or a slow client cannot grow
+//! memory without limit. Capacities come from [`QueueLimits`], read from
+//! `ROBOT_INPUT_QUEUE`, `ROBOT_SESSION_QUEUE` and `ROBOT_SUBSCRIBER_QUEUE`
+//! (`<capacity>[:block|drop_oldest|reject]`, e.g. `64:reject`).
+use crate::core::metrics;
+use prometheus::IntGauge;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// What a full queue does with one more item
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wait until the receiver makes room
+    Block,
+    /// Make room by discarding the oldest queued item
+    DropOldest,
+    /// Refuse the new item; the web console answers 429
+    Reject,
+}
+
+impl Overflow {
+    fn name(self) -> &'static str {
+        match self {
+            Overflow::Block => "block",
+            Overflow::DropOldest => "drop_oldest",
+            Overflow::Reject => "reject",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub overflow: Overflow,
+}
+
+impl QueueConfig {
+    pub const fn new(capacity: usize, overflow: Overflow) -> Self {
+        Self { capacity, overflow }
+    }
+}
+
+impl std::str::FromStr for QueueConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (capacity, overflow) = s.split_once(':').unwrap_or((s, "block"));
+        let capacity: usize = capacity
+            .trim()
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid queue capacity in '{}'", s))?;
+        if capacity == 0 {
+            anyhow::bail!("queue capacity in '{}' must be at least 1", s);
+        }
+        let overflow = match overflow.trim() {
+            "block" => Overflow::Block,
+            "drop_oldest" => Overflow::DropOldest,
+            "reject" => Overflow::Reject,
+            other => anyhow::bail!("unknown overflow policy '{}', expected block, drop_oldest or reject", other),
+        };
+        Ok(Self { capacity, overflow })
+    }
+}
+
+/// Queue sizes of one core
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueLimits {
+    /// Inputs from all handlers waiting to be dispatched to a session
+    pub input: QueueConfig,
+    /// Inputs waiting for their session to finish the previous one
+    pub session: QueueConfig,
+    /// Outputs waiting to be written to one SSE or TCP client
+    pub subscriber: QueueConfig,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        Self {
+            input: QueueConfig::new(1024, Overflow::Block),
+            session: QueueConfig::new(64, Overflow::Reject),
+            subscriber: QueueConfig::new(256, Overflow::DropOldest),
+        }
+    }
+}
+
+impl QueueLimits {
+    /// Defaults, overridden by the `ROBOT_*_QUEUE` variables that are set
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut limits = Self::default();
+        for (var, config) in [
+            ("ROBOT_INPUT_QUEUE", &mut limits.input),
+            ("ROBOT_SESSION_QUEUE", &mut limits.session),
+            ("ROBOT_SUBSCRIBER_QUEUE", &mut limits.subscriber),
+        ] {
+            if let Ok(value) = std::env::var(var) {
+                *config = value.parse().map_err(|e| anyhow::anyhow!("{}: {}", var, e))?;
+            }
+        }
+        Ok(limits)
+    }
+}
+
+#[derive(Debug)]
+pub enum SendError<T> {
+    /// The queue is full and rejects new items
+    Full(T),
+    /// The receiver is gone
+    Closed(T),
+}
+
+impl<T> std::fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Full(_) => write!(f, "queue is full"),
+            SendError::Closed(_) => write!(f, "queue is closed"),
+        }
+    }
+}
+
+impl<T: std::fmt::Debug> std::error::Error for SendError<T> {}
+
+struct State<T> {
+    items: VecDeque<T>,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    /// Wakes the receiver
+    items: Notify,
+    /// Wakes blocked senders
+    space: Notify,
+    /// Which queue this is in the overflow metric
+    kind: &'static str,
+    config: QueueConfig,
+    depth: Option<IntGauge>,
+}
+
+impl<T> Shared<T> {
+    fn set_depth(&self, len: usize) {
+        if let Some(gauge) = &self.depth {
+            gauge.set(len as i64);
+        }
+    }
+}
+
+/// A bounded queue named `kind` in metrics, reporting its length on `depth`
+pub fn channel<T>(kind: &'static str, config: QueueConfig, depth: Option<IntGauge>) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::new(),
+            senders: 1,
+            receiver_alive: true,
+        }),
+        items: Notify::new(),
+        space: Notify::new(),
+        kind,
+        config,
+        depth,
+    });
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.shared.items.notify_one();
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Queue `item`, applying the overflow policy when the queue is full
+    pub async fn send(&self, item: T) -> Result<(), SendError<T>> {
+        let mut item = item;
+        loop {
+            let space = self.shared.space.notified();
+            tokio::pin!(space);
+            space.as_mut().enable();
+            match self.push(item, true) {
+                Err(SendError::Full(back)) if self.shared.config.overflow == Overflow::Block => item = back,
+                res => return res,
+            }
+            space.await;
+        }
+    }
+
+    /// Like [`Sender::send`], but a blocking queue rejects instead of waiting
+    pub fn try_send(&self, item: T) -> Result<(), SendError<T>> {
+        self.push(item, false)
+    }
+
+    fn push(&self, item: T, may_block: bool) -> Result<(), SendError<T>> {
+        let shared = &self.shared;
+        let mut state = shared.state.lock().unwrap();
+        if !state.receiver_alive {
+            return Err(SendError::Closed(item));
+        }
+        if state.items.len() >= shared.config.capacity {
+            match shared.config.overflow {
+                Overflow::Block if may_block => return Err(SendError::Full(item)),
+                Overflow::DropOldest => {
+                    state.items.pop_front();
+                }
+                Overflow::Block | Overflow::Reject => {
+                    metrics::record_queue_overflow(shared.kind, shared.config.overflow.name());
+                    return Err(SendError::Full(item));
+                }
+            }
+            metrics::record_queue_overflow(shared.kind, shared.config.overflow.name());
+        }
+        state.items.push_back(item);
+        shared.set_depth(state.items.len());
+        drop(state);
+        shared.items.notify_one();
+        Ok(())
+    }
+
+    /// Items waiting in the queue
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().unwrap().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().receiver_alive = false;
+        self.shared.space.notify_waiters();
+    }
+}
+
+impl<T> Receiver<T> {
+    /// The next item; `None` once every sender is gone and the queue is empty
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            let items = self.shared.items.notified();
+            tokio::pin!(items);
+            items.as_mut().enable();
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if let Some(item) = state.items.pop_front() {
+                    self.shared.set_depth(state.items.len());
+                    drop(state);
+                    self.shared.space.notify_one();
+                    return Some(item);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            items.await;
+        }
+    }
+
+    /// Items waiting in the queue
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().unwrap().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn applies_overflow_policies() {
+        let (tx, mut rx) = channel("test", "2:drop_oldest".parse().unwrap(), None);
+        for i in 0..4 {
+            tx.send(i).await.unwrap();
+        }
+        assert_eq!(rx.recv().await, Some(2));
+        assert_eq!(rx.recv().await, Some(3));
+
+        let (tx, mut rx) = channel("test", QueueConfig::new(1, Overflow::Reject), None);
+        tx.send(1).await.unwrap();
+        assert!(matches!(tx.send(2).await, Err(SendError::Full(2))));
+        assert_eq!(rx.recv().await, Some(1));
+
+        let (tx, mut rx) = channel("test", QueueConfig::new(1, Overflow::Block), None);
+        tx.send(1).await.unwrap();
+        assert!(matches!(tx.try_send(2), Err(SendError::Full(2))));
+        let blocked = tokio::spawn(async move {
+            tx.send(2).await.unwrap();
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!blocked.is_finished());
+        assert_eq!(rx.recv().await, Some(1));
+        blocked.await.unwrap();
+        assert_eq!(rx.recv().await, Some(2));
+        // Every sender is gone
+        assert_eq!(rx.recv().await, None);
+
+        assert!("0:block".parse::<QueueConfig>().is_err());
+        assert!("8:spill".parse::<QueueConfig>().is_err());
+    }
+}
@@ -0,0 +1,363 @@
+//! Offline re-runs of recorded traces.
+//!
+//! [`ReplayScript`] turns a [`Trace`] into mocked clients: every LLM label
+//! answers with its recorded responses in order, and tool calls return their
+//! recorded results. [`replay`] pushes the recorded input through a fresh
+//! `RobotCore` built on those mocks and reports where the new run diverged,
+//! so prompt changes can be checked without a model or an MCP server.
+use crate::core::decision_engine::DecisionEngine;
+use crate::core::intent::IntentModule;
+use crate::core::perception::PerceptionModule;
+use crate::core::persona::Persona;
+use crate::core::trace::{MemoryTraceSink, Trace, TraceEvent, TracedLlm};
+use crate::core::workflow_engine::WorkflowEngine;
+use crate::core::RobotCore;
+use crate::llm::adapter::{ChatMessage, ChatOutput, ChatRequest, LLMClient};
+use crate::mcp::client::MCPClient;
+use crate::mcp::registry::ToolMeta;
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+type Responses = Arc<Mutex<HashMap<String, VecDeque<Result<ChatOutput, String>>>>>;
+type ToolResults = Arc<Mutex<HashMap<String, VecDeque<(Value, Result<Value, String>)>>>>;
+
+/// Recorded answers of a trace, handed out in order
+#[derive(Clone)]
+pub struct ReplayScript {
+    responses: Responses,
+    tool_results: ToolResults,
+    tools: Vec<ToolMeta>,
+    schemas: HashMap<String, Option<Value>>,
+    required: HashMap<String, Vec<String>>,
+    mismatches: Arc<Mutex<Vec<String>>>,
+}
+
+impl ReplayScript {
+    pub fn from_trace(trace: &Trace) -> Self {
+        let mut responses: HashMap<String, VecDeque<_>> = HashMap::new();
+        let mut tool_results: HashMap<String, VecDeque<_>> = HashMap::new();
+        let mut tools = Vec::new();
+        let mut schemas = HashMap::new();
+        let mut required = HashMap::new();
+        for event in trace.events() {
+            match event {
+                TraceEvent::Llm { label, response, error, .. } => {
+                    let answer = match (response, error) {
+                        (Some(r), _) => Ok(r.clone()),
+                        (None, e) => Err(e.clone().unwrap_or_default()),
+                    };
+                    responses.entry(label.clone()).or_default().push_back(answer);
+                }
+                TraceEvent::ToolCall { tool, args, result, error, .. } => {
+                    let answer = match (result, error) {
+                        (Some(r), _) => Ok(r.clone()),
+                        (None, e) => Err(e.clone().unwrap_or_default()),
+                    };
+                    tool_results
+                        .entry(tool.clone())
+                        .or_default()
+                        .push_back((args.clone(), answer));
+                }
+                TraceEvent::ToolList { tools: t } => tools = t.clone(),
+                TraceEvent::ToolSchema { tool, schema } => {
+                    schemas.insert(tool.clone(), schema.clone());
+                }
+                TraceEvent::RequiredFields { tool, fields } => {
+                    required.insert(tool.clone(), fields.clone());
+                }
+                _ => {}
+            }
+        }
+        Self {
+            responses: Arc::new(Mutex::new(responses)),
+            tool_results: Arc::new(Mutex::new(tool_results)),
+            tools,
+            schemas,
+            required,
+            mismatches: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// LLM client answering as the component recorded under `label`. The new
+    /// exchanges are traced under the same label.
+    pub fn llm(&self, label: &str) -> Arc<dyn LLMClient + Send + Sync> {
+        Arc::new(TracedLlm::new(
+            Arc::new(ReplayLlm {
+                label: label.to_string(),
+                responses: self.responses.clone(),
+            }),
+            label,
+        ))
+    }
+
+    pub fn mcp(&self) -> Arc<dyn MCPClient + Send + Sync> {
+        Arc::new(ReplayMcp {
+            script: self.clone(),
+        })
+    }
+
+    /// Tool calls whose args differ from the recording, and calls that were
+    /// not recorded at all
+    pub fn mismatches(&self) -> Vec<String> {
+        self.mismatches.lock().map(|m| m.clone()).unwrap_or_default()
+    }
+
+    fn mismatch(&self, message: String) {
+        if let Ok(mut m) = self.mismatches.lock() {
+            m.push(message);
+        }
+    }
+}
+
+struct ReplayLlm {
+    label: String,
+    responses: Responses,
+}
+
+#[async_trait]
+impl LLMClient for ReplayLlm {
+    async fn chat(&self, _req: ChatRequest) -> anyhow::Result<ChatOutput> {
+        let next = self
+            .responses
+            .lock()
+            .ok()
+            .and_then(|mut r| r.get_mut(&self.label).and_then(|q| q.pop_front()));
+        match next {
+            Some(Ok(out)) => Ok(out),
+            Some(Err(e)) => Err(anyhow::anyhow!(e)),
+            None => Err(anyhow::anyhow!("replay: no recorded '{}' response left", self.label)),
+        }
+    }
+}
+
+struct ReplayMcp {
+    script: ReplayScript,
+}
+
+#[async_trait]
+impl MCPClient for ReplayMcp {
+    async fn call(&self, tool: &str, args: Value) -> anyhow::Result<Value> {
+        let next = self
+            .script
+            .tool_results
+            .lock()
+            .ok()
+            .and_then(|mut r| r.get_mut(tool).and_then(|q| q.pop_front()));
+        let Some((recorded_args, result)) = next else {
+            self.script.mismatch(format!("unrecorded call to '{}' with {}", tool, args));
+            return Err(anyhow::anyhow!("replay: no recorded call to '{}' left", tool));
+        };
+        if recorded_args != args {
+            self.script.mismatch(format!(
+                "'{}' called with {} instead of {}",
+                tool, args, recorded_args
+            ));
+        }
+        result.map_err(|e| anyhow::anyhow!(e))
+    }
+
+    async fn list_tools(&self) -> anyhow::Result<Vec<ToolMeta>> {
+        Ok(self.script.tools.clone())
+    }
+
+    async fn required_fields(&self, tool: &str) -> anyhow::Result<Vec<String>> {
+        Ok(self.script.required.get(tool).cloned().unwrap_or_default())
+    }
+
+    async fn tool_schema(&self, tool: &str) -> anyhow::Result<Option<Value>> {
+        Ok(self.script.schemas.get(tool).cloned().flatten())
+    }
+}
+
+/// Components of the pipeline under test, built on the replay clients
+pub struct ReplayComponents {
+    pub decision_engine: Box<dyn DecisionEngine + Send + Sync>,
+    pub workflow_engine: WorkflowEngine,
+    pub perception_module: Box<dyn PerceptionModule + Send + Sync>,
+    pub intent_module: Box<dyn IntentModule + Send + Sync>,
+}
+
+/// An LLM request whose prompt differs from the recording
+#[derive(Debug)]
+pub struct PromptChange {
+    pub label: String,
+    /// Position among the requests with this label
+    pub index: usize,
+    pub recorded: Vec<ChatMessage>,
+    pub replayed: Vec<ChatMessage>,
+}
+
+#[derive(Debug)]
+pub struct ReplayReport {
+    pub trace: Trace,
+    pub prompt_changes: Vec<PromptChange>,
+    pub tool_mismatches: Vec<String>,
+    pub recorded_outputs: Vec<String>,
+    pub replayed_outputs: Vec<String>,
+}
+
+impl ReplayReport {
+    /// Same prompts, same tool calls and same outputs as the recording
+    pub fn is_unchanged(&self) -> bool {
+        self.prompt_changes.is_empty()
+            && self.tool_mismatches.is_empty()
+            && self.recorded_outputs == self.replayed_outputs
+    }
+}
+
+/// Re-run the input of `trace` through the components from `build`, which
+/// should take its LLM clients from the script (`script.llm("planner")`).
+/// Outputs are not delivered anywhere; they are compared through the trace.
+pub async fn replay(
+    trace: &Trace,
+    persona: Persona,
+    build: impl FnOnce(&ReplayScript) -> ReplayComponents,
+) -> anyhow::Result<ReplayReport> {
+    let script = ReplayScript::from_trace(trace);
+    let components = build(&script);
+    let factory_script = script.clone();
+    let core = RobotCore::new(
+        persona,
+        components.decision_engine,
+        components.workflow_engine,
+        components.perception_module,
+        components.intent_module,
+        Box::new(move |_session_id| {
+            let mcp = factory_script.mcp();
+            Box::pin(async move { Ok(mcp) })
+        }),
+    );
+    let sink = Arc::new(MemoryTraceSink::default());
+    core.set_trace_sink(sink.clone());
+
+    core.session_manager.dispatch(trace.input.clone()).await;
+    let replayed = tokio::time::timeout(Duration::from_secs(60), sink.wait_for(1))
+        .await
+        .map_err(|_| anyhow::anyhow!("replay of trace {} timed out", trace.trace_id))?
+        .remove(0);
+
+    Ok(ReplayReport {
+        prompt_changes: prompt_changes(trace, &replayed),
+        tool_mismatches: script.mismatches(),
+        recorded_outputs: trace.output_texts(),
+        replayed_outputs: replayed.output_texts(),
+        trace: replayed,
+    })
+}
+
+fn requests_by_label(trace: &Trace) -> HashMap<String, Vec<Vec<ChatMessage>>> {
+    let mut map: HashMap<String, Vec<Vec<ChatMessage>>> = HashMap::new();
+    for event in trace.events() {
+        if let TraceEvent::Llm { label, request, .. } = event {
+            map.entry(label.clone()).or_default().push(request.messages.clone());
+        }
+    }
+    map
+}
+
+/// Requests of `replayed` whose messages differ from the same request
+/// (same label, same position) in `recorded`
+pub fn prompt_changes(recorded: &Trace, replayed: &Trace) -> Vec<PromptChange> {
+    let before = requests_by_label(recorded);
+    let after = requests_by_label(replayed);
+    let mut changes = Vec::new();
+    let mut labels: Vec<&String> = before.keys().chain(after.keys()).collect();
+    labels.sort();
+    labels.dedup();
+    for label in labels {
+        let old = before.get(label).map(|v| v.as_slice()).unwrap_or_default();
+        let new = after.get(label).map(|v| v.as_slice()).unwrap_or_default();
+        for index in 0..old.len().max(new.len()) {
+            let (a, b) = (old.get(index), new.get(index));
+            let same = match (a, b) {
+                (Some(a), Some(b)) => {
+                    a.len() == b.len()
+                        && a.iter().zip(b).all(|(x, y)| x.role == y.role && x.content == y.content)
+                }
+                _ => false,
+            };
+            if !same {
+                changes.push(PromptChange {
+                    label: label.clone(),
+                    index,
+                    recorded: a.cloned().unwrap_or_default(),
+                    replayed: b.cloned().unwrap_or_default(),
+                });
+            }
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::trace::capture;
+    use crate::utils::InputEvent;
+
+    fn request(prompt: &str) -> ChatRequest {
+        ChatRequest {
+            model: "m".into(),
+            messages: vec![ChatMessage {
+                role: "user".into(),
+                content: prompt.into(),
+            }],
+            temperature: None,
+            session_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_responses_and_reports_changes() {
+        let input = InputEvent {
+            id: uuid::Uuid::new_v4(),
+            source: "test".into(),
+            session_id: Some("s".into()),
+            source_meta: None,
+            payload: serde_json::json!({"text": "hi"}),
+            identity: None,
+            attachments: Vec::new(),
+        };
+        let recording = ReplayScript::from_trace(&Trace {
+            trace_id: uuid::Uuid::new_v4(),
+            session_id: "s".into(),
+            started_at: chrono::Utc::now(),
+            duration_ms: 0,
+            input: input.clone(),
+            events: vec![],
+        });
+        assert!(recording.llm("planner").chat(request("x")).await.is_err());
+
+        // A trace as read back from a JSONL line
+        let line = serde_json::json!({
+            "trace_id": uuid::Uuid::new_v4(), "session_id": "s",
+            "started_at": chrono::Utc::now(), "duration_ms": 5, "input": input,
+            "events": [
+                {"at_ms": 1, "kind": "llm", "label": "planner", "request": request("plan it"),
+                 "response": {"text": "ok", "raw": null}, "duration_ms": 1},
+                {"at_ms": 2, "kind": "tool_call", "tool": "add", "args": {"a": 1},
+                 "result": {"content": []}, "duration_ms": 1}
+            ]
+        });
+        let recorded: Trace = serde_json::from_value(line).unwrap();
+        let script = ReplayScript::from_trace(&recorded);
+
+        let (_, replayed) = capture("s", &input, async {
+            let out = script.llm("planner").chat(request("plan it better")).await.unwrap();
+            assert_eq!(out.text, "ok");
+            script.mcp().call("add", serde_json::json!({"a": 2})).await.unwrap();
+            assert!(script.mcp().call("add", serde_json::json!({})).await.is_err());
+        })
+        .await;
+
+        let changes = prompt_changes(&recorded, &replayed);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].label, "planner");
+        assert_eq!(changes[0].replayed[0].content, "plan it better");
+        assert_eq!(script.mismatches().len(), 2);
+        assert!(prompt_changes(&recorded, &recorded).is_empty());
+    }
+}
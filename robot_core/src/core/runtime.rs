@@ -0,0 +1,165 @@
+//! State shared by one `RobotCore`, its handlers and its MCP clients.
+//!
+//! Inputs are published on an event bus as they arrive so an elicitation can
+//! take the answer it is waiting for; side-channel outputs (progress, model
+//! thoughts, echoed user messages) go out on an output bus that the core
+//! broadcasts to every output handler. Each `RobotCore` owns its own
+//! [`Runtime`], so several cores (or parallel tests) in one process do not
+//! see each other's events.
+use crate::core::metrics;
+use crate::core::queue::QueueLimits;
+use crate::utils::{InputEvent, OutputEvent};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+const BUS_CAPACITY: usize = 1024;
+
+/// How long an input taken by an elicitation is remembered. Its session
+/// normally skips it within moments; the rest are inputs that never reached
+/// a session.
+const CONSUMED_TTL: Duration = Duration::from_secs(600);
+
+/// Consumed inputs remembered at most; the oldest are forgotten first
+const MAX_CONSUMED: usize = 10_000;
+
+#[derive(Clone)]
+pub struct Runtime {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    events: broadcast::Sender<InputEvent>,
+    outputs: broadcast::Sender<OutputEvent>,
+    consumed: Mutex<HashMap<Uuid, Instant>>,
+    consumed_ttl: Duration,
+    elicitations: Mutex<HashSet<String>>,
+    queues: QueueLimits,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        Self::build(CONSUMED_TTL, QueueLimits::default())
+    }
+
+    /// Forget consumed inputs after `ttl` instead of the default ten minutes
+    pub fn with_consumed_ttl(consumed_ttl: Duration) -> Self {
+        Self::build(consumed_ttl, QueueLimits::default())
+    }
+
+    /// Size the input, session and subscriber queues by `queues`
+    pub fn with_queues(queues: QueueLimits) -> Self {
+        Self::build(CONSUMED_TTL, queues)
+    }
+
+    fn build(consumed_ttl: Duration, queues: QueueLimits) -> Self {
+        let (events, _) = broadcast::channel(BUS_CAPACITY);
+        let (outputs, _) = broadcast::channel(BUS_CAPACITY);
+        Self {
+            inner: Arc::new(Inner {
+                events,
+                outputs,
+                consumed: Mutex::new(HashMap::new()),
+                consumed_ttl,
+                elicitations: Mutex::new(HashSet::new()),
+                queues,
+            }),
+        }
+    }
+
+    pub fn queues(&self) -> QueueLimits {
+        self.inner.queues
+    }
+
+    /// Make `event` visible to elicitations waiting for an answer
+    pub fn publish_input(&self, event: &InputEvent) {
+        let _ = self.inner.events.send(event.clone());
+    }
+
+    pub fn subscribe_inputs(&self) -> broadcast::Receiver<InputEvent> {
+        self.inner.events.subscribe()
+    }
+
+    /// Send `event` to every output handler of the core
+    pub fn publish_output(&self, event: OutputEvent) {
+        let _ = self.inner.outputs.send(event);
+    }
+
+    pub fn subscribe_outputs(&self) -> broadcast::Receiver<OutputEvent> {
+        self.inner.outputs.subscribe()
+    }
+
+    /// Record that an elicitation answered with input `id`, so its session
+    /// does not handle it again
+    pub fn mark_event_consumed(&self, id: Uuid) {
+        let now = Instant::now();
+        let ttl = self.inner.consumed_ttl;
+        let mut consumed = self.inner.consumed.lock().unwrap();
+        consumed.retain(|_, at| now.duration_since(*at) < ttl);
+        if consumed.len() >= MAX_CONSUMED
+            && let Some(oldest) = consumed.iter().min_by_key(|(_, at)| **at).map(|(id, _)| *id)
+        {
+            consumed.remove(&oldest);
+        }
+        consumed.insert(id, now);
+    }
+
+    /// Whether input `id` was consumed by an elicitation, forgetting it
+    pub fn take_consumed_event(&self, id: &Uuid) -> bool {
+        let mut consumed = self.inner.consumed.lock().unwrap();
+        match consumed.remove(id) {
+            Some(at) => at.elapsed() < self.inner.consumed_ttl,
+            None => false,
+        }
+    }
+
+    pub fn set_elicitation_active(&self, session_id: &str, active: bool) {
+        let mut elicitations = self.inner.elicitations.lock().unwrap();
+        if active {
+            if elicitations.insert(session_id.to_string()) {
+                metrics::elicitation_started();
+            }
+        } else if elicitations.remove(session_id) {
+            metrics::elicitation_ended();
+        }
+    }
+
+    pub fn is_elicitation_active(&self, session_id: &str) -> bool {
+        self.inner.elicitations.lock().unwrap().contains(session_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtimes_are_isolated_and_consumed_events_expire() {
+        let a = Runtime::new();
+        let b = Runtime::new();
+        let id = Uuid::new_v4();
+        a.mark_event_consumed(id);
+        a.set_elicitation_active("s1", true);
+        assert!(!b.take_consumed_event(&id));
+        assert!(!b.is_elicitation_active("s1"));
+        assert!(a.is_elicitation_active("s1"));
+        assert!(a.take_consumed_event(&id));
+        assert!(!a.take_consumed_event(&id));
+        a.set_elicitation_active("s1", false);
+        assert!(!a.is_elicitation_active("s1"));
+
+        let short = Runtime::with_consumed_ttl(Duration::ZERO);
+        short.mark_event_consumed(id);
+        assert!(!short.take_consumed_event(&id));
+        short.mark_event_consumed(Uuid::new_v4());
+        assert_eq!(short.inner.consumed.lock().unwrap().len(), 1);
+    }
+}
@@ -1,24 +1,41 @@
-use crate::core::decision_engine::{DecisionEngine, LLMDecisionEngine};
+use crate::core::decision_engine::{DecisionEngine, LLMDecisionEngine, StepFailure};
 use crate::core::intent::{IntentDecision, IntentModule};
+use crate::core::limits::{self, LimitExceeded, RateLimitedMcp, RateLimiter};
+use crate::core::metrics;
 use crate::core::output_handler::OutputHandler;
 use crate::core::perception::PerceptionModule;
 use crate::core::persona::{OutputStyle, Persona};
+use crate::core::policy::{ConfirmationReply, Policy, PolicyMcpClient, is_internal_arg};
+use crate::core::queue;
 use crate::core::router::{EventRouter, HandlerId};
+use crate::core::runtime::Runtime;
 use crate::core::sessions::web_session::WebSession;
+use crate::core::synthesis::{is_intermediate, result_text};
 use crate::core::tasks::client::TaskAwareMcpClient;
-use crate::core::tasks::manager::TaskManager;
-use crate::core::workflow_engine::WorkflowEngine;
+use crate::core::templates::TemplateMcpClient;
+use crate::core::trace::{self, TraceEvent, TraceSink, TracedMcp};
+use crate::core::tasks::manager::{TaskManager, TaskSummary};
+use crate::core::templates::TemplateLibrary;
+use crate::core::workflow_engine::{StepOutcome, WorkflowEngine};
 use crate::mcp::client::MCPClient;
-use crate::utils::{InputEvent, OutputEvent, StepSpec, Context};
+use crate::utils::{InputEvent, OutputEvent, StepSpec, Context, WorkflowPlan};
 use async_trait::async_trait;
-use futures::future::join_all;
+use futures::future::{join_all, FutureExt};
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock as StdRwLock};
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::RwLock;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
-use crate::workflow_steps::StepStatus;
+use crate::workflow_steps::{StepStatus, PENDING_CONFIRMATION, confirmation_key};
 
+/// Upper bound on DecisionEngine re-plans for a single workflow run
+const MAX_REPLANS: usize = 2;
+
+/// Reply to an input rejected by a full session inbox
+const SESSION_BUSY: &str = "I'm still working through your earlier messages. Please wait a moment and send this again.";
+
+// Nearly every message is an Input, so boxing it would buy nothing
+#[allow(clippy::large_enum_variant)]
 pub enum SessionMessage {
     Input(InputEvent),
     Shutdown,
@@ -31,8 +48,10 @@ pub trait SessionActor: Send + Sync {
 
 pub struct RobotSession {
     pub id: String,
-    pub inbox: mpsc::UnboundedReceiver<SessionMessage>,
+    pub inbox: queue::Receiver<SessionMessage>,
     pub mcp_client: Arc<dyn MCPClient + Send + Sync>,
+    /// Outermost layer of `mcp_client`, enforcing the tool policy
+    pub policy: Arc<PolicyMcpClient>,
     pub decision_engine: Arc<Box<dyn DecisionEngine + Send + Sync>>,
     pub workflow_engine: Arc<WorkflowEngine>,
     pub perception_module: Arc<Box<dyn PerceptionModule + Send + Sync>>,
@@ -43,6 +62,9 @@ pub struct RobotSession {
     pub task_manager: Arc<TaskManager>,
     // State for pending execution (WaitUser)
     pub pending_execution: Option<(Vec<StepSpec>, usize, Context)>,
+    pub trace_sink: Option<Arc<dyn TraceSink>>,
+    pub limiter: Arc<RateLimiter>,
+    pub runtime: Runtime,
 }
 
 #[async_trait]
@@ -63,15 +85,25 @@ impl RobotSession {
         persona: Arc<Persona>,
         output_handlers: Arc<RwLock<HashMap<HandlerId, Box<dyn OutputHandler + Send + Sync>>>>,
         router: Arc<StdRwLock<EventRouter>>,
-        inbox: mpsc::UnboundedReceiver<SessionMessage>,
+        inbox: queue::Receiver<SessionMessage>,
+        runtime: Runtime,
     ) -> Self {
         let task_manager = Arc::new(TaskManager::new());
-        let aware_client = Arc::new(TaskAwareMcpClient::new(mcp_client, task_manager.clone()));
+        let limiter = Arc::new(RateLimiter::unlimited());
+        let policy = session_client(
+            &id,
+            mcp_client,
+            task_manager.clone(),
+            workflow_engine.templates.clone(),
+            Arc::new(Policy::allow_all()),
+            limiter.clone(),
+        );
 
         Self {
             id,
             inbox,
-            mcp_client: aware_client,
+            mcp_client: policy.clone(),
+            policy,
             decision_engine,
             workflow_engine,
             perception_module,
@@ -81,29 +113,47 @@ impl RobotSession {
             router,
             task_manager,
             pending_execution: None,
+            trace_sink: None,
+            limiter,
+            runtime,
         }
     }
 
     pub async fn run_inner(mut self) {
         info!("Session {} started", self.id);
+        metrics::session_started();
         while let Some(msg) = self.inbox.recv().await {
+            let (id, limiter) = (self.id.clone(), self.limiter.clone());
             match msg {
-                SessionMessage::Input(event) => {
-                    self.handle_input(event).await;
-                }
+                SessionMessage::Input(event) => match self.trace_sink.clone() {
+                    Some(sink) => {
+                        let input = event.clone();
+                        let handled = trace::capture(&id, &input, self.handle_input(event));
+                        let ((), trace) = limits::scope(id.clone(), limiter, handled).await;
+                        // Inputs consumed by an elicitation leave nothing to trace
+                        if !trace.events.is_empty()
+                            && let Err(e) = sink.write(&trace).await
+                        {
+                            error!("Failed to write trace for session {}: {}", self.id, e);
+                        }
+                    }
+                    None => limits::scope(id, limiter, self.handle_input(event)).await,
+                },
                 SessionMessage::Shutdown => {
                     info!("Session {} shutting down", self.id);
                     break;
                 }
             }
         }
+        metrics::session_ended();
+        metrics::remove_session_queue_depth(&self.id);
     }
 
     async fn handle_input(&mut self, event: InputEvent) {
         info!("Session {} processing event from {}", self.id, event.source);
 
         // check if consumed
-        if crate::utils::check_and_remove_consumed_event(&event.id) {
+        if self.runtime.take_consumed_event(&event.id) {
             info!(
                 "Skipping event {} as it was consumed by MCP elicitation",
                 event.id
@@ -115,7 +165,7 @@ impl RobotSession {
             .session_id
             .clone()
             .unwrap_or_else(|| event.source.clone());
-        if crate::utils::is_elicitation_active(&sid_key) {
+        if self.runtime.is_elicitation_active(&sid_key) {
             info!(
                 "Session {} skipping event {} because MCP elicitation is active",
                 self.id, event.id
@@ -140,7 +190,15 @@ impl RobotSession {
             }
         };
 
-        let input_text = if let Some(line) = event
+        // The field the source declares holds the text, then the usual ones
+        let declared = event
+            .source_meta
+            .as_ref()
+            .and_then(|meta| event.payload.get(&meta.content_field))
+            .and_then(|v| v.as_str());
+        let input_text = if let Some(text) = declared {
+            text.to_string()
+        } else if let Some(line) = event
             .payload
             .get("line")
             .and_then(|v: &serde_json::Value| v.as_str())
@@ -155,16 +213,112 @@ impl RobotSession {
         } else {
             String::new()
         };
+        let attachments = event.attachments.clone();
+
+        // Every input needs the model, so an exhausted budget stops it here
+        if let Err(hit) = self.limiter.llm_available(&self.id) {
+            self.say(&target_ids, &event.source, hit.to_string()).await;
+            return;
+        }
+
+        self.policy.set_caller(event.identity.clone());
 
         // Check for pending execution (Elicitation / Continuation)
         if let Some((steps, idx, mut ctx)) = self.pending_execution.take() {
             info!("Resuming pending execution at step {}", idx);
-            // Update context with new input
-            ctx.input_text = input_text;
+            if let Some(pending) = ctx.memory.get(PENDING_CONFIRMATION).cloned() {
+                // A confirmation answer, not new input for the workflow
+                let tool = pending["tool"].as_str().unwrap_or_default();
+                match ConfirmationReply::parse(&input_text) {
+                    ConfirmationReply::Approve => {
+                        info!("User approved '{}'", tool);
+                        let args = Some(pending["args"].clone()).filter(|a| !a.is_null());
+                        self.policy.approve(tool, args);
+                    }
+                    ConfirmationReply::Edit(edits) => {
+                        let internal: Vec<&str> =
+                            edits.keys().map(String::as_str).filter(|k| is_internal_arg(k)).collect();
+                        if !internal.is_empty() {
+                            let text = format!(
+                                "These arguments of '{}' can't be edited: {}. Reply 'yes' to run it, 'edit name=value ...' to change other arguments, anything else to cancel.",
+                                tool,
+                                internal.join(", ")
+                            );
+                            self.say(&target_ids, &event.source, text).await;
+                            self.pending_execution = Some((steps, idx, ctx));
+                            return;
+                        }
+                        let Some(args) = ctx.memory[PENDING_CONFIRMATION]["args"].as_object_mut() else {
+                            let text = format!(
+                                "'{}' has no arguments to edit yet. Reply 'yes' to run it, anything else to cancel.",
+                                tool
+                            );
+                            self.say(&target_ids, &event.source, text).await;
+                            self.pending_execution = Some((steps, idx, ctx));
+                            return;
+                        };
+                        // The edited call is shown for confirmation again
+                        info!("User edited '{}': {:?}", tool, edits);
+                        args.extend(edits);
+                    }
+                    ConfirmationReply::Reject => {
+                        info!("User declined '{}'", tool);
+                        self.say(&target_ids, &event.source, format!("Cancelled '{}'.", tool)).await;
+                        return;
+                    }
+                }
+            } else {
+                // Update context with new input
+                ctx.input_text = input_text;
+            }
             // Resume workflow
             self.execute_workflow(steps, idx, ctx, target_ids, event.source).await;
             return;
         }
+        self.policy.clear_approvals();
+
+        // Workflow templates: a slash-command or pattern match runs the
+        // template's plan directly, without perception or the LLM planner
+        let template_plan = self
+            .workflow_engine
+            .templates
+            .match_input(&input_text)
+            .map(|m| (m.template.name.clone(), m.template.instantiate(&m.params)));
+        if let Some((name, plan_res)) = template_plan {
+            match plan_res {
+                Ok(plan) => {
+                    info!("Input matched workflow template '{}': {:?}", name, plan);
+                    trace::record(TraceEvent::Template { name: name.clone() });
+                    trace::record(TraceEvent::Plan { plan: plan.clone() });
+                    metrics::record_plan("template", plan.steps.len());
+                    let mut ctx = Context::new((*self.persona).clone(), input_text, Some(self.id.clone()));
+                    ctx.attachments = attachments;
+                    ctx.memory = serde_json::json!({
+                        "workflow": {
+                            "plan": plan.clone(),
+                            "template": name,
+                            "current_step_index": 0
+                        }
+                    });
+                    self.execute_workflow(plan.steps, 0, ctx, target_ids, event.source).await;
+                }
+                Err(e) => {
+                    warn!("Workflow template '{}' could not be instantiated: {}", name, e);
+                    let output = OutputEvent {
+                        target: "default".to_string(),
+                        source: event.source.clone(),
+                        session_id: Some(self.id.clone()),
+                        content: serde_json::json!({
+                            "type": "text",
+                            "text": e.to_string()
+                        }),
+                        style: self.persona.style.clone(),
+                    };
+                    self.emit_to(&target_ids, output).await;
+                }
+            }
+            return;
+        }
 
         // 1. Perception Layer
         let perception = match self.perception_module.perceive(&event).await {
@@ -175,6 +329,9 @@ impl RobotSession {
             }
         };
         info!("Perception Result: {:?}", perception);
+        trace::record(TraceEvent::Perception {
+            result: serde_json::to_value(&perception).unwrap_or_default(),
+        });
 
         // 2. Intent & State Layer (The "Soul Question")
         let intent = match self
@@ -189,6 +346,9 @@ impl RobotSession {
             }
         };
 
+        let decision = format!("{:?}", intent);
+        metrics::record_intent(&decision.to_lowercase());
+        trace::record(TraceEvent::Intent { decision });
         if intent == IntentDecision::Ignore {
             info!("IntentDecision: IGNORE. Skipping response.");
             return;
@@ -201,12 +361,15 @@ impl RobotSession {
         match plan_res {
             Ok(plan) => {
                 info!("Plan decided for session {}: {:?}", self.id, plan);
+                trace::record(TraceEvent::Plan { plan: plan.clone() });
+                metrics::record_plan("planner", plan.steps.len());
 
                 let mut ctx = crate::utils::Context::new(
                     (*self.persona).clone(),
                     input_text.clone(),
                     Some(self.id.clone()),
                 );
+                ctx.attachments = attachments;
                 
                 // Initialize workflow context in memory
                 ctx.memory = serde_json::json!({
@@ -231,13 +394,7 @@ impl RobotSession {
                         style: self.persona.style.clone(),
                     };
 
-                    let handlers_guard = self.output_handlers.read().await;
-                    let futures = target_ids
-                        .iter()
-                        .filter_map(|handler_id| handlers_guard.get(handler_id))
-                        .map(|handler| handler.emit(output.clone()))
-                        .collect::<Vec<_>>();
-                    join_all(futures).await;
+                    self.emit_to(&target_ids, output).await;
                 }
                 error!("Error deciding plan: {}", e);
             }
@@ -246,13 +403,17 @@ impl RobotSession {
     
     async fn execute_workflow(
         &mut self, 
-        steps: Vec<StepSpec>, 
+        mut steps: Vec<StepSpec>, 
         start_idx: usize, 
         mut ctx: Context,
         target_ids: Vec<HandlerId>,
         event_source: String,
     ) {
-        for (i, spec) in steps.iter().enumerate().skip(start_idx) {
+        let mut replans = 0;
+        let mut failed = false;
+        let mut i = start_idx;
+        while i < steps.len() {
+            let spec = steps[i].clone();
             // Update current step index in memory
             if let Some(workflow) = ctx.memory.get_mut("workflow") {
                  if let Some(obj) = workflow.as_object_mut() {
@@ -263,13 +424,46 @@ impl RobotSession {
             info!("workflow step start: {:?}", spec);
 
             let (is_bg, task_name, task_args) = match &spec {
-                crate::utils::StepSpec::Tool { name, args, is_background, dependencies: _ } => {
+                crate::utils::StepSpec::Tool { name, args, is_background, .. } => {
                     (*is_background, name.clone(), Some(args.clone()))
                 }
                 _ => (false, "background_task".to_string(), None),
             };
 
+            // A background call cannot pause the workflow, so it is confirmed
+            // before it starts, without its args
+            if is_bg && self.policy.needs_confirmation(&task_name).await {
+                let prompt = format!(
+                    "'{}' needs your confirmation before it runs in the background.\nReply 'yes' to run it, anything else to cancel.",
+                    task_name
+                );
+                let pending = serde_json::json!({
+                    "tool": task_name,
+                    "args": null,
+                    "step": confirmation_key(&ctx),
+                });
+                if let Some(map) = ctx.memory.as_object_mut() {
+                    map.insert(PENDING_CONFIRMATION.to_string(), pending);
+                }
+                let output = OutputEvent {
+                    target: "default".to_string(),
+                    source: event_source.clone(),
+                    session_id: Some(self.id.clone()),
+                    content: serde_json::json!({
+                        "type": "text",
+                        "text": prompt
+                    }),
+                    style: self.persona.style.clone(),
+                };
+                self.emit_to(&target_ids, output).await;
+                self.pending_execution = Some((steps, i, ctx));
+                return;
+            }
+
             if is_bg {
+                if let Some(map) = ctx.memory.as_object_mut() {
+                    map.remove(PENDING_CONFIRMATION);
+                }
                 info!("Spawning background task for step: {:?}", spec);
                 let step = crate::workflow_steps::build_step(
                     &spec,
@@ -285,6 +479,8 @@ impl RobotSession {
                 let event_source = event_source.clone();
                 let event_source_task = event_source.clone();
                 let task_manager = self.task_manager.clone();
+                let task_label = task_name.clone();
+                let limiter = self.limiter.clone();
                 
                 let task_id = Uuid::new_v4().to_string();
                 let task_id_clone = task_id.clone();
@@ -300,7 +496,7 @@ impl RobotSession {
                     None => ctx.input_text.clone(),
                 };
 
-                let handle = tokio::spawn(async move {
+                let handle = tokio::spawn(limits::scope(session_id.clone(), limiter, async move {
                     let res = step.run(&mut ctx_clone, &*mcp_client).await;
                      match res {
                         Ok(res) => {
@@ -310,12 +506,28 @@ impl RobotSession {
                                     o.session_id = Some(session_id);
                                 }
 
-                                // Dispatch output
+                                // The task reports its own result: the workflow reply
+                                // was synthesized when the foreground steps finished.
+                                // Handlers hiding tool results get it as text.
+                                let report = is_intermediate(&o).then(|| OutputEvent {
+                                    content: serde_json::json!({
+                                        "type": "text",
+                                        "text": format!(
+                                            "Background task '{}' finished:\n{}",
+                                            task_label,
+                                            result_text(&o.content["result"])
+                                        ),
+                                    }),
+                                    ..o.clone()
+                                });
                                 let handlers_guard = output_handlers.read().await;
                                 let futures = target_ids_clone
                                     .iter()
                                     .filter_map(|handler_id| handlers_guard.get(handler_id))
-                                    .map(|handler| handler.emit(o.clone()))
+                                    .map(|handler| match &report {
+                                        Some(report) if !handler.show_intermediate() => handler.emit(report.clone()),
+                                        _ => handler.emit(o.clone()),
+                                    })
                                     .collect::<Vec<_>>();
                                 futures::future::join_all(futures).await;
                             }
@@ -326,7 +538,7 @@ impl RobotSession {
                     }
                     // Remove task from manager upon completion
                     task_manager.remove_task(&task_id_clone).await;
-                });
+                }));
                 
                 self
                     .task_manager
@@ -344,24 +556,19 @@ impl RobotSession {
                     }),
                     style: self.persona.style.clone(),
                 };
-                let handlers_guard = self.output_handlers.read().await;
-                for handler_id in &target_ids {
-                    if let Some(handler) = handlers_guard.get(handler_id) {
-                        let _ = handler.emit(output.clone()).await;
-                    }
-                }
+                self.emit_to(&target_ids, output).await;
                 
+                i += 1;
                 continue;
             }
 
-            let step = crate::workflow_steps::build_step(
-                &spec,
-                self.workflow_engine.resolver.clone(),
-            );
-            let res = step.run(&mut ctx, &*self.mcp_client).await;
+            let outcome = self
+                .workflow_engine
+                .run_step(&spec, &mut ctx, &*self.mcp_client)
+                .await;
 
-            match res {
-                Ok(res) => {
+            match outcome {
+                StepOutcome::Done(res) => {
                     // Handle output
                     if let Some(mut o) = res.output {
                         o.source = event_source.clone();
@@ -374,19 +581,7 @@ impl RobotSession {
                             target_ids.len()
                         );
 
-                        // Dispatch output
-                        let handlers_guard = self.output_handlers.read().await;
-                        let futures = target_ids
-                            .iter()
-                            .filter_map(|handler_id| handlers_guard.get(handler_id))
-                            .map(|handler| handler.emit(o.clone()))
-                            .collect::<Vec<_>>();
-                        let results = join_all(futures).await;
-                        for res in results {
-                            if let Err(e) = res {
-                                error!("Error emitting workflow output: {}", e);
-                            }
-                        }
+                        self.emit_to(&target_ids, o).await;
                     }
                     
                     // Handle status
@@ -406,13 +601,7 @@ impl RobotSession {
                                 style: self.persona.style.clone(),
                             };
 
-                            let handlers_guard = self.output_handlers.read().await;
-                            let futures = target_ids
-                                .iter()
-                                .filter_map(|handler_id| handlers_guard.get(handler_id))
-                                .map(|handler| handler.emit(output.clone()))
-                                .collect::<Vec<_>>();
-                            join_all(futures).await;
+                            self.emit_to(&target_ids, output).await;
                             
                             // Suspend execution
                             self.pending_execution = Some((steps, i, ctx));
@@ -427,17 +616,161 @@ impl RobotSession {
                         }
                     }
                 }
-                Err(e) => {
+                StepOutcome::Skipped(e) => {
+                    warn!("Workflow step {} skipped after error: {}", i, e);
+                }
+                StepOutcome::NeedsReplan(e) if replans < MAX_REPLANS => {
+                    replans += 1;
+                    let failure = self.step_failure(i, &spec, &e, &ctx);
+                    let history = ctx.memory["workflow"]["history"].clone();
+                    info!("Re-planning after step {} failed: {}", i, e);
+                    match self
+                        .decision_engine
+                        .replan(&self.persona, &failure, &history, &*self.mcp_client)
+                        .await
+                    {
+                        Ok(plan) if !plan.steps.is_empty() => {
+                            info!("Replanned remaining steps: {:?}", plan);
+                            steps.truncate(i + 1);
+                            steps.extend(plan.steps);
+                            if let Some(workflow) = ctx.memory.get_mut("workflow").and_then(|w| w.as_object_mut()) {
+                                workflow.insert(
+                                    "plan".to_string(),
+                                    serde_json::json!(WorkflowPlan {
+                                        steps: steps.clone(),
+                                        reasoning: plan.reasoning,
+                                    }),
+                                );
+                            }
+                        }
+                        Ok(_) => {
+                            self.report_failure(&failure, &target_ids, &event_source).await;
+                            failed = true;
+                            break;
+                        }
+                        Err(re) => {
+                            error!("Re-planning failed: {}", re);
+                            self.report_failure(&failure, &target_ids, &event_source).await;
+                            failed = true;
+                            break;
+                        }
+                    }
+                }
+                StepOutcome::NeedsReplan(e) | StepOutcome::Failed(e) => {
                     error!("Error executing workflow step: {}", e);
+                    match e.downcast_ref::<LimitExceeded>() {
+                        Some(hit) => self.say(&target_ids, &event_source, hit.to_string()).await,
+                        None => {
+                            let failure = self.step_failure(i, &spec, &e, &ctx);
+                            self.report_failure(&failure, &target_ids, &event_source).await;
+                        }
+                    }
+                    failed = true;
                     break;
                 }
             }
+            i += 1;
+        }
+
+        if !failed && let Some(mut reply) = self.workflow_engine.synthesize(&ctx).await {
+            reply.source = event_source.clone();
+            self.emit_to(&target_ids, reply).await;
+        }
+    }
+
+    /// Send a plain text message in persona style
+    async fn say(&self, target_ids: &[HandlerId], source: &str, text: String) {
+        let output = OutputEvent {
+            target: "default".to_string(),
+            source: source.to_string(),
+            session_id: Some(self.id.clone()),
+            content: serde_json::json!({
+                "type": "text",
+                "text": text
+            }),
+            style: self.persona.style.clone(),
+        };
+        self.emit_to(target_ids, output).await;
+    }
+
+    /// Send `output` to the routed handlers. Intermediate tool results only
+    /// go to handlers that asked for them.
+    async fn emit_to(&self, target_ids: &[HandlerId], output: OutputEvent) {
+        trace::record(TraceEvent::Output { event: output.clone() });
+        let intermediate = is_intermediate(&output);
+        let handlers_guard = self.output_handlers.read().await;
+        let futures = target_ids
+            .iter()
+            .filter_map(|handler_id| handlers_guard.get(handler_id).map(|h| (handler_id, h)))
+            .filter(|(_, handler)| !intermediate || handler.show_intermediate())
+            .map(|(handler_id, handler)| handler.emit(output.clone()).map(move |res| (handler_id, res)))
+            .collect::<Vec<_>>();
+        for (handler_id, res) in join_all(futures).await {
+            if let Err(e) = res {
+                metrics::record_output_failure(handler_id.name());
+                error!("Error emitting output: {}", e);
+            }
+        }
+    }
+
+    fn step_failure(&self, index: usize, spec: &StepSpec, err: &anyhow::Error, ctx: &Context) -> StepFailure {
+        StepFailure {
+            step_index: index,
+            tool: match spec {
+                StepSpec::Tool { name, .. } => Some(name.clone()),
+                _ => None,
+            },
+            error: err.to_string(),
+            input_text: ctx.input_text.clone(),
+            session_id: Some(self.id.clone()),
         }
     }
+
+    /// Tell the user, in persona style, which step could not be completed
+    async fn report_failure(&self, failure: &StepFailure, target_ids: &[HandlerId], event_source: &str) {
+        let text = match self.decision_engine.explain_failure(&self.persona, failure).await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Failed to explain workflow failure: {}", e);
+                failure.default_message()
+            }
+        };
+        let output = OutputEvent {
+            target: "default".to_string(),
+            source: event_source.to_string(),
+            session_id: Some(self.id.clone()),
+            content: serde_json::json!({
+                "type": "text",
+                "text": text
+            }),
+            style: self.persona.style.clone(),
+        };
+        self.emit_to(target_ids, output).await;
+    }
+}
+
+/// The client chain of a session: tracing, background task tools and
+/// template macros, with tool rate limits and the tool policy on top
+fn session_client(
+    session_id: &str,
+    mcp_client: Arc<dyn MCPClient + Send + Sync>,
+    task_manager: Arc<TaskManager>,
+    templates: Arc<TemplateLibrary>,
+    policy: Arc<Policy>,
+    limiter: Arc<RateLimiter>,
+) -> Arc<PolicyMcpClient> {
+    let traced_client: Arc<dyn MCPClient + Send + Sync> = Arc::new(TracedMcp::new(mcp_client));
+    let aware_client: Arc<dyn MCPClient + Send + Sync> = Arc::new(TaskAwareMcpClient::new(traced_client, task_manager));
+    let template_client: Arc<dyn MCPClient + Send + Sync> = Arc::new(TemplateMcpClient::new(aware_client, templates));
+    let limited_client: Arc<dyn MCPClient + Send + Sync> =
+        Arc::new(RateLimitedMcp::new(template_client, limiter, session_id.to_string()));
+    Arc::new(PolicyMcpClient::new(limited_client, policy))
 }
 
 pub struct SessionManager {
-    sessions: RwLock<HashMap<String, mpsc::UnboundedSender<SessionMessage>>>,
+    sessions: RwLock<HashMap<String, queue::Sender<SessionMessage>>>,
+    /// Background tasks of every session, for consoles listing and cancelling them
+    task_managers: StdRwLock<HashMap<String, Arc<TaskManager>>>,
     factory: Arc<super::McpClientFactory>,
 
     // Dependencies for spawning sessions
@@ -448,6 +781,10 @@ pub struct SessionManager {
     persona: Arc<Persona>,
     output_handlers: Arc<RwLock<HashMap<HandlerId, Box<dyn OutputHandler + Send + Sync>>>>,
     router: Arc<StdRwLock<EventRouter>>,
+    trace_sink: StdRwLock<Option<Arc<dyn TraceSink>>>,
+    policy: StdRwLock<Arc<Policy>>,
+    limiter: StdRwLock<Arc<RateLimiter>>,
+    runtime: Runtime,
 }
 
 impl SessionManager {
@@ -460,9 +797,11 @@ impl SessionManager {
         persona: Arc<Persona>,
         output_handlers: Arc<RwLock<HashMap<HandlerId, Box<dyn OutputHandler + Send + Sync>>>>,
         router: Arc<StdRwLock<EventRouter>>,
+        runtime: Runtime,
     ) -> Self {
         Self {
             sessions: RwLock::new(HashMap::new()),
+            task_managers: StdRwLock::new(HashMap::new()),
             factory,
             decision_engine,
             workflow_engine,
@@ -471,50 +810,118 @@ impl SessionManager {
             persona,
             output_handlers,
             router,
+            trace_sink: StdRwLock::new(None),
+            policy: StdRwLock::new(Arc::new(Policy::allow_all())),
+            limiter: StdRwLock::new(Arc::new(RateLimiter::unlimited())),
+            runtime,
+        }
+    }
+
+    /// Enforce `policy` on the tool calls of sessions created from now on
+    pub fn set_policy(&self, policy: Arc<Policy>) {
+        *self.policy.write().unwrap() = policy;
+    }
+
+    /// Apply `limiter` to inputs, and to the LLM and tool use of sessions
+    /// created from now on
+    pub fn set_rate_limiter(&self, limiter: Arc<RateLimiter>) {
+        *self.limiter.write().unwrap() = limiter;
+    }
+
+    /// Record a trace of every input handled by sessions created from now on
+    pub fn set_trace_sink(&self, sink: Arc<dyn TraceSink>) {
+        *self.trace_sink.write().unwrap() = Some(sink);
+    }
+
+    /// Background tasks running in `session_id`, oldest first
+    pub async fn tasks(&self, session_id: &str) -> Vec<TaskSummary> {
+        let manager = self.task_managers.read().unwrap().get(session_id).cloned();
+        let mut tasks = match manager {
+            Some(manager) => manager.list_tasks().await,
+            None => Vec::new(),
+        };
+        tasks.sort_by_key(|t| t.ordinal);
+        tasks
+    }
+
+    /// Abort background task `task_id` of `session_id`
+    pub async fn cancel_task(&self, session_id: &str, task_id: &str) -> bool {
+        let manager = self.task_managers.read().unwrap().get(session_id).cloned();
+        match manager {
+            Some(manager) => manager.cancel_task(task_id).await,
+            None => false,
         }
     }
 
     pub async fn dispatch(&self, event: InputEvent) {
+        metrics::record_input(&event.source);
         let session_id = event
             .session_id
             .clone()
             .unwrap_or_else(|| event.source.clone());
 
-        // Fast path: check if session exists with read lock
-        {
-            let guard = self.sessions.read().await;
-            if let Some(sender) = guard.get(&session_id) {
-                if sender.send(SessionMessage::Input(event.clone())).is_ok() {
-                    return;
-                }
-                // If send failed, channel is closed, we need to recreate session
-            }
+        let user = event.identity.as_ref().map_or(&session_id, |id| &id.user_id);
+        let hit = self.limiter.read().unwrap().check_message(user);
+        if let Err(hit) = hit {
+            info!("Dropping input {} of {}: {}", event.id, user, hit);
+            self.refuse(&event, &session_id, hit.to_string()).await;
+            return;
         }
 
+        // Fast path: the session exists. The sender is cloned out so a
+        // blocking inbox does not hold the lock.
+        let sender = self.sessions.read().await.get(&session_id).cloned();
+        let event = match sender {
+            Some(sender) => match self.deliver(&sender, &session_id, event).await {
+                Some(event) => event,
+                None => return,
+            },
+            None => event,
+        };
+
         // Slow path: create session with write lock
         let mut guard = self.sessions.write().await;
         // Check again in case someone else created it
-        if let Some(sender) = guard.get(&session_id) {
-            if sender.send(SessionMessage::Input(event.clone())).is_ok() {
-                return;
+        if let Some(sender) = guard.get(&session_id).cloned() {
+            drop(guard);
+            if let Some(event) = self.deliver(&sender, &session_id, event).await {
+                warn!("Session {} closed while dispatching input {}", session_id, event.id);
             }
+            return;
         }
 
         // Create new session
         info!("Creating new session actor for {}", session_id);
         match (self.factory)(session_id.clone()).await {
             Ok(mcp_client) => {
-                let (tx, rx) = mpsc::unbounded_channel();
+                let (tx, rx) = queue::channel(
+                    "session",
+                    self.runtime.queues().session,
+                    Some(metrics::session_queue_depth(&session_id)),
+                );
 
                 let task_manager = Arc::new(TaskManager::new());
-                let aware_client: Arc<dyn MCPClient + Send + Sync> = Arc::new(TaskAwareMcpClient::new(mcp_client, task_manager.clone()));
+                self.task_managers
+                    .write()
+                    .unwrap()
+                    .insert(session_id.clone(), task_manager.clone());
+                let limiter = self.limiter.read().unwrap().clone();
+                let policy = session_client(
+                    &session_id,
+                    mcp_client,
+                    task_manager.clone(),
+                    self.workflow_engine.templates.clone(),
+                    self.policy.read().unwrap().clone(),
+                    limiter.clone(),
+                );
                 let decision_engine = self.decision_engine.clone();
                 let workflow_engine = self.workflow_engine.clone();
 
                 let session = RobotSession {
                     id: session_id.clone(),
                     inbox: rx,
-                    mcp_client: aware_client,
+                    mcp_client: policy.clone(),
+                    policy,
                     decision_engine,
                     workflow_engine,
                     perception_module: self.perception_module.clone(),
@@ -524,6 +931,9 @@ impl SessionManager {
                     router: self.router.clone(),
                     task_manager,
                     pending_execution: None,
+                    trace_sink: self.trace_sink.read().unwrap().clone(),
+                    limiter,
+                    runtime: self.runtime.clone(),
                 };
 
                 // Spawn session actor
@@ -534,9 +944,9 @@ impl SessionManager {
                 };
                 tokio::spawn(actor.run());
 
-                // Store sender and dispatch
+                // Store sender and dispatch; the new inbox has room
                 guard.insert(session_id.clone(), tx.clone());
-                if let Err(e) = tx.send(SessionMessage::Input(event)) {
+                if let Err(e) = tx.try_send(SessionMessage::Input(event)) {
                     error!(
                         "Failed to dispatch event to new session {}: {}",
                         session_id, e
@@ -551,4 +961,116 @@ impl SessionManager {
             }
         }
     }
+
+    /// Queue `event` in the inbox of its session, telling the user when the
+    /// inbox is full. Hands the event back when the session has ended.
+    async fn deliver(
+        &self,
+        sender: &queue::Sender<SessionMessage>,
+        session_id: &str,
+        event: InputEvent,
+    ) -> Option<InputEvent> {
+        match sender.send(SessionMessage::Input(event)).await {
+            Ok(()) => None,
+            Err(queue::SendError::Full(SessionMessage::Input(event))) => {
+                warn!("Inbox of session {} is full, rejecting input {}", session_id, event.id);
+                self.refuse(&event, session_id, SESSION_BUSY.to_string()).await;
+                None
+            }
+            Err(queue::SendError::Closed(SessionMessage::Input(event))) => Some(event),
+            Err(_) => None,
+        }
+    }
+
+    /// Answer an input that is not handed to its session
+    async fn refuse(&self, event: &InputEvent, session_id: &str, text: String) {
+        let target_ids: Vec<HandlerId> = {
+            let router = self.router.read().unwrap();
+            if router.has_routes() {
+                router.get_outputs_for_event(event)
+            } else {
+                Vec::new()
+            }
+        };
+        let output = OutputEvent {
+            target: "default".to_string(),
+            source: event.source.clone(),
+            session_id: Some(session_id.to_string()),
+            content: serde_json::json!({
+                "type": "text",
+                "text": text
+            }),
+            style: self.persona.style.clone(),
+        };
+        let handlers = self.output_handlers.read().await;
+        let futures = handlers
+            .iter()
+            .filter(|(id, _)| target_ids.is_empty() || target_ids.contains(id))
+            .map(|(_, handler)| handler.emit(output.clone()))
+            .collect::<Vec<_>>();
+        for res in join_all(futures).await {
+            if let Err(e) = res {
+                error!("Error emitting output: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testkit::{FakeMcp, FakeTool, ScriptedLlm, TestRobot, prompts};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn background_tasks_report_without_a_second_reply() {
+        let plan = json!({"reasoning": "greet, then render", "steps": [
+            {"tool": "echo", "dependencies": []},
+            {"tool": "render", "dependencies": []}
+        ]});
+        let llm = ScriptedLlm::new()
+            .respond_to_all()
+            .plan("渲染", plan)
+            .params("echo", json!({"text": "hello"}))
+            .params("render", json!({}))
+            .synthesize("hello", "开始渲染了");
+        let mcp = FakeMcp::new()
+            .tool(FakeTool::new("echo", "Echo").param("text", "string", true).returns("hello"))
+            .tool(FakeTool::new("render", "Render a video").long_running().returns("video.mp4"));
+        let robot = TestRobot::builder(llm, mcp).build().await;
+        let mut chat = robot.conversation("carol");
+
+        chat.say("渲染一个视频").unwrap();
+        let mut texts = Vec::new();
+        for _ in 0..3 {
+            texts.push(chat.reply_text().await.unwrap());
+        }
+        assert!(texts.iter().any(|t| t.starts_with("Started background task 'render'")), "{:?}", texts);
+        assert!(texts.iter().any(|t| t == "开始渲染了"), "{:?}", texts);
+        assert!(texts.iter().any(|t| t.contains("finished:\nvideo.mp4")), "{:?}", texts);
+        assert!(chat.is_silent_for(Duration::from_millis(200)).await);
+        assert_eq!(robot.llm.prompts_matching(prompts::SYNTHESIZER).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn replanning_stops_after_max_replans_and_reports_the_failure() {
+        let plan = json!({"reasoning": "try", "steps": [{"tool": "broken", "dependencies": []}]});
+        let llm = ScriptedLlm::new()
+            .respond_to_all()
+            .plan("修复", plan.clone())
+            .on(prompts::REPLANNER, plan.to_string())
+            .on(prompts::FAILURE, "暂时无法完成")
+            .params("broken", json!({}));
+        let mcp = FakeMcp::new()
+            .tool(FakeTool::new("broken", "Always fails").handler(|_| Err(anyhow::anyhow!("connection refused"))));
+        let robot = TestRobot::builder(llm, mcp).build().await;
+        let mut chat = robot.conversation("erin");
+
+        chat.say("修复一下").unwrap();
+        assert_eq!(chat.reply_text().await.unwrap(), "暂时无法完成");
+        assert!(chat.is_silent_for(Duration::from_millis(200)).await);
+        assert_eq!(robot.llm.prompts_matching(prompts::REPLANNER).len(), super::MAX_REPLANS);
+        assert_eq!(robot.mcp.calls_to("broken").len(), super::MAX_REPLANS + 1);
+        assert_eq!(robot.llm.prompts_matching(prompts::FAILURE).len(), 1);
+    }
 }
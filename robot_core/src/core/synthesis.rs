@@ -0,0 +1,207 @@
+use crate::core::metrics;
+use crate::core::persona::Persona;
+use crate::llm::adapter::{ChatMessage, ChatRequest, LLMClient};
+use crate::utils::OutputEvent;
+use async_trait::async_trait;
+use serde_json::Value;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Content type of intermediate tool results. Output handlers only receive
+/// these when `OutputHandler::show_intermediate` returns true.
+pub const TOOL_RESULT_TYPE: &str = "tool_result";
+
+pub fn is_intermediate(event: &OutputEvent) -> bool {
+    event.content.get("type").and_then(|t| t.as_str()) == Some(TOOL_RESULT_TYPE)
+}
+
+/// Turns the results of a finished plan into the single reply the user sees
+#[async_trait]
+pub trait ResponseSynthesizer: Send + Sync {
+    /// `history` is `ctx.memory["workflow"]["history"]`. Returns `None` when
+    /// there is nothing worth saying.
+    async fn synthesize(
+        &self,
+        persona: &Persona,
+        input_text: &str,
+        history: &Value,
+        session_id: Option<String>,
+    ) -> anyhow::Result<Option<String>>;
+}
+
+/// Concatenates the text content of the tool results, without an LLM
+pub struct BasicResponseSynthesizer;
+
+#[async_trait]
+impl ResponseSynthesizer for BasicResponseSynthesizer {
+    async fn synthesize(
+        &self,
+        _persona: &Persona,
+        _input_text: &str,
+        history: &Value,
+        _session_id: Option<String>,
+    ) -> anyhow::Result<Option<String>> {
+        let text = last_results(history)
+            .iter()
+            .map(|(_, result)| result_text(result))
+            .filter(|t| !t.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(if text.is_empty() { None } else { Some(text) })
+    }
+}
+
+pub struct LLMResponseSynthesizer {
+    pub llm: Arc<dyn LLMClient + Send + Sync>,
+    pub model: String,
+}
+
+#[async_trait]
+impl ResponseSynthesizer for LLMResponseSynthesizer {
+    async fn synthesize(
+        &self,
+        persona: &Persona,
+        input_text: &str,
+        history: &Value,
+        session_id: Option<String>,
+    ) -> anyhow::Result<Option<String>> {
+        let results = last_results(history);
+        if results.is_empty() {
+            return Ok(None);
+        }
+        let findings = results
+            .iter()
+            .map(|(tool, result)| format!("- {}: {}", tool, result_text(result)))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let system = format!(
+            "You are '{}' with a {} style.\n\
+            You used some tools to answer the user's message. Their results are listed below.\n\
+            \n\
+            Rules:\n\
+            1. Answer the user's message directly in one natural reply, in the user's language.\n\
+            2. Use ONLY the facts in the tool results. Do not invent values.\n\
+            3. Do not mention tool names, JSON or internal steps unless the user asked for them.\n\
+            4. If a result is a cancellation, say briefly that it was cancelled.",
+            persona.name, persona.style
+        );
+        let user = format!("User message: {}\nTool results:\n{}", input_text, findings);
+        info!("LLMResponseSynthesizer prompt:\n{}", user);
+
+        let req = ChatRequest {
+            model: self.model.clone(),
+            messages: vec![
+                ChatMessage {
+                    role: "system".into(),
+                    content: system,
+                },
+                ChatMessage {
+                    role: "user".into(),
+                    content: user,
+                },
+            ],
+            temperature: Some(0.5),
+            session_id,
+        };
+        match metrics::observe_llm("synthesizer", self.llm.chat(req)).await {
+            Ok(out) if !out.text.trim().is_empty() => Ok(Some(out.text.trim().to_string())),
+            Ok(_) => BasicResponseSynthesizer
+                .synthesize(persona, input_text, history, None)
+                .await,
+            Err(e) => {
+                warn!("LLMResponseSynthesizer failed, falling back to raw results: {}", e);
+                BasicResponseSynthesizer
+                    .synthesize(persona, input_text, history, None)
+                    .await
+            }
+        }
+    }
+}
+
+/// Latest successful result of every executed step, in execution order.
+/// Retried steps appear several times in the history; only the last entry
+/// counts. Steps nested in control flow are told apart by their `path`, and
+/// the summary entries of control-flow steps themselves are skipped.
+fn last_results(history: &Value) -> Vec<(String, Value)> {
+    let mut results: Vec<((u64, String), (String, Value))> = Vec::new();
+    for entry in history.as_array().into_iter().flatten() {
+        if entry.get("control").is_some() {
+            continue;
+        }
+        let (Some(idx), Some(result)) = (
+            entry.get("step_index").and_then(|v| v.as_u64()),
+            entry.get("result"),
+        ) else {
+            continue;
+        };
+        let key = (
+            idx,
+            entry
+                .get("path")
+                .and_then(|p| p.as_str())
+                .unwrap_or_default()
+                .to_string(),
+        );
+        let existing = results.iter().position(|(k, _)| *k == key);
+        if entry.get("error").is_some() {
+            if let Some(pos) = existing {
+                results.remove(pos);
+            }
+            continue;
+        }
+        let tool = entry
+            .get("tool")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string();
+        match existing {
+            Some(pos) => results[pos].1 = (tool, result.clone()),
+            None => results.push((key, (tool, result.clone()))),
+        }
+    }
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Human readable text of a `CallToolResult` (its text contents joined),
+/// falling back to the JSON itself for non-text results.
+pub fn result_text(result: &Value) -> String {
+    let texts: Vec<&str> = result
+        .get("content")
+        .and_then(|c| c.as_array())
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|it| it.get("text").and_then(|t| t.as_str()))
+                .collect()
+        })
+        .unwrap_or_default();
+    if texts.is_empty() {
+        match result {
+            Value::String(s) => s.clone(),
+            Value::Null => String::new(),
+            v => v.to_string(),
+        }
+    } else {
+        texts.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn basic_synthesizer_uses_last_successful_results() {
+        let history = serde_json::json!([
+            {"step_index": 0, "tool": "sum", "args": {}, "result": {"content": [{"type": "text", "text": "3"}]}},
+            {"step_index": 1, "tool": "division", "args": {}, "result": {"content": []}, "error": "除数不能为0"},
+            {"step_index": 1, "tool": "division", "args": {}, "result": {"content": [{"type": "text", "text": "1.5"}]}}
+        ]);
+        let text = BasicResponseSynthesizer
+            .synthesize(&Persona::default(), "", &history, None)
+            .await
+            .unwrap();
+        assert_eq!(text.as_deref(), Some("3\n1.5"));
+    }
+}
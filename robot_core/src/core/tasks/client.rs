@@ -53,12 +53,14 @@ impl MCPClient for TaskAwareMcpClient {
             name: "list_running_tasks".to_string(),
             description: "CRITICAL: Call this tool FIRST when the user wants to check status or cancel a task. Returns a list of tasks with 'ordinal' (index), 'original_prompt' (user intent), and 'task_id'. Use this output to map user's natural language description to a precise 'task_id'.".to_string(),
             is_long_running: false,
+            destructive: false,
         });
         
         tools.push(ToolMeta {
             name: "cancel_task".to_string(),
             description: "Cancels a background task. REQUIRED: You MUST have a valid 'task_id' from the output of 'list_running_tasks' before calling this. DO NOT guess the ID. If you don't know the ID, call 'list_running_tasks' first.".to_string(),
             is_long_running: false,
+            destructive: false,
         });
 
         Ok(tools)
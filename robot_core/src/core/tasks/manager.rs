@@ -1,3 +1,4 @@
+use crate::core::metrics;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -53,11 +54,15 @@ impl TaskManager {
             ordinal,
             original_prompt,
         };
-        self.tasks.write().await.insert(id, task);
+        if self.tasks.write().await.insert(id, task).is_none() {
+            metrics::task_started();
+        }
     }
 
     pub async fn remove_task(&self, id: &str) {
-        self.tasks.write().await.remove(id);
+        if self.tasks.write().await.remove(id).is_some() {
+            metrics::task_finished();
+        }
     }
 
     pub async fn list_tasks(&self) -> Vec<TaskSummary> {
@@ -79,6 +84,7 @@ impl TaskManager {
         let mut tasks = self.tasks.write().await;
         if let Some(task) = tasks.remove(id) {
             task.handle.abort();
+            metrics::task_finished();
             return true;
         }
         false
@@ -0,0 +1,501 @@
+//! User-authored workflow templates.
+//!
+//! A template is a named `WorkflowPlan` with `{{param}}` placeholders, loaded
+//! from a JSON or YAML file. It runs without the LLM planner when the input
+//! is its slash-command (`/probe_push /data/a.mp4 ip=10.0.0.5`) or matches one
+//! of its regex patterns (named captures fill the params). The planner sees
+//! each template as one `macro_<name>` tool, which the `WorkflowEngine`
+//! expands into the template's steps.
+use crate::mcp::client::MCPClient;
+use crate::mcp::registry::ToolMeta;
+use crate::utils::{StepSpec, WorkflowPlan};
+use async_trait::async_trait;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Prefix of the tool names under which templates are offered to the planner
+pub const MACRO_PREFIX: &str = "macro_";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TemplateParam {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// JSON Schema type of the value: string (default), integer, number or boolean
+    #[serde(default, rename = "type")]
+    pub kind: Option<String>,
+    #[serde(default = "default_required")]
+    pub required: bool,
+    #[serde(default)]
+    pub default: Option<Value>,
+}
+
+fn default_required() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkflowTemplate {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Slash-command that triggers the template, e.g. `/probe_push`
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Regexes matched against the whole input; named groups fill params
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    #[serde(default)]
+    pub params: Vec<TemplateParam>,
+    pub plan: WorkflowPlan,
+}
+
+impl WorkflowTemplate {
+    /// Parse a YAML template. It goes through a JSON value so step kinds are
+    /// written as plain keys (`- Tool: {...}`) like in JSON plans, rather
+    /// than as YAML tags.
+    pub fn from_yaml(text: &str) -> anyhow::Result<Self> {
+        let value: Value = serde_yaml::from_str(text)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    pub fn macro_name(&self) -> String {
+        format!("{}{}", MACRO_PREFIX, self.name)
+    }
+
+    /// Usage line shown when a slash-command is missing params
+    pub fn usage(&self) -> String {
+        let params = self
+            .params
+            .iter()
+            .map(|p| if p.required { format!("<{}>", p.name) } else { format!("[{}=...]", p.name) })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let command = self.command.clone().unwrap_or_else(|| format!("/{}", self.name));
+        format!("{} {}", command, params).trim_end().to_string()
+    }
+
+    /// JSON Schema of the params, used by the parameter resolver for the macro tool
+    pub fn params_schema(&self) -> Value {
+        let mut properties = serde_json::Map::new();
+        for p in &self.params {
+            let mut prop = serde_json::json!({
+                "type": p.kind.clone().unwrap_or_else(|| "string".into()),
+                "description": p.description,
+            });
+            if let Some(d) = &p.default {
+                prop["default"] = d.clone();
+            }
+            properties.insert(p.name.clone(), prop);
+        }
+        serde_json::json!({
+            "type": "object",
+            "properties": properties,
+            "required": self.required_params(),
+        })
+    }
+
+    fn required_params(&self) -> Vec<String> {
+        self.params
+            .iter()
+            .filter(|p| p.required && p.default.is_none())
+            .map(|p| p.name.clone())
+            .collect()
+    }
+
+    /// Substitute `params` into the plan. A string that is exactly
+    /// `{{name}}` takes the param value with its type; placeholders inside
+    /// longer strings are replaced by the value's text.
+    pub fn instantiate(&self, params: &Value) -> anyhow::Result<WorkflowPlan> {
+        let mut values = serde_json::Map::new();
+        let mut missing = Vec::new();
+        for p in &self.params {
+            match params.get(&p.name).filter(|v| !v.is_null()).or(p.default.as_ref()) {
+                Some(v) => {
+                    values.insert(p.name.clone(), coerce(v, p.kind.as_deref()));
+                }
+                None if p.required => missing.push(p.name.clone()),
+                None => {}
+            }
+        }
+        if !missing.is_empty() {
+            return Err(anyhow::anyhow!(
+                "模板 '{}' 缺少参数: {}。用法: {}",
+                self.name,
+                missing.join(", "),
+                self.usage()
+            ));
+        }
+        let plan = substitute(serde_json::to_value(&self.plan)?, &values);
+        Ok(serde_json::from_value(plan)?)
+    }
+
+    /// Params from a slash-command line: `key=value` tokens by name, the
+    /// remaining tokens positionally in declaration order
+    fn parse_command_args(&self, rest: &str) -> Value {
+        let mut values = serde_json::Map::new();
+        let mut positional = Vec::new();
+        for token in tokenize(rest) {
+            match token.split_once('=') {
+                Some((key, value)) if self.params.iter().any(|p| p.name == key) => {
+                    values.insert(key.to_string(), Value::String(value.to_string()));
+                }
+                _ => positional.push(token),
+            }
+        }
+        let mut positional = positional.into_iter();
+        for p in &self.params {
+            if values.contains_key(&p.name) {
+                continue;
+            }
+            match positional.next() {
+                Some(v) => {
+                    values.insert(p.name.clone(), Value::String(v));
+                }
+                None => break,
+            }
+        }
+        Value::Object(values)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.name.is_empty() || !self.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(anyhow::anyhow!("invalid template name '{}'", self.name));
+        }
+        let plan = serde_json::to_string(&self.plan)?;
+        let mut rest = plan.as_str();
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}") else { break };
+            let name = rest[start + 2..start + end].trim();
+            if !self.params.iter().any(|p| p.name == name) {
+                return Err(anyhow::anyhow!(
+                    "template '{}' uses undeclared placeholder '{{{{{}}}}}'",
+                    self.name,
+                    name
+                ));
+            }
+            rest = &rest[start + end + 2..];
+        }
+        Ok(())
+    }
+}
+
+/// A template chosen for an input, with the params taken from it
+pub struct TemplateMatch<'a> {
+    pub template: &'a WorkflowTemplate,
+    pub params: Value,
+}
+
+#[derive(Default)]
+pub struct TemplateLibrary {
+    templates: Vec<(WorkflowTemplate, Vec<Regex>)>,
+}
+
+impl TemplateLibrary {
+    pub fn new(templates: Vec<WorkflowTemplate>) -> anyhow::Result<Self> {
+        let mut library = Self::default();
+        for t in templates {
+            library.add(t)?;
+        }
+        Ok(library)
+    }
+
+    /// Load every `*.json`, `*.yaml` and `*.yml` file in `dir`. Invalid files
+    /// are logged and skipped; a missing directory gives an empty library.
+    pub fn load_dir(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        let mut library = Self::default();
+        if !dir.is_dir() {
+            info!("workflow template dir {:?} not found, no templates loaded", dir);
+            return Ok(library);
+        }
+        let mut paths = std::fs::read_dir(dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                matches!(
+                    p.extension().and_then(|e| e.to_str()),
+                    Some("json") | Some("yaml") | Some("yml")
+                )
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+        for path in paths {
+            match Self::read_template(&path).and_then(|t| library.add(t)) {
+                Ok(()) => info!("loaded workflow template from {:?}", path),
+                Err(e) => warn!("skipping workflow template {:?}: {}", path, e),
+            }
+        }
+        Ok(library)
+    }
+
+    fn read_template(path: &Path) -> anyhow::Result<WorkflowTemplate> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&text)?,
+            _ => WorkflowTemplate::from_yaml(&text)?,
+        })
+    }
+
+    fn add(&mut self, template: WorkflowTemplate) -> anyhow::Result<()> {
+        template.validate()?;
+        if self.get(&template.name).is_some() {
+            return Err(anyhow::anyhow!("duplicate template name '{}'", template.name));
+        }
+        let patterns = template
+            .patterns
+            .iter()
+            .map(|p| Regex::new(p))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.templates.push((template, patterns));
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&WorkflowTemplate> {
+        self.templates.iter().map(|(t, _)| t).find(|t| t.name == name)
+    }
+
+    /// Template behind a `macro_<name>` tool
+    pub fn by_macro(&self, tool: &str) -> Option<&WorkflowTemplate> {
+        tool.strip_prefix(MACRO_PREFIX).and_then(|name| self.get(name))
+    }
+
+    /// Template triggered by `input`: slash-commands first, then patterns
+    pub fn match_input(&self, input: &str) -> Option<TemplateMatch<'_>> {
+        let input = input.trim();
+        if input.starts_with('/') {
+            let (command, rest) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+            for (t, _) in &self.templates {
+                if t.command.as_deref() == Some(command) {
+                    return Some(TemplateMatch {
+                        template: t,
+                        params: t.parse_command_args(rest),
+                    });
+                }
+            }
+        }
+        for (t, patterns) in &self.templates {
+            for re in patterns {
+                if let Some(caps) = re.captures(input) {
+                    let params = re
+                        .capture_names()
+                        .flatten()
+                        .filter_map(|n| caps.name(n).map(|m| (n.to_string(), Value::String(m.as_str().to_string()))))
+                        .collect::<serde_json::Map<_, _>>();
+                    return Some(TemplateMatch {
+                        template: t,
+                        params: Value::Object(params),
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    /// One tool per template, for the planner's tool list
+    pub fn macro_tools(&self) -> Vec<ToolMeta> {
+        self.templates
+            .iter()
+            .map(|(t, _)| {
+                let steps = t
+                    .plan
+                    .steps
+                    .iter()
+                    .map(step_label)
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                ToolMeta {
+                    name: t.macro_name(),
+                    description: format!(
+                        "[macro] {} Runs the fixed workflow: {}. Prefer this over calling the steps one by one.",
+                        t.description, steps
+                    ),
+                    is_long_running: false,
+                    destructive: false,
+                }
+            })
+            .collect()
+    }
+}
+
+fn step_label(spec: &StepSpec) -> String {
+    match spec {
+        StepSpec::Tool { name, .. } => name.clone(),
+        StepSpec::Branch { .. } => "Branch".into(),
+        StepSpec::ForEach { .. } => "ForEach".into(),
+        StepSpec::Parallel { .. } => "Parallel".into(),
+        StepSpec::Memory => "Memory".into(),
+        StepSpec::Profile => "Profile".into(),
+        StepSpec::Relationship => "Relationship".into(),
+    }
+}
+
+/// Offers the templates of a `TemplateLibrary` as macro tools on top of
+/// another client. The macros themselves are run by the `WorkflowEngine`.
+pub struct TemplateMcpClient {
+    inner: Arc<dyn MCPClient + Send + Sync>,
+    library: Arc<TemplateLibrary>,
+}
+
+impl TemplateMcpClient {
+    pub fn new(inner: Arc<dyn MCPClient + Send + Sync>, library: Arc<TemplateLibrary>) -> Self {
+        Self { inner, library }
+    }
+}
+
+#[async_trait]
+impl MCPClient for TemplateMcpClient {
+    async fn call(&self, tool: &str, args: Value) -> anyhow::Result<Value> {
+        if self.library.by_macro(tool).is_some() {
+            return Err(anyhow::anyhow!(
+                "macro tool '{}' must be expanded by the workflow engine",
+                tool
+            ));
+        }
+        self.inner.call(tool, args).await
+    }
+
+    async fn list_tools(&self) -> anyhow::Result<Vec<ToolMeta>> {
+        let mut tools = self.inner.list_tools().await?;
+        tools.extend(self.library.macro_tools());
+        Ok(tools)
+    }
+
+    async fn required_fields(&self, tool: &str) -> anyhow::Result<Vec<String>> {
+        match self.library.by_macro(tool) {
+            Some(t) => Ok(t.required_params()),
+            None => self.inner.required_fields(tool).await,
+        }
+    }
+
+    async fn tool_schema(&self, tool: &str) -> anyhow::Result<Option<Value>> {
+        match self.library.by_macro(tool) {
+            Some(t) => Ok(Some(t.params_schema())),
+            None => self.inner.tool_schema(tool).await,
+        }
+    }
+
+    async fn elicit_preview(&self, tool: &str) -> anyhow::Result<Option<Value>> {
+        self.inner.elicit_preview(tool).await
+    }
+}
+
+/// Whitespace separated tokens; double quotes group words
+fn tokenize(s: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in s.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+/// Text params (from commands and regex captures) converted to the declared type
+fn coerce(value: &Value, kind: Option<&str>) -> Value {
+    let Value::String(s) = value else {
+        return value.clone();
+    };
+    let s = s.trim();
+    let parsed = match kind {
+        Some("integer") => s.parse::<i64>().ok().map(Value::from),
+        Some("number") => s.parse::<f64>().ok().map(Value::from),
+        Some("boolean") => s.parse::<bool>().ok().map(Value::from),
+        _ => None,
+    };
+    parsed.unwrap_or_else(|| value.clone())
+}
+
+fn substitute(value: Value, params: &serde_json::Map<String, Value>) -> Value {
+    match value {
+        Value::String(s) => {
+            let trimmed = s.trim();
+            if let Some(name) = trimmed.strip_prefix("{{").and_then(|r| r.strip_suffix("}}"))
+                && !name.contains("{{")
+                && let Some(v) = params.get(name.trim())
+            {
+                return v.clone();
+            }
+            let mut out = s;
+            for (name, v) in params {
+                let text = match v {
+                    Value::String(t) => t.clone(),
+                    other => other.to_string(),
+                };
+                out = out.replace(&format!("{{{{{}}}}}", name), &text);
+            }
+            Value::String(out)
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(|v| substitute(v, params)).collect()),
+        Value::Object(map) => Value::Object(map.into_iter().map(|(k, v)| (k, substitute(v, params))).collect()),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROBE_PUSH: &str = r#"
+name: probe_push
+description: Probe a video file, then push it to a device
+command: /probe_push
+patterns:
+  - '^(?:推流|push)\s+(?P<file_path>\S+)\s+(?:到|to)\s+(?P<ip>[\d.]+)$'
+params:
+  - name: file_path
+  - name: ip
+  - name: port
+    type: integer
+    default: 7000
+plan:
+  steps:
+    - Tool:
+        name: ffprobe_tool
+        args: { input: "file://{{file_path}}" }
+    - Tool:
+        name: pusher_tool
+        args: { file_path: "{{file_path}}", ip: "{{ip}}", port: "{{port}}" }
+"#;
+
+    #[test]
+    fn matches_commands_and_patterns_and_fills_placeholders() {
+        let template: WorkflowTemplate = WorkflowTemplate::from_yaml(PROBE_PUSH).unwrap();
+        let library = TemplateLibrary::new(vec![template]).unwrap();
+
+        let m = library.match_input("/probe_push /data/a.mp4 ip=10.0.0.5 port=9000").unwrap();
+        let plan = m.template.instantiate(&m.params).unwrap();
+        let StepSpec::Tool { args, .. } = &plan.steps[1] else { panic!("expected tool step") };
+        assert_eq!(args, &serde_json::json!({"file_path": "/data/a.mp4", "ip": "10.0.0.5", "port": 9000}));
+
+        let m = library.match_input("推流 /data/b.mp4 到 10.0.0.6").unwrap();
+        let plan = m.template.instantiate(&m.params).unwrap();
+        let StepSpec::Tool { args, .. } = &plan.steps[0] else { panic!("expected tool step") };
+        assert_eq!(args["input"], "file:///data/b.mp4");
+        let StepSpec::Tool { args, .. } = &plan.steps[1] else { panic!("expected tool step") };
+        assert_eq!(args["port"], 7000);
+
+        let m = library.match_input("/probe_push /data/c.mp4").unwrap();
+        assert!(m.template.instantiate(&m.params).is_err());
+        assert!(library.match_input("what's the weather").is_none());
+        assert_eq!(library.by_macro("macro_probe_push").map(|t| t.name.as_str()), Some("probe_push"));
+    }
+}
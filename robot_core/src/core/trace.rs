@@ -0,0 +1,379 @@
+//! Structured traces of handled inputs.
+//!
+//! While a session handles an input, everything worth debugging is recorded
+//! into a task-local [`Trace`]: perception, intent, the plan, every LLM
+//! exchange (through [`TracedLlm`]), every tool call (through [`TracedMcp`])
+//! and the outputs sent to the user. Finished traces go to a [`TraceSink`],
+//! usually a JSONL file with one trace per line, and can be re-run offline
+//! with [`crate::core::replay`].
+//!
+//! Work spawned onto other tasks (background tool steps) is not recorded.
+use crate::llm::adapter::{ChatOutput, ChatRequest, LLMClient};
+use crate::mcp::client::MCPClient;
+use crate::mcp::registry::ToolMeta;
+use crate::utils::{InputEvent, OutputEvent, WorkflowPlan};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TraceEvent {
+    Perception {
+        result: Value,
+    },
+    Intent {
+        decision: String,
+    },
+    /// The input was handled by a workflow template instead of the planner
+    Template {
+        name: String,
+    },
+    Plan {
+        plan: WorkflowPlan,
+    },
+    /// One LLM exchange; `label` names the component (planner, resolver, ...)
+    Llm {
+        label: String,
+        request: ChatRequest,
+        #[serde(default)]
+        response: Option<ChatOutput>,
+        #[serde(default)]
+        error: Option<String>,
+        duration_ms: u64,
+    },
+    ToolList {
+        tools: Vec<ToolMeta>,
+    },
+    ToolSchema {
+        tool: String,
+        schema: Option<Value>,
+    },
+    RequiredFields {
+        tool: String,
+        fields: Vec<String>,
+    },
+    ToolCall {
+        tool: String,
+        args: Value,
+        #[serde(default)]
+        result: Option<Value>,
+        #[serde(default)]
+        error: Option<String>,
+        duration_ms: u64,
+    },
+    Output {
+        event: OutputEvent,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// Milliseconds since the input was received
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub event: TraceEvent,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Trace {
+    pub trace_id: Uuid,
+    pub session_id: String,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub duration_ms: u64,
+    pub input: InputEvent,
+    pub events: Vec<TraceEntry>,
+}
+
+impl Trace {
+    pub fn events(&self) -> impl Iterator<Item = &TraceEvent> {
+        self.events.iter().map(|e| &e.event)
+    }
+
+    /// Text of every output sent to the user, in order
+    pub fn output_texts(&self) -> Vec<String> {
+        self.events()
+            .filter_map(|e| match e {
+                TraceEvent::Output { event } => Some(output_text(event)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// The user-visible text of an output (`text`, `content` or `message`)
+pub fn output_text(event: &OutputEvent) -> String {
+    ["text", "content", "message"]
+        .iter()
+        .find_map(|k| event.content.get(*k).and_then(|v| v.as_str()))
+        .map(String::from)
+        .unwrap_or_else(|| event.content.to_string())
+}
+
+struct Recorder {
+    started: Instant,
+    events: Mutex<Vec<TraceEntry>>,
+}
+
+tokio::task_local! {
+    static CURRENT: Arc<Recorder>;
+}
+
+/// Add `event` to the trace of the input being handled, if any
+pub fn record(event: TraceEvent) {
+    let _ = CURRENT.try_with(|r| {
+        let entry = TraceEntry {
+            at_ms: r.started.elapsed().as_millis() as u64,
+            event,
+        };
+        if let Ok(mut events) = r.events.lock() {
+            events.push(entry);
+        }
+    });
+}
+
+/// Whether a trace is being recorded on this task
+pub fn is_active() -> bool {
+    CURRENT.try_with(|_| ()).is_ok()
+}
+
+/// Run `fut` while recording a trace for `input`
+pub async fn capture<F: Future>(session_id: &str, input: &InputEvent, fut: F) -> (F::Output, Trace) {
+    let recorder = Arc::new(Recorder {
+        started: Instant::now(),
+        events: Mutex::new(Vec::new()),
+    });
+    let started_at = chrono::Utc::now();
+    let output = CURRENT.scope(recorder.clone(), fut).await;
+    let events = recorder
+        .events
+        .lock()
+        .map(|mut e| std::mem::take(&mut *e))
+        .unwrap_or_default();
+    let trace = Trace {
+        trace_id: Uuid::new_v4(),
+        session_id: session_id.to_string(),
+        started_at,
+        duration_ms: recorder.started.elapsed().as_millis() as u64,
+        input: input.clone(),
+        events,
+    };
+    (output, trace)
+}
+
+/// Destination of finished traces
+#[async_trait]
+pub trait TraceSink: Send + Sync {
+    async fn write(&self, trace: &Trace) -> anyhow::Result<()>;
+}
+
+/// Appends one JSON trace per line to a file
+pub struct JsonlTraceSink {
+    file: tokio::sync::Mutex<tokio::fs::File>,
+}
+
+impl JsonlTraceSink {
+    pub async fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(Self {
+            file: tokio::sync::Mutex::new(file),
+        })
+    }
+}
+
+#[async_trait]
+impl TraceSink for JsonlTraceSink {
+    async fn write(&self, trace: &Trace) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(trace)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+/// Writes every trace to each of its sinks
+pub struct TeeTraceSink {
+    sinks: Vec<Arc<dyn TraceSink>>,
+}
+
+impl TeeTraceSink {
+    pub fn new(sinks: Vec<Arc<dyn TraceSink>>) -> Self {
+        Self { sinks }
+    }
+}
+
+#[async_trait]
+impl TraceSink for TeeTraceSink {
+    async fn write(&self, trace: &Trace) -> anyhow::Result<()> {
+        // One failing sink does not keep the trace from the others
+        let mut failure = None;
+        for sink in &self.sinks {
+            if let Err(e) = sink.write(trace).await {
+                failure = Some(e);
+            }
+        }
+        failure.map_or(Ok(()), Err)
+    }
+}
+
+/// Keeps traces in memory, for replay and tests
+#[derive(Default)]
+pub struct MemoryTraceSink {
+    traces: Mutex<Vec<Trace>>,
+    written: tokio::sync::Notify,
+}
+
+impl MemoryTraceSink {
+    pub fn traces(&self) -> Vec<Trace> {
+        self.traces.lock().map(|t| t.clone()).unwrap_or_default()
+    }
+
+    /// Wait until at least `count` traces were written
+    pub async fn wait_for(&self, count: usize) -> Vec<Trace> {
+        loop {
+            let notified = self.written.notified();
+            let traces = self.traces();
+            if traces.len() >= count {
+                return traces;
+            }
+            notified.await;
+        }
+    }
+}
+
+#[async_trait]
+impl TraceSink for MemoryTraceSink {
+    async fn write(&self, trace: &Trace) -> anyhow::Result<()> {
+        if let Ok(mut traces) = self.traces.lock() {
+            traces.push(trace.clone());
+        }
+        self.written.notify_waiters();
+        Ok(())
+    }
+}
+
+/// Read a JSONL trace file written by [`JsonlTraceSink`]
+pub fn read_jsonl(path: impl AsRef<Path>) -> anyhow::Result<Vec<Trace>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| Ok(serde_json::from_str(l)?))
+        .collect()
+}
+
+/// Records the exchanges of an LLM client under `label`
+pub struct TracedLlm {
+    inner: Arc<dyn LLMClient + Send + Sync>,
+    label: String,
+}
+
+impl TracedLlm {
+    pub fn new(inner: Arc<dyn LLMClient + Send + Sync>, label: impl Into<String>) -> Self {
+        Self {
+            inner,
+            label: label.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl LLMClient for TracedLlm {
+    async fn chat(&self, req: ChatRequest) -> anyhow::Result<ChatOutput> {
+        if !is_active() {
+            return self.inner.chat(req).await;
+        }
+        let started = Instant::now();
+        let res = self.inner.chat(req.clone()).await;
+        record(TraceEvent::Llm {
+            label: self.label.clone(),
+            request: req,
+            response: res.as_ref().ok().cloned(),
+            error: res.as_ref().err().map(|e| e.to_string()),
+            duration_ms: started.elapsed().as_millis() as u64,
+        });
+        res
+    }
+}
+
+/// Records tool calls, and the tool list and schemas replay needs
+pub struct TracedMcp {
+    inner: Arc<dyn MCPClient + Send + Sync>,
+}
+
+impl TracedMcp {
+    pub fn new(inner: Arc<dyn MCPClient + Send + Sync>) -> Self {
+        Self { inner }
+    }
+}
+
+/// Whether the current trace already holds an event matching `pred`
+fn recorded(pred: impl Fn(&TraceEvent) -> bool) -> bool {
+    CURRENT
+        .try_with(|r| r.events.lock().map(|e| e.iter().any(|e| pred(&e.event))).unwrap_or(false))
+        .unwrap_or(false)
+}
+
+#[async_trait]
+impl MCPClient for TracedMcp {
+    async fn call(&self, tool: &str, args: Value) -> anyhow::Result<Value> {
+        if !is_active() {
+            return self.inner.call(tool, args).await;
+        }
+        let started = Instant::now();
+        let res = self.inner.call(tool, args.clone()).await;
+        record(TraceEvent::ToolCall {
+            tool: tool.to_string(),
+            args,
+            result: res.as_ref().ok().cloned(),
+            error: res.as_ref().err().map(|e| e.to_string()),
+            duration_ms: started.elapsed().as_millis() as u64,
+        });
+        res
+    }
+
+    async fn list_tools(&self) -> anyhow::Result<Vec<ToolMeta>> {
+        let tools = self.inner.list_tools().await?;
+        if is_active() && !recorded(|e| matches!(e, TraceEvent::ToolList { .. })) {
+            record(TraceEvent::ToolList { tools: tools.clone() });
+        }
+        Ok(tools)
+    }
+
+    async fn required_fields(&self, tool: &str) -> anyhow::Result<Vec<String>> {
+        let fields = self.inner.required_fields(tool).await?;
+        if is_active() && !recorded(|e| matches!(e, TraceEvent::RequiredFields { tool: t, .. } if t == tool)) {
+            record(TraceEvent::RequiredFields {
+                tool: tool.to_string(),
+                fields: fields.clone(),
+            });
+        }
+        Ok(fields)
+    }
+
+    async fn tool_schema(&self, tool: &str) -> anyhow::Result<Option<Value>> {
+        let schema = self.inner.tool_schema(tool).await?;
+        if is_active() && !recorded(|e| matches!(e, TraceEvent::ToolSchema { tool: t, .. } if t == tool)) {
+            record(TraceEvent::ToolSchema {
+                tool: tool.to_string(),
+                schema: schema.clone(),
+            });
+        }
+        Ok(schema)
+    }
+
+    async fn elicit_preview(&self, tool: &str) -> anyhow::Result<Option<Value>> {
+        self.inner.elicit_preview(tool).await
+    }
+}
@@ -0,0 +1,590 @@
+//! Content-addressed store for files uploaded by users.
+//!
+//! An upload is streamed to a temporary file while its SHA-256 (the address)
+//! and MD5 (what the browser checks before uploading) are computed, then moved
+//! to `<dir>/blobs/<sha256>`. Identical files are kept once; each records the
+//! users that uploaded it, which is what per-user quotas count. The registry
+//! is saved to `<dir>/registry.json`, so uploads survive a restart and other
+//! processes (the MCP server) can find them.
+//!
+//! Files are referred to as `upload://<sha256>` and reach the core as the
+//! [`Attachment`]s of an input. [`UploadStore::gc`] forgets files not used for
+//! the retention period and deletes blobs the registry does not know.
+use crate::core::auth::Identity;
+use crate::utils::Attachment;
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+pub const URI_SCHEME: &str = "upload://";
+
+/// Owner of uploads made while authentication is disabled
+const ANONYMOUS: &str = "anonymous";
+
+/// Temporary files older than this are from interrupted uploads
+const STALE_UPLOAD: Duration = Duration::from_secs(3600);
+
+#[derive(Clone, Debug)]
+pub struct UploadConfig {
+    pub dir: PathBuf,
+    /// Largest single file
+    pub max_file_size: u64,
+    /// Bytes each user may keep stored; `None` for no limit
+    pub quota_per_user: Option<u64>,
+    /// Files unused for this long are removed by [`UploadStore::gc`]
+    pub retention: Duration,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("uploads"),
+            max_file_size: 1024 * 1024 * 1024,
+            quota_per_user: None,
+            retention: Duration::from_secs(30 * 24 * 3600),
+        }
+    }
+}
+
+impl UploadConfig {
+    /// Defaults, overridden by `ROBOT_UPLOAD_DIR`, `ROBOT_UPLOAD_MAX_MB`,
+    /// `ROBOT_UPLOAD_QUOTA_MB` and `ROBOT_UPLOAD_RETENTION_DAYS`
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        if let Ok(dir) = std::env::var("ROBOT_UPLOAD_DIR") {
+            config.dir = PathBuf::from(dir);
+        }
+        let number = |var: &str| -> anyhow::Result<Option<u64>> {
+            match std::env::var(var) {
+                Ok(value) => value
+                    .trim()
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| anyhow::anyhow!("{}: invalid number '{}'", var, value)),
+                Err(_) => Ok(None),
+            }
+        };
+        if let Some(mb) = number("ROBOT_UPLOAD_MAX_MB")? {
+            config.max_file_size = mb * 1024 * 1024;
+        }
+        if let Some(mb) = number("ROBOT_UPLOAD_QUOTA_MB")? {
+            config.quota_per_user = Some(mb * 1024 * 1024);
+        }
+        if let Some(days) = number("ROBOT_UPLOAD_RETENTION_DAYS")? {
+            config.retention = Duration::from_secs(days * 24 * 3600);
+        }
+        Ok(config)
+    }
+}
+
+/// Why an upload was refused
+#[derive(Debug, PartialEq, Eq)]
+pub enum UploadError {
+    TooLarge { limit: u64 },
+    QuotaExceeded { quota: u64 },
+}
+
+impl std::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UploadError::TooLarge { limit } => write!(f, "file is larger than {} bytes", limit),
+            UploadError::QuotaExceeded { quota } => write!(f, "upload quota of {} bytes exceeded", quota),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredFile {
+    pub sha256: String,
+    pub md5: String,
+    /// Name of the first upload of the content
+    pub filename: String,
+    pub mime_type: String,
+    pub size: u64,
+    /// Users that uploaded the content
+    pub owners: BTreeSet<String>,
+    /// Milliseconds since the Unix epoch
+    pub uploaded_at: u64,
+    /// Last upload or reference from a message
+    pub last_used: u64,
+}
+
+impl StoredFile {
+    pub fn uri(&self) -> String {
+        format!("{}{}", URI_SCHEME, self.sha256)
+    }
+
+    /// The file as an MCP resource link, the form clients are shown attachments in
+    pub fn resource_link(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "resource_link",
+            "uri": self.uri(),
+            "name": self.filename,
+            "mimeType": self.mime_type,
+            "size": self.size,
+        })
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Registry {
+    files: HashMap<String, StoredFile>,
+}
+
+/// What one [`UploadStore::gc`] run removed
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GcReport {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+pub struct UploadStore {
+    config: UploadConfig,
+    registry: RwLock<Registry>,
+}
+
+impl UploadStore {
+    /// Open the store in `config.dir`, reading its registry if there is one.
+    /// Directories are created by the first upload.
+    pub async fn open(config: UploadConfig) -> anyhow::Result<Self> {
+        let path = config.dir.join("registry.json");
+        let registry = match tokio::fs::read(&path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| anyhow::anyhow!("invalid upload registry {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Registry::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            config,
+            registry: RwLock::new(registry),
+        })
+    }
+
+    pub fn config(&self) -> &UploadConfig {
+        &self.config
+    }
+
+    /// Start an upload of `filename` for `identity`; feed it with
+    /// [`Upload::write`] and store it with [`UploadStore::finish`]
+    pub async fn begin(&self, identity: Option<&Identity>, filename: &str) -> anyhow::Result<Upload> {
+        let owner = owner(identity);
+        let tmp_dir = self.config.dir.join("tmp");
+        tokio::fs::create_dir_all(&tmp_dir).await?;
+        let path = tmp_dir.join(Uuid::new_v4().to_string());
+        let file = tokio::fs::File::create(&path).await?;
+        let limit = match self.config.quota_per_user {
+            Some(quota) => {
+                let left = quota.saturating_sub(self.usage(&owner).await);
+                self.config.max_file_size.min(left)
+            }
+            None => self.config.max_file_size,
+        };
+        Ok(Upload {
+            owner,
+            filename: filename.to_string(),
+            path,
+            file,
+            sha256: Sha256::new(),
+            md5: Md5::new(),
+            size: 0,
+            head: Vec::new(),
+            limit,
+            quota: self.config.quota_per_user,
+            max_file_size: self.config.max_file_size,
+        })
+    }
+
+    /// Store a finished upload, or record its owner if the content is
+    /// already stored
+    pub async fn finish(&self, upload: Upload) -> anyhow::Result<StoredFile> {
+        let Upload {
+            owner,
+            filename,
+            path,
+            mut file,
+            sha256,
+            md5,
+            size,
+            head,
+            ..
+        } = upload;
+        file.flush().await?;
+        drop(file);
+        let sha256 = hex::encode(sha256.finalize());
+        let md5 = hex::encode(md5.finalize());
+
+        let blob = self.blob_path(&sha256);
+        let mut registry = self.registry.write().await;
+        if tokio::fs::try_exists(&blob).await.unwrap_or(false) {
+            tokio::fs::remove_file(&path).await?;
+        } else {
+            if let Some(parent) = blob.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::rename(&path, &blob).await?;
+        }
+
+        let now = now_ms();
+        let entry = registry.files.entry(sha256.clone()).or_insert_with(|| StoredFile {
+            sha256: sha256.clone(),
+            md5,
+            mime_type: sniff_mime(&head, &filename).to_string(),
+            filename: safe_name(&filename),
+            size,
+            owners: BTreeSet::new(),
+            uploaded_at: now,
+            last_used: now,
+        });
+        entry.owners.insert(owner);
+        entry.last_used = now;
+        let stored = entry.clone();
+        self.save(&registry).await?;
+        Ok(stored)
+    }
+
+    pub async fn get(&self, sha256: &str) -> Option<StoredFile> {
+        self.registry.read().await.files.get(sha256).cloned()
+    }
+
+    pub async fn find_md5(&self, md5: &str) -> Option<StoredFile> {
+        self.registry.read().await.files.values().find(|f| f.md5 == md5).cloned()
+    }
+
+    /// The stored file `uri` (`upload://<sha256>`) refers to
+    pub async fn resolve(&self, uri: &str) -> Option<StoredFile> {
+        self.get(uri.strip_prefix(URI_SCHEME)?).await
+    }
+
+    /// Record that `identity` uses an already stored file, as when the
+    /// browser skips uploading content the store has. Counts against the
+    /// quota like an upload.
+    pub async fn claim(&self, identity: Option<&Identity>, sha256: &str) -> anyhow::Result<Option<StoredFile>> {
+        let owner = owner(identity);
+        let usage = self.usage(&owner).await;
+        let mut registry = self.registry.write().await;
+        let Some(entry) = registry.files.get_mut(sha256) else {
+            return Ok(None);
+        };
+        if !entry.owners.contains(&owner) {
+            if let Some(quota) = self.config.quota_per_user
+                && usage + entry.size > quota
+            {
+                return Err(UploadError::QuotaExceeded { quota }.into());
+            }
+            entry.owners.insert(owner);
+        }
+        entry.last_used = now_ms();
+        let stored = entry.clone();
+        self.save(&registry).await?;
+        Ok(Some(stored))
+    }
+
+    /// Keep the files a message refers to from being collected
+    pub async fn touch(&self, sha256: &str) {
+        let mut registry = self.registry.write().await;
+        if let Some(entry) = registry.files.get_mut(sha256) {
+            entry.last_used = now_ms();
+            if let Err(e) = self.save(&registry).await {
+                warn!("failed to save upload registry: {}", e);
+            }
+        }
+    }
+
+    /// Whether `identity` may download the file
+    pub fn may_read(&self, file: &StoredFile, identity: Option<&Identity>) -> bool {
+        identity.is_none() || file.owners.contains(&owner(identity))
+    }
+
+    /// Bytes stored for `owner`
+    pub async fn usage(&self, owner: &str) -> u64 {
+        self.registry
+            .read()
+            .await
+            .files
+            .values()
+            .filter(|f| f.owners.contains(owner))
+            .map(|f| f.size)
+            .sum()
+    }
+
+    pub fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.config.dir.join("blobs").join(sha256)
+    }
+
+    /// `file` as attached to an input, with the absolute path of its content
+    pub fn attachment(&self, file: &StoredFile) -> Attachment {
+        let blob = self.blob_path(&file.sha256);
+        let path = std::path::absolute(&blob).unwrap_or(blob);
+        Attachment {
+            uri: file.uri(),
+            name: file.filename.clone(),
+            mime_type: file.mime_type.clone(),
+            size: file.size,
+            sha256: file.sha256.clone(),
+            path: Some(path.to_string_lossy().into_owned()),
+        }
+    }
+
+    /// Forget files unused for the retention period and delete blobs and
+    /// temporary files nothing refers to
+    pub async fn gc(&self) -> anyhow::Result<GcReport> {
+        let mut report = GcReport::default();
+        let mut registry = self.registry.write().await;
+        let oldest = now_ms().saturating_sub(self.config.retention.as_millis() as u64);
+        let expired: Vec<String> = registry
+            .files
+            .values()
+            .filter(|f| f.last_used < oldest)
+            .map(|f| f.sha256.clone())
+            .collect();
+        for sha256 in &expired {
+            registry.files.remove(sha256);
+        }
+        if !expired.is_empty() {
+            self.save(&registry).await?;
+        }
+
+        if let Ok(mut blobs) = tokio::fs::read_dir(self.config.dir.join("blobs")).await {
+            while let Some(entry) = blobs.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !registry.files.contains_key(&name) {
+                    report.bytes += entry.metadata().await.map(|m| m.len()).unwrap_or(0);
+                    report.files += 1;
+                    tokio::fs::remove_file(entry.path()).await?;
+                }
+            }
+        }
+        if let Ok(mut tmp) = tokio::fs::read_dir(self.config.dir.join("tmp")).await {
+            while let Some(entry) = tmp.next_entry().await? {
+                let stale = entry
+                    .metadata()
+                    .await
+                    .and_then(|m| m.modified())
+                    .is_ok_and(|at| at.elapsed().is_ok_and(|age| age > STALE_UPLOAD));
+                if stale {
+                    tokio::fs::remove_file(entry.path()).await?;
+                }
+            }
+        }
+        if report.files > 0 {
+            info!("upload gc removed {} files, {} bytes", report.files, report.bytes);
+        }
+        Ok(report)
+    }
+
+    /// Run [`UploadStore::gc`] every `every`
+    pub fn spawn_gc(self: Arc<Self>, every: Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.gc().await {
+                    warn!("upload gc failed: {}", e);
+                }
+            }
+        })
+    }
+
+    async fn save(&self, registry: &Registry) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.config.dir).await?;
+        let path = self.config.dir.join("registry.json");
+        let tmp = self.config.dir.join("registry.json.tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec_pretty(registry)?).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+}
+
+/// An upload in progress
+pub struct Upload {
+    owner: String,
+    filename: String,
+    path: PathBuf,
+    file: tokio::fs::File,
+    sha256: Sha256,
+    md5: Md5,
+    size: u64,
+    /// First bytes, for sniffing the MIME type
+    head: Vec<u8>,
+    /// Bytes this upload may have, the smaller of file size and quota left
+    limit: u64,
+    quota: Option<u64>,
+    max_file_size: u64,
+}
+
+impl Upload {
+    pub async fn write(&mut self, chunk: &[u8]) -> anyhow::Result<()> {
+        self.size += chunk.len() as u64;
+        if self.size > self.limit {
+            let _ = tokio::fs::remove_file(&self.path).await;
+            return Err(match self.quota {
+                Some(quota) if self.limit < self.max_file_size => UploadError::QuotaExceeded { quota },
+                _ => UploadError::TooLarge {
+                    limit: self.max_file_size,
+                },
+            }
+            .into());
+        }
+        if self.head.len() < 512 {
+            let take = (512 - self.head.len()).min(chunk.len());
+            self.head.extend_from_slice(&chunk[..take]);
+        }
+        self.sha256.update(chunk);
+        self.md5.update(chunk);
+        self.file.write_all(chunk).await?;
+        Ok(())
+    }
+}
+
+/// A prompt line per attachment, telling the model which URI to pass to
+/// tools that take a file
+pub fn describe_attachments(attachments: &[Attachment]) -> Option<String> {
+    if attachments.is_empty() {
+        return None;
+    }
+    let mut text = String::from("Attached files (MCP resources; pass the uri to tools that take a file):\n");
+    for file in attachments {
+        text.push_str(&format!("- {} ({}, {}, {} bytes)\n", file.uri, file.name, file.mime_type, file.size));
+    }
+    Some(text)
+}
+
+fn owner(identity: Option<&Identity>) -> String {
+    identity.map_or_else(|| ANONYMOUS.to_string(), |i| i.user_id.clone())
+}
+
+fn safe_name(filename: &str) -> String {
+    filename
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+/// MIME type from the first bytes of a file, then from its extension
+pub fn sniff_mime(head: &[u8], filename: &str) -> &'static str {
+    const MAGIC: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"OggS", "audio/ogg"),
+        (b"ID3", "audio/mpeg"),
+        (b"fLaC", "audio/flac"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+    ];
+    if let Some((_, mime)) = MAGIC.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return mime;
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" {
+        match &head[8..12] {
+            b"WEBP" => return "image/webp",
+            b"WAVE" => return "audio/wav",
+            b"AVI " => return "video/x-msvideo",
+            _ => {}
+        }
+    }
+    if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        return "video/mp4";
+    }
+
+    let extension = filename.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase());
+    let by_extension = match extension.as_deref() {
+        Some("json") => Some("application/json"),
+        Some("md") => Some("text/markdown"),
+        Some("csv") => Some("text/csv"),
+        Some("html" | "htm") => Some("text/html"),
+        Some("svg") => Some("image/svg+xml"),
+        _ => None,
+    };
+    // A cut-off multi-byte character at the end of the head is still text
+    let text = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    match (text, by_extension) {
+        (true, Some(mime)) => mime,
+        (true, None) => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::core::auth::AuthMethod;
+
+    fn user(id: &str) -> Identity {
+        Identity {
+            user_id: id.to_string(),
+            method: AuthMethod::ApiToken,
+        }
+    }
+
+    async fn upload(store: &UploadStore, who: &Identity, name: &str, data: &[u8]) -> anyhow::Result<StoredFile> {
+        let mut upload = store.begin(Some(who), name).await?;
+        for chunk in data.chunks(3) {
+            upload.write(chunk).await?;
+        }
+        store.finish(upload).await
+    }
+
+    #[tokio::test]
+    async fn dedups_by_content_and_enforces_quotas() {
+        let dir = std::env::temp_dir().join(format!("robot-uploads-{}", Uuid::new_v4()));
+        let config = UploadConfig {
+            dir: dir.clone(),
+            quota_per_user: Some(16),
+            ..UploadConfig::default()
+        };
+        let store = UploadStore::open(config.clone()).await.unwrap();
+        let (alice, bob) = (user("alice"), user("bob"));
+
+        let first = upload(&store, &alice, "a.png", b"\x89PNG\r\n\x1a\nabcd").await.unwrap();
+        assert_eq!(first.mime_type, "image/png");
+        assert_eq!(first.size, 12);
+        let again = upload(&store, &bob, "b.png", b"\x89PNG\r\n\x1a\nabcd").await.unwrap();
+        assert_eq!(again.sha256, first.sha256);
+        assert_eq!(again.owners.len(), 2);
+        assert!(store.blob_path(&first.sha256).exists());
+
+        // Alice has 4 of her 16 bytes left
+        let err = upload(&store, &alice, "c.txt", b"hello").await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<UploadError>(),
+            Some(&UploadError::QuotaExceeded { quota: 16 })
+        );
+
+        // The registry survives a restart; orphaned blobs are collected
+        tokio::fs::write(store.blob_path("orphan"), b"x").await.unwrap();
+        let store = UploadStore::open(config).await.unwrap();
+        let found = store.resolve(&first.uri()).await.unwrap();
+        assert_eq!(found.md5, first.md5);
+        assert!(store.may_read(&found, Some(&bob)));
+        assert!(!store.may_read(&found, Some(&user("eve"))));
+        assert_eq!(store.gc().await.unwrap(), GcReport { files: 1, bytes: 1 });
+        assert!(store.get(&first.sha256).await.is_some());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
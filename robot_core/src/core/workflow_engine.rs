@@ -1,23 +1,342 @@
+use crate::core::limits::LimitExceeded;
 use crate::core::output_handler::OutputHandler;
 use crate::core::persona::Persona;
+use crate::core::synthesis::{BasicResponseSynthesizer, ResponseSynthesizer, is_intermediate};
+use crate::core::templates::{TemplateLibrary, WorkflowTemplate};
 use crate::mcp::client::MCPClient;
-use crate::utils::WorkflowPlan;
-use crate::workflow_steps::{NoopResolver, ParameterResolver, StepResult, StepStatus, build_step};
+use crate::utils::{Context, OutputEvent, RecoveryPolicy, StepSpec, WorkflowPlan};
+use crate::workflow_steps::expr::{lookup, workflow_scope};
+use crate::workflow_steps::{
+    NoopResolver, PENDING_CONFIRMATION, ParameterResolver, StepResult, StepStatus, build_step,
+    current_step_path, resolve_args,
+};
+use async_recursion::async_recursion;
+use futures::future::join_all;
+use serde_json::Value;
 use std::sync::Arc;
-use tracing::info;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Result of running a step under its `RecoveryPolicy`
+pub enum StepOutcome {
+    /// The step (or its retry / substitute) succeeded
+    Done(StepResult),
+    /// The step failed and its policy says to continue without it
+    Skipped(anyhow::Error),
+    /// The step failed and its policy asks the DecisionEngine for a new plan
+    NeedsReplan(anyhow::Error),
+    /// The step failed and nothing else can be tried
+    Failed(anyhow::Error),
+}
 
 pub struct WorkflowEngine {
     pub resolver: Arc<dyn ParameterResolver + Send + Sync>,
+    pub synthesizer: Arc<dyn ResponseSynthesizer>,
+    pub templates: Arc<TemplateLibrary>,
 }
 
 impl WorkflowEngine {
     pub fn new() -> Self {
         Self {
             resolver: Arc::new(NoopResolver),
+            synthesizer: Arc::new(BasicResponseSynthesizer),
+            templates: Arc::new(TemplateLibrary::default()),
         }
     }
     pub fn new_with_resolver(resolver: Arc<dyn ParameterResolver + Send + Sync>) -> Self {
-        Self { resolver }
+        Self {
+            resolver,
+            synthesizer: Arc::new(BasicResponseSynthesizer),
+            templates: Arc::new(TemplateLibrary::default()),
+        }
+    }
+
+    /// Replace the synthesizer that turns finished plans into the final reply
+    pub fn with_synthesizer(mut self, synthesizer: Arc<dyn ResponseSynthesizer>) -> Self {
+        self.synthesizer = synthesizer;
+        self
+    }
+
+    /// Workflow templates triggered by commands and offered as macro tools
+    pub fn with_templates(mut self, templates: Arc<TemplateLibrary>) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    /// Build the final reply for a finished workflow, if any step produced a result
+    pub async fn synthesize(&self, ctx: &Context) -> Option<OutputEvent> {
+        let history = ctx.memory.get("workflow").and_then(|w| w.get("history"))?;
+        let text = match self
+            .synthesizer
+            .synthesize(&ctx.persona, &ctx.input_text, history, ctx.session_id.clone())
+            .await
+        {
+            Ok(Some(text)) => text,
+            Ok(None) => return None,
+            Err(e) => {
+                warn!("response synthesis failed: {}", e);
+                return None;
+            }
+        };
+        Some(OutputEvent {
+            target: "default".into(),
+            source: "system".into(),
+            session_id: ctx.session_id.clone(),
+            content: serde_json::json!({"type": "text", "text": text}),
+            style: ctx.persona.style.clone(),
+        })
+    }
+
+    /// Run a single step, applying the retry / skip / substitute part of its
+    /// recovery policy. Re-planning is left to the caller since it needs the
+    /// DecisionEngine. Control-flow steps run their children recursively.
+    #[async_recursion]
+    pub async fn run_step(&self, spec: &StepSpec, ctx: &mut Context, mcp: &dyn MCPClient) -> StepOutcome {
+        // A resumed workflow runs the composite step that waited once more;
+        // its children that already finished keep their recorded result
+        if let Some(path) = current_step_path(ctx)
+            && has_run(ctx, &path)
+        {
+            info!("step {} already ran, not running it again", path);
+            return StepOutcome::Done(StepResult { status: StepStatus::Continue, output: None });
+        }
+        if let StepSpec::Tool { name, args, .. } = spec
+            && let Some(template) = self.templates.by_macro(name)
+        {
+            return self.run_macro(template, name, args, ctx, mcp).await;
+        }
+        match spec {
+            StepSpec::Branch { condition, then, otherwise } => {
+                let scope = workflow_scope(ctx);
+                let (label, children) = match condition.evaluate(&scope) {
+                    Ok(true) => ("then", then),
+                    Ok(false) => ("otherwise", otherwise),
+                    Err(e) => return StepOutcome::Failed(e),
+                };
+                info!("branch condition {:?} took '{}'", condition, label);
+                let prefix = format!("{}/{}/", step_path(ctx), label);
+                let outcome = self.run_children(children, label, ctx, mcp).await;
+                if waits(&outcome) {
+                    return outcome;
+                }
+                let result = child_results(ctx, &prefix).pop().unwrap_or(Value::Null);
+                record_control(ctx, "Branch", serde_json::json!({"branch": label, "result": result}));
+                return outcome;
+            }
+            StepSpec::ForEach { items, body } => {
+                let scope = workflow_scope(ctx);
+                let elements = match foreach_items(&scope, items) {
+                    Ok(v) => v,
+                    Err(e) => return StepOutcome::Failed(e),
+                };
+                info!("for_each over {} items from {}", elements.len(), items);
+                let saved = (workflow_get(ctx, "item"), workflow_get(ctx, "item_index"));
+                let mut results = Vec::new();
+                let mut outcome = StepOutcome::Done(StepResult { status: StepStatus::Continue, output: None });
+                for (k, element) in elements.into_iter().enumerate() {
+                    workflow_set(ctx, "item", Some(element.clone()));
+                    workflow_set(ctx, "item_index", Some(serde_json::json!(k)));
+                    let children: Vec<StepSpec> = body.iter().map(|c| bind_item(c, &element)).collect();
+                    let label = format!("item[{}]", k);
+                    let prefix = format!("{}/{}/", step_path(ctx), label);
+                    outcome = self.run_children(&children, &label, ctx, mcp).await;
+                    results.push(child_results(ctx, &prefix).pop().unwrap_or(Value::Null));
+                    if !matches!(&outcome, StepOutcome::Done(r) if matches!(r.status, StepStatus::Continue)) {
+                        break;
+                    }
+                }
+                workflow_set(ctx, "item", saved.0);
+                workflow_set(ctx, "item_index", saved.1);
+                if waits(&outcome) {
+                    return outcome;
+                }
+                record_control(ctx, "ForEach", Value::Array(results));
+                return outcome;
+            }
+            StepSpec::Parallel { steps } => {
+                let base = step_path(ctx);
+                let history_len = history_len(ctx);
+                let runs = steps.iter().enumerate().map(|(j, child)| {
+                    let mut child_ctx = ctx.clone();
+                    workflow_set(&mut child_ctx, "current_step_path", Some(Value::String(format!("{}/{}", base, j))));
+                    async move {
+                        let outcome = self.run_step(child, &mut child_ctx, mcp).await;
+                        (outcome, child_ctx)
+                    }
+                });
+                let finished = join_all(runs).await;
+
+                let mut results = Vec::new();
+                let mut first_failure = None;
+                let mut waiting = None;
+                // Every child started from the parent's pending confirmation;
+                // only one that waits again hands its own back
+                if let Some(map) = ctx.memory.as_object_mut() {
+                    map.remove(PENDING_CONFIRMATION);
+                }
+                for (j, (outcome, child_ctx)) in finished.into_iter().enumerate() {
+                    let new_entries = child_ctx.memory["workflow"]["history"]
+                        .as_array()
+                        .map(|h| h.iter().skip(history_len).cloned().collect::<Vec<_>>())
+                        .unwrap_or_default();
+                    append_history(ctx, new_entries);
+                    results.push(step_result(&child_ctx, &format!("{}/{}", base, j)).unwrap_or(Value::Null));
+                    match outcome {
+                        StepOutcome::Done(res) if matches!(res.status, StepStatus::WaitUser(_)) => {
+                            if waiting.is_none() {
+                                if let Some(pending) = child_ctx.memory.get(PENDING_CONFIRMATION).cloned()
+                                    && let Some(map) = ctx.memory.as_object_mut()
+                                {
+                                    map.insert(PENDING_CONFIRMATION.to_string(), pending);
+                                }
+                                waiting = Some(res);
+                            }
+                        }
+                        StepOutcome::Done(_) | StepOutcome::Skipped(_) => {}
+                        failure => {
+                            if first_failure.is_none() {
+                                first_failure = Some(failure);
+                            }
+                        }
+                    }
+                }
+                if let Some(failure) = first_failure {
+                    record_control(ctx, "Parallel", Value::Array(results));
+                    return failure;
+                }
+                // The block runs again once the user answered
+                if let Some(res) = waiting {
+                    return StepOutcome::Done(res);
+                }
+                record_control(ctx, "Parallel", Value::Array(results));
+                return StepOutcome::Done(StepResult {
+                    status: StepStatus::Continue,
+                    output: None,
+                });
+            }
+            _ => {}
+        }
+
+        let step = build_step(spec, self.resolver.clone());
+        let err = match step.run(ctx, mcp).await {
+            Ok(res) => return StepOutcome::Done(res),
+            Err(e) => e,
+        };
+        warn!("workflow step failed: {}", err);
+        // Retrying or replanning only runs into the same limit
+        if err.downcast_ref::<LimitExceeded>().is_some() {
+            return StepOutcome::Failed(err);
+        }
+
+        let policy = match spec {
+            StepSpec::Tool { on_error, .. } => on_error.clone(),
+            _ => RecoveryPolicy::Abort,
+        };
+        match policy {
+            RecoveryPolicy::Retry { max_attempts, backoff_ms } => {
+                let mut last_err = err;
+                // The first run counts as an attempt
+                for attempt in 1..max_attempts {
+                    let delay = backoff_ms.saturating_mul(1u64 << (attempt - 1).min(16));
+                    info!("retrying step (attempt {}/{}) after {}ms", attempt + 1, max_attempts, delay);
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                    match step.run(ctx, mcp).await {
+                        Ok(res) => return StepOutcome::Done(res),
+                        Err(e) if e.downcast_ref::<LimitExceeded>().is_some() => return StepOutcome::Failed(e),
+                        Err(e) => {
+                            warn!("retry {} failed: {}", attempt + 1, e);
+                            last_err = e;
+                        }
+                    }
+                }
+                StepOutcome::Failed(last_err)
+            }
+            RecoveryPolicy::Skip => StepOutcome::Skipped(err),
+            RecoveryPolicy::Substitute { tool } => {
+                let StepSpec::Tool { args, is_background, dependencies, .. } = spec else {
+                    return StepOutcome::Failed(err);
+                };
+                info!("substituting failed step with tool '{}'", tool);
+                let alt = StepSpec::Tool {
+                    name: tool,
+                    args: args.clone(),
+                    is_background: *is_background,
+                    dependencies: dependencies.clone(),
+                    on_error: RecoveryPolicy::Abort,
+                };
+                match build_step(&alt, self.resolver.clone()).run(ctx, mcp).await {
+                    Ok(res) => StepOutcome::Done(res),
+                    Err(e) => StepOutcome::Failed(e),
+                }
+            }
+            RecoveryPolicy::Replan => StepOutcome::NeedsReplan(err),
+            RecoveryPolicy::Abort => StepOutcome::Failed(err),
+        }
+    }
+
+    /// Expand a `macro_<name>` tool call into the steps of its template. The
+    /// params are resolved like tool args, against the template's schema.
+    async fn run_macro(
+        &self,
+        template: &WorkflowTemplate,
+        tool: &str,
+        args: &Value,
+        ctx: &mut Context,
+        mcp: &dyn MCPClient,
+    ) -> StepOutcome {
+        let params = match resolve_args(&*self.resolver, mcp, tool, args, ctx).await {
+            Ok(p) => p,
+            Err(e) => return StepOutcome::Failed(e),
+        };
+        let plan = match template.instantiate(&params) {
+            Ok(plan) => plan,
+            Err(e) => return StepOutcome::Failed(e),
+        };
+        info!("expanding macro '{}' into {} steps", tool, plan.steps.len());
+        let prefix = format!("{}/macro/", step_path(ctx));
+        let saved = workflow_get(ctx, "scope_prefix");
+        workflow_set(ctx, "scope_prefix", Some(Value::String(prefix.clone())));
+        let outcome = self.run_children(&plan.steps, "macro", ctx, mcp).await;
+        workflow_set(ctx, "scope_prefix", saved);
+        if waits(&outcome) {
+            return outcome;
+        }
+        let result = child_results(ctx, &prefix).pop().unwrap_or(Value::Null);
+        record_control(ctx, "Macro", serde_json::json!({"template": template.name, "params": params, "result": result}));
+        outcome
+    }
+
+    /// Run `children` in order, each tagged with a `current_step_path` below
+    /// the parent's. Stops at the first child that does not continue.
+    async fn run_children(
+        &self,
+        children: &[StepSpec],
+        label: &str,
+        ctx: &mut Context,
+        mcp: &dyn MCPClient,
+    ) -> StepOutcome {
+        let base = step_path(ctx);
+        let saved = workflow_get(ctx, "current_step_path");
+        let mut last = StepResult {
+            status: StepStatus::Continue,
+            output: None,
+        };
+        for (j, child) in children.iter().enumerate() {
+            workflow_set(ctx, "current_step_path", Some(Value::String(format!("{}/{}/{}", base, label, j))));
+            let outcome = self.run_step(child, ctx, mcp).await;
+            workflow_set(ctx, "current_step_path", saved.clone());
+            match outcome {
+                StepOutcome::Done(res) => {
+                    if !matches!(res.status, StepStatus::Continue) {
+                        return StepOutcome::Done(res);
+                    }
+                    last = res;
+                }
+                StepOutcome::Skipped(e) => info!("nested step skipped after error: {}", e),
+                other => return other,
+            }
+        }
+        StepOutcome::Done(last)
     }
 
     pub async fn execute_simple(
@@ -39,6 +358,7 @@ impl WorkflowEngine {
             }
         });
         
+        let mut waiting = false;
         for (i, spec) in plan.steps.iter().enumerate() {
             // Update current step index in memory
             if let Some(workflow) = ctx.memory.get_mut("workflow") {
@@ -48,8 +368,14 @@ impl WorkflowEngine {
             }
 
             info!("workflow step start: {:?}", spec);
-            let step = build_step(spec, self.resolver.clone());
-            let res: StepResult = step.run(&mut ctx, mcp).await?;
+            let res: StepResult = match self.run_step(spec, &mut ctx, mcp).await {
+                StepOutcome::Done(res) => res,
+                StepOutcome::Skipped(e) => {
+                    info!("workflow step skipped after error: {}", e);
+                    continue;
+                }
+                StepOutcome::NeedsReplan(e) | StepOutcome::Failed(e) => return Err(e),
+            };
             if let Some(mut o) = res.output {
                 o.source = input_source.clone();
 
@@ -57,8 +383,11 @@ impl WorkflowEngine {
                     "workflow step produced output, dispatching to {} handlers",
                     outputs.len()
                 );
+                let intermediate = is_intermediate(&o);
                 for h in outputs {
-                    h.emit(o.clone()).await?;
+                    if !intermediate || h.show_intermediate() {
+                        h.emit(o.clone()).await?;
+                    }
                 }
             }
             match res.status {
@@ -68,6 +397,7 @@ impl WorkflowEngine {
                 }
                 StepStatus::WaitUser(prompt) => {
                     info!("workflow step requests user input: {}", prompt);
+                    waiting = true;
                     // Emit prompt
                     let output = crate::utils::OutputEvent {
                         target: "default".into(),
@@ -85,7 +415,410 @@ impl WorkflowEngine {
             }
             info!("workflow step done: {:?}", spec);
         }
+        if !waiting && let Some(mut reply) = self.synthesize(&ctx).await {
+            reply.source = input_source.clone();
+            for h in outputs {
+                h.emit(reply.clone()).await?;
+            }
+        }
         info!("workflow execute complete");
         Ok(())
     }
 }
+
+fn workflow_get(ctx: &Context, key: &str) -> Option<Value> {
+    ctx.memory.get("workflow").and_then(|w| w.get(key)).cloned()
+}
+
+fn workflow_set(ctx: &mut Context, key: &str, value: Option<Value>) {
+    let Some(workflow) = ctx.memory.get_mut("workflow").and_then(|w| w.as_object_mut()) else {
+        return;
+    };
+    match value {
+        Some(v) => {
+            workflow.insert(key.to_string(), v);
+        }
+        None => {
+            workflow.remove(key);
+        }
+    }
+}
+
+/// Path of the step currently running: its nested path, or its top-level index
+fn step_path(ctx: &Context) -> String {
+    current_step_path(ctx).unwrap_or_else(|| {
+        workflow_get(ctx, "current_step_index")
+            .and_then(|v| v.as_u64())
+            .unwrap_or_default()
+            .to_string()
+    })
+}
+
+fn history_len(ctx: &Context) -> usize {
+    ctx.memory["workflow"]["history"]
+        .as_array()
+        .map(|h| h.len())
+        .unwrap_or(0)
+}
+
+/// Whether the step at nested `path` already has an entry in the history
+fn has_run(ctx: &Context, path: &str) -> bool {
+    ctx.memory["workflow"]["history"]
+        .as_array()
+        .is_some_and(|h| h.iter().any(|e| e.get("path").and_then(|p| p.as_str()) == Some(path)))
+}
+
+/// Whether a step stopped to wait for the user
+fn waits(outcome: &StepOutcome) -> bool {
+    matches!(outcome, StepOutcome::Done(res) if matches!(res.status, StepStatus::WaitUser(_)))
+}
+
+/// Latest successful result of the step at nested `path`
+fn step_result(ctx: &Context, path: &str) -> Option<Value> {
+    ctx.memory["workflow"]["history"]
+        .as_array()?
+        .iter()
+        .rev()
+        .filter(|e| e.get("error").is_none())
+        .find(|e| e.get("path").and_then(|p| p.as_str()) == Some(path))
+        .and_then(|e| e.get("result").cloned())
+}
+
+/// Successful results of the direct children below `prefix` (paths
+/// `<prefix><n>`), including nested control-flow summaries. Children that
+/// finished before the workflow waited for the user count as well.
+fn child_results(ctx: &Context, prefix: &str) -> Vec<Value> {
+    let is_direct_child = |e: &Value| {
+        e.get("path")
+            .and_then(|p| p.as_str())
+            .and_then(|p| p.strip_prefix(prefix))
+            .is_some_and(|rest| !rest.contains('/'))
+    };
+    ctx.memory["workflow"]["history"]
+        .as_array()
+        .map(|h| {
+            h.iter()
+                .filter(|e| e.get("error").is_none() && is_direct_child(e))
+                .filter_map(|e| e.get("result").cloned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn append_history(ctx: &mut Context, entries: Vec<Value>) {
+    let Some(workflow) = ctx.memory.get_mut("workflow").and_then(|w| w.as_object_mut()) else {
+        return;
+    };
+    let history = workflow
+        .entry("history")
+        .or_insert_with(|| Value::Array(Vec::new()));
+    if let Some(arr) = history.as_array_mut() {
+        arr.extend(entries);
+    }
+}
+
+/// Record the combined result of a control-flow step so later steps can
+/// address it as `steps[N]`
+fn record_control(ctx: &mut Context, kind: &str, result: Value) {
+    let mut entry = serde_json::json!({
+        "step_index": workflow_get(ctx, "current_step_index").unwrap_or(Value::Null),
+        "control": kind,
+        "result": result,
+    });
+    if let Some(path) = current_step_path(ctx) {
+        entry["path"] = Value::String(path);
+    }
+    append_history(ctx, vec![entry]);
+}
+
+/// Elements of the array at `path`. Text holding a JSON array is parsed,
+/// other text is split into non-empty lines.
+fn foreach_items(scope: &Value, path: &str) -> anyhow::Result<Vec<Value>> {
+    match lookup(scope, path)? {
+        Some(Value::Array(items)) => Ok(items),
+        Some(Value::String(s)) => match serde_json::from_str::<Vec<Value>>(&s) {
+            Ok(items) => Ok(items),
+            Err(_) => Ok(s
+                .lines()
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty())
+                .map(|l| Value::String(l.to_string()))
+                .collect()),
+        },
+        Some(other) => Err(anyhow::anyhow!("ForEach items at '{}' is not an array: {}", path, other)),
+        None => Err(anyhow::anyhow!("ForEach items not found at '{}'", path)),
+    }
+}
+
+/// Tool steps without explicit args receive the current element as input
+fn bind_item(spec: &StepSpec, item: &Value) -> StepSpec {
+    match spec {
+        StepSpec::Tool { name, args, is_background, dependencies, on_error } if args.is_null() => {
+            StepSpec::Tool {
+                name: name.clone(),
+                args: item.clone(),
+                is_background: *is_background,
+                dependencies: dependencies.clone(),
+                on_error: on_error.clone(),
+            }
+        }
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::registry::ToolMeta;
+    use async_trait::async_trait;
+
+    /// Echoes `args.text` back, or lists two files for `ls`
+    struct EchoMcp;
+
+    #[async_trait]
+    impl MCPClient for EchoMcp {
+        async fn call(&self, tool: &str, args: Value) -> anyhow::Result<Value> {
+            let text = match tool {
+                "ls" => "[\"a.mp4\", \"b.mp4\"]".to_string(),
+                _ => args["text"].as_str().unwrap_or_default().to_string(),
+            };
+            Ok(serde_json::json!({"content": [{"type": "text", "text": text}]}))
+        }
+        async fn list_tools(&self) -> anyhow::Result<Vec<ToolMeta>> {
+            Ok(Vec::new())
+        }
+        async fn required_fields(&self, _tool: &str) -> anyhow::Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+        async fn tool_schema(&self, _tool: &str) -> anyhow::Result<Option<Value>> {
+            Ok(None)
+        }
+    }
+
+    /// `broken` always fails, `backup` and `echo` answer
+    fn flaky_mcp() -> crate::testkit::FakeMcp {
+        use crate::testkit::{FakeMcp, FakeTool};
+        FakeMcp::new()
+            .tool(FakeTool::new("broken", "Fails").handler(|_| Err(anyhow::anyhow!("connection refused"))))
+            .tool(FakeTool::new("backup", "Works instead").returns("backed up"))
+            .tool(FakeTool::new("echo", "Echo").returns("echoed"))
+    }
+
+    fn broken_step(on_error: Value) -> StepSpec {
+        serde_json::from_value(serde_json::json!(
+            {"Tool": {"name": "broken", "args": {"text": "hi"}, "on_error": on_error}}
+        ))
+        .unwrap()
+    }
+
+    fn workflow_ctx() -> Context {
+        let mut ctx = Context::new(Persona::default(), "do it".into(), None);
+        ctx.memory = serde_json::json!({"workflow": {"current_step_index": 0}});
+        ctx
+    }
+
+    #[tokio::test]
+    async fn retry_runs_the_step_max_attempts_times() {
+        let mcp = flaky_mcp();
+        let spec = broken_step(serde_json::json!({"Retry": {"max_attempts": 3, "backoff_ms": 1}}));
+        let outcome = WorkflowEngine::new().run_step(&spec, &mut workflow_ctx(), &mcp).await;
+        assert!(matches!(outcome, StepOutcome::Failed(ref e) if e.to_string().contains("connection refused")));
+        assert_eq!(mcp.calls_to("broken").len(), 3);
+    }
+
+    #[tokio::test]
+    async fn skip_goes_on_with_the_next_step() {
+        let mcp = flaky_mcp();
+        let plan = WorkflowPlan {
+            steps: vec![
+                broken_step(serde_json::json!("Skip")),
+                serde_json::from_value(serde_json::json!({"Tool": {"name": "echo", "args": {"text": "next"}}})).unwrap(),
+            ],
+            reasoning: None,
+        };
+        WorkflowEngine::new()
+            .execute_simple(plan, &Persona::default(), &mcp, &[], "do it".into(), "test".into())
+            .await
+            .unwrap();
+        assert_eq!(mcp.calls_to("broken").len(), 1);
+        assert_eq!(mcp.calls_to("echo").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn substitute_calls_the_other_tool_with_the_same_args() {
+        let mcp = flaky_mcp();
+        let spec = broken_step(serde_json::json!({"Substitute": {"tool": "backup"}}));
+        let mut ctx = workflow_ctx();
+        let outcome = WorkflowEngine::new().run_step(&spec, &mut ctx, &mcp).await;
+        assert!(matches!(outcome, StepOutcome::Done(ref r) if matches!(r.status, StepStatus::Continue)));
+        assert_eq!(mcp.calls_to("broken").len(), 1);
+        let backup = mcp.calls_to("backup");
+        assert_eq!(backup.len(), 1);
+        assert_eq!(backup[0].args, serde_json::json!({"text": "hi"}));
+        let last = ctx.memory["workflow"]["history"].as_array().unwrap().last().cloned().unwrap();
+        assert_eq!(last["tool"], "backup");
+    }
+
+    #[tokio::test]
+    async fn replan_and_abort_are_left_to_the_caller() {
+        let mcp = flaky_mcp();
+        let engine = WorkflowEngine::new();
+        let outcome = engine.run_step(&broken_step(serde_json::json!("Replan")), &mut workflow_ctx(), &mcp).await;
+        assert!(matches!(outcome, StepOutcome::NeedsReplan(_)));
+        let outcome = engine.run_step(&broken_step(serde_json::json!("Abort")), &mut workflow_ctx(), &mcp).await;
+        assert!(matches!(outcome, StepOutcome::Failed(_)));
+        assert_eq!(mcp.calls_to("broken").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn runs_branch_foreach_and_parallel_steps() {
+        let plan: WorkflowPlan = serde_json::from_value(serde_json::json!({
+            "steps": [
+                {"Tool": {"name": "ls", "args": {}}},
+                {"ForEach": {"items": "steps[0].content[0].text", "body": [
+                    {"Tool": {"name": "echo", "args": {"text": "probe"}}}
+                ]}},
+                {"Branch": {
+                    "condition": {"Expr": "steps[1] contains \"x\""},
+                    "then": [{"Tool": {"name": "echo", "args": {"text": "unexpected"}}}],
+                    "otherwise": [{"Parallel": {"steps": [
+                        {"Tool": {"name": "echo", "args": {"text": "left"}}},
+                        {"Tool": {"name": "echo", "args": {"text": "right"}}}
+                    ]}}]
+                }}
+            ]
+        }))
+        .unwrap();
+
+        let mut ctx = Context::new(Persona::default(), "probe all files".into(), None);
+        ctx.memory = serde_json::json!({"workflow": {"plan": plan.clone(), "current_step_index": 0}});
+        let engine = WorkflowEngine::new();
+        for (i, spec) in plan.steps.iter().enumerate() {
+            workflow_set(&mut ctx, "current_step_index", Some(serde_json::json!(i)));
+            assert!(matches!(engine.run_step(spec, &mut ctx, &EchoMcp).await, StepOutcome::Done(_)));
+        }
+
+        let scope = workflow_scope(&ctx);
+        assert_eq!(scope["steps"][1].as_array().map(|a| a.len()), Some(2));
+        assert_eq!(scope["steps"][2]["branch"], "otherwise");
+        let texts: Vec<String> = scope["steps"][2]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(crate::core::synthesis::result_text)
+            .collect();
+        assert_eq!(texts, vec!["left", "right"]);
+    }
+
+    #[tokio::test]
+    async fn expands_macro_tools_into_template_steps() {
+        let template: WorkflowTemplate = serde_json::from_value(serde_json::json!({
+            "name": "greet",
+            "params": [{"name": "who"}],
+            "plan": {"steps": [
+                {"Tool": {"name": "echo", "args": {"text": "hello {{who}}"}}},
+                {"Branch": {
+                    "condition": {"Expr": "steps[0].content[0].text contains \"world\""},
+                    "then": [{"Tool": {"name": "echo", "args": {"text": "matched"}}}]
+                }}
+            ]}
+        }))
+        .unwrap();
+        let engine = WorkflowEngine::new()
+            .with_templates(Arc::new(TemplateLibrary::new(vec![template]).unwrap()));
+        let spec = StepSpec::Tool {
+            name: "macro_greet".into(),
+            args: serde_json::json!({"who": "world"}),
+            is_background: false,
+            dependencies: vec![],
+            on_error: RecoveryPolicy::Abort,
+        };
+
+        let mut ctx = Context::new(Persona::default(), "greet the world".into(), None);
+        ctx.memory = serde_json::json!({"workflow": {"current_step_index": 0}});
+        assert!(matches!(engine.run_step(&spec, &mut ctx, &EchoMcp).await, StepOutcome::Done(_)));
+
+        let scope = workflow_scope(&ctx);
+        assert_eq!(scope["steps"][0]["template"], "greet");
+        assert_eq!(scope["steps"][0]["result"]["branch"], "then");
+        assert!(workflow_get(&ctx, "scope_prefix").is_none());
+    }
+
+    #[tokio::test]
+    async fn parallel_blocks_wait_for_confirmation_of_their_children() {
+        use crate::core::policy::{Policy, PolicyMcpClient};
+        use crate::testkit::{FakeMcp, FakeTool};
+        let fake = FakeMcp::new()
+            .tool(FakeTool::new("echo", "Echo").returns("left"))
+            .tool(FakeTool::new("rm", "Delete a file").destructive().returns("removed"));
+        let mcp = PolicyMcpClient::new(Arc::new(fake.clone()), Arc::new(Policy::allow_all()));
+        let spec: StepSpec = serde_json::from_value(serde_json::json!(
+            {"Parallel": {"steps": [
+                {"Tool": {"name": "echo", "args": {"text": "left"}}},
+                {"Tool": {"name": "rm", "args": {"path": "/tmp/old"}}}
+            ]}}
+        ))
+        .unwrap();
+        let engine = WorkflowEngine::new();
+        let mut ctx = Context::new(Persona::default(), "clean up".into(), None);
+        ctx.memory = serde_json::json!({"workflow": {"current_step_index": 0}});
+
+        let StepOutcome::Done(res) = engine.run_step(&spec, &mut ctx, &mcp).await else {
+            panic!("the block should wait");
+        };
+        assert!(matches!(res.status, StepStatus::WaitUser(ref p) if p.contains("'rm'")));
+        let pending = ctx.memory[PENDING_CONFIRMATION].clone();
+        assert_eq!((pending["tool"].as_str(), pending["step"].as_str()), (Some("rm"), Some("0/1")));
+        assert!(fake.calls_to("rm").is_empty());
+
+        mcp.approve("rm", Some(pending["args"].clone()));
+        let StepOutcome::Done(res) = engine.run_step(&spec, &mut ctx, &mcp).await else {
+            panic!("the approved block should finish");
+        };
+        assert!(matches!(res.status, StepStatus::Continue));
+        assert_eq!(fake.calls_to("rm").len(), 1);
+        assert_eq!(fake.calls_to("echo").len(), 1);
+        assert!(ctx.memory.get(PENDING_CONFIRMATION).is_none());
+    }
+
+    #[tokio::test]
+    async fn resumed_for_each_does_not_repeat_finished_items() {
+        use crate::core::policy::{Policy, PolicyMcpClient};
+        use crate::testkit::{FakeMcp, FakeTool};
+        let fake = FakeMcp::new()
+            .tool(FakeTool::new("echo", "Echo").returns("seen"))
+            .tool(FakeTool::new("rm", "Delete a file").destructive().returns("removed"));
+        let mcp = PolicyMcpClient::new(Arc::new(fake.clone()), Arc::new(Policy::allow_all()));
+        let spec: StepSpec = serde_json::from_value(serde_json::json!(
+            {"ForEach": {"items": "input", "body": [
+                {"Tool": {"name": "echo", "args": {}}},
+                {"Tool": {"name": "rm", "args": {"path": "/tmp/old"}}}
+            ]}}
+        ))
+        .unwrap();
+        let engine = WorkflowEngine::new();
+        let mut ctx = Context::new(Persona::default(), "a\nb".into(), None);
+        ctx.memory = serde_json::json!({"workflow": {"current_step_index": 0}});
+
+        let wait = |outcome: StepOutcome| matches!(outcome, StepOutcome::Done(r) if matches!(r.status, StepStatus::WaitUser(_)));
+        assert!(wait(engine.run_step(&spec, &mut ctx, &mcp).await));
+        assert_eq!((fake.calls_to("echo").len(), fake.calls_to("rm").len()), (1, 0));
+
+        // The approval covers the first item only
+        mcp.approve("rm", Some(serde_json::json!({"path": "/tmp/old"})));
+        assert!(wait(engine.run_step(&spec, &mut ctx, &mcp).await));
+        assert_eq!((fake.calls_to("echo").len(), fake.calls_to("rm").len()), (2, 1));
+        assert_eq!(ctx.memory[PENDING_CONFIRMATION]["step"], "0/item[1]/1");
+
+        mcp.approve("rm", Some(serde_json::json!({"path": "/tmp/old"})));
+        let StepOutcome::Done(res) = engine.run_step(&spec, &mut ctx, &mcp).await else {
+            panic!("the approved loop should finish");
+        };
+        assert!(matches!(res.status, StepStatus::Continue));
+        assert_eq!((fake.calls_to("echo").len(), fake.calls_to("rm").len()), (2, 2));
+        let history = ctx.memory["workflow"]["history"].as_array().unwrap();
+        let summaries: Vec<&Value> = history.iter().filter(|e| e.get("control").is_some()).collect();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0]["result"].as_array().map(|r| r.len()), Some(2));
+    }
+}
@@ -1,6 +1,7 @@
 pub mod core;
 pub mod llm;
 pub mod mcp;
+pub mod testkit;
 pub mod tentacles;
 pub mod utils;
 pub mod workflow_steps;
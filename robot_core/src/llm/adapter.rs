@@ -28,3 +28,10 @@ pub struct ChatOutput {
 pub trait LLMClient {
     async fn chat(&self, req: ChatRequest) -> anyhow::Result<ChatOutput>;
 }
+
+#[async_trait]
+impl<T: LLMClient + Send + Sync + ?Sized> LLMClient for std::sync::Arc<T> {
+    async fn chat(&self, req: ChatRequest) -> anyhow::Result<ChatOutput> {
+        (**self).chat(req).await
+    }
+}
@@ -1,3 +1,4 @@
+use crate::core::runtime::Runtime;
 use crate::llm::adapter::{ChatOutput, ChatRequest, LLMClient};
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -15,11 +16,23 @@ use url::Url;
 pub struct LMStudioClient {
     pub base_url: Url,
     pub api_key: Option<String>,
+    /// Where the thoughts of session requests are shown
+    pub runtime: Option<Runtime>,
 }
 
 impl LMStudioClient {
     pub fn new(base_url: Url, api_key: Option<String>) -> Self {
-        Self { base_url, api_key }
+        Self {
+            base_url,
+            api_key,
+            runtime: None,
+        }
+    }
+
+    /// Publish the `<think>` part of session requests as outputs of `runtime`
+    pub fn with_runtime(mut self, runtime: Runtime) -> Self {
+        self.runtime = Some(runtime);
+        self
     }
 }
 
@@ -62,7 +75,7 @@ impl LLMClient for LMStudioClient {
         }
 
         if let Some(thought_content) = &thought {
-            if let Some(sid) = &req.session_id {
+            if let (Some(sid), Some(runtime)) = (&req.session_id, &self.runtime) {
                 let evt = crate::utils::OutputEvent {
                     target: "default".into(),
                     source: "llm".into(),
@@ -73,7 +86,7 @@ impl LLMClient for LMStudioClient {
                     }),
                     style: "neutral".to_string(),
                 };
-                 let _ = crate::utils::output_bus().send(evt);
+                runtime.publish_output(evt);
             }
         }
 
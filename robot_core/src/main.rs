@@ -2,14 +2,26 @@
 extern crate robot_core;
 
 use robot_core::core::{
-    decision_engine::LLMDecisionEngine, intent::LLMIntentModule,
-    perception::BasicPerceptionModule, persona::Persona, workflow_engine::WorkflowEngine,
-    RobotCore,
+    auth::Auth, decision_engine::LLMDecisionEngine, intent::LLMIntentModule,
+    limits::{RateLimitedLlm, RateLimits},
+    perception::BasicPerceptionModule, persona::Persona, policy::Policy,
+    queue::QueueLimits, replay::{replay, ReplayComponents}, runtime::Runtime,
+    synthesis::LLMResponseSynthesizer,
+    templates::TemplateLibrary,
+    trace::{read_jsonl, JsonlTraceSink, TeeTraceSink, TraceSink, TracedLlm},
+    uploads::{UploadConfig, UploadStore},
+    workflow_engine::WorkflowEngine, RobotCore,
 };
+use robot_core::llm::adapter::LLMClient;
 use robot_core::llm::lmstudio::LMStudioClient;
 use robot_core::mcp::rmcp_client::RmcpStdIoClient;
+use robot_core::tentacles::bot_console::{BotConfig, BotHandler, BotInput};
+use robot_core::tentacles::email_console::{EmailConfig, EmailHandler, EmailInput};
+use robot_core::tentacles::history::History;
+use robot_core::tentacles::stdio_console::{ConsoleIo, StdioHandler, StdioInput};
 use robot_core::tentacles::web_console::{WebHandler, WebInput, WebOutput};
 use robot_core::tentacles::tcp_console::{TcpHandler, TcpInput};
+use robot_core::tentacles::webhook::{WebhookConfig, WebhookHandler, WebhookInput};
 use robot_core::workflow_steps::LlmParameterResolver;
 use std::sync::Arc;
 use tracing_subscriber;
@@ -17,9 +29,18 @@ use url::Url;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    // `--console` chats on stdin/stdout, so logs go to stderr and only
+    // warnings are shown unless RUST_LOG asks for more
+    let args: Vec<String> = std::env::args().collect();
+    let console = args.iter().any(|a| a == "--console");
+    let default_filter = if console { "warn" } else { "info,rmcp=info" };
     let filter = tracing_subscriber::EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| "info,rmcp=info".into());
-    tracing_subscriber::fmt().with_env_filter(filter).init();
+        .unwrap_or_else(|_| default_filter.into());
+    if console {
+        tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr).init();
+    } else {
+        tracing_subscriber::fmt().with_env_filter(filter).init();
+    }
 
     let persona = Persona::default();
     let base =
@@ -27,44 +48,46 @@ async fn main() -> anyhow::Result<()> {
     let url = Url::parse(&base)?;
     let api_key = std::env::var("LMSTUDIO_API_KEY").ok();
     let model = std::env::var("LMSTUDIO_MODEL").unwrap_or_else(|_| "default".to_string());
-    let llm_for_decision = LMStudioClient::new(url.clone(), api_key.clone());
-
-    // Create MCP client for decision engine (system session)
-    // Note: LLMDecisionEngine no longer holds mcp_client, it receives it at runtime from the session.
-    // However, for testing or system-level decisions outside a session, we might need one?
-    // But currently DecisionEngine is only used within RobotSession.
-    
-    let decision = Box::new(LLMDecisionEngine::new(
-        Box::new(llm_for_decision.clone()),
-        model.clone(),
-    ));
-
-    let perception = Box::new(BasicPerceptionModule);
-    let intent = Box::new(LLMIntentModule::new(
-        Box::new(llm_for_decision),
-        model.clone(),
-    ));
+    let templates_dir = std::env::var("ROBOT_TEMPLATES_DIR").unwrap_or_else(|_| "templates".to_string());
+    let templates = Arc::new(TemplateLibrary::load_dir(&templates_dir)?);
 
-    let param_resolver = Arc::new(LlmParameterResolver {
-        llm: Arc::new(LMStudioClient::new(url.clone(), api_key.clone())),
-        model: std::env::var("LMSTUDIO_MODEL").unwrap_or_else(|_| "default".to_string()),
-    });
-    let workflow = WorkflowEngine::new_with_resolver(param_resolver);
+    // `--replay traces.jsonl` re-runs recorded traces against their recorded
+    // LLM and tool responses and prints what changed
+    if let Some(pos) = args.iter().position(|a| a == "--replay") {
+        let path = args
+            .get(pos + 1)
+            .ok_or_else(|| anyhow::anyhow!("--replay needs a trace file"))?;
+        return replay_traces(path, persona, &model, templates).await;
+    }
+
+    let runtime = Runtime::with_queues(QueueLimits::from_env()?);
+    let components = build_components(
+        |label| {
+            Arc::new(RateLimitedLlm::new(Arc::new(TracedLlm::new(
+                Arc::new(LMStudioClient::new(url.clone(), api_key.clone()).with_runtime(runtime.clone())),
+                label,
+            ))))
+        },
+        &model,
+        templates,
+    );
 
     // Create factory for per-session clients
     let factory_url = url.clone();
     let factory_api_key = api_key.clone();
     let factory_model = model.clone();
+    let factory_runtime = runtime.clone();
 
     let mcp_client_factory: robot_core::core::McpClientFactory =
         Box::new(move |session_id: String| {
             let url = factory_url.clone();
             let api_key = factory_api_key.clone();
             let model = factory_model.clone();
+            let runtime = factory_runtime.clone();
 
             Box::pin(async move {
-                let llm = LMStudioClient::new(url, api_key);
-                let client = RmcpStdIoClient::new(Arc::new(llm), model, session_id).await?;
+                let llm = LMStudioClient::new(url, api_key).with_runtime(runtime.clone());
+                let client = RmcpStdIoClient::new(Arc::new(llm), model, session_id, runtime).await?;
                 Ok(Arc::new(client)
                     as Arc<
                         dyn robot_core::mcp::client::MCPClient + Send + Sync,
@@ -72,23 +95,54 @@ async fn main() -> anyhow::Result<()> {
             })
         });
 
-    let mut core = RobotCore::new(
+    let mut core = RobotCore::with_runtime(
+        runtime,
         persona,
-        decision,
-        workflow,
-        perception,
-        intent,
+        components.decision_engine,
+        components.workflow_engine,
+        components.perception_module,
+        components.intent_module,
         mcp_client_factory,
     );
 
+    let mut trace_sinks: Vec<Arc<dyn TraceSink>> = Vec::new();
+    if let Ok(trace_file) = std::env::var("ROBOT_TRACE_FILE") {
+        trace_sinks.push(Arc::new(JsonlTraceSink::open(&trace_file).await?));
+        tracing::info!("Writing traces to {}", trace_file);
+    }
+
+    if let Ok(policy_file) = std::env::var("ROBOT_POLICY_FILE") {
+        core.set_policy(Arc::new(Policy::load(&policy_file)?));
+        tracing::info!("Enforcing tool policy from {}", policy_file);
+    }
+
+    if let Ok(limits_file) = std::env::var("ROBOT_LIMITS_FILE") {
+        core.set_rate_limits(RateLimits::load(&limits_file)?);
+        tracing::info!("Applying rate limits from {}", limits_file);
+    }
+
+    let auth = Arc::new(Auth::from_env()?);
+    if auth.is_enabled() {
+        tracing::info!("Console authentication enabled");
+    }
+
+    // The input server also serves the WebSocket endpoint for the outputs
+    let history = Arc::new(History::from_env().await?);
+    if let Ok(dir) = std::env::var("ROBOT_HISTORY_DIR") {
+        tracing::info!("Keeping web console history in {}", dir);
+    }
+    let web_output = WebOutput::with_history(8081, auth.clone(), history).await?;
+    let uploads = Arc::new(UploadStore::open(UploadConfig::from_env()?).await?);
+    uploads.clone().spawn_gc(std::time::Duration::from_secs(3600));
+    let web_input = WebInput::with_output(8080, auth.clone(), &web_output, uploads.clone()).await?;
     register_handlers!(core => {
         WebHandler: (
-            WebInput::new(8080).await?,
-            WebOutput::new(8081).await?
+            web_input,
+            web_output
         ) -> [WebHandler],
     });
 
-    let (tcp_input, tcp_output, _) = TcpInput::new(9000).await?;
+    let (tcp_input, tcp_output, _) = TcpInput::with_auth(9000, auth).await?;
 
     register_handlers!(core => {
         TcpHandler: (
@@ -97,7 +151,127 @@ async fn main() -> anyhow::Result<()> {
         ) -> [TcpHandler],
     });
 
+    if let Some(config) = BotConfig::from_env()? {
+        let (bot_input, bot_output) = BotInput::start(config, uploads.clone()).await?;
+        register_handlers!(core => {
+            BotHandler: (
+                bot_input,
+                bot_output
+            ) -> [BotHandler],
+        });
+    }
+
+    if let Some(config) = EmailConfig::from_env()? {
+        let (email_input, email_output) = EmailInput::start(config, uploads).await?;
+        register_handlers!(core => {
+            EmailHandler: (
+                email_input,
+                email_output
+            ) -> [EmailHandler],
+        });
+    }
+
+    if let Ok(webhook_file) = std::env::var("ROBOT_WEBHOOK_FILE") {
+        let (webhook_input, webhook_output) = WebhookInput::start(WebhookConfig::load(&webhook_file)?).await?;
+        register_handlers!(core => {
+            WebhookHandler: (
+                webhook_input,
+                webhook_output
+            ) -> [WebhookHandler],
+        });
+        tracing::info!("Serving webhooks from {}", webhook_file);
+    }
+
+    if console {
+        let (console_input, console_output) =
+            StdioInput::start(ConsoleIo::stdio(), Some(core.session_manager.clone()));
+        trace_sinks.push(console_input.trace_sink());
+        register_handlers!(core => {
+            StdioHandler: (
+                console_input,
+                console_output
+            ) -> [StdioHandler],
+        });
+    }
+
+    // Sessions are only created from here on, so they all get the sinks
+    match trace_sinks.len() {
+        0 => {}
+        1 => core.set_trace_sink(trace_sinks.remove(0)),
+        _ => core.set_trace_sink(Arc::new(TeeTraceSink::new(trace_sinks))),
+    }
+
     loop {
         core.run_once().await?;
     }
 }
+
+/// Decision, workflow, perception and intent components, taking one LLM
+/// client per role from `llm`
+fn build_components(
+    llm: impl Fn(&str) -> Arc<dyn LLMClient + Send + Sync>,
+    model: &str,
+    templates: Arc<TemplateLibrary>,
+) -> ReplayComponents {
+    let param_resolver = Arc::new(LlmParameterResolver {
+        llm: llm("resolver"),
+        model: model.to_string(),
+    });
+    let synthesizer = Arc::new(LLMResponseSynthesizer {
+        llm: llm("synthesizer"),
+        model: model.to_string(),
+    });
+    ReplayComponents {
+        decision_engine: Box::new(LLMDecisionEngine::new(Box::new(llm("planner")), model.to_string())),
+        workflow_engine: WorkflowEngine::new_with_resolver(param_resolver)
+            .with_synthesizer(synthesizer)
+            .with_templates(templates),
+        perception_module: Box::new(BasicPerceptionModule),
+        intent_module: Box::new(LLMIntentModule::new(Box::new(llm("intent")), model.to_string())),
+    }
+}
+
+async fn replay_traces(
+    path: &str,
+    persona: Persona,
+    model: &str,
+    templates: Arc<TemplateLibrary>,
+) -> anyhow::Result<()> {
+    let traces = read_jsonl(path)?;
+    let mut changed = 0;
+    for trace in &traces {
+        let report = replay(trace, persona.clone(), |script| {
+            build_components(|label| script.llm(label), model, templates.clone())
+        })
+        .await?;
+        println!("== trace {} ({})", trace.trace_id, trace.session_id);
+        if report.is_unchanged() {
+            println!("unchanged");
+            continue;
+        }
+        changed += 1;
+        for change in &report.prompt_changes {
+            println!("-- prompt changed: {} #{}", change.label, change.index);
+            for m in &change.recorded {
+                println!("  - [{}] {}", m.role, m.content);
+            }
+            for m in &change.replayed {
+                println!("  + [{}] {}", m.role, m.content);
+            }
+        }
+        for mismatch in &report.tool_mismatches {
+            println!("-- tool: {}", mismatch);
+        }
+        if report.recorded_outputs != report.replayed_outputs {
+            println!("-- outputs");
+            for o in &report.recorded_outputs {
+                println!("  - {}", o);
+            }
+            for o in &report.replayed_outputs {
+                println!("  + {}", o);
+            }
+        }
+    }
+    println!("{} of {} traces changed", changed, traces.len());
+    Ok(())
+}
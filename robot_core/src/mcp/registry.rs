@@ -6,6 +6,10 @@ pub struct ToolMeta {
     pub description: String,
     #[serde(default)]
     pub is_long_running: bool,
+    /// The server marks the tool as changing its environment destructively
+    /// (MCP `destructiveHint` of a tool that is not read-only)
+    #[serde(default)]
+    pub destructive: bool,
 }
 
 #[derive(Default)]
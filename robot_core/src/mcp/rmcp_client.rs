@@ -1,8 +1,10 @@
+use crate::core::metrics;
 use crate::core::persona::OutputStyle;
 use crate::llm::adapter::{ChatMessage, ChatRequest, LLMClient};
 use crate::mcp::client::MCPClient;
 use crate::mcp::registry::ToolMeta;
-use crate::utils::{OutputEvent, output_bus};
+use crate::core::runtime::Runtime;
+use crate::utils::OutputEvent;
 use async_trait::async_trait;
 use futures::future::BoxFuture;
 use rmcp::{
@@ -22,6 +24,7 @@ pub struct RmcpStdIoClient {
     llm: Arc<dyn LLMClient + Send + Sync>,
     model: String,
     session_id: String,
+    runtime: Runtime,
     service: tokio::sync::Mutex<Option<RunningService<RoleClient, RobotClientHandler>>>,
     shared: Arc<Mutex<SharedCtx>>,
 }
@@ -33,6 +36,7 @@ pub struct RobotClientHandler {
     llm: Arc<dyn LLMClient + Send + Sync>,
     model: String,
     session_id: String,
+    runtime: Runtime,
 }
 
 #[derive(Default)]
@@ -42,9 +46,12 @@ pub struct SharedCtx {
     pub last_elicitation_schema: Option<serde_json::Value>,
     pub preview_only: bool,
     pub current_call_tool_request_id: Option<RequestId>,
+    /// Field errors found by client-side validation of the current call,
+    /// shown with the next elicitation prompt
+    pub elicitation_hint: Option<String>,
 }
 
-fn is_cancel_text(s: &str) -> bool {
+pub(crate) fn is_cancel_text(s: &str) -> bool {
     let t = s.trim().to_ascii_lowercase();
     if t.is_empty() {
         return false;
@@ -89,9 +96,7 @@ impl rmcp::handler::client::ClientHandler for RobotClientHandler {
                 }),
                 style: OutputStyle::Neutral.to_string(),
             };
-            if let Err(e) = output_bus().send(output_event) {
-                eprintln!("[mcp_client] Failed to send progress output event: {}", e);
-            }
+            self.runtime.publish_output(output_event);
         }
     }
 
@@ -103,21 +108,25 @@ impl rmcp::handler::client::ClientHandler for RobotClientHandler {
     {
         async move {
             let sid = self.session_id.clone();
-            crate::utils::set_elicitation_active(&sid, true);
+            self.runtime.set_elicitation_active(&sid, true);
             let schema_str =
                 serde_json::to_string_pretty(&request.requested_schema).unwrap_or_default();
             eprintln!("Message: {}", request.message);
             eprintln!("Schema: {}", schema_str);
             eprintln!("Please provide input (Natural language or JSON): ");
 
-            {
+            let message = {
                 let mut guard = self.shared.lock().unwrap();
                 guard.last_elicitation_message = Some(request.message.clone());
                 guard.last_elicitation_schema = Some(
                     serde_json::to_value(&request.requested_schema)
                         .unwrap_or(serde_json::Value::Null),
                 );
-            }
+                match guard.elicitation_hint.take() {
+                    Some(hint) => format!("{}\n{}", hint, request.message),
+                    None => request.message.clone(),
+                }
+            };
             let sid = self.session_id.clone();
 
             // Emit output event to prompt the user
@@ -126,17 +135,15 @@ impl rmcp::handler::client::ClientHandler for RobotClientHandler {
                 source: "mcp".into(),
                 session_id: Some(sid.clone()),
                 content: serde_json::json!({
-                    "message": request.message,
+                    "message": message,
                     "schema": request.requested_schema
                 }),
                 style: OutputStyle::Neutral.to_string(),
             };
 
-            if let Err(e) = output_bus().send(output_event) {
-                eprintln!("[elicit] Failed to send output event: {}", e);
-            }
+            let mut rx = self.runtime.subscribe_inputs();
+            self.runtime.publish_output(output_event);
 
-            let mut rx = crate::utils::event_bus().subscribe();
             let input_event = loop {
                 match rx.recv().await {
                     Ok(ev) => {
@@ -158,7 +165,7 @@ impl rmcp::handler::client::ClientHandler for RobotClientHandler {
             };
 
             if is_cancel_text(&input) {
-                crate::utils::mark_event_consumed(input_event.id);
+                self.runtime.mark_event_consumed(input_event.id);
                 let output_event = OutputEvent {
                     target: "default".into(),
                     source: "mcp".into(),
@@ -169,7 +176,7 @@ impl rmcp::handler::client::ClientHandler for RobotClientHandler {
                     }),
                     style: OutputStyle::Neutral.to_string() ,
                 };
-                let _ = output_bus().send(output_event);
+                self.runtime.publish_output(output_event);
                 let request_id = {
                     let guard = self.shared.lock().unwrap();
                     guard.current_call_tool_request_id.clone()
@@ -183,7 +190,8 @@ impl rmcp::handler::client::ClientHandler for RobotClientHandler {
                         })
                         .await;
                 }
-                crate::utils::set_elicitation_active(&sid, false);
+                self.runtime.set_elicitation_active(&sid, false);
+                metrics::record_elicitation("cancelled");
                 return Ok(CreateElicitationResult {
                     action: ElicitationAction::Cancel,
                     content: None,
@@ -195,7 +203,7 @@ impl rmcp::handler::client::ClientHandler for RobotClientHandler {
                 Ok(v) => {
                     eprintln!("[elicit] Successfully parsed as direct JSON: {:?}", v);
                     // Mark event as consumed so core doesn't process it again
-                    crate::utils::mark_event_consumed(input_event.id);
+                    self.runtime.mark_event_consumed(input_event.id);
                     v
                 }
                 Err(_) => {
@@ -232,7 +240,7 @@ impl rmcp::handler::client::ClientHandler for RobotClientHandler {
                         session_id: Some(self.session_id.clone()),
                     };
 
-                    match self.llm.chat(req).await {
+                    match metrics::observe_llm("elicitation", self.llm.chat(req)).await {
                         Ok(response) => {
                             let text = response.text.trim();
                             eprintln!("[elicit] LLM response: {}", text);
@@ -254,12 +262,13 @@ impl rmcp::handler::client::ClientHandler for RobotClientHandler {
 
                             match serde_json::from_str(json_str) {
                                 Ok(v) => {
-                                    crate::utils::mark_event_consumed(input_event.id);
+                                    self.runtime.mark_event_consumed(input_event.id);
                                     v
                                 }
                                 Err(e) => {
                                     eprintln!("[elicit] ERROR: LLM produced invalid JSON: {}", e);
-                                    crate::utils::set_elicitation_active(&sid, false);
+                                    self.runtime.set_elicitation_active(&sid, false);
+                                    metrics::record_elicitation("failed");
                                     return Err(rmcp::ErrorData::invalid_params(
                                         format!("Failed to parse LLM output as JSON: {}", e),
                                         None,
@@ -269,7 +278,8 @@ impl rmcp::handler::client::ClientHandler for RobotClientHandler {
                         }
                         Err(e) => {
                             eprintln!("[elicit] ERROR: LLM call failed: {}", e);
-                            crate::utils::set_elicitation_active(&sid, false);
+                            self.runtime.set_elicitation_active(&sid, false);
+                            metrics::record_elicitation("failed");
                             return Err(rmcp::ErrorData::internal_error(
                                 format!("LLM transformation failed: {}", e),
                                 None,
@@ -280,7 +290,8 @@ impl rmcp::handler::client::ClientHandler for RobotClientHandler {
             };
 
             eprintln!("[elicit] ✓ Parsed and returning to server\n");
-            crate::utils::set_elicitation_active(&sid, false);
+            self.runtime.set_elicitation_active(&sid, false);
+            metrics::record_elicitation("accepted");
             Ok(CreateElicitationResult {
                 action: ElicitationAction::Accept,
                 content: Some(parsed),
@@ -313,6 +324,7 @@ impl RmcpStdIoClient {
         llm: Arc<dyn LLMClient + Send + Sync>,
         model: String,
         session_id: String,
+        runtime: Runtime,
     ) -> anyhow::Result<Self> {
         let server_addr =
             std::env::var("ROBOT_MCP_SERVER_ADDR").unwrap_or_else(|_| "127.0.0.1:9001".to_string());
@@ -322,6 +334,7 @@ impl RmcpStdIoClient {
             llm,
             model,
             session_id,
+            runtime,
             service: tokio::sync::Mutex::new(None),
             shared,
         })
@@ -356,6 +369,7 @@ impl RmcpStdIoClient {
             llm: self.llm.clone(),
             model: self.model.clone(),
             session_id: self.session_id.clone(),
+            runtime: self.runtime.clone(),
         };
         Ok(handler.serve(stream).await?)
     }
@@ -388,6 +402,7 @@ impl RmcpStdIoClient {
             llm: self.llm.clone(),
             model: self.model.clone(),
             session_id: self.session_id.clone(),
+            runtime: self.runtime.clone(),
         };
         Ok(handler.serve(stream).await?)
     }
@@ -466,17 +481,9 @@ impl RmcpStdIoClient {
             }
         }
     }
-}
-
-#[async_trait]
-impl MCPClient for RmcpStdIoClient {
-    async fn call(&self, tool: &str, args: serde_json::Value) -> anyhow::Result<serde_json::Value> {
-        tracing::info!(
-            "RmcpStdIoClient calling tool '{}' with args: {:?}",
-            tool,
-            args
-        );
 
+    /// Call `tool`, eliciting its missing required fields from the user
+    async fn call_tool(&self, tool: &str, args: serde_json::Value) -> anyhow::Result<serde_json::Value> {
         // Only elicit when REQUIRED fields are missing/empty
         let required = self.required_fields(tool).await.unwrap_or_default();
         let mut missing_fields: Vec<String> = Vec::new();
@@ -651,6 +658,28 @@ impl MCPClient for RmcpStdIoClient {
         }
         Ok(val)
     }
+}
+
+#[async_trait]
+impl MCPClient for RmcpStdIoClient {
+    async fn call(&self, tool: &str, args: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+        tracing::info!(
+            "RmcpStdIoClient calling tool '{}' with args: {:?}",
+            tool,
+            args
+        );
+
+        self.shared.lock().unwrap().elicitation_hint = args
+            .get("__elicitation")
+            .and_then(|e| e.get("hint"))
+            .and_then(|h| h.as_str())
+            .map(String::from);
+
+        // The hint is for elicitations of this call only, whatever its outcome
+        let result = self.call_tool(tool, args).await;
+        self.shared.lock().unwrap().elicitation_hint.take();
+        result
+    }
 
     async fn list_tools(&self) -> anyhow::Result<Vec<ToolMeta>> {
         let tools = self
@@ -666,10 +695,16 @@ impl MCPClient for RmcpStdIoClient {
                     .and_then(|m| m.get("isLongRunning"))
                     .and_then(|v| v.as_bool())
                     .unwrap_or(false);
+                // Tools without annotations are not treated as destructive
+                let destructive = t
+                    .annotations
+                    .as_ref()
+                    .is_some_and(|a| a.read_only_hint != Some(true) && a.is_destructive());
                 ToolMeta {
                     name: t.name.to_string(),
                     description,
                     is_long_running,
+                    destructive,
                 }
             })
             .collect();
@@ -738,6 +773,7 @@ impl MCPClient for RmcpStdIoClient {
 #[cfg(test)]
 mod tests {
     use super::RmcpStdIoClient;
+    use crate::core::runtime::Runtime;
 
     use crate::llm::adapter::{ChatOutput, ChatRequest, LLMClient};
     use crate::mcp::client::MCPClient;
@@ -765,6 +801,7 @@ mod tests {
             mock_llm,
             "test-model".to_string(),
             "test-session".to_string(),
+            Runtime::new(),
         )
         .await
         .unwrap();
@@ -0,0 +1,1089 @@
+//! Chat bot tentacle for a Telegram-style Bot HTTP API.
+//!
+//! Updates arrive by long polling `getUpdates` or, when a webhook port is
+//! configured, as POSTs to `/bot/webhook`, checked against the secret token
+//! registered with `setWebhook`. Every chat is one session, `bot:<chat_id>`;
+//! in group chats its members share it and the sender is named in the payload
+//! and `source_meta`. Files sent to the bot are downloaded into the
+//! [`UploadStore`] and attached to the input.
+//!
+//! Replies go out with `sendMessage`, quoting the last message in group chats.
+//! Progress notifications edit one status message per progress token, and
+//! elicitations are shown with inline buttons whose presses come back as the
+//! answer, like the answer frames of the web console.
+use crate::core::auth::constant_time_eq;
+use crate::core::input_handler::{InputHandler, SourceMetadata, SourceType, TypedInputHandler};
+use crate::core::output_handler::{OutputHandler, OutputMetadata, TypedOutputHandler};
+use crate::core::queue::{self, Overflow, QueueConfig};
+use crate::core::router::HandlerMarker;
+use crate::core::synthesis::TOOL_RESULT_TYPE;
+use crate::core::uploads::UploadStore;
+use crate::utils::{Attachment, InputEvent, OutputEvent};
+use anyhow::{Result, anyhow, bail};
+use async_trait::async_trait;
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+pub struct BotSource;
+impl SourceType for BotSource {}
+
+/// Type marker for chat bot handlers
+pub struct BotHandler;
+impl HandlerMarker for BotHandler {
+    const ID: &'static str = "bot";
+}
+
+/// Updates are taken one at a time, so a busy core holds up the polling
+/// (or the webhook replies) instead of buffering without bound
+const INGRESS_QUEUE: QueueConfig = QueueConfig::new(256, Overflow::Block);
+
+const SESSION_PREFIX: &str = "bot:";
+pub const WEBHOOK_PATH: &str = "/bot/webhook";
+const SECRET_HEADER: &str = "x-telegram-bot-api-secret-token";
+
+/// Longest text the API accepts in one message
+const MAX_MESSAGE_CHARS: usize = 4096;
+/// Longest `callback_data` of an inline button, in bytes
+const MAX_CALLBACK_DATA: usize = 64;
+const BUTTONS_PER_ROW: usize = 3;
+
+/// Time allowed for API calls, on top of the long-polling timeout
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+pub enum BotMode {
+    /// Long-poll `getUpdates`, each request held open for up to `timeout`
+    Polling { timeout: Duration },
+    /// Receive updates at [`WEBHOOK_PATH`] on `port`. `public_url`, when
+    /// set, is registered with `setWebhook`; otherwise the webhook is
+    /// expected to be registered already.
+    Webhook {
+        port: u16,
+        public_url: Option<String>,
+        secret: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct BotConfig {
+    pub token: String,
+    /// Base URL of the Bot API, without the `/bot<token>` part
+    pub api_url: String,
+    pub mode: BotMode,
+    /// Chats the bot answers in; `None` for every chat
+    pub allowed_chats: Option<HashSet<i64>>,
+}
+
+impl BotConfig {
+    /// Long polling against the public Telegram API
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            token: token.into(),
+            api_url: "https://api.telegram.org".to_string(),
+            mode: BotMode::Polling {
+                timeout: Duration::from_secs(30),
+            },
+            allowed_chats: None,
+        }
+    }
+
+    /// `None` unless `ROBOT_BOT_TOKEN` is set. `ROBOT_BOT_API_URL` points at
+    /// another Bot API server, `ROBOT_BOT_WEBHOOK_PORT` switches to webhook
+    /// mode (with `ROBOT_BOT_WEBHOOK_URL` and `ROBOT_BOT_WEBHOOK_SECRET`),
+    /// and `ROBOT_BOT_ALLOWED_CHATS` is a comma-separated list of chat ids.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(token) = std::env::var("ROBOT_BOT_TOKEN") else {
+            return Ok(None);
+        };
+        let mut config = Self::new(token);
+        if let Ok(url) = std::env::var("ROBOT_BOT_API_URL") {
+            config.api_url = url.trim_end_matches('/').to_string();
+        }
+        if let Ok(port) = std::env::var("ROBOT_BOT_WEBHOOK_PORT") {
+            config.mode = BotMode::Webhook {
+                port: port
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("ROBOT_BOT_WEBHOOK_PORT: invalid port '{}'", port))?,
+                public_url: std::env::var("ROBOT_BOT_WEBHOOK_URL").ok(),
+                secret: std::env::var("ROBOT_BOT_WEBHOOK_SECRET").ok(),
+            };
+        }
+        if let Ok(chats) = std::env::var("ROBOT_BOT_ALLOWED_CHATS") {
+            let ids = chats
+                .split(',')
+                .map(str::trim)
+                .filter(|c| !c.is_empty())
+                .map(|c| c.parse().map_err(|_| anyhow!("ROBOT_BOT_ALLOWED_CHATS: invalid chat id '{}'", c)))
+                .collect::<Result<_>>()?;
+            config.allowed_chats = Some(ids);
+        }
+        Ok(Some(config))
+    }
+}
+
+/// The session of chat `chat_id`
+pub fn session_of(chat_id: i64) -> String {
+    format!("{}{}", SESSION_PREFIX, chat_id)
+}
+
+/// The chat of a bot session
+pub fn chat_of(session_id: &str) -> Option<i64> {
+    session_id.strip_prefix(SESSION_PREFIX)?.parse().ok()
+}
+
+#[derive(Clone)]
+struct BotApi {
+    http: reqwest::Client,
+    api_url: String,
+    token: String,
+}
+
+impl BotApi {
+    fn new(config: &BotConfig) -> Result<Self> {
+        Ok(Self {
+            http: reqwest::Client::builder().build()?,
+            api_url: config.api_url.trim_end_matches('/').to_string(),
+            token: config.token.clone(),
+        })
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value> {
+        self.call_for(method, params, REQUEST_TIMEOUT).await
+    }
+
+    async fn call_for(&self, method: &str, params: Value, timeout: Duration) -> Result<Value> {
+        let url = format!("{}/bot{}/{}", self.api_url, self.token, method);
+        // The URL holds the token, so it is kept out of errors and logs
+        let reply: Value = self
+            .http
+            .post(url)
+            .timeout(timeout)
+            .json(&params)
+            .send()
+            .await
+            .map_err(|e| e.without_url())?
+            .json()
+            .await
+            .map_err(|e| e.without_url())?;
+        if reply.get("ok").and_then(|ok| ok.as_bool()) != Some(true) {
+            bail!(
+                "{} failed: {}",
+                method,
+                reply.get("description").and_then(|d| d.as_str()).unwrap_or("unknown error")
+            );
+        }
+        Ok(reply.get("result").cloned().unwrap_or(Value::Null))
+    }
+
+    async fn download(&self, file_id: &str) -> Result<reqwest::Response> {
+        let file = self.call("getFile", json!({ "file_id": file_id })).await?;
+        let path = file
+            .get("file_path")
+            .and_then(|p| p.as_str())
+            .ok_or_else(|| anyhow!("getFile returned no file_path for {}", file_id))?;
+        let url = format!("{}/file/bot{}/{}", self.api_url, self.token, path);
+        self.http
+            .get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.without_url().into())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<Message>,
+    callback_query: Option<CallbackQuery>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    message_id: i64,
+    chat: Chat,
+    from: Option<User>,
+    /// Unix seconds
+    #[serde(default)]
+    date: u64,
+    text: Option<String>,
+    caption: Option<String>,
+    reply_to_message: Option<Box<Message>>,
+    document: Option<FileRef>,
+    /// The same picture in several sizes, the largest last
+    #[serde(default)]
+    photo: Vec<FileRef>,
+    audio: Option<FileRef>,
+    video: Option<FileRef>,
+    voice: Option<FileRef>,
+}
+
+impl Message {
+    /// Files of the message, with the names given to those sent without one
+    fn files(&self) -> Vec<(&FileRef, &'static str)> {
+        [
+            (self.document.as_ref(), "document"),
+            (self.photo.last(), "photo.jpg"),
+            (self.audio.as_ref(), "audio"),
+            (self.video.as_ref(), "video.mp4"),
+            (self.voice.as_ref(), "voice.ogg"),
+        ]
+        .into_iter()
+        .filter_map(|(file, name)| file.map(|f| (f, name)))
+        .collect()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Chat {
+    id: i64,
+    #[serde(rename = "type")]
+    kind: String,
+    title: Option<String>,
+}
+
+impl Chat {
+    fn is_private(&self) -> bool {
+        self.kind == "private"
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    id: i64,
+    username: Option<String>,
+    #[serde(default)]
+    first_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileRef {
+    file_id: String,
+    file_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CallbackQuery {
+    id: String,
+    from: User,
+    message: Option<Message>,
+    data: Option<String>,
+}
+
+struct BotShared {
+    api: BotApi,
+    config: BotConfig,
+    /// Name of the bot, dropped from messages that mention it
+    username: String,
+    uploads: Arc<UploadStore>,
+    sender: queue::Sender<InputEvent>,
+    /// Status message of each running progress token, by chat
+    progress: StdMutex<HashMap<(i64, String), i64>>,
+    /// Last message of each group chat, which replies quote
+    last_message: StdMutex<HashMap<i64, i64>>,
+}
+
+pub struct BotInput {
+    receiver: Mutex<queue::Receiver<InputEvent>>,
+    task: Option<tokio::task::JoinHandle<()>>,
+    webhook_port: Option<u16>,
+}
+
+pub struct BotOutput {
+    shared: Arc<BotShared>,
+}
+
+impl BotInput {
+    fn create_metadata() -> SourceMetadata {
+        SourceMetadata {
+            name: "bot".to_string(),
+            format_hint: "text".to_string(),
+            content_field: "content".to_string(),
+            description: "User input from a chat bot.".to_string(),
+        }
+    }
+
+    /// Connect to the Bot API and start receiving updates. Returns the input
+    /// and the output of the bot, sharing the same connection.
+    pub async fn start(config: BotConfig, uploads: Arc<UploadStore>) -> Result<(Self, BotOutput)> {
+        let api = BotApi::new(&config)?;
+        let me = api.call("getMe", json!({})).await?;
+        let username = me
+            .get("username")
+            .and_then(|u| u.as_str())
+            .unwrap_or_default()
+            .to_string();
+
+        let (sender, receiver) = queue::channel("bot", INGRESS_QUEUE, None);
+        let shared = Arc::new(BotShared {
+            api: api.clone(),
+            config: config.clone(),
+            username,
+            uploads,
+            sender,
+            progress: StdMutex::new(HashMap::new()),
+            last_message: StdMutex::new(HashMap::new()),
+        });
+
+        let (task, webhook_port) = match config.mode {
+            BotMode::Polling { timeout } => {
+                // getUpdates is refused while a webhook is registered
+                api.call("deleteWebhook", json!({})).await?;
+                (tokio::spawn(poll_updates(shared.clone(), timeout)), None)
+            }
+            BotMode::Webhook {
+                port,
+                ref public_url,
+                ref secret,
+            } => {
+                let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+                let port = listener.local_addr()?.port();
+                if let Some(url) = public_url {
+                    let mut params = json!({ "url": url, "allowed_updates": ["message", "callback_query"] });
+                    if let Some(secret) = secret {
+                        params["secret_token"] = json!(secret);
+                    }
+                    api.call("setWebhook", params).await?;
+                }
+                let app = Router::new()
+                    .route(WEBHOOK_PATH, post(webhook))
+                    .with_state(shared.clone());
+                info!("Chat bot webhook listening on port {}", port);
+                let task = tokio::spawn(async move {
+                    if let Err(e) = axum::serve(listener, app).await {
+                        error!("Chat bot webhook server error: {}", e);
+                    }
+                });
+                (task, Some(port))
+            }
+        };
+        info!("Chat bot @{} started", shared.username);
+
+        let input = Self {
+            receiver: Mutex::new(receiver),
+            task: Some(task),
+            webhook_port,
+        };
+        Ok((input, BotOutput { shared }))
+    }
+
+    /// Port the webhook server is bound to, in webhook mode
+    pub fn webhook_port(&self) -> Option<u16> {
+        self.webhook_port
+    }
+}
+
+async fn poll_updates(shared: Arc<BotShared>, timeout: Duration) {
+    let mut offset = 0;
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        let params = json!({
+            "offset": offset,
+            "timeout": timeout.as_secs(),
+            "allowed_updates": ["message", "callback_query"],
+        });
+        match shared.api.call_for("getUpdates", params, timeout + REQUEST_TIMEOUT).await {
+            Ok(updates) => {
+                backoff = Duration::from_secs(1);
+                for update in updates.as_array().into_iter().flatten() {
+                    if let Some(id) = update.get("update_id").and_then(|id| id.as_i64()) {
+                        offset = offset.max(id + 1);
+                    }
+                    handle_update(&shared, update.clone()).await;
+                }
+            }
+            Err(e) => {
+                warn!("getUpdates failed, retrying in {:?}: {}", backoff, e);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+async fn webhook(
+    State(shared): State<Arc<BotShared>>,
+    headers: HeaderMap,
+    Json(update): Json<Value>,
+) -> StatusCode {
+    if let BotMode::Webhook {
+        secret: Some(secret), ..
+    } = &shared.config.mode
+    {
+        let given = headers.get(SECRET_HEADER).map(|v| v.as_bytes()).unwrap_or_default();
+        if !constant_time_eq(given, secret.as_bytes()) {
+            return StatusCode::UNAUTHORIZED;
+        }
+    }
+    handle_update(&shared, update).await;
+    StatusCode::OK
+}
+
+async fn handle_update(shared: &BotShared, update: Value) {
+    let update: Update = match serde_json::from_value(update) {
+        Ok(update) => update,
+        Err(e) => {
+            warn!("Ignoring malformed bot update: {}", e);
+            return;
+        }
+    };
+    let result = if let Some(message) = update.message {
+        shared.on_message(message).await
+    } else if let Some(query) = update.callback_query {
+        shared.on_callback(query).await
+    } else {
+        Ok(())
+    };
+    if let Err(e) = result {
+        warn!("Bot update {} failed: {}", update.update_id, e);
+    }
+}
+
+impl BotShared {
+    fn allows(&self, chat_id: i64) -> bool {
+        self.config.allowed_chats.as_ref().is_none_or(|chats| chats.contains(&chat_id))
+    }
+
+    async fn on_message(&self, message: Message) -> Result<()> {
+        let chat_id = message.chat.id;
+        if !self.allows(chat_id) {
+            debug!("Ignoring message from chat {}", chat_id);
+            return Ok(());
+        }
+
+        let raw = message.text.as_deref().or(message.caption.as_deref()).unwrap_or_default();
+        let mut content = match command(raw, &self.username) {
+            Some("start") => String::new(),
+            Some("cancel") => "cancel".to_string(),
+            _ => strip_mention(raw, &self.username),
+        };
+
+        let mut attachments = Vec::new();
+        for (file, default_name) in message.files() {
+            let name = file.file_name.as_deref().unwrap_or(default_name);
+            match self.store(file, name).await {
+                Ok(attachment) => attachments.push(attachment),
+                Err(e) => {
+                    warn!("Failed to receive {} in chat {}: {}", name, chat_id, e);
+                    self.say(chat_id, &format!("Could not receive {}: {}", name, e), None)
+                        .await?;
+                }
+            }
+        }
+        if content.is_empty() && attachments.is_empty() {
+            return Ok(());
+        }
+
+        let reply_to = message.reply_to_message.as_ref();
+        if let Some(quoted) = reply_to.and_then(|m| m.text.as_deref().or(m.caption.as_deref())) {
+            content = format!("{}\n(in reply to: {})", content, quoted);
+        }
+        if !message.chat.is_private() {
+            self.last_message.lock().unwrap().insert(chat_id, message.message_id);
+        }
+
+        let payload = json!({
+            "content": content,
+            "timestamp": message.date * 1000,
+            "message_id": message.message_id,
+            "reply_to": reply_to.map(|m| m.message_id),
+        });
+        self.publish(&message.chat, message.from.as_ref(), payload, attachments).await
+    }
+
+    /// A press of an inline button, sent as the answer it stands for
+    async fn on_callback(&self, query: CallbackQuery) -> Result<()> {
+        self.api
+            .call("answerCallbackQuery", json!({ "callback_query_id": query.id }))
+            .await?;
+        let (Some(message), Some(data)) = (query.message, query.data) else {
+            return Ok(());
+        };
+        if !self.allows(message.chat.id) {
+            return Ok(());
+        }
+        // The question is answered, so its buttons go
+        let cleared = self
+            .api
+            .call(
+                "editMessageReplyMarkup",
+                json!({
+                    "chat_id": message.chat.id,
+                    "message_id": message.message_id,
+                    "reply_markup": { "inline_keyboard": [] },
+                }),
+            )
+            .await;
+        if let Err(e) = cleared {
+            debug!("Could not remove the buttons of message {}: {}", message.message_id, e);
+        }
+
+        let payload = json!({
+            "content": data,
+            "timestamp": now_ms(),
+            "message_id": message.message_id,
+        });
+        self.publish(&message.chat, Some(&query.from), payload, Vec::new()).await
+    }
+
+    async fn publish(
+        &self,
+        chat: &Chat,
+        from: Option<&User>,
+        mut payload: Value,
+        attachments: Vec<Attachment>,
+    ) -> Result<()> {
+        payload["chat_id"] = json!(chat.id);
+        payload["chat_type"] = json!(chat.kind);
+        payload["user_id"] = json!(from.map(|u| u.id));
+        payload["username"] = json!(from.and_then(|u| u.username.clone()));
+
+        let sender = match from {
+            Some(User {
+                id,
+                username: Some(name),
+                ..
+            }) => format!("user {} (@{})", id, name),
+            Some(user) => format!("user {} ({})", user.id, user.first_name),
+            None => "an unknown user".to_string(),
+        };
+        let place = match (&chat.title, chat.is_private()) {
+            (_, true) => format!("private chat {}", chat.id),
+            (Some(title), false) => format!("{} chat {} \"{}\"", chat.kind, chat.id, title),
+            (None, false) => format!("{} chat {}", chat.kind, chat.id),
+        };
+        let event = InputEvent {
+            id: Uuid::new_v4(),
+            source: "bot".to_string(),
+            session_id: Some(session_of(chat.id)),
+            source_meta: Some(SourceMetadata {
+                description: format!("User input from a chat bot, sent by {} in {}.", sender, place),
+                ..BotInput::create_metadata()
+            }),
+            payload,
+            identity: None,
+            attachments,
+        };
+        self.sender
+            .send(event)
+            .await
+            .map_err(|_| anyhow!("bot input queue is closed"))
+    }
+
+    /// Download a file of a message into the upload store
+    async fn store(&self, file: &FileRef, name: &str) -> Result<Attachment> {
+        let mut response = self.api.download(&file.file_id).await?;
+        let mut upload = self.uploads.begin(None, name).await?;
+        while let Some(chunk) = response.chunk().await.map_err(|e| e.without_url())? {
+            upload.write(&chunk).await?;
+        }
+        let stored = self.uploads.finish(upload).await?;
+        Ok(self.uploads.attachment(&stored))
+    }
+
+    /// Send `text`, split at the API's message length; `buttons` go under
+    /// the last part
+    async fn say(&self, chat_id: i64, text: &str, buttons: Option<&[(String, String)]>) -> Result<Value> {
+        let reply_to = self.last_message.lock().unwrap().get(&chat_id).copied();
+        let parts = split_text(text, MAX_MESSAGE_CHARS);
+        let mut sent = Value::Null;
+        for (i, part) in parts.iter().enumerate() {
+            let mut params = json!({ "chat_id": chat_id, "text": part });
+            if let Some(message_id) = reply_to {
+                params["reply_parameters"] = json!({
+                    "message_id": message_id,
+                    "allow_sending_without_reply": true,
+                });
+            }
+            if let Some(buttons) = buttons.filter(|_| i + 1 == parts.len()) {
+                params["reply_markup"] = keyboard(buttons);
+            }
+            sent = self.api.call("sendMessage", params).await?;
+        }
+        Ok(sent)
+    }
+
+    /// Show a progress notification, editing the status message of its token
+    async fn show_progress(&self, chat_id: i64, token: String, text: String) -> Result<()> {
+        let key = (chat_id, token);
+        let existing = self.progress.lock().unwrap().get(&key).copied();
+        match existing {
+            Some(message_id) => {
+                let edited = self
+                    .api
+                    .call(
+                        "editMessageText",
+                        json!({ "chat_id": chat_id, "message_id": message_id, "text": text }),
+                    )
+                    .await;
+                // Editing to the same text is refused, which is harmless
+                if let Err(e) = edited {
+                    debug!("Could not update progress message {}: {}", message_id, e);
+                }
+            }
+            None => {
+                let sent = self.say(chat_id, &text, None).await?;
+                if let Some(message_id) = sent.get("message_id").and_then(|id| id.as_i64()) {
+                    self.progress.lock().unwrap().insert(key, message_id);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for BotInput {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl InputHandler for BotInput {
+    async fn poll(&self) -> Result<Option<InputEvent>> {
+        Ok(self.receiver.lock().await.recv().await)
+    }
+
+    fn metadata(&self) -> Option<SourceMetadata> {
+        Some(Self::create_metadata())
+    }
+}
+
+#[async_trait]
+impl TypedInputHandler<BotSource> for BotInput {
+    async fn poll(&self) -> Result<Option<InputEvent>> {
+        <Self as InputHandler>::poll(self).await
+    }
+}
+
+/// How an output is shown in a chat
+#[derive(Debug, PartialEq)]
+enum Rendered {
+    Skip,
+    Text(String),
+    Progress { token: String, text: String },
+    /// An elicitation: its message with a button per choice
+    Question { text: String, buttons: Vec<(String, String)> },
+}
+
+fn render(content: &Value) -> Rendered {
+    let field = |name: &str| content.get(name).and_then(|v| v.as_str()).map(str::to_string);
+    match content.get("type").and_then(|t| t.as_str()) {
+        Some("text") => Rendered::Text(field("text").unwrap_or_else(|| content.to_string())),
+        Some("progress") => Rendered::Progress {
+            token: content.get("token").map(|t| t.to_string()).unwrap_or_default(),
+            text: progress_text(content),
+        },
+        Some("tool_cancel") => Rendered::Text(field("message").unwrap_or_else(|| "Cancelled".to_string())),
+        // Echoes of what users typed, reasoning and raw tool results
+        Some("user_message" | "think") => Rendered::Skip,
+        Some(t) if t == TOOL_RESULT_TYPE => Rendered::Skip,
+        Some("elicitation") => question(content),
+        None if content.get("schema").is_some() => question(content),
+        _ => match content {
+            Value::String(s) => Rendered::Text(s.clone()),
+            _ => Rendered::Text(field("content").unwrap_or_else(|| content.to_string())),
+        },
+    }
+}
+
+fn progress_text(content: &Value) -> String {
+    let message = content.get("message").and_then(|m| m.as_str()).unwrap_or("Working");
+    let progress = content.get("progress").and_then(|p| p.as_f64());
+    match (progress, content.get("total").and_then(|t| t.as_f64())) {
+        (Some(done), Some(total)) if total > 0.0 => format!("⏳ {} ({:.0}%)", message, done / total * 100.0),
+        (Some(done), _) => format!("⏳ {} ({})", message, done),
+        _ => format!("⏳ {}", message),
+    }
+}
+
+/// Buttons for the fields of an elicitation schema that have a fixed set of
+/// values, enums and booleans, each answering with `{"<field>": <value>}`.
+/// A cancel button is always there.
+fn question(content: &Value) -> Rendered {
+    let text = content
+        .get("message")
+        .and_then(|m| m.as_str())
+        .unwrap_or("Please provide the missing input")
+        .to_string();
+    let properties = content
+        .pointer("/schema/properties")
+        .and_then(|p| p.as_object())
+        .cloned()
+        .unwrap_or_default();
+    let single = properties.len() == 1;
+
+    let mut buttons = Vec::new();
+    for (name, property) in &properties {
+        let choices = match property.get("enum").and_then(|e| e.as_array()) {
+            Some(values) => values.clone(),
+            None if property.get("type").and_then(|t| t.as_str()) == Some("boolean") => {
+                vec![json!(true), json!(false)]
+            }
+            None => continue,
+        };
+        for value in choices {
+            let data = json!({ name.clone(): value }).to_string();
+            if data.len() > MAX_CALLBACK_DATA {
+                continue;
+            }
+            let shown = match &value {
+                Value::String(s) => s.clone(),
+                Value::Bool(true) => "Yes".to_string(),
+                Value::Bool(false) => "No".to_string(),
+                other => other.to_string(),
+            };
+            let label = if single { shown } else { format!("{}: {}", name, shown) };
+            buttons.push((label, data));
+        }
+    }
+    buttons.push(("Cancel".to_string(), "cancel".to_string()));
+    Rendered::Question { text, buttons }
+}
+
+fn keyboard(buttons: &[(String, String)]) -> Value {
+    let rows: Vec<Value> = buttons
+        .chunks(BUTTONS_PER_ROW)
+        .map(|row| {
+            row.iter()
+                .map(|(label, data)| json!({ "text": label, "callback_data": data }))
+                .collect()
+        })
+        .collect();
+    json!({ "inline_keyboard": rows })
+}
+
+/// The command of a `/command` or `/command@bot` message addressed to this bot
+fn command<'a>(text: &'a str, username: &str) -> Option<&'a str> {
+    let word = text.trim().strip_prefix('/')?.split_whitespace().next()?;
+    match word.split_once('@') {
+        Some((command, to)) if to.eq_ignore_ascii_case(username) => Some(command),
+        Some(_) => None,
+        None => Some(word),
+    }
+}
+
+/// `text` without mentions of the bot
+fn strip_mention(text: &str, username: &str) -> String {
+    if username.is_empty() {
+        return text.trim().to_string();
+    }
+    let mention = format!("@{}", username);
+    text.split_whitespace()
+        .filter(|word| !word.eq_ignore_ascii_case(&mention))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn split_text(text: &str, max_chars: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.is_empty() {
+        return vec![String::new()];
+    }
+    chars.chunks(max_chars).map(|c| c.iter().collect()).collect()
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[async_trait]
+impl OutputHandler for BotOutput {
+    async fn emit(&self, event: OutputEvent) -> Result<()> {
+        // Every output reaches every handler; the bot only shows its chats'
+        let Some(chat_id) = event.session_id.as_deref().and_then(chat_of) else {
+            return Ok(());
+        };
+        if !self.shared.allows(chat_id) {
+            return Ok(());
+        }
+        match render(&event.content) {
+            Rendered::Skip => {}
+            Rendered::Text(text) => {
+                // A reply ends the progress of whatever produced it
+                self.shared.progress.lock().unwrap().retain(|(chat, _), _| *chat != chat_id);
+                self.shared.say(chat_id, &text, None).await?;
+            }
+            Rendered::Progress { token, text } => self.shared.show_progress(chat_id, token, text).await?,
+            Rendered::Question { text, buttons } => {
+                self.shared.say(chat_id, &text, Some(&buttons)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn metadata(&self) -> Option<OutputMetadata> {
+        Some(OutputMetadata {
+            name: "bot".to_string(),
+            format: "text".to_string(),
+            description: "Messages to chats of a chat bot.".to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl TypedOutputHandler<BotSource> for BotOutput {
+    async fn emit(&self, event: OutputEvent) -> Result<()> {
+        <Self as OutputHandler>::emit(self, event).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::uploads::UploadConfig;
+    use axum::extract::Path;
+    use axum::routing::get;
+
+    const TOKEN: &str = "TEST";
+
+    /// A Bot API that serves queued updates and records every call
+    #[derive(Default)]
+    struct MockApi {
+        updates: StdMutex<Vec<Value>>,
+        calls: StdMutex<Vec<(String, Value)>>,
+    }
+
+    impl MockApi {
+        fn calls(&self, method: &str) -> Vec<Value> {
+            let calls = self.calls.lock().unwrap();
+            calls.iter().filter(|(m, _)| m == method).map(|(_, p)| p.clone()).collect()
+        }
+    }
+
+    async fn mock_method(
+        State(mock): State<Arc<MockApi>>,
+        Path(method): Path<String>,
+        Json(params): Json<Value>,
+    ) -> Json<Value> {
+        mock.calls.lock().unwrap().push((method.clone(), params));
+        let result = match method.as_str() {
+            "getMe" => json!({ "id": 1, "is_bot": true, "username": "robot_bot" }),
+            "getUpdates" => {
+                let updates = std::mem::take(&mut *mock.updates.lock().unwrap());
+                if updates.is_empty() {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                json!(updates)
+            }
+            "getFile" => json!({ "file_id": "f1", "file_path": "documents/notes.txt" }),
+            "sendMessage" => json!({ "message_id": 100 }),
+            _ => json!(true),
+        };
+        Json(json!({ "ok": true, "result": result }))
+    }
+
+    async fn mock_file(Path(path): Path<String>) -> Result<&'static str, StatusCode> {
+        match path.as_str() {
+            "documents/notes.txt" => Ok("meeting notes"),
+            _ => Err(StatusCode::NOT_FOUND),
+        }
+    }
+
+    async fn mock_server() -> (Arc<MockApi>, String) {
+        let mock = Arc::new(MockApi::default());
+        let app = Router::new()
+            .route(&format!("/bot{}/{{method}}", TOKEN), post(mock_method))
+            .route(&format!("/file/bot{}/{{*path}}", TOKEN), get(mock_file))
+            .with_state(mock.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (mock, url)
+    }
+
+    async fn uploads() -> (Arc<UploadStore>, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("robot-bot-{}", Uuid::new_v4()));
+        let config = UploadConfig {
+            dir: dir.clone(),
+            ..UploadConfig::default()
+        };
+        (Arc::new(UploadStore::open(config).await.unwrap()), dir)
+    }
+
+    async fn next_input(input: &BotInput) -> InputEvent {
+        tokio::time::timeout(Duration::from_secs(5), InputHandler::poll(input))
+            .await
+            .expect("timed out waiting for input")
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn polls_group_messages_and_answers_with_buttons() {
+        let (mock, api_url) = mock_server().await;
+        let (uploads, dir) = uploads().await;
+        let config = BotConfig {
+            api_url,
+            mode: BotMode::Polling {
+                timeout: Duration::from_secs(0),
+            },
+            ..BotConfig::new(TOKEN)
+        };
+        let (input, output) = BotInput::start(config, uploads).await.unwrap();
+
+        let group = json!({ "id": -42, "type": "group", "title": "Team" });
+        let alice = json!({ "id": 7, "username": "alice", "first_name": "Alice" });
+        mock.updates.lock().unwrap().push(json!({
+            "update_id": 1,
+            "message": {
+                "message_id": 10,
+                "chat": group,
+                "from": alice,
+                "date": 1_700_000_000,
+                "caption": "@robot_bot what is in this?",
+                "document": { "file_id": "f1", "file_name": "notes.txt" }
+            }
+        }));
+
+        let event = next_input(&input).await;
+        assert_eq!(event.source, "bot");
+        assert_eq!(event.session_id.as_deref(), Some("bot:-42"));
+        assert_eq!(event.payload["content"], "what is in this?");
+        assert_eq!(event.payload["user_id"], 7);
+        assert!(event.source_meta.unwrap().description.contains("@alice"));
+        assert_eq!(event.attachments.len(), 1);
+        assert_eq!(event.attachments[0].name, "notes.txt");
+        assert_eq!(event.attachments[0].mime_type, "text/plain");
+        let path = event.attachments[0].path.clone().unwrap();
+        assert_eq!(tokio::fs::read_to_string(path).await.unwrap(), "meeting notes");
+
+        // An elicitation for the group is asked with buttons, quoting Alice
+        let ask = OutputEvent {
+            target: "default".to_string(),
+            source: "mcp".to_string(),
+            session_id: Some("bot:-42".to_string()),
+            content: json!({
+                "message": "Overwrite the file?",
+                "schema": { "type": "object", "properties": { "overwrite": { "type": "boolean" } } }
+            }),
+            style: "neutral".to_string(),
+        };
+        OutputHandler::emit(&output, ask).await.unwrap();
+        // Outputs of other consoles' sessions are not sent anywhere
+        let elsewhere = OutputEvent {
+            session_id: Some("web-session".to_string()),
+            content: json!({ "type": "text", "text": "hi" }),
+            ..output_event()
+        };
+        OutputHandler::emit(&output, elsewhere).await.unwrap();
+
+        let sent = mock.calls("sendMessage");
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0]["chat_id"], -42);
+        assert_eq!(sent[0]["reply_parameters"]["message_id"], 10);
+        let buttons = &sent[0]["reply_markup"]["inline_keyboard"][0];
+        assert_eq!(buttons[0], json!({ "text": "Yes", "callback_data": "{\"overwrite\":true}" }));
+        assert_eq!(buttons[2], json!({ "text": "Cancel", "callback_data": "cancel" }));
+
+        // Pressing a button answers the elicitation of the chat's session
+        mock.updates.lock().unwrap().push(json!({
+            "update_id": 2,
+            "callback_query": {
+                "id": "q1",
+                "from": alice,
+                "message": { "message_id": 100, "chat": group },
+                "data": "{\"overwrite\":true}"
+            }
+        }));
+        let answer = next_input(&input).await;
+        assert_eq!(answer.session_id.as_deref(), Some("bot:-42"));
+        assert_eq!(answer.payload["content"], "{\"overwrite\":true}");
+        assert_eq!(mock.calls("answerCallbackQuery")[0]["callback_query_id"], "q1");
+
+        // The next poll asks for updates after those already seen
+        let next_poll = async {
+            while !mock.calls("getUpdates").iter().any(|p| p["offset"] == 3) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), next_poll).await.unwrap();
+        let _ = tokio::fs::remove_dir_all(dir).await;
+    }
+
+    fn output_event() -> OutputEvent {
+        OutputEvent {
+            target: "all".to_string(),
+            source: "test".to_string(),
+            session_id: None,
+            content: Value::Null,
+            style: "neutral".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn webhook_requires_the_secret_and_progress_edits_one_message() {
+        let (mock, api_url) = mock_server().await;
+        let config = BotConfig {
+            api_url,
+            mode: BotMode::Webhook {
+                port: 0,
+                public_url: Some("https://robot.example/bot/webhook".to_string()),
+                secret: Some("s3cret".to_string()),
+            },
+            ..BotConfig::new(TOKEN)
+        };
+        let (uploads, _) = uploads().await;
+        let (input, output) = BotInput::start(config, uploads).await.unwrap();
+        assert_eq!(mock.calls("setWebhook")[0]["secret_token"], "s3cret");
+
+        let url = format!("http://127.0.0.1:{}{}", input.webhook_port().unwrap(), WEBHOOK_PATH);
+        let update = json!({
+            "update_id": 5,
+            "message": {
+                "message_id": 1,
+                "chat": { "id": 9, "type": "private" },
+                "from": { "id": 9, "first_name": "Bob" },
+                "text": "/cancel"
+            }
+        });
+        let http = reqwest::Client::new();
+        let refused = http.post(&url).json(&update).send().await.unwrap();
+        assert_eq!(refused.status(), StatusCode::UNAUTHORIZED);
+        let accepted = http
+            .post(&url)
+            .header(SECRET_HEADER, "s3cret")
+            .json(&update)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(accepted.status(), StatusCode::OK);
+        let event = next_input(&input).await;
+        assert_eq!(event.session_id.as_deref(), Some("bot:9"));
+        assert_eq!(event.payload["content"], "cancel");
+
+        for done in [1, 2] {
+            let progress = OutputEvent {
+                session_id: Some("bot:9".to_string()),
+                content: json!({ "type": "progress", "token": "t", "progress": done, "total": 4, "message": "Probing" }),
+                ..output_event()
+            };
+            OutputHandler::emit(&output, progress).await.unwrap();
+        }
+        assert_eq!(mock.calls("sendMessage")[0]["text"], "⏳ Probing (25%)");
+        let edits = mock.calls("editMessageText");
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0]["message_id"], 100);
+        assert_eq!(edits[0]["text"], "⏳ Probing (50%)");
+    }
+}
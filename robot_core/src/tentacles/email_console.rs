@@ -0,0 +1,1002 @@
+//! Email tentacle: conversations by mail, over IMAP and SMTP.
+//!
+//! The mailbox is polled over IMAP for unseen messages. Every thread is one
+//! session, `email:<message id>` of its first message: the first entry of
+//! `References`, else `In-Reply-To`, else the message's own `Message-ID`, so
+//! replies keep their session across restarts. Replies that only name one of
+//! our own messages in `In-Reply-To` are matched through the ids sent so far.
+//! The text without its quoted history becomes the input, and attachments
+//! are stored in the [`UploadStore`] and attached to it. A message is marked
+//! `\Seen` once it has been handed to the core.
+//!
+//! Outputs of a thread's session are mailed back over SMTP to the last
+//! sender, with `In-Reply-To` and `References` set so mail clients keep the
+//! thread together. Progress notifications and reasoning are not mailed.
+use crate::core::input_handler::{InputHandler, SourceMetadata, SourceType, TypedInputHandler};
+use crate::core::output_handler::{OutputHandler, OutputMetadata, TypedOutputHandler};
+use crate::core::queue::{self, Overflow, QueueConfig};
+use crate::core::router::HandlerMarker;
+use crate::core::synthesis::TOOL_RESULT_TYPE;
+use crate::core::uploads::UploadStore;
+use crate::utils::{Attachment, InputEvent, OutputEvent};
+use anyhow::{Result, anyhow, bail};
+use async_trait::async_trait;
+use lettre::message::{Mailbox, header::ContentType};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use mail_parser::{MessageParser, MimeHeaders};
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+pub struct EmailSource;
+impl SourceType for EmailSource {}
+
+/// Type marker for email handlers
+pub struct EmailHandler;
+impl HandlerMarker for EmailHandler {
+    const ID: &'static str = "email";
+}
+
+/// Messages are handed over one at a time, so a busy core holds up the
+/// polling instead of marking mail as seen that was never looked at
+const INGRESS_QUEUE: QueueConfig = QueueConfig::new(64, Overflow::Block);
+
+const SESSION_PREFIX: &str = "email:";
+/// Time allowed for connecting to and talking with the mail servers
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Threads and message ids are forgotten after a week without mail, or
+/// beyond this many; a later reply finds its session again from the root
+/// of its References
+const THREAD_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+const MAX_REMEMBERED: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Security {
+    /// TLS from the start (IMAPS on 993, SMTPS on 465)
+    Tls,
+    /// Plain connection upgraded with STARTTLS
+    StartTls,
+    /// No encryption, for local servers and test stand-ins
+    None,
+}
+
+impl Security {
+    fn parse(name: &str, text: &str) -> Result<Self> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "tls" => Ok(Self::Tls),
+            "starttls" => Ok(Self::StartTls),
+            "none" => Ok(Self::None),
+            other => bail!("{}: expected tls, starttls or none, got '{}'", name, other),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub security: Security,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmailConfig {
+    /// Address the robot reads and sends as
+    pub address: String,
+    pub username: String,
+    pub password: String,
+    pub imap: ServerConfig,
+    pub smtp: ServerConfig,
+    pub mailbox: String,
+    pub poll_interval: Duration,
+    /// Senders the robot answers, lowercase; `None` for everyone
+    pub allowed_senders: Option<HashSet<String>>,
+}
+
+impl EmailConfig {
+    /// `None` unless `ROBOT_EMAIL_ADDRESS` is set. `ROBOT_EMAIL_IMAP_HOST`
+    /// and `ROBOT_EMAIL_PASSWORD` are then required; `ROBOT_EMAIL_USER`
+    /// defaults to the address, `ROBOT_EMAIL_SMTP_HOST` to the IMAP host.
+    /// `ROBOT_EMAIL_{IMAP,SMTP}_SECURITY` (`tls`, `starttls` or `none`,
+    /// defaulting to `tls` and `starttls`) pick the default ports, which
+    /// `ROBOT_EMAIL_{IMAP,SMTP}_PORT` override. `ROBOT_EMAIL_MAILBOX`,
+    /// `ROBOT_EMAIL_POLL_SECS` and `ROBOT_EMAIL_ALLOWED_SENDERS` (a
+    /// comma-separated list) are optional.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(address) = std::env::var("ROBOT_EMAIL_ADDRESS") else {
+            return Ok(None);
+        };
+        let var = |name: &str| std::env::var(name).map_err(|_| anyhow!("{} must be set for email", name));
+        let server = |prefix: &str, host: String, security: Security, ports: [u16; 3]| -> Result<ServerConfig> {
+            let security = match std::env::var(format!("{}_SECURITY", prefix)) {
+                Ok(text) => Security::parse(&format!("{}_SECURITY", prefix), &text)?,
+                Err(_) => security,
+            };
+            let port = match std::env::var(format!("{}_PORT", prefix)) {
+                Ok(port) => port
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("{}_PORT: invalid port '{}'", prefix, port))?,
+                Err(_) => match security {
+                    Security::Tls => ports[0],
+                    Security::StartTls => ports[1],
+                    Security::None => ports[2],
+                },
+            };
+            Ok(ServerConfig { host, port, security })
+        };
+
+        let imap_host = var("ROBOT_EMAIL_IMAP_HOST")?;
+        let smtp_host = std::env::var("ROBOT_EMAIL_SMTP_HOST").unwrap_or_else(|_| imap_host.clone());
+        let poll_secs = match std::env::var("ROBOT_EMAIL_POLL_SECS") {
+            Ok(secs) => secs
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("ROBOT_EMAIL_POLL_SECS: invalid number '{}'", secs))?,
+            Err(_) => 60,
+        };
+        let allowed_senders = std::env::var("ROBOT_EMAIL_ALLOWED_SENDERS").ok().map(|senders| {
+            senders
+                .split(',')
+                .map(|s| s.trim().to_ascii_lowercase())
+                .filter(|s| !s.is_empty())
+                .collect()
+        });
+        Ok(Some(Self {
+            username: std::env::var("ROBOT_EMAIL_USER").unwrap_or_else(|_| address.clone()),
+            password: var("ROBOT_EMAIL_PASSWORD")?,
+            imap: server("ROBOT_EMAIL_IMAP", imap_host, Security::Tls, [993, 143, 143])?,
+            smtp: server("ROBOT_EMAIL_SMTP", smtp_host, Security::StartTls, [465, 587, 25])?,
+            mailbox: std::env::var("ROBOT_EMAIL_MAILBOX").unwrap_or_else(|_| "INBOX".to_string()),
+            poll_interval: Duration::from_secs(poll_secs),
+            allowed_senders,
+            address,
+        }))
+    }
+}
+
+/// The session of the thread started by message `root_id`
+pub fn session_of(root_id: &str) -> String {
+    format!("{}{}", SESSION_PREFIX, root_id)
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+fn tls_connector() -> tokio_rustls::TlsConnector {
+    let roots = tokio_rustls::rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = tokio_rustls::rustls::ClientConfig::builder_with_provider(Arc::new(
+        tokio_rustls::rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .expect("the ring provider supports the default protocol versions")
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    tokio_rustls::TlsConnector::from(Arc::new(config))
+}
+
+/// An untagged IMAP response: its text, with the literals it carried
+/// (message bodies) taken out
+struct Untagged {
+    text: String,
+    literals: Vec<Vec<u8>>,
+}
+
+/// The few IMAP commands polling a mailbox needs
+struct Imap {
+    stream: BufReader<Box<dyn Stream>>,
+    tag: u32,
+}
+
+impl Imap {
+    async fn connect(server: &ServerConfig) -> Result<Self> {
+        let tcp = TcpStream::connect((server.host.as_str(), server.port)).await?;
+        let stream: Box<dyn Stream> = match server.security {
+            Security::None => Box::new(tcp),
+            Security::Tls => Box::new(Self::tls(server, tcp).await?),
+            Security::StartTls => {
+                let mut plain = Self {
+                    stream: BufReader::new(Box::new(tcp)),
+                    tag: 0,
+                };
+                plain.greeting().await?;
+                plain.command("STARTTLS").await?;
+                // Nothing follows the tagged OK, so no bytes are left buffered
+                let tcp = Self::tls(server, plain.stream.into_inner()).await?;
+                return Ok(Self {
+                    stream: BufReader::new(Box::new(tcp)),
+                    tag: plain.tag,
+                });
+            }
+        };
+        let mut imap = Self {
+            stream: BufReader::new(stream),
+            tag: 0,
+        };
+        imap.greeting().await?;
+        Ok(imap)
+    }
+
+    async fn tls<S: Stream>(server: &ServerConfig, stream: S) -> Result<impl Stream + use<S>> {
+        let name = tokio_rustls::rustls::pki_types::ServerName::try_from(server.host.clone())
+            .map_err(|_| anyhow!("invalid IMAP host name '{}'", server.host))?;
+        Ok(tls_connector().connect(name, stream).await?)
+    }
+
+    async fn greeting(&mut self) -> Result<()> {
+        let greeting = self.read_response().await?;
+        if !greeting.text.starts_with("* OK") {
+            bail!("IMAP server refused the connection: {}", greeting.text);
+        }
+        Ok(())
+    }
+
+    /// One response line, with the literals (`{<n>}` followed by `n` bytes)
+    /// it spans
+    async fn read_response(&mut self) -> Result<Untagged> {
+        let mut text = String::new();
+        let mut literals = Vec::new();
+        loop {
+            let mut line = Vec::new();
+            if self.stream.read_until(b'\n', &mut line).await? == 0 {
+                bail!("IMAP server closed the connection");
+            }
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            text.push_str(line);
+            let size = line
+                .strip_suffix('}')
+                .and_then(|l| l.rsplit_once('{'))
+                .and_then(|(_, size)| size.parse::<usize>().ok());
+            let Some(size) = size else {
+                return Ok(Untagged { text, literals });
+            };
+            let mut literal = vec![0; size];
+            self.stream.read_exact(&mut literal).await?;
+            literals.push(literal);
+        }
+    }
+
+    /// Run `command`, returning its untagged responses once it completed OK
+    async fn command(&mut self, command: &str) -> Result<Vec<Untagged>> {
+        self.tag += 1;
+        let tag = format!("a{}", self.tag);
+        let stream = self.stream.get_mut();
+        stream.write_all(format!("{} {}\r\n", tag, command).as_bytes()).await?;
+        stream.flush().await?;
+        // Only the command name is shown in errors; LOGIN carries the password
+        let name = command.split_whitespace().take(2).collect::<Vec<_>>().join(" ");
+
+        let mut responses = Vec::new();
+        loop {
+            let response = self.read_response().await?;
+            match response.text.strip_prefix(&tag) {
+                Some(status) => {
+                    let status = status.trim_start();
+                    if status.starts_with("OK") {
+                        return Ok(responses);
+                    }
+                    bail!("IMAP {} failed: {}", name, status);
+                }
+                None => responses.push(response),
+            }
+        }
+    }
+
+    async fn login(&mut self, username: &str, password: &str) -> Result<()> {
+        self.command(&format!("LOGIN {} {}", quote(username), quote(password)))
+            .await
+            .map(drop)
+    }
+
+    /// UIDs of the unseen messages of the selected mailbox
+    async fn unseen(&mut self) -> Result<Vec<u32>> {
+        let responses = self.command("UID SEARCH UNSEEN").await?;
+        Ok(responses
+            .iter()
+            .filter_map(|r| r.text.strip_prefix("* SEARCH"))
+            .flat_map(|uids| uids.split_whitespace().filter_map(|uid| uid.parse().ok()))
+            .collect())
+    }
+
+    /// The raw message `uid`, leaving it unseen
+    async fn fetch(&mut self, uid: u32) -> Result<Vec<u8>> {
+        let responses = self.command(&format!("UID FETCH {} BODY.PEEK[]", uid)).await?;
+        responses
+            .into_iter()
+            .find(|r| r.text.contains("FETCH"))
+            .and_then(|r| r.literals.into_iter().next())
+            .ok_or_else(|| anyhow!("IMAP server sent no body for message {}", uid))
+    }
+}
+
+/// An IMAP quoted string
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// What is needed to answer in a thread
+#[derive(Clone, Debug)]
+struct Thread {
+    subject: String,
+    /// Who gets the replies: the last sender
+    reply_to: Mailbox,
+    /// The message the next reply answers
+    last_id: String,
+    /// Ids of the thread's messages, oldest first
+    references: Vec<String>,
+}
+
+/// Entries dropped `ttl` after they were last set, the oldest first beyond
+/// `cap`
+struct Recent<V> {
+    entries: HashMap<String, (V, Instant)>,
+    ttl: Duration,
+    cap: usize,
+}
+
+impl<V> Recent<V> {
+    fn new(ttl: Duration, cap: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            ttl,
+            cap,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&V> {
+        self.entries
+            .get(key)
+            .filter(|(_, at)| at.elapsed() < self.ttl)
+            .map(|(value, _)| value)
+    }
+
+    fn insert(&mut self, key: String, value: V) {
+        let now = Instant::now();
+        let ttl = self.ttl;
+        self.entries.retain(|_, (_, at)| now.duration_since(*at) < ttl);
+        if self.entries.len() >= self.cap
+            && !self.entries.contains_key(&key)
+            && let Some(oldest) = self.entries.iter().min_by_key(|(_, (_, at))| *at).map(|(k, _)| k.clone())
+        {
+            self.entries.remove(&oldest);
+        }
+        self.entries.insert(key, (value, now));
+    }
+}
+
+struct EmailShared {
+    config: EmailConfig,
+    uploads: Arc<UploadStore>,
+    sender: queue::Sender<InputEvent>,
+    smtp: AsyncSmtpTransport<Tokio1Executor>,
+    /// Session of every message id seen or sent
+    sessions: StdMutex<Recent<String>>,
+    threads: StdMutex<Recent<Thread>>,
+}
+
+pub struct EmailInput {
+    receiver: Mutex<queue::Receiver<InputEvent>>,
+    task: Option<tokio::task::JoinHandle<()>>,
+}
+
+pub struct EmailOutput {
+    shared: Arc<EmailShared>,
+}
+
+impl EmailInput {
+    fn create_metadata() -> SourceMetadata {
+        SourceMetadata {
+            name: "email".to_string(),
+            format_hint: "text".to_string(),
+            content_field: "content".to_string(),
+            description: "An email sent to the robot.".to_string(),
+        }
+    }
+
+    /// Start polling the mailbox. Returns the input and the output mailing
+    /// the replies.
+    pub async fn start(config: EmailConfig, uploads: Arc<UploadStore>) -> Result<(Self, EmailOutput)> {
+        let smtp = {
+            let host = config.smtp.host.as_str();
+            let builder = match config.smtp.security {
+                Security::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+                Security::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+                Security::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            };
+            let builder = builder.port(config.smtp.port).timeout(Some(REQUEST_TIMEOUT));
+            match config.password.is_empty() {
+                true => builder.build(),
+                false => builder
+                    .credentials(Credentials::new(config.username.clone(), config.password.clone()))
+                    .build(),
+            }
+        };
+        info!(
+            "Reading email for {} from {}:{} every {:?}",
+            config.address, config.imap.host, config.imap.port, config.poll_interval
+        );
+
+        let (sender, receiver) = queue::channel("email", INGRESS_QUEUE, None);
+        let shared = Arc::new(EmailShared {
+            config,
+            uploads,
+            sender,
+            smtp,
+            sessions: StdMutex::new(Recent::new(THREAD_TTL, MAX_REMEMBERED)),
+            threads: StdMutex::new(Recent::new(THREAD_TTL, MAX_REMEMBERED)),
+        });
+        let poller = shared.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                let polled = tokio::time::timeout(REQUEST_TIMEOUT * 10, poller.poll_mailbox()).await;
+                match polled {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!("Email poll failed: {}", e),
+                    Err(_) => warn!("Email poll timed out"),
+                }
+                tokio::time::sleep(poller.config.poll_interval).await;
+            }
+        });
+
+        let input = Self {
+            receiver: Mutex::new(receiver),
+            task: Some(task),
+        };
+        Ok((input, EmailOutput { shared }))
+    }
+}
+
+impl EmailShared {
+    /// Hand every unseen message to the core, marking it seen afterwards
+    async fn poll_mailbox(&self) -> Result<()> {
+        let mut imap = Imap::connect(&self.config.imap).await?;
+        imap.login(&self.config.username, &self.config.password).await?;
+        imap.command(&format!("SELECT {}", quote(&self.config.mailbox))).await?;
+        for uid in imap.unseen().await? {
+            let raw = imap.fetch(uid).await?;
+            // Mail that cannot be read is marked seen too, not fetched forever
+            if let Err(e) = self.on_message(&raw).await {
+                warn!("Failed to read email {}: {}", uid, e);
+            }
+            imap.command(&format!("UID STORE {} +FLAGS.SILENT (\\Seen)", uid)).await?;
+        }
+        imap.command("LOGOUT").await?;
+        Ok(())
+    }
+
+    async fn on_message(&self, raw: &[u8]) -> Result<()> {
+        let message = MessageParser::default()
+            .parse(raw)
+            .ok_or_else(|| anyhow!("not an email message"))?;
+        let sender = message
+            .reply_to()
+            .or(message.from())
+            .and_then(|a| a.first())
+            .ok_or_else(|| anyhow!("email has no sender"))?;
+        let address = sender.address().unwrap_or_default().to_ascii_lowercase();
+        // Our own mail and autoresponders would answer each other forever
+        let automatic = message
+            .header("Auto-Submitted")
+            .and_then(|h| h.as_text())
+            .is_some_and(|v| !v.eq_ignore_ascii_case("no"));
+        if automatic || address == self.config.address.to_ascii_lowercase() {
+            debug!("Ignoring automatic email from {}", address);
+            return Ok(());
+        }
+        if let Some(allowed) = &self.config.allowed_senders
+            && !allowed.contains(&address)
+        {
+            debug!("Ignoring email from {}", address);
+            return Ok(());
+        }
+        let reply_to = Mailbox::new(
+            sender.name().map(str::to_string),
+            address.parse().map_err(|e| anyhow!("invalid sender '{}': {}", address, e))?,
+        );
+
+        let id = message
+            .message_id()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{}@robot", Uuid::new_v4()));
+        let ids = |header: &mail_parser::HeaderValue| -> Vec<String> {
+            header
+                .as_text_list()
+                .map(|l| l.iter().map(|id| id.to_string()).collect())
+                .unwrap_or_default()
+        };
+        let references = ids(message.references());
+        let in_reply_to = ids(message.in_reply_to());
+        let session_id = self.thread_of(&id, &references, &in_reply_to);
+
+        let subject = message.subject().unwrap_or_default().to_string();
+        let mut content = strip_quoted(&message.body_text(0).unwrap_or_default());
+        let mut attachments = Vec::new();
+        for part in message.attachments() {
+            let name = part.attachment_name().unwrap_or("attachment");
+            match self.store(part.contents(), name).await {
+                Ok(attachment) => attachments.push(attachment),
+                Err(e) => warn!("Failed to store attachment '{}' of email {}: {}", name, id, e),
+            }
+        }
+        if content.is_empty() && attachments.is_empty() {
+            content = subject.clone();
+        }
+
+        {
+            let mut threads = self.threads.lock().unwrap();
+            let mut thread = threads.get(&session_id).cloned().unwrap_or_else(|| Thread {
+                subject: subject.clone(),
+                reply_to: reply_to.clone(),
+                last_id: id.clone(),
+                references: references.clone(),
+            });
+            thread.reply_to = reply_to;
+            thread.last_id = id.clone();
+            if !thread.references.contains(&id) {
+                thread.references.push(id.clone());
+            }
+            threads.insert(session_id.clone(), thread);
+        }
+
+        let from = sender.name().map_or_else(|| address.clone(), |n| format!("{} <{}>", n, address));
+        let event = InputEvent {
+            id: Uuid::new_v4(),
+            source: "email".to_string(),
+            session_id: Some(session_id),
+            source_meta: Some(SourceMetadata {
+                description: format!("An email from {} with the subject \"{}\".", from, subject),
+                ..EmailInput::create_metadata()
+            }),
+            payload: json!({
+                "content": content,
+                "subject": subject,
+                "from": from,
+                "message_id": id,
+            }),
+            identity: None,
+            attachments,
+        };
+        self.sender
+            .send(event)
+            .await
+            .map_err(|_| anyhow!("email input queue is closed"))
+    }
+
+    /// The session of a message, recording its id
+    fn thread_of(&self, id: &str, references: &[String], in_reply_to: &[String]) -> String {
+        thread_of(&mut self.sessions.lock().unwrap(), id, references, in_reply_to)
+    }
+
+    async fn store(&self, contents: &[u8], name: &str) -> Result<Attachment> {
+        let mut upload = self.uploads.begin(None, name).await?;
+        upload.write(contents).await?;
+        let stored = self.uploads.finish(upload).await?;
+        Ok(self.uploads.attachment(&stored))
+    }
+
+    /// Mail `text` as a reply in the thread of `session_id`
+    async fn reply(&self, session_id: &str, text: String) -> Result<()> {
+        let Some(thread) = self.threads.lock().unwrap().get(session_id).cloned() else {
+            debug!("No email thread for session {}", session_id);
+            return Ok(());
+        };
+        let domain = self.config.address.rsplit_once('@').map_or("robot", |(_, d)| d);
+        let id = format!("{}@{}", Uuid::new_v4(), domain);
+        let subject = match thread.subject.get(..3) {
+            Some(re) if re.eq_ignore_ascii_case("re:") => thread.subject.clone(),
+            _ => format!("Re: {}", thread.subject),
+        };
+        let references = thread
+            .references
+            .iter()
+            .map(|id| format!("<{}>", id))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let email = lettre::Message::builder()
+            .from(self.config.address.parse()?)
+            .to(thread.reply_to)
+            .subject(subject)
+            .message_id(Some(format!("<{}>", id)))
+            .in_reply_to(format!("<{}>", thread.last_id))
+            .references(references)
+            .header(ContentType::TEXT_PLAIN)
+            .body(text)?;
+        self.smtp.send(email).await?;
+        self.sessions.lock().unwrap().insert(id, session_id.to_string());
+        Ok(())
+    }
+}
+
+/// The session of a message in `sessions`, or the one of the root of its
+/// References, recording its id
+fn thread_of(sessions: &mut Recent<String>, id: &str, references: &[String], in_reply_to: &[String]) -> String {
+    let known = references
+        .iter()
+        .chain(in_reply_to)
+        .chain(std::iter::once(&id.to_string()))
+        .find_map(|id| sessions.get(id).cloned());
+    let session = known.unwrap_or_else(|| {
+        let root = references.first().or(in_reply_to.first()).map_or(id, String::as_str);
+        session_of(root)
+    });
+    sessions.insert(id.to_string(), session.clone());
+    session
+}
+
+/// `text` without the quoted message it answers and the signature
+fn strip_quoted(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut kept = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        let next_quoted = lines[i + 1..]
+            .iter()
+            .find(|l| !l.trim().is_empty())
+            .is_some_and(|l| l.starts_with('>'));
+        // "On <date>, <someone> wrote:" above the quote, or the signature
+        if line.starts_with('>') || *line == "-- " || (trimmed.ends_with("wrote:") && next_quoted) {
+            break;
+        }
+        kept.push(*line);
+    }
+    kept.join("\n").trim().to_string()
+}
+
+/// The text to mail for an output, if any
+fn render(content: &Value) -> Option<String> {
+    let field = |name: &str| content.get(name).and_then(|v| v.as_str()).map(str::to_string);
+    match content.get("type").and_then(|t| t.as_str()) {
+        Some("text") => Some(field("text").unwrap_or_else(|| content.to_string())),
+        Some("tool_cancel") => Some(field("message").unwrap_or_else(|| "Cancelled".to_string())),
+        // Nobody wants a mail per progress step
+        Some("progress" | "user_message" | "think") => None,
+        Some(t) if t == TOOL_RESULT_TYPE => None,
+        Some("elicitation") => Some(question(content)),
+        None if content.get("schema").is_some() => Some(question(content)),
+        _ => match content {
+            Value::String(s) => Some(s.clone()),
+            _ => Some(field("content").unwrap_or_else(|| content.to_string())),
+        },
+    }
+}
+
+/// An elicitation as a mail, listing what the reply should contain
+fn question(content: &Value) -> String {
+    let message = content
+        .get("message")
+        .and_then(|m| m.as_str())
+        .unwrap_or("Please provide the missing input");
+    let properties = content
+        .pointer("/schema/properties")
+        .and_then(|p| p.as_object())
+        .cloned()
+        .unwrap_or_default();
+    let mut text = message.to_string();
+    if !properties.is_empty() {
+        text.push_str("\n\nPlease reply with:");
+        for (name, property) in &properties {
+            let about = property
+                .get("description")
+                .or(property.get("title"))
+                .and_then(|d| d.as_str())
+                .unwrap_or_default();
+            match property.get("enum").and_then(|e| e.as_array()) {
+                Some(values) => {
+                    let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                    text.push_str(&format!("\n- {}: {} (one of {})", name, about, values.join(", ")));
+                }
+                None => text.push_str(&format!("\n- {}: {}", name, about)),
+            }
+        }
+    }
+    text
+}
+
+impl Drop for EmailInput {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl InputHandler for EmailInput {
+    async fn poll(&self) -> Result<Option<InputEvent>> {
+        Ok(self.receiver.lock().await.recv().await)
+    }
+
+    fn metadata(&self) -> Option<SourceMetadata> {
+        Some(Self::create_metadata())
+    }
+}
+
+#[async_trait]
+impl TypedInputHandler<EmailSource> for EmailInput {
+    async fn poll(&self) -> Result<Option<InputEvent>> {
+        <Self as InputHandler>::poll(self).await
+    }
+}
+
+#[async_trait]
+impl OutputHandler for EmailOutput {
+    async fn emit(&self, event: OutputEvent) -> Result<()> {
+        // Every output reaches every handler; only email threads are mailed
+        let Some(session_id) = event.session_id.as_deref().filter(|s| s.starts_with(SESSION_PREFIX)) else {
+            return Ok(());
+        };
+        if let Some(text) = render(&event.content) {
+            self.shared.reply(session_id, text).await?;
+        }
+        Ok(())
+    }
+
+    fn metadata(&self) -> Option<OutputMetadata> {
+        Some(OutputMetadata {
+            name: "email".to_string(),
+            format: "text".to_string(),
+            description: "Replies mailed to the threads of an email inbox.".to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl TypedOutputHandler<EmailSource> for EmailOutput {
+    async fn emit(&self, event: OutputEvent) -> Result<()> {
+        <Self as OutputHandler>::emit(self, event).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::uploads::UploadConfig;
+    use tokio::net::TcpListener;
+
+    /// An IMAP server holding `messages` (uid, raw) that records the UIDs
+    /// marked seen
+    #[derive(Default)]
+    struct MockImap {
+        messages: StdMutex<Vec<(u32, String)>>,
+        seen: StdMutex<Vec<u32>>,
+    }
+
+    async fn serve_imap(mock: Arc<MockImap>, stream: TcpStream) -> Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        write.write_all(b"* OK IMAP ready\r\n").await?;
+        while let Some(line) = lines.next_line().await? {
+            let (tag, command) = line.split_once(' ').unwrap();
+            let mut reply = String::new();
+            if command.starts_with("LOGIN") {
+                assert_eq!(command, "LOGIN \"robot@example.com\" \"pa\\\"ss\"");
+            } else if command.starts_with("SELECT") {
+                reply.push_str("* 2 EXISTS\r\n");
+            } else if command == "UID SEARCH UNSEEN" {
+                let seen = mock.seen.lock().unwrap();
+                let uids: Vec<String> = mock
+                    .messages
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|(uid, _)| !seen.contains(uid))
+                    .map(|(uid, _)| uid.to_string())
+                    .collect();
+                reply.push_str(&format!("* SEARCH {}\r\n", uids.join(" ")).replace(" \r", "\r"));
+            } else if let Some(rest) = command.strip_prefix("UID FETCH ") {
+                let uid: u32 = rest.split(' ').next().unwrap().parse()?;
+                let messages = mock.messages.lock().unwrap();
+                let (_, raw) = messages.iter().find(|(u, _)| *u == uid).unwrap();
+                reply.push_str(&format!("* 1 FETCH (UID {} BODY[] {{{}}}\r\n{})\r\n", uid, raw.len(), raw));
+            } else if let Some(rest) = command.strip_prefix("UID STORE ") {
+                mock.seen.lock().unwrap().push(rest.split(' ').next().unwrap().parse()?);
+            } else if command == "LOGOUT" {
+                reply.push_str("* BYE\r\n");
+            }
+            write.write_all(format!("{}{} OK done\r\n", reply, tag).as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    /// An SMTP server collecting the messages sent through it
+    async fn serve_smtp(mails: Arc<StdMutex<Vec<String>>>, stream: TcpStream) -> Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        write.write_all(b"220 localhost ready\r\n").await?;
+        while let Some(line) = lines.next_line().await? {
+            let verb = line.split(' ').next().unwrap_or_default().to_ascii_uppercase();
+            let reply = match verb.as_str() {
+                "EHLO" | "HELO" => "250-localhost\r\n250 AUTH PLAIN",
+                "AUTH" => "235 authenticated",
+                "DATA" => {
+                    write.write_all(b"354 go ahead\r\n").await?;
+                    let mut data = Vec::new();
+                    while let Some(line) = lines.next_line().await? {
+                        if line == "." {
+                            break;
+                        }
+                        data.push(line);
+                    }
+                    mails.lock().unwrap().push(data.join("\r\n"));
+                    "250 queued"
+                }
+                "QUIT" => {
+                    write.write_all(b"221 bye\r\n").await?;
+                    return Ok(());
+                }
+                _ => "250 ok",
+            };
+            write.write_all(format!("{}\r\n", reply).as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    async fn listen<F, Fut>(serve: F) -> u16
+    where
+        F: Fn(TcpStream) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream));
+            }
+        });
+        port
+    }
+
+    async fn next_input(input: &EmailInput) -> InputEvent {
+        let polled = tokio::time::timeout(Duration::from_secs(5), InputHandler::poll(input));
+        polled.await.unwrap().unwrap().unwrap()
+    }
+
+    const FIRST: &str = "From: Ada <ada@example.com>\r\n\
+To: robot@example.com\r\n\
+Subject: Disk report\r\n\
+Message-ID: <m1@example.com>\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+\r\n\
+--b\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Please look at the attached log.\r\n\
+\r\n\
+-- \r\n\
+Ada\r\n\
+--b\r\n\
+Content-Type: text/plain; name=\"disk.log\"\r\n\
+Content-Disposition: attachment; filename=\"disk.log\"\r\n\
+\r\n\
+sda1 98% full\r\n\
+--b--\r\n";
+
+    #[tokio::test]
+    async fn threads_mail_into_sessions_and_replies_in_the_thread() {
+        let imap = Arc::new(MockImap::default());
+        imap.messages.lock().unwrap().push((7, FIRST.to_string()));
+        let mails = Arc::new(StdMutex::new(Vec::new()));
+        let imap_port = listen({
+            let imap = imap.clone();
+            move |stream| serve_imap(imap.clone(), stream)
+        })
+        .await;
+        let smtp_port = listen({
+            let mails = mails.clone();
+            move |stream| serve_smtp(mails.clone(), stream)
+        })
+        .await;
+
+        let config = EmailConfig {
+            address: "robot@example.com".to_string(),
+            username: "robot@example.com".to_string(),
+            password: "pa\"ss".to_string(),
+            imap: ServerConfig {
+                host: "127.0.0.1".to_string(),
+                port: imap_port,
+                security: Security::None,
+            },
+            smtp: ServerConfig {
+                host: "127.0.0.1".to_string(),
+                port: smtp_port,
+                security: Security::None,
+            },
+            mailbox: "INBOX".to_string(),
+            poll_interval: Duration::from_millis(20),
+            allowed_senders: None,
+        };
+        let uploads = UploadConfig {
+            dir: std::env::temp_dir().join(format!("robot-email-{}", Uuid::new_v4())),
+            ..UploadConfig::default()
+        };
+        let uploads = Arc::new(UploadStore::open(uploads).await.unwrap());
+        let (input, output) = EmailInput::start(config, uploads).await.unwrap();
+
+        let first = next_input(&input).await;
+        assert_eq!(first.session_id.as_deref(), Some("email:m1@example.com"));
+        assert_eq!(first.payload["content"], "Please look at the attached log.");
+        assert_eq!(first.payload["subject"], "Disk report");
+        assert_eq!(first.attachments.len(), 1);
+        assert_eq!(first.attachments[0].name, "disk.log");
+        // Marked seen right after it was handed over
+        let seen = async {
+            while imap.seen.lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), seen).await.unwrap();
+        assert_eq!(imap.seen.lock().unwrap().clone(), vec![7]);
+
+        // Progress is not mailed, text is, as a reply in the thread
+        let reply = |content: Value| OutputEvent {
+            target: "default".to_string(),
+            source: "system".to_string(),
+            session_id: Some("email:m1@example.com".to_string()),
+            content,
+            style: "neutral".to_string(),
+        };
+        OutputHandler::emit(&output, reply(json!({ "type": "progress", "progress": 1 })))
+            .await
+            .unwrap();
+        OutputHandler::emit(&output, reply(json!({ "type": "text", "text": "sda1 is nearly full." })))
+            .await
+            .unwrap();
+        let mail = mails.lock().unwrap().clone();
+        assert_eq!(mail.len(), 1);
+        assert!(mail[0].contains("Subject: Re: Disk report"), "{}", mail[0]);
+        assert!(mail[0].contains("In-Reply-To: <m1@example.com>"), "{}", mail[0]);
+        assert!(mail[0].contains("References: <m1@example.com>"), "{}", mail[0]);
+        assert!(mail[0].contains("To: Ada <ada@example.com>"), "{}", mail[0]);
+        assert!(mail[0].contains("sda1 is nearly full."));
+
+        // A reply naming only our message stays in the session
+        let our_id = mail[0]
+            .lines()
+            .find_map(|l| l.strip_prefix("Message-ID: "))
+            .unwrap()
+            .to_string();
+        let second = format!(
+            "From: ada@example.com\r\nSubject: Re: Disk report\r\nMessage-ID: <m2@example.com>\r\n\
+In-Reply-To: {}\r\n\r\nThanks, clean it up.\r\n\r\nOn Monday, robot wrote:\r\n> sda1 is nearly full.\r\n",
+            our_id
+        );
+        imap.messages.lock().unwrap().push((8, second));
+        let second = next_input(&input).await;
+        assert_eq!(second.session_id.as_deref(), Some("email:m1@example.com"));
+        assert_eq!(second.payload["content"], "Thanks, clean it up.");
+    }
+
+    #[test]
+    fn forgotten_threads_are_found_again_from_their_root() {
+        let ids = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let mut sessions = Recent::new(THREAD_TTL, 2);
+        assert_eq!(thread_of(&mut sessions, "m1@x", &[], &[]), "email:m1@x");
+        // Our reply only names the first message by its own id
+        sessions.insert("r1@robot".to_string(), "email:m1@x".to_string());
+        assert_eq!(thread_of(&mut sessions, "m2@x", &[], &ids(&["r1@robot"])), "email:m1@x");
+
+        // Two other threads push the first one out
+        thread_of(&mut sessions, "a@x", &[], &[]);
+        thread_of(&mut sessions, "b@x", &[], &[]);
+        assert_eq!(sessions.entries.len(), 2);
+        assert!(sessions.get("m2@x").is_none());
+        let references = ids(&["m1@x", "r1@robot", "m2@x"]);
+        assert_eq!(thread_of(&mut sessions, "m3@x", &references, &ids(&["m2@x"])), "email:m1@x");
+
+        let mut expiring = Recent::new(Duration::ZERO, 10);
+        expiring.insert("m1@x".to_string(), "email:m1@x".to_string());
+        assert!(expiring.get("m1@x").is_none());
+        expiring.insert("m2@x".to_string(), "email:m2@x".to_string());
+        assert_eq!(expiring.entries.len(), 1);
+    }
+}
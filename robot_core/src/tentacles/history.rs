@@ -0,0 +1,362 @@
+//! Per-session message history of the web console.
+//!
+//! Every output sent to a session is appended to its log with an id that
+//! increases within the session and a timestamp. Logs are kept in memory and,
+//! with `ROBOT_HISTORY_DIR` set, appended to `<dir>/<session>.jsonl` so they
+//! survive a restart; a session's file is read the first time it is used.
+//! [`Retention`] bounds how many messages and how old a message a session
+//! keeps. The ids are what clients resume from: the WebSocket `since`
+//! parameter and the SSE `Last-Event-ID`.
+use crate::utils::OutputEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+
+/// Messages returned by one page when the client does not ask for a size
+pub const DEFAULT_PAGE: usize = 50;
+
+/// Largest page a client may ask for
+pub const MAX_PAGE: usize = 500;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredMessage {
+    /// Position in the session, starting at 1
+    pub id: u64,
+    pub session_id: String,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub event: OutputEvent,
+}
+
+/// How much history each session keeps
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retention {
+    pub max_messages: usize,
+    /// Messages older than this are dropped; `None` keeps them
+    pub max_age: Option<Duration>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_messages: 1000,
+            max_age: None,
+        }
+    }
+}
+
+impl Retention {
+    /// Defaults, overridden by `ROBOT_HISTORY_MAX_MESSAGES` and
+    /// `ROBOT_HISTORY_MAX_AGE_SECS`
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut retention = Self::default();
+        if let Ok(value) = std::env::var("ROBOT_HISTORY_MAX_MESSAGES") {
+            retention.max_messages = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("ROBOT_HISTORY_MAX_MESSAGES: invalid count '{}'", value))?;
+        }
+        if let Ok(value) = std::env::var("ROBOT_HISTORY_MAX_AGE_SECS") {
+            let secs: u64 = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("ROBOT_HISTORY_MAX_AGE_SECS: invalid seconds '{}'", value))?;
+            retention.max_age = Some(Duration::from_secs(secs));
+        }
+        Ok(retention)
+    }
+}
+
+/// Which messages of a session to return, oldest first. With `before`, the
+/// newest `limit` messages older than it; otherwise the oldest `limit`
+/// messages newer than `after`.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct Page {
+    pub before: Option<u64>,
+    pub after: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Default)]
+struct SessionLog {
+    last_id: u64,
+    messages: VecDeque<StoredMessage>,
+    /// Lines of the file no longer in `messages`
+    stale: usize,
+    /// Highest id a client acknowledged
+    acked: u64,
+}
+
+pub struct History {
+    dir: Option<PathBuf>,
+    retention: Retention,
+    sessions: Mutex<HashMap<String, SessionLog>>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::memory(Retention::default())
+    }
+}
+
+impl History {
+    /// History that is lost when the process exits
+    pub fn memory(retention: Retention) -> Self {
+        Self {
+            dir: None,
+            retention,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// History kept in one JSONL file per session under `dir`
+    pub async fn open(dir: impl AsRef<Path>, retention: Retention) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        tokio::fs::create_dir_all(&dir).await?;
+        Ok(Self {
+            dir: Some(dir),
+            retention,
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// On disk under `ROBOT_HISTORY_DIR` when it is set, in memory otherwise
+    pub async fn from_env() -> anyhow::Result<Self> {
+        let retention = Retention::from_env()?;
+        match std::env::var("ROBOT_HISTORY_DIR") {
+            Ok(dir) => Self::open(dir, retention).await,
+            Err(_) => Ok(Self::memory(retention)),
+        }
+    }
+
+    /// Append `event` to the log of `session_id`. A failed write is logged;
+    /// the message is still kept in memory and returned.
+    pub async fn append(&self, session_id: &str, event: OutputEvent) -> StoredMessage {
+        let mut sessions = self.sessions.lock().await;
+        let log = self.load(&mut sessions, session_id).await;
+        log.last_id += 1;
+        let message = StoredMessage {
+            id: log.last_id,
+            session_id: session_id.to_string(),
+            timestamp: now_ms(),
+            event,
+        };
+        log.messages.push_back(message.clone());
+        let dropped = self.retain(log);
+
+        if let Some(path) = self.path(session_id) {
+            let result = if dropped > 0 && log.stale >= log.messages.len() {
+                // Rewrite the file once most of it has expired
+                log.stale = 0;
+                rewrite(&path, &log.messages).await
+            } else {
+                append_line(&path, &message).await
+            };
+            if let Err(e) = result {
+                error!("failed to write history of session {}: {}", session_id, e);
+            }
+        }
+        message
+    }
+
+    pub async fn page(&self, session_id: &str, page: Page) -> Vec<StoredMessage> {
+        let limit = page.limit.unwrap_or(DEFAULT_PAGE).clamp(1, MAX_PAGE);
+        let mut sessions = self.sessions.lock().await;
+        let log = self.load(&mut sessions, session_id).await;
+        self.retain(log);
+        let matching = log
+            .messages
+            .iter()
+            .filter(|m| page.before.is_none_or(|before| m.id < before))
+            .filter(|m| page.after.is_none_or(|after| m.id > after));
+        if page.before.is_some() {
+            let mut newest: Vec<StoredMessage> = matching.rev().take(limit).cloned().collect();
+            newest.reverse();
+            newest
+        } else {
+            matching.take(limit).cloned().collect()
+        }
+    }
+
+    /// Every kept message of `session_id` after `since`, or after the last
+    /// acknowledged one
+    pub async fn replay(&self, session_id: &str, since: Option<u64>) -> Vec<StoredMessage> {
+        let mut sessions = self.sessions.lock().await;
+        let log = self.load(&mut sessions, session_id).await;
+        self.retain(log);
+        let since = since.unwrap_or(log.acked);
+        log.messages.iter().filter(|m| m.id > since).cloned().collect()
+    }
+
+    pub async fn ack(&self, session_id: &str, id: u64) {
+        if let Some(log) = self.sessions.lock().await.get_mut(session_id) {
+            log.acked = log.acked.max(id.min(log.last_id));
+        }
+    }
+
+    /// Newest messages across the sessions used since startup that pass
+    /// `visible`, oldest first
+    pub async fn recent(&self, limit: usize, visible: impl Fn(&str) -> bool) -> Vec<StoredMessage> {
+        let sessions = self.sessions.lock().await;
+        let mut messages: Vec<StoredMessage> = sessions
+            .iter()
+            .filter(|(sid, _)| visible(sid))
+            .flat_map(|(_, log)| log.messages.iter().rev().take(limit).cloned())
+            .collect();
+        messages.sort_by_key(|m| m.timestamp);
+        let skip = messages.len().saturating_sub(limit);
+        messages.split_off(skip)
+    }
+
+    /// Sessions used since startup
+    pub async fn sessions(&self) -> Vec<String> {
+        self.sessions.lock().await.keys().cloned().collect()
+    }
+
+    async fn load<'a>(&self, sessions: &'a mut HashMap<String, SessionLog>, session_id: &str) -> &'a mut SessionLog {
+        if !sessions.contains_key(session_id) {
+            let log = match self.path(session_id) {
+                Some(path) => read_log(&path).await,
+                None => SessionLog::default(),
+            };
+            sessions.insert(session_id.to_string(), log);
+        }
+        sessions.get_mut(session_id).expect("session log was just inserted")
+    }
+
+    /// Drop what the retention no longer allows, returning how many
+    fn retain(&self, log: &mut SessionLog) -> usize {
+        let before = log.messages.len();
+        while log.messages.len() > self.retention.max_messages {
+            log.messages.pop_front();
+        }
+        if let Some(max_age) = self.retention.max_age {
+            let oldest = now_ms().saturating_sub(max_age.as_millis() as u64);
+            while log.messages.front().is_some_and(|m| m.timestamp < oldest) {
+                log.messages.pop_front();
+            }
+        }
+        let dropped = before - log.messages.len();
+        log.stale += dropped;
+        dropped
+    }
+
+    fn path(&self, session_id: &str) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(format!("{}.jsonl", file_name(session_id))))
+    }
+}
+
+/// `session_id` with everything but ASCII letters, digits, `-` and `_`
+/// escaped, so it cannot leave the history directory
+fn file_name(session_id: &str) -> String {
+    let mut name = String::with_capacity(session_id.len());
+    for b in session_id.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+            name.push(b as char);
+        } else {
+            name.push_str(&format!("%{:02X}", b));
+        }
+    }
+    name
+}
+
+async fn read_log(path: &Path) -> SessionLog {
+    let text = match tokio::fs::read_to_string(path).await {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return SessionLog::default(),
+        Err(e) => {
+            error!("failed to read history {}: {}", path.display(), e);
+            return SessionLog::default();
+        }
+    };
+    let mut log = SessionLog::default();
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        match serde_json::from_str::<StoredMessage>(line) {
+            Ok(message) => {
+                log.last_id = log.last_id.max(message.id);
+                log.messages.push_back(message);
+            }
+            Err(e) => {
+                warn!("skipping unreadable line in {}: {}", path.display(), e);
+                log.stale += 1;
+            }
+        }
+    }
+    log
+}
+
+async fn append_line(path: &Path, message: &StoredMessage) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(&line).await?;
+    file.flush().await?;
+    Ok(())
+}
+
+async fn rewrite(path: &Path, messages: &VecDeque<StoredMessage>) -> anyhow::Result<()> {
+    let mut text = Vec::new();
+    for message in messages {
+        text.extend(serde_json::to_vec(message)?);
+        text.push(b'\n');
+    }
+    let tmp = path.with_extension("jsonl.tmp");
+    tokio::fs::write(&tmp, text).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(text: &str) -> OutputEvent {
+        OutputEvent {
+            target: "default".to_string(),
+            source: "test".to_string(),
+            session_id: Some("s/1".to_string()),
+            content: serde_json::json!({ "content": text }),
+            style: "neutral".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_survive_a_restart_within_retention() {
+        let dir = std::env::temp_dir().join(format!("robot-history-{}", uuid::Uuid::new_v4()));
+        let retention = Retention {
+            max_messages: 4,
+            max_age: None,
+        };
+        let history = History::open(&dir, retention).await.unwrap();
+        for i in 1..=6 {
+            assert_eq!(history.append("s/1", output(&i.to_string())).await.id, i);
+        }
+        assert!(dir.join("s%2F1.jsonl").exists());
+
+        let ids = |messages: Vec<StoredMessage>| messages.iter().map(|m| m.id).collect::<Vec<_>>();
+        let page = |before, after, limit| Page { before, after, limit };
+        assert_eq!(ids(history.page("s/1", Page::default()).await), vec![3, 4, 5, 6]);
+        assert_eq!(ids(history.page("s/1", page(Some(6), None, Some(2))).await), vec![4, 5]);
+        assert_eq!(ids(history.page("s/1", page(None, Some(3), Some(2))).await), vec![4, 5]);
+
+        // Ids continue after a restart and the expired messages stay gone
+        let history = History::open(&dir, retention).await.unwrap();
+        assert_eq!(ids(history.replay("s/1", Some(4)).await), vec![5, 6]);
+        assert_eq!(history.append("s/1", output("7")).await.id, 7);
+        assert_eq!(ids(history.page("s/1", Page::default()).await), vec![4, 5, 6, 7]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,2 +1,9 @@
+pub mod bot_console;
+pub mod email_console;
+pub mod history;
+pub mod stdio_console;
+pub mod web_assets;
 pub mod web_console;
+pub mod web_socket;
 pub mod tcp_console;
+pub mod webhook;
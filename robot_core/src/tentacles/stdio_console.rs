@@ -0,0 +1,859 @@
+//! Interactive console on stdin/stdout, for trying the robot locally.
+//!
+//! Each line typed is an input of the console's current session,
+//! `console:<uuid>`. Outputs are printed by type: replies as text, reasoning
+//! and tool results dimmed, progress as a bar redrawn in place. An
+//! elicitation asks for its fields one prompt at a time and answers with
+//! the JSON object of the values given.
+//!
+//! Lines starting with `/` are commands:
+//!
+//! - `/session` shows the current session, `/session new` starts another
+//! - `/tasks` lists the background tasks of the session
+//! - `/cancel` cancels what the session is doing, `/cancel <n>` aborts task
+//!   `n` of `/tasks` (its ordinal or id)
+//! - `/trace` prints the trace of the last input; `/trace on` prints every
+//!   trace as it finishes, `/trace off` stops
+//! - `/help` lists the commands
+use crate::core::input_handler::{InputHandler, SourceMetadata, SourceType, TypedInputHandler};
+use crate::core::output_handler::{OutputHandler, OutputMetadata, TypedOutputHandler};
+use crate::core::queue::{self, Overflow, QueueConfig};
+use crate::core::router::HandlerMarker;
+use crate::core::session::SessionManager;
+use crate::core::synthesis::TOOL_RESULT_TYPE;
+use crate::core::trace::{Trace, TraceEvent, TraceSink, output_text};
+use crate::utils::{InputEvent, OutputEvent, StepSpec};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tracing::warn;
+use uuid::Uuid;
+
+pub struct StdioSource;
+impl SourceType for StdioSource {}
+
+/// Type marker for console handlers
+pub struct StdioHandler;
+impl HandlerMarker for StdioHandler {
+    const ID: &'static str = "stdio";
+}
+
+/// Lines are read one at a time; typing ahead of a busy core waits in stdin
+const INGRESS_QUEUE: QueueConfig = QueueConfig::new(16, Overflow::Block);
+
+const SESSION_PREFIX: &str = "console:";
+const PROGRESS_WIDTH: usize = 30;
+/// Tool results are cut to this many characters
+const MAX_RESULT_CHARS: usize = 300;
+
+const HELP: &str = "Commands:
+  /session          show the current session
+  /session new      start a new session
+  /tasks            list the background tasks of the session
+  /cancel           cancel what the session is doing
+  /cancel <n>       abort background task n (ordinal or id)
+  /trace            show the trace of the last input
+  /trace on|off     show every trace as it finishes, or stop
+  /help             show this help";
+
+/// Where the console reads and writes
+pub struct ConsoleIo {
+    reader: Box<dyn AsyncBufRead + Send + Unpin>,
+    writer: Box<dyn AsyncWrite + Send + Unpin>,
+    color: bool,
+}
+
+impl ConsoleIo {
+    /// The process's stdin and stdout, colored when stdout is a terminal
+    pub fn stdio() -> Self {
+        Self {
+            reader: Box::new(tokio::io::BufReader::new(tokio::io::stdin())),
+            writer: Box::new(tokio::io::stdout()),
+            color: std::io::stdout().is_terminal(),
+        }
+    }
+
+    /// Any reader and writer, without colors
+    pub fn new(
+        reader: impl AsyncBufRead + Send + Unpin + 'static,
+        writer: impl AsyncWrite + Send + Unpin + 'static,
+    ) -> Self {
+        Self {
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+            color: false,
+        }
+    }
+}
+
+/// One field of an elicitation being asked for
+#[derive(Debug)]
+struct Field {
+    name: String,
+    kind: String,
+    description: Option<String>,
+    options: Vec<Value>,
+    default: Option<Value>,
+    required: bool,
+}
+
+/// An elicitation being answered field by field
+struct Pending {
+    session_id: String,
+    fields: Vec<Field>,
+    answers: Map<String, Value>,
+}
+
+impl Pending {
+    fn next_field(&self) -> Option<&Field> {
+        self.fields.iter().find(|f| !self.answers.contains_key(&f.name))
+    }
+}
+
+/// What a line typed during an elicitation leads to
+enum Answered {
+    /// The value was not accepted; the problem and the prompt again
+    Retry(String, String),
+    /// The prompt of the next field
+    Next(String),
+    /// Every field has a value: the session and the answers to send
+    Done(String, Value),
+}
+
+struct Console {
+    writer: Mutex<Box<dyn AsyncWrite + Send + Unpin>>,
+    color: bool,
+    session_id: StdMutex<String>,
+    pending: StdMutex<Option<Pending>>,
+    /// Whether the last thing printed is a progress bar waiting to be redrawn
+    progress_open: AtomicBool,
+    show_traces: AtomicBool,
+    last_traces: StdMutex<HashMap<String, Trace>>,
+    sessions: Option<Arc<SessionManager>>,
+    sender: queue::Sender<InputEvent>,
+}
+
+pub struct StdioInput {
+    console: Arc<Console>,
+    receiver: Mutex<queue::Receiver<InputEvent>>,
+    task: Option<tokio::task::JoinHandle<()>>,
+}
+
+pub struct StdioOutput {
+    console: Arc<Console>,
+}
+
+/// Keeps the last trace of each console session for `/trace`
+pub struct StdioTraceSink {
+    console: Arc<Console>,
+}
+
+fn new_session() -> String {
+    format!("{}{}", SESSION_PREFIX, Uuid::new_v4())
+}
+
+impl StdioInput {
+    fn create_metadata() -> SourceMetadata {
+        SourceMetadata {
+            name: "stdio".to_string(),
+            format_hint: "text".to_string(),
+            content_field: "content".to_string(),
+            description: "User input typed into the local console.".to_string(),
+        }
+    }
+
+    /// Start reading lines from `io`. `sessions` lets `/tasks` and
+    /// `/cancel <n>` reach the background tasks of the core's sessions.
+    pub fn start(io: ConsoleIo, sessions: Option<Arc<SessionManager>>) -> (Self, StdioOutput) {
+        let (sender, receiver) = queue::channel("stdio", INGRESS_QUEUE, None);
+        let console = Arc::new(Console {
+            writer: Mutex::new(io.writer),
+            color: io.color,
+            session_id: StdMutex::new(new_session()),
+            pending: StdMutex::new(None),
+            progress_open: AtomicBool::new(false),
+            show_traces: AtomicBool::new(false),
+            last_traces: StdMutex::new(HashMap::new()),
+            sessions,
+            sender,
+        });
+
+        let reader = console.clone();
+        let mut lines = io.reader.lines();
+        let task = tokio::spawn(async move {
+            reader
+                .print(&format!("Robot console, session {}. Type /help for commands.", reader.session()))
+                .await;
+            loop {
+                match lines.next_line().await {
+                    Ok(Some(line)) => {
+                        if let Err(e) = reader.on_line(line.trim()).await {
+                            reader.print(&reader.paint("31", &format!("error: {}", e))).await;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!("Failed to read the console: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        let input = Self {
+            console: console.clone(),
+            receiver: Mutex::new(receiver),
+            task: Some(task),
+        };
+        (input, StdioOutput { console })
+    }
+
+    /// A trace sink feeding `/trace`; install it with
+    /// [`crate::core::RobotCore::set_trace_sink`]
+    pub fn trace_sink(&self) -> Arc<dyn TraceSink> {
+        Arc::new(StdioTraceSink {
+            console: self.console.clone(),
+        })
+    }
+
+    /// The session lines are currently sent to
+    pub fn session(&self) -> String {
+        self.console.session()
+    }
+}
+
+impl Console {
+    fn session(&self) -> String {
+        self.session_id.lock().unwrap().clone()
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        match self.color {
+            true => format!("\x1b[{}m{}\x1b[0m", code, text),
+            false => text.to_string(),
+        }
+    }
+
+    /// Print `text` on its own line, after a progress bar if one is open
+    async fn print(&self, text: &str) {
+        let mut out = String::new();
+        if self.progress_open.swap(false, Ordering::SeqCst) {
+            out.push('\n');
+        }
+        out.push_str(text);
+        out.push('\n');
+        self.write(&out).await;
+    }
+
+    async fn write(&self, text: &str) {
+        let mut writer = self.writer.lock().await;
+        if let Err(e) = async {
+            writer.write_all(text.as_bytes()).await?;
+            writer.flush().await
+        }
+        .await
+        {
+            warn!("Failed to write to the console: {}", e);
+        }
+    }
+
+    async fn on_line(&self, line: &str) -> Result<()> {
+        if line.is_empty() && self.pending.lock().unwrap().is_none() {
+            return Ok(());
+        }
+        if let Some(command) = line.strip_prefix('/') {
+            return self.command(command).await;
+        }
+        if self.pending.lock().unwrap().is_some() {
+            return self.answer(line).await;
+        }
+        self.send(line).await
+    }
+
+    async fn send(&self, content: &str) -> Result<()> {
+        self.send_to(&self.session(), content).await
+    }
+
+    async fn send_to(&self, session_id: &str, content: &str) -> Result<()> {
+        let event = InputEvent {
+            id: Uuid::new_v4(),
+            source: "stdio".to_string(),
+            session_id: Some(session_id.to_string()),
+            source_meta: Some(StdioInput::create_metadata()),
+            payload: json!({ "content": content }),
+            identity: None,
+            attachments: Vec::new(),
+        };
+        self.sender
+            .send(event)
+            .await
+            .map_err(|_| anyhow!("console input queue is closed"))
+    }
+
+    async fn command(&self, command: &str) -> Result<()> {
+        let mut words = command.split_whitespace();
+        match (words.next().unwrap_or_default(), words.next()) {
+            ("session", None) => self.print(&format!("Session {}", self.session())).await,
+            ("session", Some("new")) => {
+                let session_id = new_session();
+                *self.session_id.lock().unwrap() = session_id.clone();
+                *self.pending.lock().unwrap() = None;
+                self.print(&format!("Started session {}", session_id)).await;
+            }
+            ("tasks", None) => self.list_tasks().await?,
+            ("cancel", None) => {
+                // Like the cancel frames of the web console, an abandoned
+                // elicitation included
+                let pending = self.pending.lock().unwrap().take();
+                let session_id = pending.map_or_else(|| self.session(), |p| p.session_id);
+                self.send_to(&session_id, "cancel").await?;
+            }
+            ("cancel", Some(task)) => self.cancel_task(task).await?,
+            ("trace", None) => {
+                let trace = self.last_traces.lock().unwrap().get(&self.session()).cloned();
+                match trace {
+                    Some(trace) => self.print(&self.describe_trace(&trace)).await,
+                    None => self.print("No trace of this session yet").await,
+                }
+            }
+            ("trace", Some(toggle @ ("on" | "off"))) => {
+                self.show_traces.store(toggle == "on", Ordering::SeqCst);
+                self.print(&format!("Traces {}", toggle)).await;
+            }
+            ("help", _) => self.print(HELP).await,
+            _ => self.print(&format!("Unknown command /{}; /help lists the commands", command)).await,
+        }
+        Ok(())
+    }
+
+    fn sessions(&self) -> Result<&SessionManager> {
+        self.sessions
+            .as_deref()
+            .ok_or_else(|| anyhow!("this console cannot see the core's tasks"))
+    }
+
+    async fn list_tasks(&self) -> Result<()> {
+        let tasks = self.sessions()?.tasks(&self.session()).await;
+        if tasks.is_empty() {
+            self.print("No background tasks").await;
+            return Ok(());
+        }
+        let now = chrono::Utc::now();
+        let lines: Vec<String> = tasks
+            .iter()
+            .map(|t| {
+                let secs = (now - t.start_time).num_seconds();
+                format!("  #{} {} ({}s) {}  [{}]", t.ordinal, t.name, secs, t.original_prompt, t.id)
+            })
+            .collect();
+        self.print(&format!("Background tasks:\n{}", lines.join("\n"))).await;
+        Ok(())
+    }
+
+    async fn cancel_task(&self, task: &str) -> Result<()> {
+        let sessions = self.sessions()?;
+        let session_id = self.session();
+        let tasks = sessions.tasks(&session_id).await;
+        let found = tasks
+            .iter()
+            .find(|t| t.id == task || task.trim_start_matches('#').parse() == Ok(t.ordinal));
+        let Some(found) = found else {
+            self.print(&format!("No task {}; /tasks lists them", task)).await;
+            return Ok(());
+        };
+        match sessions.cancel_task(&session_id, &found.id).await {
+            true => self.print(&format!("Cancelled task #{} {}", found.ordinal, found.name)).await,
+            false => self.print(&format!("Task #{} already finished", found.ordinal)).await,
+        }
+        Ok(())
+    }
+
+    /// Take `line` as the value of the field asked for, then ask for the
+    /// next one or send the answers
+    async fn answer(&self, line: &str) -> Result<()> {
+        let answered = {
+            let mut pending = self.pending.lock().unwrap();
+            let Some(state) = pending.as_mut() else {
+                return Ok(());
+            };
+            let asked = state.next_field().expect("pending elicitations have fields left");
+            match parse_answer(asked, line) {
+                Err(problem) => Answered::Retry(problem, self.prompt(asked)),
+                Ok(value) => {
+                    let name = asked.name.clone();
+                    state.answers.insert(name, value.unwrap_or(Value::Null));
+                    match state.next_field() {
+                        Some(field) => Answered::Next(self.prompt(field)),
+                        None => {
+                            let done = pending.take().unwrap();
+                            // Fields left empty are left out
+                            let answers = done.answers.into_iter().filter(|(_, v)| !v.is_null()).collect();
+                            Answered::Done(done.session_id, Value::Object(answers))
+                        }
+                    }
+                }
+            }
+        };
+        match answered {
+            Answered::Retry(problem, prompt) => {
+                self.print(&self.paint("31", &problem)).await;
+                self.write(&prompt).await;
+            }
+            Answered::Next(prompt) => self.write(&prompt).await,
+            Answered::Done(session_id, answers) => self.send_to(&session_id, &answers.to_string()).await?,
+        }
+        Ok(())
+    }
+
+    fn prompt(&self, field: &Field) -> String {
+        let mut prompt = format!("  {}", field.name);
+        if let Some(description) = &field.description {
+            prompt.push_str(&format!(" ({})", description));
+        }
+        if !field.options.is_empty() {
+            let options: Vec<String> = field.options.iter().map(shown).collect();
+            prompt.push_str(&format!(" [{}]", options.join("/")));
+        } else if field.kind == "boolean" {
+            prompt.push_str(" [y/n]");
+        }
+        if let Some(default) = &field.default {
+            prompt.push_str(&format!(" = {}", shown(default)));
+        } else if !field.required {
+            prompt.push_str(" (optional)");
+        }
+        format!("{}: ", self.paint("33", &prompt))
+    }
+
+    /// Start asking for the fields of an elicitation
+    async fn ask(&self, session_id: &str, content: &Value) {
+        let message = content
+            .get("message")
+            .and_then(|m| m.as_str())
+            .unwrap_or("Please provide the missing input");
+        let fields = schema_fields(content.get("schema").unwrap_or(&Value::Null));
+        self.print(&self.paint("33", &format!("? {}", message))).await;
+        if fields.is_empty() {
+            // Nothing structured to ask for; the next line is the answer
+            return;
+        }
+        self.print(&self.paint("2", "  (/cancel to cancel)")).await;
+        let prompt = self.prompt(&fields[0]);
+        *self.pending.lock().unwrap() = Some(Pending {
+            session_id: session_id.to_string(),
+            fields,
+            answers: Map::new(),
+        });
+        self.write(&prompt).await;
+    }
+
+    async fn show(&self, event: &OutputEvent) {
+        let Some(session_id) = event.session_id.as_deref().filter(|s| s.starts_with(SESSION_PREFIX)) else {
+            return;
+        };
+        // Sessions left with `/session new` are still heard from
+        let tag = match session_id == self.session() {
+            true => String::new(),
+            false => format!("[{}] ", session_id[SESSION_PREFIX.len()..].chars().take(8).collect::<String>()),
+        };
+        let content = &event.content;
+        let field = |name: &str| content.get(name).and_then(|v| v.as_str()).map(str::to_string);
+        match content.get("type").and_then(|t| t.as_str()) {
+            Some("user_message") => {}
+            Some("progress") => self.show_progress(&tag, content).await,
+            Some("think") => {
+                let thought = field("content").unwrap_or_default();
+                self.print(&self.paint("2", &format!("{}💭 {}", tag, thought.trim()))).await;
+            }
+            Some(t) if t == TOOL_RESULT_TYPE => {
+                let tool = field("tool").unwrap_or_default();
+                let result = content.get("result").map(result_text).unwrap_or_default();
+                self.print(&self.paint("2", &format!("{}🔧 {} → {}", tag, tool, truncate(&result)))).await;
+            }
+            Some("tool_cancel") => {
+                let message = field("message").unwrap_or_else(|| "Cancelled".to_string());
+                self.print(&self.paint("33", &format!("{}{}", tag, message))).await;
+            }
+            Some("elicitation") => self.ask(session_id, content).await,
+            None if content.get("schema").is_some() => self.ask(session_id, content).await,
+            _ => {
+                let text = output_text(event);
+                self.print(&format!("{}{} {}", tag, self.paint("36", "robot>"), text)).await;
+            }
+        }
+    }
+
+    /// Draw a progress bar over the previous one
+    async fn show_progress(&self, tag: &str, content: &Value) {
+        let message = content.get("message").and_then(|m| m.as_str()).unwrap_or("Working");
+        let progress = content.get("progress").and_then(|p| p.as_f64()).unwrap_or(0.0);
+        let bar = match content.get("total").and_then(|t| t.as_f64()) {
+            Some(total) if total > 0.0 => {
+                let ratio = (progress / total).clamp(0.0, 1.0);
+                let filled = (ratio * PROGRESS_WIDTH as f64).round() as usize;
+                format!(
+                    "[{}{}] {:>3.0}%",
+                    "#".repeat(filled),
+                    "-".repeat(PROGRESS_WIDTH - filled),
+                    ratio * 100.0
+                )
+            }
+            _ => format!("[{}]", progress),
+        };
+        let line = format!("\r\x1b[2K{}{} {}", tag, bar, message);
+        let line = match self.color {
+            true => line,
+            // Without a terminal every update is a line of its own
+            false => format!("{}{} {}\n", tag, bar, message),
+        };
+        if self.color {
+            self.progress_open.store(true, Ordering::SeqCst);
+        }
+        self.write(&line).await;
+    }
+
+    /// A summary of what happened while handling one input
+    fn describe_trace(&self, trace: &Trace) -> String {
+        let mut lines = vec![format!("trace {} ({} ms)", trace.trace_id, trace.duration_ms)];
+        for entry in &trace.events {
+            let line = match &entry.event {
+                TraceEvent::Perception { .. } | TraceEvent::ToolList { .. } => continue,
+                TraceEvent::ToolSchema { .. } | TraceEvent::RequiredFields { .. } => continue,
+                TraceEvent::Intent { decision } => format!("intent {}", decision),
+                TraceEvent::Template { name } => format!("template {}", name),
+                TraceEvent::Plan { plan } => {
+                    let steps: Vec<String> = plan.steps.iter().map(step_name).collect();
+                    format!("plan {}", steps.join(", "))
+                }
+                TraceEvent::Llm {
+                    label,
+                    error,
+                    duration_ms,
+                    ..
+                } => format!(
+                    "llm {} {} ms{}",
+                    label,
+                    duration_ms,
+                    error.as_ref().map(|e| format!(" failed: {}", e)).unwrap_or_default()
+                ),
+                TraceEvent::ToolCall {
+                    tool,
+                    args,
+                    error,
+                    duration_ms,
+                    ..
+                } => format!(
+                    "tool {} {} {} ms{}",
+                    tool,
+                    args,
+                    duration_ms,
+                    error.as_ref().map(|e| format!(" failed: {}", e)).unwrap_or_default()
+                ),
+                TraceEvent::Output { event } => format!("output {}", truncate(&output_text(event))),
+            };
+            lines.push(format!("  {:>6} ms  {}", entry.at_ms, line));
+        }
+        self.paint("2", &lines.join("\n"))
+    }
+}
+
+fn step_name(step: &StepSpec) -> String {
+    match step {
+        StepSpec::Tool { name, .. } => name.clone(),
+        StepSpec::Branch { .. } => "branch".to_string(),
+        StepSpec::ForEach { .. } => "for-each".to_string(),
+        StepSpec::Parallel { steps } => {
+            let steps: Vec<String> = steps.iter().map(step_name).collect();
+            format!("parallel({})", steps.join(", "))
+        }
+        other => format!("{:?}", other).to_lowercase(),
+    }
+}
+
+/// The text parts of a tool result, or its JSON
+fn result_text(result: &Value) -> String {
+    let texts: Vec<&str> = result
+        .get("content")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|part| part.get("text").and_then(|t| t.as_str()))
+        .collect();
+    match texts.is_empty() {
+        true => result.to_string(),
+        false => texts.join(" "),
+    }
+}
+
+fn truncate(text: &str) -> String {
+    let text = text.replace('\n', " ");
+    match text.char_indices().nth(MAX_RESULT_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+fn shown(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// The fields of an elicitation schema, the required ones first
+fn schema_fields(schema: &Value) -> Vec<Field> {
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(|r| r.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|r| r.as_str())
+        .collect();
+    let Some(properties) = schema.get("properties").and_then(|p| p.as_object()) else {
+        return Vec::new();
+    };
+    let mut fields: Vec<Field> = properties
+        .iter()
+        .map(|(name, property)| Field {
+            name: name.clone(),
+            kind: property
+                .get("type")
+                .and_then(|t| t.as_str())
+                .unwrap_or("string")
+                .to_string(),
+            description: property
+                .get("description")
+                .or(property.get("title"))
+                .and_then(|d| d.as_str())
+                .map(str::to_string),
+            options: property
+                .get("enum")
+                .and_then(|e| e.as_array())
+                .cloned()
+                .unwrap_or_default(),
+            default: property.get("default").cloned(),
+            required: required.contains(&name.as_str()),
+        })
+        .collect();
+    fields.sort_by_key(|f| !f.required);
+    fields
+}
+
+/// The value typed for `field`; `None` for an optional field left empty
+fn parse_answer(field: &Field, text: &str) -> Result<Option<Value>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return match (&field.default, field.required) {
+            (Some(default), _) => Ok(Some(default.clone())),
+            (None, false) => Ok(None),
+            (None, true) => Err(format!("{} is required", field.name)),
+        };
+    }
+    if !field.options.is_empty() {
+        // The option itself, or its number in the list
+        let chosen = field
+            .options
+            .iter()
+            .find(|o| shown(o).eq_ignore_ascii_case(text))
+            .or_else(|| text.parse::<usize>().ok().and_then(|n| field.options.get(n.wrapping_sub(1))));
+        return chosen
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| format!("{} must be one of the options", field.name));
+    }
+    match field.kind.as_str() {
+        "boolean" => match text.to_ascii_lowercase().as_str() {
+            "y" | "yes" | "true" | "1" => Ok(Some(json!(true))),
+            "n" | "no" | "false" | "0" => Ok(Some(json!(false))),
+            _ => Err(format!("{} is yes or no", field.name)),
+        },
+        "integer" => text
+            .parse::<i64>()
+            .map(|n| Some(json!(n)))
+            .map_err(|_| format!("{} must be a whole number", field.name)),
+        "number" => text
+            .parse::<f64>()
+            .map(|n| Some(json!(n)))
+            .map_err(|_| format!("{} must be a number", field.name)),
+        "array" => Ok(Some(json!(
+            text.split(',').map(str::trim).filter(|s| !s.is_empty()).collect::<Vec<_>>()
+        ))),
+        "object" => serde_json::from_str(text)
+            .map(Some)
+            .map_err(|_| format!("{} must be a JSON object", field.name)),
+        _ => Ok(Some(json!(text))),
+    }
+}
+
+impl Drop for StdioInput {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl InputHandler for StdioInput {
+    async fn poll(&self) -> Result<Option<InputEvent>> {
+        Ok(self.receiver.lock().await.recv().await)
+    }
+
+    fn metadata(&self) -> Option<SourceMetadata> {
+        Some(Self::create_metadata())
+    }
+}
+
+#[async_trait]
+impl TypedInputHandler<StdioSource> for StdioInput {
+    async fn poll(&self) -> Result<Option<InputEvent>> {
+        <Self as InputHandler>::poll(self).await
+    }
+}
+
+#[async_trait]
+impl OutputHandler for StdioOutput {
+    async fn emit(&self, event: OutputEvent) -> Result<()> {
+        self.console.show(&event).await;
+        Ok(())
+    }
+
+    fn metadata(&self) -> Option<OutputMetadata> {
+        Some(OutputMetadata {
+            name: "stdio".to_string(),
+            format: "text".to_string(),
+            description: "Outputs printed to the local console.".to_string(),
+        })
+    }
+
+    fn show_intermediate(&self) -> bool {
+        true
+    }
+}
+
+#[async_trait]
+impl TypedOutputHandler<StdioSource> for StdioOutput {
+    async fn emit(&self, event: OutputEvent) -> Result<()> {
+        <Self as OutputHandler>::emit(self, event).await
+    }
+}
+
+#[async_trait]
+impl TraceSink for StdioTraceSink {
+    async fn write(&self, trace: &Trace) -> Result<()> {
+        if !trace.session_id.starts_with(SESSION_PREFIX) {
+            return Ok(());
+        }
+        if self.console.show_traces.load(Ordering::SeqCst) {
+            self.console.print(&self.console.describe_trace(trace)).await;
+        }
+        self.console
+            .last_traces
+            .lock()
+            .unwrap()
+            .insert(trace.session_id.clone(), trace.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, BufReader, DuplexStream};
+
+    /// Read what the console printed until `text` shows up
+    async fn expect(screen: &mut DuplexStream, seen: &mut String, text: &str) {
+        let read = async {
+            while !seen.contains(text) {
+                let mut buf = [0; 1024];
+                let n = screen.read(&mut buf).await.unwrap();
+                assert!(n > 0, "console closed before printing {:?}:\n{}", text, seen);
+                seen.push_str(&String::from_utf8_lossy(&buf[..n]));
+            }
+        };
+        if tokio::time::timeout(Duration::from_secs(5), read).await.is_err() {
+            panic!("console did not print {:?}:\n{}", text, seen);
+        }
+        seen.drain(..seen.find(text).unwrap() + text.len());
+    }
+
+    async fn next_input(input: &StdioInput) -> InputEvent {
+        let polled = tokio::time::timeout(Duration::from_secs(5), InputHandler::poll(input));
+        polled.await.unwrap().unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn sends_lines_and_asks_for_elicitation_fields() {
+        let (mut keyboard, stdin) = tokio::io::duplex(4096);
+        let (stdout, mut screen) = tokio::io::duplex(4096);
+        let (input, output) = StdioInput::start(ConsoleIo::new(BufReader::new(stdin), stdout), None);
+        let mut seen = String::new();
+        expect(&mut screen, &mut seen, "Type /help").await;
+
+        keyboard.write_all(b"convert the video\n").await.unwrap();
+        let event = next_input(&input).await;
+        let session_id = event.session_id.clone().unwrap();
+        assert!(session_id.starts_with("console:"));
+        assert_eq!(event.payload["content"], "convert the video");
+
+        let show = |content: Value| OutputEvent {
+            target: "default".to_string(),
+            source: "mcp".to_string(),
+            session_id: Some(session_id.clone()),
+            content,
+            style: "neutral".to_string(),
+        };
+        OutputHandler::emit(&output, show(json!({ "type": "progress", "progress": 1, "total": 4, "message": "probing" })))
+            .await
+            .unwrap();
+        expect(&mut screen, &mut seen, "[########----------------------]  25% probing").await;
+
+        // Each field is asked for in turn; bad values are asked again
+        let elicitation = json!({
+            "message": "Which output?",
+            "schema": {
+                "type": "object",
+                "properties": {
+                    "format": { "type": "string", "enum": ["mp4", "webm"] },
+                    "crf": { "type": "integer", "description": "quality" },
+                    "note": { "type": "string" }
+                },
+                "required": ["format", "crf"]
+            }
+        });
+        OutputHandler::emit(&output, show(elicitation)).await.unwrap();
+        expect(&mut screen, &mut seen, "? Which output?").await;
+        expect(&mut screen, &mut seen, "crf (quality): ").await;
+        keyboard.write_all(b"high\n").await.unwrap();
+        expect(&mut screen, &mut seen, "crf must be a whole number").await;
+        keyboard.write_all(b"23\n").await.unwrap();
+        expect(&mut screen, &mut seen, "format [mp4/webm]: ").await;
+        keyboard.write_all(b"2\n").await.unwrap();
+        expect(&mut screen, &mut seen, "note (optional): ").await;
+        keyboard.write_all(b"\n").await.unwrap();
+        let answer = next_input(&input).await;
+        assert_eq!(answer.session_id.as_deref(), Some(session_id.as_str()));
+        let answer: Value = serde_json::from_str(answer.payload["content"].as_str().unwrap()).unwrap();
+        assert_eq!(answer, json!({ "format": "webm", "crf": 23 }));
+
+        OutputHandler::emit(&output, show(json!({ "type": "text", "text": "Done." }))).await.unwrap();
+        expect(&mut screen, &mut seen, "robot> Done.").await;
+
+        // Commands are not sent to the core
+        keyboard.write_all(b"/tasks\n").await.unwrap();
+        expect(&mut screen, &mut seen, "cannot see the core's tasks").await;
+        keyboard.write_all(b"/session new\n").await.unwrap();
+        expect(&mut screen, &mut seen, "Started session console:").await;
+        keyboard.write_all(b"/cancel\n").await.unwrap();
+        let cancel = next_input(&input).await;
+        assert_eq!(cancel.payload["content"], "cancel");
+        assert_ne!(cancel.session_id.as_deref(), Some(session_id.as_str()));
+        assert_eq!(cancel.session_id, Some(input.session()));
+    }
+}
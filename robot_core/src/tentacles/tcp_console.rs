@@ -1,16 +1,22 @@
+use crate::core::auth::{Auth, Identity};
 use crate::core::input_handler::{InputHandler, SourceMetadata, SourceType, TypedInputHandler};
 use crate::core::output_handler::{OutputHandler, TypedOutputHandler};
 use crate::core::persona::OutputStyle;
+use crate::core::queue::{self, Overflow, QueueConfig, QueueLimits};
 use crate::core::router::HandlerMarker;
+use crate::core::runtime::Runtime;
+use crate::core::synthesis::is_intermediate;
 use crate::utils::{InputEvent, OutputEvent};
 use anyhow::Result;
 use async_trait::async_trait;
+use serde_json::{Value, json};
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpListener;
-use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio::sync::{Mutex, RwLock};
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
@@ -24,16 +30,44 @@ impl HandlerMarker for TcpHandler {
     const ID: &'static str = "tcp";
 }
 
+/// Lines read faster than the core takes them stop the reads of their
+/// connection, leaving the backpressure to TCP
+const INGRESS_QUEUE: QueueConfig = QueueConfig::new(256, Overflow::Block);
+
+/// Line protocol a client switches to by sending
+/// `{"type":"hello","protocol":"jsonl/1"}`, at any point of the connection.
+/// From then on every line is one JSON frame with a `type`:
+///
+/// - from the client: `message` (`content` text), `answer` (`content`
+///   answering an elicitation), `cancel`, and `login` (`token`)
+/// - from the server: `hello`, `login_required`, `logged_in` (`user_id`),
+///   `session` (`session_id`), `output` (`source`, `style`, `session_id`
+///   and `content` of an output event) and `error` (`message`)
+///
+/// Text sent before the switch, like the welcome line, is not JSON.
+pub const JSON_PROTOCOL: &str = "jsonl/1";
+
+/// A connected client
+struct Peer {
+    sender: queue::Sender<String>,
+    /// Whether it switched to [`JSON_PROTOCOL`]
+    json: Arc<AtomicBool>,
+}
+
 pub struct TcpSharedState {
-    // Map session_id to the sender for that connection
-    peers: HashMap<String, mpsc::UnboundedSender<String>>,
+    // Map session_id to the peer of that connection
+    peers: HashMap<String, Peer>,
+    auth: Arc<Auth>,
+    /// Set when the input is added to a core; user messages are echoed on it
+    runtime: Arc<OnceLock<Runtime>>,
 }
 
 pub struct TcpInput {
-    receiver: Arc<Mutex<mpsc::UnboundedReceiver<InputEvent>>>,
+    receiver: Arc<Mutex<queue::Receiver<InputEvent>>>,
     #[allow(dead_code)]
     state: Arc<RwLock<TcpSharedState>>,
     server_handle: Option<tokio::task::JoinHandle<()>>,
+    runtime: Arc<OnceLock<Runtime>>,
 }
 
 pub struct TcpOutput {
@@ -53,10 +87,19 @@ impl TcpInput {
     /// Creates a new TCP console listening on the specified port.
     /// Returns a tuple of (TcpInput, TcpOutput, port) sharing the same server state.
     pub async fn new(port: u16) -> Result<(Self, TcpOutput, u16)> {
-        let (input_sender, input_receiver) = mpsc::unbounded_channel();
+        Self::with_auth(port, Arc::new(Auth::disabled())).await
+    }
+
+    /// Like [`TcpInput::new`], but once `auth` is enabled each connection has
+    /// to `login <token>` before it can chat
+    pub async fn with_auth(port: u16, auth: Arc<Auth>) -> Result<(Self, TcpOutput, u16)> {
+        let (input_sender, input_receiver) = queue::channel("tcp", INGRESS_QUEUE, None);
         
+        let runtime = Arc::new(OnceLock::new());
         let state = Arc::new(RwLock::new(TcpSharedState {
             peers: HashMap::new(),
+            auth,
+            runtime: runtime.clone(),
         }));
 
         let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
@@ -92,6 +135,7 @@ impl TcpInput {
             receiver: Arc::new(Mutex::new(input_receiver)),
             state: state.clone(),
             server_handle: Some(server_handle),
+            runtime,
         };
 
         let output = TcpOutput {
@@ -106,26 +150,50 @@ async fn handle_connection(
     stream: tokio::net::TcpStream,
     addr: SocketAddr,
     state: Arc<RwLock<TcpSharedState>>,
-    input_sender: mpsc::UnboundedSender<InputEvent>,
+    input_sender: queue::Sender<InputEvent>,
 ) -> Result<()> {
     let session_id = Uuid::new_v4().to_string();
     info!("Session {} started for {}", session_id, addr);
 
     let (reader, mut writer) = stream.into_split();
-    
+    let mut buf_reader = BufReader::new(reader);
+
+    // Welcome message
+    let _ = writer.write_all(b"Welcome to Robot TCP Console!\n").await;
+
+    let (auth, runtime) = {
+        let state = state.read().await;
+        (state.auth.clone(), state.runtime.clone())
+    };
+    // Clients may switch to JSON lines before logging in
+    let mut json = false;
+    let identity = if auth.is_enabled() {
+        match login(&auth, &mut buf_reader, &mut writer, &mut json).await? {
+            Some(identity) => {
+                auth.claim(&session_id, Some(&identity));
+                Some(identity)
+            }
+            None => {
+                info!("Closing unauthenticated connection from {}", addr);
+                return Ok(());
+            }
+        }
+    } else {
+        None
+    };
+    let _ = writer.write_all(session_line(&session_id, json).as_bytes()).await;
+
     // Channel for sending messages to this client
-    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let config = runtime.get().map_or(QueueLimits::default().subscriber, |r| r.queues().subscriber);
+    let (tx, mut rx) = queue::channel::<String>("subscriber", config, None);
+    let json = Arc::new(AtomicBool::new(json));
 
     // Register peer
     {
         let mut state_guard = state.write().await;
-        state_guard.peers.insert(session_id.clone(), tx);
+        state_guard.peers.insert(session_id.clone(), Peer { sender: tx.clone(), json: json.clone() });
     }
 
-    // Welcome message
-    let _ = writer.write_all(b"Welcome to Robot TCP Console!\n").await;
-    let _ = writer.write_all(format!("Session ID: {}\n", session_id).as_bytes()).await;
-
     // Task to write outgoing messages to the socket
     let mut write_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
@@ -141,7 +209,6 @@ async fn handle_connection(
     });
 
     // Read loop
-    let mut buf_reader = BufReader::new(reader);
     let mut line = String::new();
 
     loop {
@@ -151,7 +218,27 @@ async fn handle_connection(
                 match bytes_read {
                     Ok(0) => break, // EOF
                     Ok(_) => {
-                        let content = line.trim().to_string();
+                        let line = line.trim();
+                        if is_hello(line) {
+                            // Replies go through the peer queue to stay in
+                            // order with the outputs already queued
+                            if !json.swap(true, Ordering::SeqCst) {
+                                let _ = tx.send(frame(json!({"type": "hello", "protocol": JSON_PROTOCOL}))).await;
+                                let _ = tx.send(session_line(&session_id, true)).await;
+                            }
+                            continue;
+                        }
+                        let content = if json.load(Ordering::SeqCst) {
+                            match parse_frame(line) {
+                                Ok(content) => content,
+                                Err(message) => {
+                                    let _ = tx.send(frame(json!({"type": "error", "message": message}))).await;
+                                    continue;
+                                }
+                            }
+                        } else {
+                            line.to_string()
+                        };
                         if !content.is_empty() {
                             let event = InputEvent {
                                 id: Uuid::new_v4(),
@@ -165,10 +252,9 @@ async fn handle_connection(
                                         .unwrap_or_default()
                                         .as_millis() as u64,
                                 }),
+                                identity: identity.clone(),
+                                attachments: Vec::new(),
                             };
-                            
-                            // Publish to global event bus for elicitation consumers
-                            let _ = crate::utils::event_bus().send(event.clone());
 
                             // Echo user message to output bus for broadcast
                             let output_echo = OutputEvent {
@@ -185,9 +271,11 @@ async fn handle_connection(
                                 }),
                                 style: OutputStyle::Neutral.to_string(),
                             };
-                            let _ = crate::utils::output_bus().send(output_echo);
+                            if let Some(runtime) = runtime.get() {
+                                runtime.publish_output(output_echo);
+                            }
                             
-                            if let Err(_) = input_sender.send(event) {
+                            if input_sender.send(event).await.is_err() {
                                 break;
                             }
                         }
@@ -215,6 +303,116 @@ async fn handle_connection(
     Ok(())
 }
 
+/// One line of [`JSON_PROTOCOL`]
+fn frame(value: Value) -> String {
+    format!("{}\n", value)
+}
+
+fn session_line(session_id: &str, json: bool) -> String {
+    if json {
+        frame(json!({"type": "session", "session_id": session_id}))
+    } else {
+        format!("Session ID: {}\n", session_id)
+    }
+}
+
+/// Whether `line` asks to switch to [`JSON_PROTOCOL`]
+fn is_hello(line: &str) -> bool {
+    line.starts_with('{')
+        && serde_json::from_str::<Value>(line).is_ok_and(|v| {
+            v["type"] == "hello" && v["protocol"] == JSON_PROTOCOL
+        })
+}
+
+/// Input text of a client frame of [`JSON_PROTOCOL`]. Answers to
+/// elicitations are sent on as JSON text, like the other consoles do.
+fn parse_frame(line: &str) -> std::result::Result<String, String> {
+    let value: Value = serde_json::from_str(line).map_err(|e| format!("Invalid frame: {}", e))?;
+    match value["type"].as_str() {
+        Some("message") => value["content"]
+            .as_str()
+            .map(|c| c.trim().to_string())
+            .ok_or_else(|| "A message needs a text content".to_string()),
+        Some("answer") => match &value["content"] {
+            Value::Null => Err("An answer needs a content".to_string()),
+            Value::String(s) => Ok(s.clone()),
+            other => Ok(other.to_string()),
+        },
+        Some("cancel") => Ok("cancel".to_string()),
+        Some(other) => Err(format!("Unknown frame type: {}", other)),
+        None => Err("Frames need a type".to_string()),
+    }
+}
+
+const LOGIN_ATTEMPTS: usize = 3;
+
+/// Ask for `login <token>` until a token is accepted. `None` when the client
+/// gave up, disconnected or ran out of attempts. A hello switches `json` on,
+/// after which the exchange uses `login_required` and `login` frames.
+async fn login(
+    auth: &Auth,
+    reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+    writer: &mut tokio::net::tcp::OwnedWriteHalf,
+    json: &mut bool,
+) -> Result<Option<Identity>> {
+    let mut line = String::new();
+    let mut attempts = 0;
+    let mut prompt = true;
+    while attempts < LOGIN_ATTEMPTS {
+        if prompt {
+            if *json {
+                writer.write_all(frame(json!({"type": "login_required"})).as_bytes()).await?;
+            } else {
+                writer.write_all(b"Login required: send `login <token>`\n").await?;
+            }
+        }
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if is_hello(line) {
+            if !*json {
+                *json = true;
+                writer
+                    .write_all(frame(json!({"type": "hello", "protocol": JSON_PROTOCOL})).as_bytes())
+                    .await?;
+            }
+            prompt = true;
+            continue;
+        }
+        attempts += 1;
+        let token = if *json {
+            serde_json::from_str::<Value>(line)
+                .ok()
+                .filter(|v| v["type"] == "login")
+                .and_then(|v| v["token"].as_str().map(|t| t.to_string()))
+        } else {
+            line.strip_prefix("login ").map(|t| t.to_string())
+        };
+        if let Some(identity) = token.and_then(|token| auth.authenticate(&token)) {
+            if *json {
+                let logged_in = json!({"type": "logged_in", "user_id": identity.user_id});
+                writer.write_all(frame(logged_in).as_bytes()).await?;
+            } else {
+                writer
+                    .write_all(format!("Logged in as {}\n", identity.user_id).as_bytes())
+                    .await?;
+            }
+            return Ok(Some(identity));
+        }
+        if *json {
+            writer
+                .write_all(frame(json!({"type": "error", "message": "Invalid token"})).as_bytes())
+                .await?;
+        } else {
+            writer.write_all(b"Invalid token\n").await?;
+        }
+        prompt = true;
+    }
+    Ok(None)
+}
+
 #[async_trait]
 impl InputHandler for TcpInput {
     async fn poll(&self) -> Result<Option<InputEvent>> {
@@ -228,6 +426,10 @@ impl InputHandler for TcpInput {
     fn metadata(&self) -> Option<SourceMetadata> {
         Some(Self::create_metadata())
     }
+
+    fn bind(&self, runtime: &Runtime) {
+        let _ = self.runtime.set(runtime.clone());
+    }
 }
 
 #[async_trait]
@@ -251,24 +453,69 @@ impl OutputHandler for TcpOutput {
         info!("TcpOutput received event: {:?}", event);
         let state = self.state.read().await;
         
-        // User requested full debug output of the content, not just the "content" field.
-        let message = event.content.to_string();
+        // Plain text replies are printed as-is; everything else keeps the full
+        // debug output of the content, not just the "content" field.
+        let message = match event.content.get("type").and_then(|t| t.as_str()) {
+            Some("text") => event
+                .content
+                .get("text")
+                .and_then(|t| t.as_str())
+                .map(|t| t.to_string())
+                .unwrap_or_else(|| event.content.to_string()),
+            _ => event.content.to_string(),
+        };
 
         // Format output
         let formatted_msg = format!("[{}] {:?}: {}\n", event.source, event.style, message);
-
-        if event.target == "all" {
-            for sender in state.peers.values() {
-                let _ = sender.send(formatted_msg.clone());
-            }
-        } else if let Some(sid) = &event.session_id {
-            if let Some(sender) = state.peers.get(sid) {
-                let _ = sender.send(formatted_msg);
-            }
+        let json_msg = frame(json!({
+            "type": "output",
+            "source": event.source,
+            "style": event.style,
+            "session_id": event.session_id,
+            "content": event.content,
+        }));
+        // Tool results are only rendered by JSON clients
+        let text_too = !is_intermediate(&event);
+
+        let targets: Vec<&Peer> = if event.target == "all" {
+            // Broadcasts stay within the sessions of the same user
+            state
+                .peers
+                .iter()
+                .filter(|(sid, _)| {
+                    event
+                        .session_id
+                        .as_ref()
+                        .is_none_or(|from| state.auth.shares_owner(from, sid))
+                })
+                .map(|(_, peer)| peer)
+                .collect()
+        } else {
+            event
+                .session_id
+                .as_ref()
+                .and_then(|sid| state.peers.get(sid))
+                .into_iter()
+                .collect()
+        };
+        let targets: Vec<(queue::Sender<String>, bool)> = targets
+            .into_iter()
+            .map(|peer| (peer.sender.clone(), peer.json.load(Ordering::SeqCst)))
+            .filter(|(_, json)| *json || text_too)
+            .collect();
+        // Not holding the lock while a blocking peer queue waits
+        drop(state);
+        for (sender, json) in targets {
+            let msg = if json { &json_msg } else { &formatted_msg };
+            let _ = sender.send(msg.clone()).await;
         }
 
         Ok(())
     }
+
+    fn show_intermediate(&self) -> bool {
+        true
+    }
 }
 
 #[async_trait]
@@ -394,7 +641,7 @@ mod tests {
             source: "test".to_string(),
             session_id: event.session_id.clone(),
             content: serde_json::json!("Response from Test"),
-            style: OutputStyle::Neutral,
+            style: OutputStyle::Neutral.to_string(),
         };
         // Use explicit trait method call to avoid ambiguity
         OutputHandler::emit(&output, output_event).await?;
@@ -403,4 +650,156 @@ mod tests {
         
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_tcp_login() -> Result<()> {
+        use crate::core::auth::ApiTokens;
+        let auth = Arc::new(Auth::disabled().with_authenticator(ApiTokens::new().with("alice", "a-token")));
+        let (input, _output, port) = TcpInput::with_auth(0, auth.clone()).await?;
+
+        let stream = tokio::net::TcpStream::connect(("127.0.0.1", port)).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        let mut expect = async |text: &str| loop {
+            let line = lines.next_line().await.unwrap().expect("connection closed");
+            if line.contains(text) {
+                return line;
+            }
+        };
+
+        expect("Login required").await;
+        writer.write_all(b"login wrong\n").await?;
+        expect("Invalid token").await;
+        writer.write_all(b"login a-token\n").await?;
+        expect("Logged in as alice").await;
+        let session_id = expect("Session ID: ").await.trim_start_matches("Session ID: ").to_string();
+        writer.write_all(b"Hello\n").await?;
+
+        let event = tokio::time::timeout(Duration::from_secs(2), InputHandler::poll(&input))
+            .await
+            .expect("Timed out waiting for input")?
+            .unwrap();
+        assert_eq!(event.identity.unwrap().user_id, "alice");
+        assert_eq!(auth.owner(&session_id).as_deref(), Some("alice"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tcp_json_protocol() -> Result<()> {
+        let (input, output, port) = TcpInput::new(0).await?;
+
+        let stream = tokio::net::TcpStream::connect(("127.0.0.1", port)).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        let mut next_frame = async |kind: &str| loop {
+            let line = lines.next_line().await.unwrap().expect("connection closed");
+            if let Ok(value) = serde_json::from_str::<Value>(&line)
+                && value["type"] == kind
+            {
+                return value;
+            }
+        };
+
+        writer.write_all(b"{\"type\":\"hello\",\"protocol\":\"jsonl/1\"}\n").await?;
+        assert_eq!(next_frame("hello").await["protocol"], JSON_PROTOCOL);
+        let session_id = next_frame("session").await["session_id"].as_str().unwrap().to_string();
+
+        writer.write_all(b"not json\n").await?;
+        assert!(next_frame("error").await["message"].as_str().unwrap().starts_with("Invalid frame"));
+
+        writer.write_all(b"{\"type\":\"message\",\"content\":\"Hello\"}\n").await?;
+        writer.write_all(b"{\"type\":\"answer\",\"content\":{\"crf\":23}}\n").await?;
+        let mut contents = Vec::new();
+        for _ in 0..2 {
+            let event = tokio::time::timeout(Duration::from_secs(2), InputHandler::poll(&input))
+                .await
+                .expect("Timed out waiting for input")?
+                .unwrap();
+            assert_eq!(event.session_id.as_deref(), Some(session_id.as_str()));
+            contents.push(event.payload["content"].as_str().unwrap().to_string());
+        }
+        assert_eq!(contents, ["Hello", r#"{"crf":23}"#]);
+
+        let content = json!({"type": "tool_result", "tool": "ls", "args": {}, "result": "a"});
+        let event = OutputEvent {
+            target: "all".to_string(),
+            source: "task".to_string(),
+            session_id: Some(session_id.clone()),
+            content: content.clone(),
+            style: OutputStyle::Neutral.to_string(),
+        };
+        OutputHandler::emit(&output, event).await?;
+        let out = next_frame("output").await;
+        assert_eq!(out["content"], content);
+        assert_eq!(out["session_id"], session_id.as_str());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tcp_json_login_and_elicitation_round_trip() -> Result<()> {
+        use crate::core::auth::ApiTokens;
+        let auth = Arc::new(Auth::disabled().with_authenticator(ApiTokens::new().with("alice", "a-token")));
+        let (input, output, port) = TcpInput::with_auth(0, auth).await?;
+
+        let stream = tokio::net::TcpStream::connect(("127.0.0.1", port)).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        // After the hello every line is a frame
+        let mut next_frame = async |json: bool| loop {
+            let line = lines.next_line().await.unwrap().expect("connection closed");
+            match serde_json::from_str::<Value>(&line) {
+                Ok(value) => return value,
+                Err(_) if !json => continue,
+                Err(e) => panic!("not a frame: {} ({})", line, e),
+            }
+        };
+        let next_event = async || {
+            tokio::time::timeout(Duration::from_secs(2), InputHandler::poll(&input))
+                .await
+                .expect("Timed out waiting for input")
+                .unwrap()
+                .unwrap()
+        };
+
+        writer.write_all(b"{\"type\":\"hello\",\"protocol\":\"jsonl/1\"}\n").await?;
+        assert_eq!(next_frame(false).await, json!({"type": "hello", "protocol": JSON_PROTOCOL}));
+        assert_eq!(next_frame(true).await["type"], "login_required");
+        writer.write_all(b"{\"type\":\"login\",\"token\":\"wrong\"}\n").await?;
+        assert_eq!(next_frame(true).await, json!({"type": "error", "message": "Invalid token"}));
+        assert_eq!(next_frame(true).await["type"], "login_required");
+        writer.write_all(b"{\"type\":\"login\",\"token\":\"a-token\"}\n").await?;
+        assert_eq!(next_frame(true).await, json!({"type": "logged_in", "user_id": "alice"}));
+        let session = next_frame(true).await;
+        assert_eq!(session["type"], "session");
+        let session_id = session["session_id"].as_str().unwrap().to_string();
+
+        writer.write_all(b"{\"type\":\"message\",\"content\":\"push the stream\"}\n").await?;
+        let event = next_event().await;
+        assert_eq!(event.identity.unwrap().user_id, "alice");
+        assert_eq!(event.payload["content"], "push the stream");
+
+        let content = json!({
+            "type": "elicitation",
+            "message": "Where to push?",
+            "schema": {"properties": {"ip": {"type": "string"}}, "required": ["ip"]}
+        });
+        let event = OutputEvent {
+            target: "all".to_string(),
+            source: "mcp".to_string(),
+            session_id: Some(session_id.clone()),
+            content: content.clone(),
+            style: OutputStyle::Neutral.to_string(),
+        };
+        OutputHandler::emit(&output, event).await?;
+        let out = next_frame(true).await;
+        assert_eq!((out["type"].as_str(), &out["content"]), (Some("output"), &content));
+
+        writer.write_all(b"{\"type\":\"answer\",\"content\":{\"ip\":\"10.0.0.5\"}}\n").await?;
+        writer.write_all(b"{\"type\":\"cancel\"}\n").await?;
+        let answer = next_event().await;
+        assert_eq!(answer.session_id.as_deref(), Some(session_id.as_str()));
+        assert_eq!(answer.payload["content"], r#"{"ip":"10.0.0.5"}"#);
+        assert_eq!(next_event().await.payload["content"], "cancel");
+        Ok(())
+    }
 }
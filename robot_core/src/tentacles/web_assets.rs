@@ -0,0 +1,81 @@
+//! The web_chat frontend, embedded in the binary.
+//!
+//! `WebInput` serves `index.html`, `script.js` and `styles.css` at `/`, so a
+//! browser pointed at the input port needs no separate static hosting. The
+//! page asks `/api/config` where the output server is and which features and
+//! authentication the console has instead of relying on hardcoded ports.
+use crate::tentacles::web_console::WebInputState;
+use axum::{
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Path, content type and contents of each embedded file
+const ASSETS: &[(&str, &str, &[u8])] = &[
+    (
+        "index.html",
+        "text/html; charset=utf-8",
+        include_bytes!("../../../web_chat/index.html"),
+    ),
+    (
+        "script.js",
+        "text/javascript; charset=utf-8",
+        include_bytes!("../../../web_chat/script.js"),
+    ),
+    (
+        "styles.css",
+        "text/css; charset=utf-8",
+        include_bytes!("../../../web_chat/styles.css"),
+    ),
+];
+
+/// What the frontend needs to know about the console it was served by
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebConfig {
+    pub input_port: u16,
+    /// Port of the SSE and history server, when the console has one
+    pub output_port: Option<u16>,
+    /// Optional endpoints the console serves, e.g. `websocket`
+    pub features: Vec<String>,
+    /// `none`, or `token` when API calls need a token from `/api/login`
+    pub auth: String,
+}
+
+pub(crate) async fn config(State(state): State<Arc<WebInputState>>) -> Json<WebConfig> {
+    Json(state.config.clone())
+}
+
+pub(crate) async fn index() -> Response {
+    asset("index.html")
+}
+
+pub(crate) async fn file(Path(path): Path<String>) -> Response {
+    asset(&path)
+}
+
+fn asset(path: &str) -> Response {
+    match ASSETS.iter().find(|(name, _, _)| *name == path) {
+        Some((_, content_type, body)) => ([(header::CONTENT_TYPE, *content_type)], *body).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_embedded_files_only() {
+        let response = asset("script.js");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/javascript; charset=utf-8"
+        );
+        assert_eq!(asset("../Cargo.toml").status(), StatusCode::NOT_FOUND);
+        assert_eq!(asset("index.html").status(), StatusCode::OK);
+    }
+}
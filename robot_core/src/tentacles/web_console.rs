@@ -1,32 +1,36 @@
+use crate::core::auth::{Auth, Identity, is_channel_session};
 use crate::core::input_handler::{InputHandler, SourceMetadata, SourceType, TypedInputHandler};
 use crate::core::output_handler::{OutputHandler, TypedOutputHandler};
 use crate::core::persona::OutputStyle;
+use crate::core::queue::{self, Overflow, QueueConfig, QueueLimits};
 use crate::core::router::HandlerMarker;
+use crate::core::runtime::Runtime;
+use crate::core::uploads::{StoredFile, UploadConfig, UploadError, UploadStore};
+use crate::tentacles::history::{self, History, Page, StoredMessage};
+use crate::tentacles::web_assets::{self, WebConfig};
+use crate::tentacles::web_socket;
 use crate::utils::{InputEvent, OutputEvent};
 use anyhow::Result;
 use async_trait::async_trait;
 use axum::{
     Router,
     extract::{DefaultBodyLimit, Multipart, Path, Query, State},
-    http::StatusCode,
-    response::{Json, Sse},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Json, Sse},
     routing::{get, post},
 };
 use futures::stream::Stream;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::Infallible;
-use std::path::PathBuf;
-use std::sync::Arc;
-use tokio::sync::{Mutex, mpsc};
+use std::sync::{Arc, OnceLock};
+use tokio::io::AsyncReadExt;
+use tokio::sync::Mutex;
 use tokio_stream::StreamExt;
 use tower_http::cors::{Any, CorsLayer};
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
-use md5::{Digest, Md5};
-use tokio::sync::RwLock;
-
 // Web source type marker
 pub struct WebSource;
 impl SourceType for WebSource {}
@@ -37,14 +41,6 @@ impl HandlerMarker for WebHandler {
     const ID: &'static str = "web";
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct FileInfo {
-    pub md5: String,
-    pub path: String,
-    pub filename: String,
-    pub size: u64,
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WebMessage {
     pub content: String,
@@ -68,19 +64,101 @@ pub struct WebResponse {
     pub data: Option<serde_json::Value>,
 }
 
+/// Messages posted faster than the core takes them are answered with 429
+const INGRESS_QUEUE: QueueConfig = QueueConfig::new(256, Overflow::Reject);
+
 pub struct WebInputState {
-    pub input_sender: mpsc::UnboundedSender<InputEvent>,
-    pub file_registry: Arc<RwLock<HashMap<String, FileInfo>>>,
+    pub input_sender: queue::Sender<InputEvent>,
+    pub uploads: Arc<UploadStore>,
+    pub auth: Arc<Auth>,
+    /// Set when the input is added to a core; user messages are echoed on it
+    pub runtime: Arc<OnceLock<Runtime>>,
+    /// Output side of the console, for the WebSocket endpoint
+    pub output: Option<Arc<WebOutputState>>,
+    /// Reported to the frontend at `/api/config`
+    pub config: WebConfig,
 }
 
 pub struct WebOutputState {
-    pub messages: Arc<Mutex<Vec<OutputEvent>>>,
-    pub subscribers: Arc<Mutex<HashMap<String, HashMap<Uuid, mpsc::UnboundedSender<OutputEvent>>>>>,
+    /// Port the output server listens on
+    pub port: u16,
+    /// Every output sent to a session, numbered within it
+    pub history: Arc<History>,
+    pub subscribers: Arc<Mutex<HashMap<String, HashMap<Uuid, queue::Sender<StoredMessage>>>>>,
+    pub auth: Arc<Auth>,
+    /// Sizes the queue of each client; set when the output is added to a core
+    pub runtime: OnceLock<Runtime>,
+}
+
+impl WebOutputState {
+    /// Append `event` to the history of every session it goes to
+    async fn record(&self, event: &OutputEvent) -> Vec<StoredMessage> {
+        let sessions: Vec<String> = if event.target == "all" {
+            // Broadcast to everyone, or to the sessions of the same user
+            let subscribers: Vec<String> = self.subscribers.lock().await.keys().cloned().collect();
+            let mut known: Vec<String> = self
+                .history
+                .sessions()
+                .await
+                .iter()
+                .chain(subscribers.iter())
+                .chain(event.session_id.iter())
+                .cloned()
+                .collect();
+            known.sort();
+            known.dedup();
+            known
+                .into_iter()
+                .filter(|sid| {
+                    event
+                        .session_id
+                        .as_ref()
+                        .is_none_or(|from| self.auth.shares_owner(from, sid))
+                })
+                .collect()
+        } else {
+            event.session_id.iter().cloned().collect()
+        };
+        let mut stored = Vec::with_capacity(sessions.len());
+        for sid in sessions {
+            stored.push(self.history.append(&sid, event.clone()).await);
+        }
+        stored
+    }
+
+    /// Register a client of `session_id`; outputs stored from now on are
+    /// queued for it
+    pub async fn subscribe(&self, session_id: &str) -> (Uuid, queue::Receiver<StoredMessage>) {
+        let config = match self.runtime.get() {
+            Some(runtime) => runtime.queues().subscriber,
+            None => QueueLimits::default().subscriber,
+        };
+        let (sender, receiver) = queue::channel("subscriber", config, None);
+        let id = Uuid::new_v4();
+        self.subscribers
+            .lock()
+            .await
+            .entry(session_id.to_string())
+            .or_default()
+            .insert(id, sender);
+        (id, receiver)
+    }
+
+    pub async fn unsubscribe(&self, session_id: &str, id: Uuid) {
+        let mut subscribers = self.subscribers.lock().await;
+        if let Some(map) = subscribers.get_mut(session_id) {
+            map.remove(&id);
+            if map.is_empty() {
+                subscribers.remove(session_id);
+            }
+        }
+    }
 }
 
 pub struct WebInput {
-    pub receiver: Arc<Mutex<mpsc::UnboundedReceiver<InputEvent>>>,
+    pub receiver: Arc<Mutex<queue::Receiver<InputEvent>>>,
     pub server_handle: Option<tokio::task::JoinHandle<()>>,
+    runtime: Arc<OnceLock<Runtime>>,
 }
 
 impl WebInput {
@@ -96,19 +174,81 @@ impl WebInput {
     }
 
     pub async fn new(port: u16) -> Result<Self> {
-        let (input_sender, input_receiver) = mpsc::unbounded_channel();
+        Self::with_auth(port, Arc::new(Auth::disabled())).await
+    }
+
+    /// Like [`WebInput::new`], requiring a token from `auth` on every API
+    /// call once it is enabled
+    pub async fn with_auth(port: u16, auth: Arc<Auth>) -> Result<Self> {
+        let uploads = Arc::new(UploadStore::open(UploadConfig::default()).await?);
+        Self::start(port, auth, None, uploads).await
+    }
+
+    /// Like [`WebInput::with_auth`], also serving the WebSocket endpoint
+    /// `/api/ws/{session_id}`, which streams the outputs of `output`, and
+    /// keeping uploaded files in `uploads`
+    pub async fn with_output(
+        port: u16,
+        auth: Arc<Auth>,
+        output: &WebOutput,
+        uploads: Arc<UploadStore>,
+    ) -> Result<Self> {
+        Self::start(port, auth, Some(output.state.clone()), uploads).await
+    }
+
+    async fn start(
+        port: u16,
+        auth: Arc<Auth>,
+        output: Option<Arc<WebOutputState>>,
+        uploads: Arc<UploadStore>,
+    ) -> Result<Self> {
+        let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+        let port = listener.local_addr()?.port();
+        info!("WebInput server listening on port {}", port);
+
+        let (input_sender, input_receiver) = queue::channel("web", INGRESS_QUEUE, None);
+        let runtime = Arc::new(OnceLock::new());
+        let has_socket = output.is_some();
+        let mut features = vec!["upload".to_string(), "history".to_string()];
+        if has_socket {
+            features.push("websocket".to_string());
+        }
+        let config = WebConfig {
+            input_port: port,
+            output_port: output.as_ref().map(|o| o.port),
+            features,
+            auth: if auth.is_enabled() { "token" } else { "none" }.to_string(),
+        };
+        let body_limit = usize::try_from(uploads.config().max_file_size)
+            .unwrap_or(usize::MAX)
+            .saturating_add(1024 * 1024);
         let input_state = WebInputState {
             input_sender,
-            file_registry: Arc::new(RwLock::new(HashMap::new())),
+            uploads,
+            auth,
+            runtime: runtime.clone(),
+            output,
+            config,
         };
 
-        let app = Router::new()
+        let mut app = Router::new();
+        if has_socket {
+            app = app.route("/api/ws/{session_id}", get(web_socket::connect));
+        }
+        let app = app
+            .route("/", get(web_assets::index))
+            .route("/{file}", get(web_assets::file))
+            .route("/api/config", get(web_assets::config))
             .route("/api/send/{session_id}", post(send_message))
             .route("/api/session", post(create_session))
+            .route("/api/login", post(login))
             .route("/api/upload", post(upload_file))
             .route("/api/check_file", post(check_file))
+            .route("/api/files/{sha256}", get(download_file))
             .route("/health", get(health_check))
-            .layer(DefaultBodyLimit::max(1024 * 1024 * 1024)) // 1GB limit
+            .route("/metrics", get(metrics))
+            // One file per request, plus room for the multipart framing
+            .layer(DefaultBodyLimit::max(body_limit))
             .layer(
                 CorsLayer::new()
                     .allow_origin(Any)
@@ -117,9 +257,6 @@ impl WebInput {
             )
             .with_state(Arc::new(input_state));
 
-        let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
-        info!("WebInput server listening on port {}", port);
-
         let server_handle = tokio::spawn(async move {
             if let Err(e) = axum::serve(listener, app).await {
                 error!("WebInput server error: {}", e);
@@ -129,6 +266,7 @@ impl WebInput {
         Ok(Self {
             receiver: Arc::new(Mutex::new(input_receiver)),
             server_handle: Some(server_handle),
+            runtime,
         })
     }
 }
@@ -153,6 +291,10 @@ impl InputHandler for WebInput {
     fn metadata(&self) -> Option<SourceMetadata> {
         Some(Self::create_metadata())
     }
+
+    fn bind(&self, runtime: &Runtime) {
+        let _ = self.runtime.set(runtime.clone());
+    }
 }
 
 #[async_trait]
@@ -177,9 +319,28 @@ pub struct WebOutput {
 
 impl WebOutput {
     pub async fn new(port: u16) -> Result<Self> {
+        Self::with_auth(port, Arc::new(Auth::disabled())).await
+    }
+
+    /// Like [`WebOutput::new`], only serving a user the sessions they own
+    /// once `auth` is enabled
+    pub async fn with_auth(port: u16, auth: Arc<Auth>) -> Result<Self> {
+        Self::with_history(port, auth, Arc::new(History::default())).await
+    }
+
+    /// Like [`WebOutput::with_auth`], keeping the outputs of each session in
+    /// `history`
+    pub async fn with_history(port: u16, auth: Arc<Auth>, history: Arc<History>) -> Result<Self> {
+        let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+        let port = listener.local_addr()?.port();
+        info!("WebOutput server listening on port {}", port);
+
         let state = Arc::new(WebOutputState {
-            messages: Arc::new(Mutex::new(Vec::new())),
+            port,
+            history,
             subscribers: Arc::new(Mutex::new(HashMap::new())),
+            auth,
+            runtime: OnceLock::new(),
         });
 
         let app = Router::new()
@@ -187,6 +348,7 @@ impl WebOutput {
             .route("/api/messages/{session_id}", get(get_messages_by_session)) // 获取指定会话的消息
             .route("/api/subscribe", get(subscribe_to_messages)) //获取主动通知消息
             .route("/health", get(health_check))
+            .route("/metrics", get(metrics))
             .layer(
                 CorsLayer::new()
                     .allow_origin(Any)
@@ -195,9 +357,6 @@ impl WebOutput {
             )
             .with_state(state.clone());
 
-        let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
-        info!("WebOutput server listening on port {}", port);
-
         let server_handle = tokio::spawn(async move {
             if let Err(e) = axum::serve(listener, app).await {
                 error!("WebOutput server error: {}", e);
@@ -216,52 +375,44 @@ impl OutputHandler for WebOutput {
     async fn emit(&self, event: OutputEvent) -> Result<()> {
         info!("web output emitting message");
 
-        // Store the message
-        {
-            let mut messages = self.state.messages.lock().await;
-            messages.push(event.clone());
+        // Store the event in the history of each recipient session, then
+        // notify their subscribers. The senders are collected first so a
+        // client with a blocking queue does not hold the lock.
+        let stored = self.state.record(&event).await;
+        let targets: Vec<(Uuid, queue::Sender<StoredMessage>, StoredMessage)> = {
+            let subscribers = self.state.subscribers.lock().await;
+            stored
+                .into_iter()
+                .filter_map(|message| subscribers.get(&message.session_id).map(|map| (message, map)))
+                .flat_map(|(message, map)| {
+                    map.iter().map(move |(id, sender)| (*id, sender.clone(), message.clone()))
+                })
+                .collect()
+        };
 
-            // Keep only the last 100 messages to prevent memory growth
-            if messages.len() > 100 {
-                messages.remove(0);
+        let mut closed = Vec::new();
+        for (id, sender, message) in targets {
+            let sid = message.session_id.clone();
+            // A full queue has already dropped or refused the event
+            if let Err(queue::SendError::Closed(_)) = sender.send(message).await {
+                closed.push((sid, id));
             }
         }
-
-        // Notify subscribers
-        {
-            let mut subscribers = self.state.subscribers.lock().await;
-
-            if event.target == "all" {
-                // Broadcast to everyone
-                for map in subscribers.values_mut() {
-                    let mut to_remove = Vec::new();
-                    for (id, sender) in map.iter() {
-                        if sender.send(event.clone()).is_err() {
-                            to_remove.push(*id);
-                        }
-                    }
-                    for id in to_remove {
-                        map.remove(&id);
-                    }
-                }
-            } else if let Some(sid) = &event.session_id {
-                // Send only to session subscribers
-                if let Some(map) = subscribers.get_mut(sid) {
-                    let mut to_remove = Vec::new();
-                    for (id, sender) in map.iter() {
-                        if sender.send(event.clone()).is_err() {
-                            to_remove.push(*id);
-                        }
-                    }
-                    for id in to_remove {
-                        map.remove(&id);
-                    }
-                }
-            }
+        for (sid, id) in closed {
+            self.state.unsubscribe(&sid, id).await;
         }
 
         Ok(())
     }
+
+    /// The web UI renders tool results as collapsible details
+    fn show_intermediate(&self) -> bool {
+        true
+    }
+
+    fn bind(&self, runtime: &Runtime) {
+        let _ = self.state.runtime.set(runtime.clone());
+    }
 }
 
 #[async_trait]
@@ -274,16 +425,49 @@ impl TypedOutputHandler<WebSource> for WebOutput {
 // HTTP handlers for WebInput
 async fn send_message(
     State(state): State<Arc<WebInputState>>,
+    Path(path_session_id): Path<String>,
+    headers: HeaderMap,
     Json(message): Json<WebMessage>,
 ) -> Result<Json<WebResponse>, StatusCode> {
-    let mut combined_content = message.content.clone();
-    if let Some(files) = &message.files {
-        if !files.is_empty() {
-            combined_content.push_str("\n\n[System Note: User uploaded files]\n");
-            for file in files {
-                combined_content.push_str(&format!("- {}\n", file));
-            }
+    let identity = caller(&state.auth, &headers, None)?;
+    let mut message = message;
+    if state.auth.is_enabled() {
+        let session_id = message.session_id.get_or_insert(path_session_id);
+        if !state.auth.claim(session_id, identity.as_ref()) {
+            return Err(StatusCode::FORBIDDEN);
         }
+    } else if message.session_id.as_deref().is_some_and(is_channel_session) {
+        // Chats of the channel tentacles are not open to the web
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    submit(&state, message, identity).await?;
+    Ok(Json(WebResponse {
+        success: true,
+        message: "Message sent successfully".to_string(),
+        data: None,
+    }))
+}
+
+/// Queue `message` for the core and echo it to the other clients
+pub(crate) async fn submit(
+    state: &WebInputState,
+    message: WebMessage,
+    identity: Option<Identity>,
+) -> Result<Uuid, StatusCode> {
+    // Attachments travel beside the text; clients are shown resource links
+    let mut attachments = Vec::new();
+    let mut links = Vec::new();
+    for uri in message.files.iter().flatten() {
+        let file = state
+            .uploads
+            .resolve(uri)
+            .await
+            .filter(|f| state.uploads.may_read(f, identity.as_ref()))
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        state.uploads.touch(&file.sha256).await;
+        attachments.push(state.uploads.attachment(&file));
+        links.push(file.resource_link());
     }
 
     let input_event = InputEvent {
@@ -297,12 +481,20 @@ async fn send_message(
             description: "User input from web chat interface.".to_string(),
         }),
         payload: serde_json::json!({
-            "content": combined_content,
-            "timestamp": message.timestamp,
-            "files": message.files
+            "content": message.content.clone(),
+            "timestamp": message.timestamp
         }),
+        identity,
+        attachments,
     };
 
+    let id = input_event.id;
+    match state.input_sender.send(input_event).await {
+        Ok(()) => {}
+        Err(queue::SendError::Full(_)) => return Err(StatusCode::TOO_MANY_REQUESTS),
+        Err(queue::SendError::Closed(_)) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+
     // Echo user message to output bus for broadcast
     let output_echo = OutputEvent {
         target: "all".to_string(),
@@ -312,70 +504,100 @@ async fn send_message(
             "type": "user_message",
             "content": message.content,
             "timestamp": message.timestamp,
-            "files": message.files
+            "files": links
         }),
         style: OutputStyle::Neutral.to_string(),
     };
-    let _ = crate::utils::output_bus().send(output_echo);
-
-    // publish to global event bus for elicitation consumers
-    let _ = crate::utils::event_bus().send(input_event.clone());
-    match state.input_sender.send(input_event) {
-        Ok(_) => Ok(Json(WebResponse {
-            success: true,
-            message: "Message sent successfully".to_string(),
-            data: None,
-        })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    if let Some(runtime) = state.runtime.get() {
+        runtime.publish_output(output_echo);
     }
+
+    Ok(id)
 }
 
 // HTTP handlers for WebOutput
-async fn get_messages(State(state): State<Arc<WebOutputState>>) -> Json<Vec<OutputEvent>> {
-    let messages = state.messages.lock().await;
-    Json(messages.clone())
+#[derive(Deserialize)]
+struct RecentQuery {
+    limit: Option<usize>,
 }
 
+/// Newest messages of the sessions the caller may read
+async fn get_messages(
+    State(state): State<Arc<WebOutputState>>,
+    Query(q): Query<RecentQuery>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<StoredMessage>>, StatusCode> {
+    let identity = caller(&state.auth, &headers, None)?;
+    let limit = q.limit.unwrap_or(history::DEFAULT_PAGE).clamp(1, history::MAX_PAGE);
+    let messages = state
+        .history
+        .recent(limit, |sid| state.auth.may_read(sid, identity.as_ref()))
+        .await;
+    Ok(Json(messages))
+}
+
+/// One page of the history of a session: `?before=<id>` to scroll back,
+/// `?after=<id>` to catch up, `&limit=<n>` for the page size
 async fn get_messages_by_session(
     State(state): State<Arc<WebOutputState>>,
     Path(session_id): Path<String>,
-) -> Json<Vec<OutputEvent>> {
-    let messages = state.messages.lock().await;
-    let filtered: Vec<OutputEvent> = messages
-        .iter()
-        .filter(|msg| msg.session_id.as_deref() == Some(&session_id))
-        .cloned()
-        .collect();
-    Json(filtered)
+    Query(page): Query<Page>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<StoredMessage>>, StatusCode> {
+    let identity = caller(&state.auth, &headers, None)?;
+    if !state.auth.may_read(&session_id, identity.as_ref()) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(Json(state.history.page(&session_id, page).await))
 }
 
 #[derive(Deserialize)]
 struct SubscribeQuery {
     session_id: Option<String>,
+    /// EventSource cannot set an Authorization header
+    token: Option<String>,
+    /// For clients that open a new EventSource instead of letting the
+    /// browser reconnect with a `Last-Event-ID` header
+    last_event_id: Option<u64>,
 }
 
 async fn subscribe_to_messages(
     State(state): State<Arc<WebOutputState>>,
     Query(q): Query<SubscribeQuery>,
-) -> Sse<impl Stream<Item = Result<axum::response::sse::Event, Infallible>>> {
-    let subscriber_id = Uuid::new_v4();
-    let (sender, receiver) = mpsc::unbounded_channel();
-
-    {
-        let mut subscribers = state.subscribers.lock().await;
-        let sid = q.session_id.clone().unwrap_or_else(|| "web".to_string());
-        subscribers
-            .entry(sid)
-            .or_insert_with(HashMap::new)
-            .insert(subscriber_id, sender);
-    }
-
-    let stream = tokio_stream::wrappers::UnboundedReceiverStream::new(receiver).map(|event| {
-        let json = serde_json::to_string(&event).unwrap_or_default();
-        Ok(axum::response::sse::Event::default().data(json))
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<axum::response::sse::Event, Infallible>>>, StatusCode> {
+    let identity = caller(&state.auth, &headers, q.token.as_deref())?;
+    let sid = q.session_id.clone().unwrap_or_else(|| "web".to_string());
+    if !state.auth.may_read(&sid, identity.as_ref()) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    // Each event carries its history id; a reconnecting client gets what it
+    // missed first. Subscribing before the replay loses nothing in between,
+    // and live messages already replayed are skipped.
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .or(q.last_event_id);
+    let (_, receiver) = state.subscribe(&sid).await;
+    let missed = match last_event_id {
+        Some(id) => state.history.replay(&sid, Some(id)).await,
+        None => Vec::new(),
+    };
+    let last_sent = missed.last().map(|m| m.id).or(last_event_id).unwrap_or(0);
+
+    let live = futures::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|message| (message, receiver))
+    })
+    .filter(move |message| message.id > last_sent);
+    let stream = tokio_stream::iter(missed).chain(live).map(|message| {
+        let json = serde_json::to_string(&message.event).unwrap_or_default();
+        Ok(axum::response::sse::Event::default()
+            .id(message.id.to_string())
+            .data(json))
     });
 
-    Sse::new(stream)
+    Ok(Sse::new(stream))
 }
 
 async fn health_check() -> Json<WebResponse> {
@@ -386,10 +608,70 @@ async fn health_check() -> Json<WebResponse> {
     })
 }
 
-async fn create_session() -> Json<CreateSessionResponse> {
-    Json(CreateSessionResponse {
-        session_id: Uuid::new_v4().to_string(),
-    })
+async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        crate::core::metrics::render(),
+    )
+}
+
+/// Identity of the caller from `Authorization: Bearer <token>` or `token`.
+/// `None` when authentication is disabled.
+pub(crate) fn caller(auth: &Auth, headers: &HeaderMap, token: Option<&str>) -> Result<Option<Identity>, StatusCode> {
+    if !auth.is_enabled() {
+        return Ok(None);
+    }
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or(token)
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    auth.authenticate(token).map(Some).ok_or(StatusCode::UNAUTHORIZED)
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    token: String,
+}
+
+/// Exchange an API token for a session token, when session tokens are
+/// configured
+async fn login(
+    State(state): State<Arc<WebInputState>>,
+    Json(req): Json<LoginRequest>,
+) -> Result<Json<WebResponse>, StatusCode> {
+    if !state.auth.is_enabled() {
+        return Ok(Json(WebResponse {
+            success: true,
+            message: "Authentication is disabled".to_string(),
+            data: None,
+        }));
+    }
+    let identity = state.auth.authenticate(&req.token).ok_or(StatusCode::UNAUTHORIZED)?;
+    let data = match state.auth.issue_session_token(&identity) {
+        Some((token, expires_at)) => serde_json::json!({
+            "user_id": identity.user_id,
+            "token": token,
+            "expires_at": expires_at
+        }),
+        None => serde_json::json!({ "user_id": identity.user_id, "token": req.token }),
+    };
+    Ok(Json(WebResponse {
+        success: true,
+        message: "Logged in".to_string(),
+        data: Some(data),
+    }))
+}
+
+async fn create_session(
+    State(state): State<Arc<WebInputState>>,
+    headers: HeaderMap,
+) -> Result<Json<CreateSessionResponse>, StatusCode> {
+    let identity = caller(&state.auth, &headers, None)?;
+    let session_id = Uuid::new_v4().to_string();
+    state.auth.claim(&session_id, identity.as_ref());
+    Ok(Json(CreateSessionResponse { session_id }))
 }
 
 #[derive(Deserialize)]
@@ -400,106 +682,185 @@ struct CheckFileRequest {
 #[derive(Serialize)]
 struct CheckFileResponse {
     exists: bool,
-    file: Option<FileInfo>,
+    file: Option<UploadedFile>,
+}
+
+/// A stored file as the upload endpoints report it
+#[derive(Serialize)]
+struct UploadedFile {
+    uri: String,
+    #[serde(flatten)]
+    file: StoredFile,
 }
 
+impl From<StoredFile> for UploadedFile {
+    fn from(file: StoredFile) -> Self {
+        Self { uri: file.uri(), file }
+    }
+}
+
+fn upload_status(e: anyhow::Error) -> StatusCode {
+    match e.downcast_ref::<UploadError>() {
+        Some(UploadError::TooLarge { .. }) => StatusCode::PAYLOAD_TOO_LARGE,
+        Some(UploadError::QuotaExceeded { .. }) => StatusCode::INSUFFICIENT_STORAGE,
+        None => {
+            error!("Upload failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Whether the store already has content with this MD5, so the browser can
+/// skip uploading it. A hit counts against the caller's quota.
 async fn check_file(
     State(state): State<Arc<WebInputState>>,
+    headers: HeaderMap,
     Json(req): Json<CheckFileRequest>,
-) -> Json<CheckFileResponse> {
-    let registry = state.file_registry.read().await;
-    if let Some(info) = registry.get(&req.md5) {
-        Json(CheckFileResponse {
-            exists: true,
-            file: Some(info.clone()),
-        })
-    } else {
-        Json(CheckFileResponse {
-            exists: false,
-            file: None,
-        })
-    }
+) -> Result<Json<CheckFileResponse>, StatusCode> {
+    let identity = caller(&state.auth, &headers, None)?;
+    let file = match state.uploads.find_md5(&req.md5).await {
+        Some(found) => state
+            .uploads
+            .claim(identity.as_ref(), &found.sha256)
+            .await
+            .map_err(upload_status)?,
+        None => None,
+    };
+    Ok(Json(CheckFileResponse {
+        exists: file.is_some(),
+        file: file.map(UploadedFile::from),
+    }))
 }
 
+/// Store each multipart field, streaming it to disk
 async fn upload_file(
     State(state): State<Arc<WebInputState>>,
+    headers: HeaderMap,
     mut multipart: Multipart,
 ) -> Result<Json<WebResponse>, StatusCode> {
-    let mut file_paths = Vec::new();
-    let upload_dir = PathBuf::from("uploads");
-    if !upload_dir.exists() {
-        tokio::fs::create_dir_all(&upload_dir).await.map_err(|e| {
-            error!("Failed to create upload dir: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-    }
+    let identity = caller(&state.auth, &headers, None)?;
+    let mut uris = Vec::new();
+    let mut files = Vec::new();
 
-    while let Some(field) = multipart.next_field().await.map_err(|e| {
+    while let Some(mut field) = multipart.next_field().await.map_err(|e| {
         error!("Multipart error: {}", e);
         StatusCode::BAD_REQUEST
     })? {
         let file_name = field.file_name().unwrap_or("unknown_file").to_string();
-        let safe_name: String = file_name
-            .chars()
-            .map(|c| {
-                if c.is_alphanumeric() || c == '.' {
-                    c
-                } else {
-                    '_'
-                }
-            })
-            .collect();
-        let saved_filename = format!("{}_{}", Uuid::new_v4(), safe_name);
-        let path = upload_dir.join(&saved_filename);
-
-        let data = field.bytes().await.map_err(|e| {
-            error!("Bytes read error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-        // Calculate MD5
-        let size = data.len() as u64;
-        let mut hasher = Md5::new();
-        hasher.update(&data);
-        let hash = hasher.finalize();
-        let md5_hex = hex::encode(hash);
-
-        // Check registry first
-        {
-            let registry = state.file_registry.read().await;
-            if let Some(info) = registry.get(&md5_hex) {
-                file_paths.push(info.path.clone());
-                continue;
-            }
-        }
-
-        tokio::fs::write(&path, data).await.map_err(|e| {
-            error!("File write error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-        let abs_path = std::fs::canonicalize(&path).unwrap_or(path);
-        let uri = format!("file://{}", abs_path.display());
-        file_paths.push(uri.clone());
-
-        // Update registry
-        {
-            let mut registry = state.file_registry.write().await;
-            registry.insert(
-                md5_hex.clone(),
-                FileInfo {
-                    md5: md5_hex,
-                    path: uri,
-                    filename: safe_name,
-                    size,
-                },
-            );
+        let mut upload = state
+            .uploads
+            .begin(identity.as_ref(), &file_name)
+            .await
+            .map_err(upload_status)?;
+        while let Some(chunk) = field.chunk().await.map_err(|e| {
+            error!("Multipart read error: {}", e);
+            StatusCode::BAD_REQUEST
+        })? {
+            upload.write(&chunk).await.map_err(upload_status)?;
         }
+        let stored = state.uploads.finish(upload).await.map_err(upload_status)?;
+        uris.push(stored.uri());
+        files.push(UploadedFile::from(stored));
     }
 
     Ok(Json(WebResponse {
         success: true,
         message: "Upload successful".to_string(),
-        data: Some(serde_json::json!({ "files": file_paths })),
+        data: Some(serde_json::json!({ "files": uris, "details": files })),
     }))
 }
+
+/// The content of an uploaded file, for the users that uploaded it
+async fn download_file(
+    State(state): State<Arc<WebInputState>>,
+    Path(sha256): Path<String>,
+    Query(q): Query<DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    let identity = caller(&state.auth, &headers, q.token.as_deref())?;
+    let file = state.uploads.get(&sha256).await.ok_or(StatusCode::NOT_FOUND)?;
+    if !state.uploads.may_read(&file, identity.as_ref()) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let blob = tokio::fs::File::open(state.uploads.blob_path(&file.sha256))
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let chunks = futures::stream::unfold(blob, |mut blob| async move {
+        let mut buf = vec![0u8; 64 * 1024];
+        match blob.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok::<_, std::io::Error>(bytes::Bytes::from(buf)), blob))
+            }
+            Err(e) => Some((Err(e), blob)),
+        }
+    });
+    Ok((
+        [
+            (header::CONTENT_TYPE, file.mime_type.clone()),
+            (header::CONTENT_LENGTH, file.size.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", file.filename),
+            ),
+        ],
+        axum::body::Body::from_stream(chunks),
+    ))
+}
+
+#[derive(Deserialize)]
+struct DownloadQuery {
+    /// Links opened by the browser cannot carry an Authorization header
+    token: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::auth::ApiTokens;
+
+    #[tokio::test]
+    async fn web_users_cannot_take_over_channel_sessions() {
+        let auth = Arc::new(Auth::disabled().with_authenticator(ApiTokens::new().with("alice", "a-token")));
+        let dir = std::env::temp_dir().join(format!("robot-web-{}", Uuid::new_v4()));
+        let uploads = UploadStore::open(UploadConfig {
+            dir: dir.clone(),
+            ..UploadConfig::default()
+        })
+        .await
+        .unwrap();
+        let (input_sender, mut input_receiver) = queue::channel("web", INGRESS_QUEUE, None);
+        let state = Arc::new(WebInputState {
+            input_sender,
+            uploads: Arc::new(uploads),
+            auth: auth.clone(),
+            runtime: Arc::new(OnceLock::new()),
+            output: None,
+            config: WebConfig {
+                input_port: 0,
+                output_port: None,
+                features: Vec::new(),
+                auth: "token".to_string(),
+            },
+        });
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer a-token".parse().unwrap());
+        let send = |session_id: &str| {
+            let message = WebMessage {
+                content: "hello".to_string(),
+                timestamp: 0,
+                session_id: None,
+                files: None,
+            };
+            send_message(State(state.clone()), Path(session_id.to_string()), headers.clone(), Json(message))
+        };
+
+        assert_eq!(send("bot:42").await.err(), Some(StatusCode::FORBIDDEN));
+        assert_eq!(auth.owner("bot:42"), None);
+        assert!(send("s1").await.is_ok());
+        let event = input_receiver.recv().await.unwrap();
+        assert_eq!(event.session_id.as_deref(), Some("s1"));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
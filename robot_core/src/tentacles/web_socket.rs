@@ -0,0 +1,242 @@
+//! WebSocket transport of the web console.
+//!
+//! One connection per session at `/api/ws/{session_id}` carries user
+//! messages, elicitation answers, cancellations and typing/ack signals from
+//! the browser, and every output of the session back to it. Outputs carry
+//! their id in the session history; a client that reconnects with
+//! `?since=<id>` gets the ones it missed before the live stream continues. The SSE and POST
+//! endpoints stay available for clients that cannot open a socket.
+use crate::core::auth::Identity;
+use crate::tentacles::history::StoredMessage;
+use crate::tentacles::web_console::{WebInputState, WebMessage, caller, submit};
+use axum::{
+    extract::{
+        Path, Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct ConnectQuery {
+    /// Id of the last output the client has seen
+    since: Option<u64>,
+    /// Browsers cannot set an Authorization header on a WebSocket
+    token: Option<String>,
+}
+
+/// Frames sent by the client
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientFrame {
+    Message {
+        content: String,
+        #[serde(default)]
+        timestamp: u64,
+        #[serde(default)]
+        files: Option<Vec<String>>,
+    },
+    /// Answer to an elicitation; objects are passed on as JSON so they need
+    /// no conversion by the model
+    Answer { content: serde_json::Value },
+    /// Cancel the pending elicitation or tool call
+    Cancel,
+    Typing,
+    /// The client has shown every output up to `id`
+    Ack { id: u64 },
+}
+
+/// Frames sent to the client
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerFrame<'a> {
+    Output(&'a StoredMessage),
+    Accepted { id: Uuid },
+    Error { status: u16, message: String },
+}
+
+pub(crate) async fn connect(
+    State(state): State<Arc<WebInputState>>,
+    Path(session_id): Path<String>,
+    Query(q): Query<ConnectQuery>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<Response, StatusCode> {
+    let identity = caller(&state.auth, &headers, q.token.as_deref())?;
+    if !state.auth.claim(&session_id, identity.as_ref()) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if state.output.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(ws.on_upgrade(move |socket| serve(socket, state, session_id, identity, q.since)))
+}
+
+async fn serve(
+    mut socket: WebSocket,
+    state: Arc<WebInputState>,
+    session_id: String,
+    identity: Option<Identity>,
+    since: Option<u64>,
+) {
+    let Some(output) = state.output.clone() else {
+        return;
+    };
+    info!("web socket opened for session {}", session_id);
+
+    // Subscribe before replaying so nothing stored in between is lost;
+    // live outputs already replayed are skipped by their id.
+    let (subscriber, mut receiver) = output.subscribe(&session_id).await;
+    let mut last_sent = since.unwrap_or(0);
+    for item in output.history.replay(&session_id, since).await {
+        last_sent = item.id;
+        if send(&mut socket, &ServerFrame::Output(&item)).await.is_err() {
+            output.unsubscribe(&session_id, subscriber).await;
+            return;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            item = receiver.recv() => {
+                let Some(item) = item else { break };
+                if item.id <= last_sent {
+                    continue;
+                }
+                last_sent = item.id;
+                if send(&mut socket, &ServerFrame::Output(&item)).await.is_err() {
+                    break;
+                }
+            }
+            frame = socket.recv() => {
+                let text = match frame {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        warn!("web socket of session {} failed: {}", session_id, e);
+                        break;
+                    }
+                };
+                let reply = match serde_json::from_str::<ClientFrame>(text.as_str()) {
+                    Ok(frame) => handle(&state, &session_id, identity.clone(), frame).await,
+                    Err(e) => Some(ServerFrame::Error {
+                        status: StatusCode::BAD_REQUEST.as_u16(),
+                        message: e.to_string(),
+                    }),
+                };
+                if let Some(reply) = reply
+                    && send(&mut socket, &reply).await.is_err()
+                {
+                    break;
+                }
+            }
+        }
+    }
+
+    output.unsubscribe(&session_id, subscriber).await;
+    info!("web socket closed for session {}", session_id);
+}
+
+/// Act on one client frame, returning the reply if it gets one
+async fn handle(
+    state: &WebInputState,
+    session_id: &str,
+    identity: Option<Identity>,
+    frame: ClientFrame,
+) -> Option<ServerFrame<'static>> {
+    let output = state.output.as_ref()?;
+    let (content, timestamp, files) = match frame {
+        ClientFrame::Message {
+            content,
+            timestamp,
+            files,
+        } => (content, timestamp, files),
+        ClientFrame::Answer { content } => {
+            let content = match content {
+                serde_json::Value::String(s) => s,
+                other => other.to_string(),
+            };
+            (content, now(), None)
+        }
+        ClientFrame::Cancel => ("cancel".to_string(), now(), None),
+        ClientFrame::Typing => return None,
+        ClientFrame::Ack { id } => {
+            output.history.ack(session_id, id).await;
+            return None;
+        }
+    };
+    let message = WebMessage {
+        content,
+        timestamp,
+        session_id: Some(session_id.to_string()),
+        files,
+    };
+    Some(match submit(state, message, identity).await {
+        Ok(id) => ServerFrame::Accepted { id },
+        Err(status) => ServerFrame::Error {
+            status: status.as_u16(),
+            message: status.canonical_reason().unwrap_or("error").to_string(),
+        },
+    })
+}
+
+async fn send(socket: &mut WebSocket, frame: &ServerFrame<'_>) -> Result<(), axum::Error> {
+    let json = serde_json::to_string(frame).unwrap_or_default();
+    socket.send(Message::Text(json.into())).await
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::output_handler::OutputHandler;
+    use crate::tentacles::web_console::WebOutput;
+    use crate::utils::OutputEvent;
+
+    fn output(target: &str, session_id: &str, text: &str) -> OutputEvent {
+        OutputEvent {
+            target: target.to_string(),
+            source: "test".to_string(),
+            session_id: Some(session_id.to_string()),
+            content: serde_json::json!({ "content": text }),
+            style: "neutral".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn broadcasts_reach_the_history_of_every_session() {
+        let web = WebOutput::new(0).await.unwrap();
+        let state = web.state.clone();
+        let (_, mut live) = state.subscribe("s1").await;
+
+        web.emit(output("default", "s1", "a")).await.unwrap();
+        web.emit(output("default", "s1", "b")).await.unwrap();
+        // A broadcast is stored in both sessions
+        web.emit(output("all", "s2", "c")).await.unwrap();
+
+        let ids = |items: Vec<StoredMessage>| items.iter().map(|s| s.id).collect::<Vec<_>>();
+        assert_eq!(ids(state.history.replay("s1", Some(1)).await), vec![2, 3]);
+        assert_eq!(ids(state.history.replay("s2", None).await), vec![1]);
+        for expected in 1..=3 {
+            assert_eq!(live.recv().await.unwrap().id, expected);
+        }
+
+        // Without `since`, a reconnect resumes after the last ack
+        state.history.ack("s1", 2).await;
+        assert_eq!(ids(state.history.replay("s1", None).await), vec![3]);
+        state.history.ack("s1", 99).await;
+        assert!(state.history.replay("s1", None).await.is_empty());
+    }
+}
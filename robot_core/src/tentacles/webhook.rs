@@ -0,0 +1,826 @@
+//! Generic HTTP webhooks, in and out.
+//!
+//! Other systems (CI, monitoring, ticketing) POST JSON to `/hooks/<name>`.
+//! Each hook maps the body into an input with JSONPath templates, `{{$.path}}`
+//! placeholders resolved in the posted document, and may require an
+//! HMAC-SHA256 signature of the raw body. Outputs of matching sessions are
+//! POSTed to the configured URLs, signed the same way, retried with
+//! exponential backoff and finally appended to a dead-letter file.
+//!
+//! ```yaml
+//! port: 8090
+//! dead_letter: webhook-dead-letter.jsonl
+//! inputs:
+//!   - name: alerts
+//!     secret_env: ALERTS_SECRET          # or `secret: ...`
+//!     signature_header: X-Hub-Signature-256
+//!     session: "{{$.labels.service}}"    # session webhook:alerts:<service>
+//!     content: "Alert {{$.labels.alertname}} is {{$.status}}: {{$.annotations.summary}}"
+//!     fields:
+//!       severity: "{{$.labels.severity}}"
+//! outputs:
+//!   - name: ops
+//!     url: https://ops.example.com/robot
+//!     secret_env: OPS_SECRET
+//!     sessions: ["webhook:alerts*"]
+//!     body:
+//!       session: "{{$.session_id}}"
+//!       text: "{{$.text}}"
+//! ```
+use crate::core::input_handler::{InputHandler, SourceMetadata, SourceType, TypedInputHandler};
+use crate::core::output_handler::{OutputHandler, OutputMetadata, TypedOutputHandler};
+use crate::core::queue::{self, Overflow, QueueConfig};
+use crate::core::router::HandlerMarker;
+use crate::utils::{InputEvent, OutputEvent};
+use crate::workflow_steps::expr::lookup;
+use anyhow::{Result, anyhow, bail};
+use async_trait::async_trait;
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    routing::post,
+};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+pub struct WebhookSource;
+impl SourceType for WebhookSource {}
+
+/// Type marker for webhook handlers
+pub struct WebhookHandler;
+impl HandlerMarker for WebhookHandler {
+    const ID: &'static str = "webhook";
+}
+
+/// Senders are told to retry (429) rather than held while the core is busy
+const INGRESS_QUEUE: QueueConfig = QueueConfig::new(256, Overflow::Reject);
+/// Outputs waiting for delivery to one URL; overflow goes to the dead letters
+const DELIVERY_QUEUE: QueueConfig = QueueConfig::new(1024, Overflow::Reject);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+const SESSION_PREFIX: &str = "webhook:";
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// JSON lines of the outputs that could not be delivered
+    #[serde(default = "default_dead_letter")]
+    pub dead_letter: PathBuf,
+    #[serde(default)]
+    pub inputs: Vec<HookInput>,
+    #[serde(default)]
+    pub outputs: Vec<HookOutput>,
+}
+
+fn default_port() -> u16 {
+    8090
+}
+
+fn default_dead_letter() -> PathBuf {
+    PathBuf::from("webhook-dead-letter.jsonl")
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookInput {
+    /// Served at `/hooks/<name>`
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Key of the HMAC-SHA256 signature; posts are unsigned without one
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Environment variable holding the secret
+    #[serde(default)]
+    pub secret_env: Option<String>,
+    /// Header carrying the signature, `sha256=<hex>` or bare hex
+    #[serde(default = "default_signature_header")]
+    pub signature_header: String,
+    /// Template of the session key; every post shares `webhook:<name>` without one
+    #[serde(default)]
+    pub session: Option<String>,
+    /// Template of the text the core sees
+    pub content: String,
+    /// Payload field the text is stored in, announced in `source_meta`
+    #[serde(default = "default_content_field")]
+    pub content_field: String,
+    /// More payload fields, each a template
+    #[serde(default)]
+    pub fields: BTreeMap<String, Value>,
+}
+
+fn default_signature_header() -> String {
+    "X-Hub-Signature-256".to_string()
+}
+
+fn default_content_field() -> String {
+    "content".to_string()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookOutput {
+    pub name: String,
+    pub url: String,
+    /// Signs the body into `signature_header` when set
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub secret_env: Option<String>,
+    #[serde(default = "default_signature_header")]
+    pub signature_header: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Sessions whose outputs are sent; `prefix*` matches a prefix
+    #[serde(default = "default_sessions")]
+    pub sessions: Vec<String>,
+    /// Content types sent: `text`, `progress`, `elicitation`, ...
+    #[serde(default = "default_types")]
+    pub types: Vec<String>,
+    /// Template of the posted JSON, filled from the output event and its
+    /// `text`; the event itself when absent
+    #[serde(default)]
+    pub body: Option<Value>,
+    /// Attempts after the first one
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Wait before the first retry, doubled after each
+    #[serde(default = "default_retry_delay_ms")]
+    pub retry_delay_ms: u64,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_sessions() -> Vec<String> {
+    vec![format!("{}*", SESSION_PREFIX)]
+}
+
+fn default_types() -> Vec<String> {
+    vec!["text".to_string()]
+}
+
+fn default_retries() -> u32 {
+    5
+}
+
+fn default_retry_delay_ms() -> u64 {
+    1000
+}
+
+fn default_timeout_secs() -> u64 {
+    10
+}
+
+impl WebhookConfig {
+    /// Parse the YAML config, reading the `secret_env` variables and checking
+    /// the templates
+    pub fn from_yaml(text: &str) -> Result<Self> {
+        let mut config: Self = serde_yaml::from_str(text)?;
+        for input in &mut config.inputs {
+            input.secret = secret(&input.secret, &input.secret_env)?;
+            check_template(&Value::String(input.content.clone()))?;
+            if let Some(session) = &input.session {
+                check_template(&Value::String(session.clone()))?;
+            }
+            for template in input.fields.values() {
+                check_template(template)?;
+            }
+        }
+        for output in &mut config.outputs {
+            output.secret = secret(&output.secret, &output.secret_env)?;
+            if let Some(body) = &output.body {
+                check_template(body)?;
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("failed to read webhook config {:?}: {}", path, e))?;
+        Self::from_yaml(&text).map_err(|e| anyhow!("invalid webhook config {:?}: {}", path, e))
+    }
+}
+
+fn secret(literal: &Option<String>, env: &Option<String>) -> Result<Option<String>> {
+    match (literal, env) {
+        (Some(_), Some(_)) => bail!("give either secret or secret_env, not both"),
+        (_, Some(var)) => std::env::var(var)
+            .map(Some)
+            .map_err(|_| anyhow!("secret_env: {} is not set", var)),
+        (literal, None) => Ok(literal.clone()),
+    }
+}
+
+/// The path of a `$.a.b[0]` JSONPath, as [`lookup`] takes it
+fn json_path(path: &str) -> Option<&str> {
+    let rest = path.trim().strip_prefix('$')?;
+    Some(rest.strip_prefix('.').unwrap_or(rest))
+}
+
+/// Fill the `{{$.path}}` placeholders of a template. A string that is one
+/// placeholder becomes the value at the path, keeping its type (null when
+/// absent); placeholders inside text become the value's text.
+pub fn render(template: &Value, doc: &Value) -> Value {
+    match template {
+        Value::String(s) => render_str(s, doc),
+        Value::Array(items) => Value::Array(items.iter().map(|t| render(t, doc)).collect()),
+        Value::Object(map) => Value::Object(map.iter().map(|(k, t)| (k.clone(), render(t, doc))).collect()),
+        other => other.clone(),
+    }
+}
+
+fn render_str(template: &str, doc: &Value) -> Value {
+    let resolve = |expr: &str| -> Option<Value> {
+        let path = json_path(expr)?;
+        lookup(doc, path).ok().flatten()
+    };
+    if let Some(expr) = template.strip_prefix("{{").and_then(|r| r.strip_suffix("}}"))
+        && !expr.contains("{{")
+    {
+        return resolve(expr).unwrap_or(Value::Null);
+    }
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        match resolve(&rest[start + 2..start + len]) {
+            Some(Value::String(s)) => out.push_str(&s),
+            Some(Value::Null) | None => {}
+            Some(other) => out.push_str(&other.to_string()),
+        }
+        rest = &rest[start + len + 2..];
+    }
+    out.push_str(rest);
+    Value::String(out)
+}
+
+/// Fail on placeholders that are not valid JSONPaths
+fn check_template(template: &Value) -> Result<()> {
+    match template {
+        Value::String(s) => {
+            let mut rest = s.as_str();
+            while let Some(start) = rest.find("{{") {
+                let len = rest[start..]
+                    .find("}}")
+                    .ok_or_else(|| anyhow!("unclosed '{{{{' in template '{}'", s))?;
+                let expr = &rest[start + 2..start + len];
+                let path = json_path(expr)
+                    .ok_or_else(|| anyhow!("placeholder '{}' in '{}' is not a JSONPath ($.field)", expr, s))?;
+                lookup(&Value::Null, path)?;
+                rest = &rest[start + len + 2..];
+            }
+            Ok(())
+        }
+        Value::Array(items) => items.iter().try_for_each(check_template),
+        Value::Object(map) => map.values().try_for_each(check_template),
+        _ => Ok(()),
+    }
+}
+
+fn signature(secret: &str, body: &[u8]) -> Hmac<Sha256> {
+    // HMAC takes keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key");
+    mac.update(body);
+    mac
+}
+
+/// `sha256=<hex>` of `body`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    format!("sha256={}", hex::encode(signature(secret, body).finalize().into_bytes()))
+}
+
+/// Whether `header` holds the signature of `body`, compared in constant time
+pub fn verify(secret: &str, body: &[u8], header: &str) -> bool {
+    let header = header.trim();
+    let hex_digest = header.strip_prefix("sha256=").unwrap_or(header);
+    match hex::decode(hex_digest) {
+        Ok(expected) => signature(secret, body).verify_slice(&expected).is_ok(),
+        Err(_) => false,
+    }
+}
+
+struct HookState {
+    hooks: HashMap<String, HookInput>,
+    sender: queue::Sender<InputEvent>,
+}
+
+pub struct WebhookInput {
+    receiver: Mutex<queue::Receiver<InputEvent>>,
+    server_handle: Option<tokio::task::JoinHandle<()>>,
+    port: Option<u16>,
+}
+
+pub struct WebhookOutput {
+    targets: Vec<Target>,
+}
+
+struct Target {
+    config: Arc<HookOutput>,
+    queue: queue::Sender<Value>,
+    dead_letter: Arc<DeadLetter>,
+}
+
+impl WebhookInput {
+    /// Start the hook server (when the config has inputs) and the delivery
+    /// workers of the outputs
+    pub async fn start(config: WebhookConfig) -> Result<(Self, WebhookOutput)> {
+        let (sender, receiver) = queue::channel("webhook", INGRESS_QUEUE, None);
+
+        let (server_handle, port) = if config.inputs.is_empty() {
+            (None, None)
+        } else {
+            for hook in config.inputs.iter().filter(|h| h.secret.is_none()) {
+                warn!("Webhook '{}' accepts unsigned posts", hook.name);
+            }
+            let state = Arc::new(HookState {
+                hooks: config.inputs.iter().map(|h| (h.name.clone(), h.clone())).collect(),
+                sender,
+            });
+            let listener = TcpListener::bind(("0.0.0.0", config.port)).await?;
+            let port = listener.local_addr()?.port();
+            info!("Webhook server listening on port {}", port);
+            let app = Router::new()
+                .route("/hooks/{name}", post(receive))
+                .with_state(state);
+            let handle = tokio::spawn(async move {
+                if let Err(e) = axum::serve(listener, app).await {
+                    error!("Webhook server error: {}", e);
+                }
+            });
+            (Some(handle), Some(port))
+        };
+
+        let dead_letter = Arc::new(DeadLetter {
+            path: config.dead_letter.clone(),
+            lock: Mutex::new(()),
+        });
+        let http = reqwest::Client::new();
+        let targets = config
+            .outputs
+            .into_iter()
+            .map(|output| {
+                let output = Arc::new(output);
+                let (queue, receiver) = queue::channel("webhook_delivery", DELIVERY_QUEUE, None);
+                tokio::spawn(deliver(http.clone(), output.clone(), receiver, dead_letter.clone()));
+                Target {
+                    config: output,
+                    queue,
+                    dead_letter: dead_letter.clone(),
+                }
+            })
+            .collect();
+
+        let input = Self {
+            receiver: Mutex::new(receiver),
+            server_handle,
+            port,
+        };
+        Ok((input, WebhookOutput { targets }))
+    }
+
+    /// Port of the hook server, when the config has inputs
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+async fn receive(
+    State(state): State<Arc<HookState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let hook = state.hooks.get(&name).ok_or(StatusCode::NOT_FOUND)?;
+    if let Some(secret) = &hook.secret {
+        let given = headers
+            .get(hook.signature_header.as_str())
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        if !verify(secret, &body, given) {
+            warn!("Rejected webhook '{}' post with a bad signature", name);
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    }
+    let document: Value = serde_json::from_slice(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let event = hook_event(hook, document);
+    let reply = json!({ "id": event.id, "session_id": event.session_id });
+    match state.sender.try_send(event) {
+        Ok(()) => Ok((StatusCode::ACCEPTED, Json(reply))),
+        Err(queue::SendError::Full(_)) => Err(StatusCode::TOO_MANY_REQUESTS),
+        Err(queue::SendError::Closed(_)) => Err(StatusCode::SERVICE_UNAVAILABLE),
+    }
+}
+
+/// The input for a document posted to `hook`
+fn hook_event(hook: &HookInput, document: Value) -> InputEvent {
+    let key = hook
+        .session
+        .as_ref()
+        .map(|t| match render_str(t, &document) {
+            Value::String(s) => s,
+            Value::Null => String::new(),
+            other => other.to_string(),
+        })
+        .filter(|k| !k.is_empty());
+    let session_id = match key {
+        Some(key) => format!("{}{}:{}", SESSION_PREFIX, hook.name, key),
+        None => format!("{}{}", SESSION_PREFIX, hook.name),
+    };
+
+    let mut payload = serde_json::Map::new();
+    for (field, template) in &hook.fields {
+        payload.insert(field.clone(), render(template, &document));
+    }
+    payload.insert(hook.content_field.clone(), render_str(&hook.content, &document));
+    payload.insert("hook".to_string(), json!(hook.name));
+    payload.insert("body".to_string(), document);
+
+    InputEvent {
+        id: Uuid::new_v4(),
+        source: "webhook".to_string(),
+        session_id: Some(session_id),
+        source_meta: Some(SourceMetadata {
+            name: "webhook".to_string(),
+            format_hint: "structured".to_string(),
+            content_field: hook.content_field.clone(),
+            description: hook
+                .description
+                .clone()
+                .unwrap_or_else(|| format!("JSON posted to webhook '{}'.", hook.name)),
+        }),
+        payload: Value::Object(payload),
+        identity: None,
+        attachments: Vec::new(),
+    }
+}
+
+#[async_trait]
+impl InputHandler for WebhookInput {
+    async fn poll(&self) -> Result<Option<InputEvent>> {
+        Ok(self.receiver.lock().await.recv().await)
+    }
+
+    fn metadata(&self) -> Option<SourceMetadata> {
+        Some(SourceMetadata {
+            name: "webhook".to_string(),
+            format_hint: "structured".to_string(),
+            content_field: "content".to_string(),
+            description: "JSON posted by other systems to a webhook.".to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl TypedInputHandler<WebhookSource> for WebhookInput {
+    async fn poll(&self) -> Result<Option<InputEvent>> {
+        <Self as InputHandler>::poll(self).await
+    }
+}
+
+impl Drop for WebhookInput {
+    fn drop(&mut self) {
+        if let Some(handle) = self.server_handle.take() {
+            handle.abort();
+        }
+    }
+}
+
+/// Content type of an output as `types` filters it: the `type` field, or
+/// `elicitation` for questions carrying a schema
+fn kind_of(content: &Value) -> &str {
+    match content.get("type").and_then(|t| t.as_str()) {
+        Some(kind) => kind,
+        None if content.get("schema").is_some() => "elicitation",
+        None => "text",
+    }
+}
+
+/// What a person would read of an output
+fn text_of(content: &Value) -> String {
+    ["text", "message", "content"]
+        .iter()
+        .find_map(|field| content.get(field).and_then(|v| v.as_str()))
+        .map(str::to_string)
+        .unwrap_or_else(|| match content {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        })
+}
+
+impl HookOutput {
+    fn wants(&self, event: &OutputEvent) -> bool {
+        let session = event.session_id.as_deref().unwrap_or_default();
+        let session_matches = self.sessions.iter().any(|p| match p.strip_suffix('*') {
+            Some(prefix) => session.starts_with(prefix),
+            None => p == session,
+        });
+        session_matches && self.types.iter().any(|t| t == kind_of(&event.content))
+    }
+
+    fn body(&self, event: &OutputEvent) -> Value {
+        let mut doc = serde_json::to_value(event).unwrap_or_default();
+        doc["text"] = Value::String(text_of(&event.content));
+        match &self.body {
+            Some(template) => render(template, &doc),
+            None => doc,
+        }
+    }
+}
+
+#[async_trait]
+impl OutputHandler for WebhookOutput {
+    async fn emit(&self, event: OutputEvent) -> Result<()> {
+        for target in self.targets.iter().filter(|t| t.config.wants(&event)) {
+            let body = target.config.body(&event);
+            // Delivery is retried in the background; a full queue is not waited on
+            if let Err(queue::SendError::Full(body) | queue::SendError::Closed(body)) = target.queue.try_send(body) {
+                target
+                    .dead_letter
+                    .write(&target.config, &body, "delivery queue is full", 0)
+                    .await;
+            }
+        }
+        Ok(())
+    }
+
+    fn metadata(&self) -> Option<OutputMetadata> {
+        Some(OutputMetadata {
+            name: "webhook".to_string(),
+            format: "json".to_string(),
+            description: "Outputs POSTed to the URLs of other systems.".to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl TypedOutputHandler<WebhookSource> for WebhookOutput {
+    async fn emit(&self, event: OutputEvent) -> Result<()> {
+        <Self as OutputHandler>::emit(self, event).await
+    }
+}
+
+/// Why one POST failed, and whether trying again can help
+struct Failure {
+    message: String,
+    retryable: bool,
+}
+
+async fn deliver(
+    http: reqwest::Client,
+    output: Arc<HookOutput>,
+    mut queue: queue::Receiver<Value>,
+    dead_letter: Arc<DeadLetter>,
+) {
+    while let Some(body) = queue.recv().await {
+        let mut delay = Duration::from_millis(output.retry_delay_ms);
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let failure = match send(&http, &output, &body).await {
+                Ok(()) => break,
+                Err(failure) => failure,
+            };
+            if !failure.retryable || attempts > output.retries {
+                warn!(
+                    "Giving up on webhook '{}' after {} attempts: {}",
+                    output.name, attempts, failure.message
+                );
+                dead_letter.write(&output, &body, &failure.message, attempts).await;
+                break;
+            }
+            warn!(
+                "Webhook '{}' failed, retrying in {:?}: {}",
+                output.name, delay, failure.message
+            );
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+}
+
+async fn send(http: &reqwest::Client, output: &HookOutput, body: &Value) -> Result<(), Failure> {
+    let bytes = serde_json::to_vec(body).unwrap_or_default();
+    let mut request = http
+        .post(&output.url)
+        .timeout(Duration::from_secs(output.timeout_secs))
+        .header("content-type", "application/json");
+    for (name, value) in &output.headers {
+        request = request.header(name, value);
+    }
+    if let Some(secret) = &output.secret {
+        request = request.header(output.signature_header.as_str(), sign(secret, &bytes));
+    }
+    let response = request.body(bytes).send().await.map_err(|e| Failure {
+        message: e.to_string(),
+        retryable: true,
+    })?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    // Other client errors will not go away by sending the same body again
+    Err(Failure {
+        message: format!("status {}", status),
+        retryable: status.is_server_error()
+            || status == StatusCode::REQUEST_TIMEOUT
+            || status == StatusCode::TOO_MANY_REQUESTS,
+    })
+}
+
+/// Outputs that could not be delivered, one JSON object per line
+struct DeadLetter {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl DeadLetter {
+    async fn write(&self, output: &HookOutput, body: &Value, error: &str, attempts: u32) {
+        let entry = json!({
+            "output": output.name,
+            "url": output.url,
+            "error": error,
+            "attempts": attempts,
+            "failed_at": chrono::Utc::now().to_rfc3339(),
+            "body": body,
+        });
+        let _guard = self.lock.lock().await;
+        let written = async {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await?;
+            file.write_all(format!("{}\n", entry).as_bytes()).await
+        };
+        if let Err(e) = written.await {
+            error!("Failed to write webhook dead letter to {:?}: {}", self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex as StdMutex;
+
+    const CONFIG: &str = r#"
+port: 0
+inputs:
+  - name: alerts
+    secret: hook-secret
+    session: "{{$.labels.service}}"
+    content: "Alert {{$.labels.alertname}} is {{$.status}}"
+    content_field: summary
+    fields:
+      severity: "{{$.labels.severity}}"
+      count: "{{$.alerts[0].count}}"
+outputs:
+  - name: ops
+    url: URL
+    secret: out-secret
+    retries: 2
+    retry_delay_ms: 10
+    body:
+      session: "{{$.session_id}}"
+      text: "Robot says: {{$.text}}"
+"#;
+
+    /// A receiver that fails the first `failures` posts with 503
+    #[derive(Default)]
+    struct Receiver {
+        failures: StdMutex<usize>,
+        received: StdMutex<Vec<(String, Value)>>,
+    }
+
+    async fn receiver_post(
+        State(receiver): State<Arc<Receiver>>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> StatusCode {
+        let mut failures = receiver.failures.lock().unwrap();
+        if *failures > 0 {
+            *failures -= 1;
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+        let signature = headers["X-Hub-Signature-256"].to_str().unwrap().to_string();
+        assert!(verify("out-secret", &body, &signature));
+        let body = serde_json::from_slice(&body).unwrap();
+        receiver.received.lock().unwrap().push((signature, body));
+        StatusCode::OK
+    }
+
+    fn reply(session: &str) -> OutputEvent {
+        OutputEvent {
+            target: "default".to_string(),
+            source: "system".to_string(),
+            session_id: Some(session.to_string()),
+            content: json!({ "type": "text", "text": "on it" }),
+            style: "neutral".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn maps_signed_posts_and_delivers_outputs_with_retries() {
+        let receiver = Arc::new(Receiver::default());
+        *receiver.failures.lock().unwrap() = 1;
+        let app = Router::new().route("/in", post(receiver_post)).with_state(receiver.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/in", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let dead_letter = std::env::temp_dir().join(format!("robot-dead-{}.jsonl", Uuid::new_v4()));
+        let mut config = WebhookConfig::from_yaml(&CONFIG.replace("URL", &url)).unwrap();
+        config.dead_letter = dead_letter.clone();
+        let (input, output) = WebhookInput::start(config).await.unwrap();
+
+        let body = br#"{"status":"firing","labels":{"alertname":"DiskFull","service":"db","severity":"page"},"alerts":[{"count":3}]}"#;
+        let hook = format!("http://127.0.0.1:{}/hooks/alerts", input.port().unwrap());
+        let http = reqwest::Client::new();
+        let unsigned = http.post(&hook).body(body.to_vec()).send().await.unwrap();
+        assert_eq!(unsigned.status(), StatusCode::UNAUTHORIZED);
+        let signed = http
+            .post(&hook)
+            .header("X-Hub-Signature-256", sign("hook-secret", body))
+            .body(body.to_vec())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(signed.status(), StatusCode::ACCEPTED);
+
+        let event = InputHandler::poll(&input).await.unwrap().unwrap();
+        assert_eq!(event.session_id.as_deref(), Some("webhook:alerts:db"));
+        assert_eq!(event.source_meta.unwrap().content_field, "summary");
+        assert_eq!(event.payload["summary"], "Alert DiskFull is firing");
+        assert_eq!(event.payload["severity"], "page");
+        assert_eq!(event.payload["count"], 3);
+
+        // The first delivery gets a 503 and is retried; other sessions' and
+        // non-text outputs are not sent
+        OutputHandler::emit(&output, reply("webhook:alerts:db")).await.unwrap();
+        OutputHandler::emit(&output, reply("web-session")).await.unwrap();
+        let progress = OutputEvent {
+            content: json!({ "type": "progress", "progress": 1 }),
+            ..reply("webhook:alerts:db")
+        };
+        OutputHandler::emit(&output, progress).await.unwrap();
+
+        let delivered = async {
+            while receiver.received.lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), delivered).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let received = receiver.received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].1, json!({ "session": "webhook:alerts:db", "text": "Robot says: on it" }));
+
+        // A receiver that keeps failing ends in the dead letters
+        *receiver.failures.lock().unwrap() = 10;
+        OutputHandler::emit(&output, reply("webhook:alerts:db")).await.unwrap();
+        let dead = async {
+            loop {
+                if let Ok(text) = tokio::fs::read_to_string(&dead_letter).await
+                    && !text.is_empty()
+                {
+                    return text;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let text = tokio::time::timeout(Duration::from_secs(5), dead).await.unwrap();
+        let entry: Value = serde_json::from_str(text.lines().next().unwrap()).unwrap();
+        assert_eq!(entry["output"], "ops");
+        assert_eq!(entry["attempts"], 3);
+        assert_eq!(entry["error"], "status 503 Service Unavailable");
+        let _ = tokio::fs::remove_file(dead_letter).await;
+    }
+
+    #[test]
+    fn rejects_templates_that_are_not_json_paths() {
+        let config = "inputs:\n  - name: ci\n    content: \"Build {{build.status}}\"\n";
+        let err = WebhookConfig::from_yaml(config).unwrap_err();
+        assert!(err.to_string().contains("not a JSONPath"), "{}", err);
+    }
+}
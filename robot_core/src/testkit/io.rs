@@ -0,0 +1,142 @@
+//! In-memory input and output handlers.
+use crate::core::input_handler::{InputHandler, SourceMetadata};
+use crate::core::output_handler::OutputHandler;
+use crate::core::router::HandlerMarker;
+use crate::utils::{InputEvent, OutputEvent};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+use uuid::Uuid;
+
+/// Type marker for the in-memory handlers
+pub struct MemoryHandler;
+impl HandlerMarker for MemoryHandler {
+    const ID: &'static str = "memory";
+}
+
+pub struct MemoryInput {
+    receiver: tokio::sync::Mutex<mpsc::UnboundedReceiver<InputEvent>>,
+}
+
+/// Sends messages into a [`MemoryInput`]
+#[derive(Clone)]
+pub struct MemoryInputSender {
+    sender: mpsc::UnboundedSender<InputEvent>,
+}
+
+impl MemoryInput {
+    pub fn new() -> (Self, MemoryInputSender) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (
+            Self {
+                receiver: tokio::sync::Mutex::new(receiver),
+            },
+            MemoryInputSender { sender },
+        )
+    }
+
+    fn create_metadata() -> SourceMetadata {
+        SourceMetadata {
+            name: "memory".to_string(),
+            format_hint: "text".to_string(),
+            content_field: "content".to_string(),
+            description: "User input from an in-memory test conversation.".to_string(),
+        }
+    }
+}
+
+impl MemoryInputSender {
+    /// Send `text` as the next message of `session_id`
+    pub fn send(&self, session_id: &str, text: &str) -> anyhow::Result<InputEvent> {
+        let event = InputEvent {
+            id: Uuid::new_v4(),
+            source: "memory".to_string(),
+            session_id: Some(session_id.to_string()),
+            source_meta: Some(MemoryInput::create_metadata()),
+            payload: serde_json::json!({ "content": text }),
+            identity: None,
+            attachments: Vec::new(),
+        };
+        self.sender
+            .send(event.clone())
+            .map_err(|_| anyhow::anyhow!("memory input closed"))?;
+        Ok(event)
+    }
+}
+
+#[async_trait]
+impl InputHandler for MemoryInput {
+    async fn poll(&self) -> anyhow::Result<Option<InputEvent>> {
+        Ok(self.receiver.lock().await.recv().await)
+    }
+
+    fn metadata(&self) -> Option<SourceMetadata> {
+        Some(Self::create_metadata())
+    }
+}
+
+/// Collects every emitted output; clones share the same log
+#[derive(Clone, Default)]
+pub struct MemoryOutput {
+    events: Arc<Mutex<Vec<OutputEvent>>>,
+    emitted: Arc<Notify>,
+    show_intermediate: bool,
+}
+
+impl MemoryOutput {
+    pub fn new(show_intermediate: bool) -> Self {
+        Self {
+            show_intermediate,
+            ..Self::default()
+        }
+    }
+
+    pub fn events(&self) -> Vec<OutputEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Wait until an output at position `from` or later matches `pred`;
+    /// returns its position and the event
+    pub async fn wait_for(
+        &self,
+        from: usize,
+        timeout: Duration,
+        pred: impl Fn(&OutputEvent) -> bool,
+    ) -> anyhow::Result<(usize, OutputEvent)> {
+        let wait = async {
+            loop {
+                let notified = self.emitted.notified();
+                let found = self
+                    .events
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .enumerate()
+                    .skip(from)
+                    .find(|(_, e)| pred(e))
+                    .map(|(i, e)| (i, e.clone()));
+                if let Some(found) = found {
+                    return found;
+                }
+                notified.await;
+            }
+        };
+        tokio::time::timeout(timeout, wait)
+            .await
+            .map_err(|_| anyhow::anyhow!("no matching output within {:?}", timeout))
+    }
+}
+
+#[async_trait]
+impl OutputHandler for MemoryOutput {
+    async fn emit(&self, event: OutputEvent) -> anyhow::Result<()> {
+        self.events.lock().unwrap().push(event);
+        self.emitted.notify_waiters();
+        Ok(())
+    }
+
+    fn show_intermediate(&self) -> bool {
+        self.show_intermediate
+    }
+}
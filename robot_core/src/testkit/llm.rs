@@ -0,0 +1,159 @@
+//! Scripted LLM: prompts are matched against regexes and answered with
+//! canned responses.
+use crate::llm::adapter::{ChatOutput, ChatRequest, LLMClient};
+use async_trait::async_trait;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Patterns matching the system prompt of each built-in LLM component
+pub mod prompts {
+    pub const INTENT: &str = "decide whether to RESPOND or IGNORE";
+    pub const PLANNER: &str = "smart workflow planner";
+    pub const REPLANNER: &str = "recovering from a failed step";
+    pub const RESOLVER: &str = "strict parameter extractor|Convert user's input to a JSON object of tool parameters";
+    pub const AUDITOR: &str = "Parameter Auditor";
+    pub const SYNTHESIZER: &str = "You used some tools to answer";
+    pub const FAILURE: &str = "A tool you used to answer the user failed";
+}
+
+struct Rule {
+    pattern: Regex,
+    responses: VecDeque<String>,
+    /// Keep answering with the last response instead of running out
+    repeat: bool,
+}
+
+/// `LLMClient` answering from a script.
+///
+/// The prompt (all messages joined by newlines) is matched against the rules
+/// in the order they were added; the first rule with a response left answers.
+/// Unmatched prompts fail the call and are kept in [`ScriptedLlm::unmatched`].
+#[derive(Default)]
+pub struct ScriptedLlm {
+    rules: Mutex<Vec<Rule>>,
+    requests: Mutex<Vec<ChatRequest>>,
+    unmatched: Mutex<Vec<String>>,
+}
+
+impl ScriptedLlm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn rule(self, pattern: &str, responses: Vec<String>, repeat: bool) -> Self {
+        let pattern = Regex::new(pattern).unwrap_or_else(|e| panic!("invalid prompt pattern '{}': {}", pattern, e));
+        self.rules.lock().unwrap().push(Rule {
+            pattern,
+            responses: responses.into(),
+            repeat,
+        });
+        self
+    }
+
+    /// Answer every prompt matching `pattern` with `response`
+    pub fn on(self, pattern: &str, response: impl Into<String>) -> Self {
+        self.rule(pattern, vec![response.into()], true)
+    }
+
+    /// Answer the next prompts matching `pattern` with `responses`, one each
+    pub fn on_seq<S: Into<String>>(self, pattern: &str, responses: impl IntoIterator<Item = S>) -> Self {
+        self.rule(pattern, responses.into_iter().map(Into::into).collect(), false)
+    }
+
+    /// Intent module answers RESPOND to everything
+    pub fn respond_to_all(self) -> Self {
+        self.on(prompts::INTENT, "Reason: scripted\nDecision: RESPOND")
+    }
+
+    /// Intent module answers IGNORE to messages containing `text`
+    pub fn ignore(self, text: &str) -> Self {
+        let pattern = format!("(?s)(?:{}).*Message: .*{}", prompts::INTENT, regex::escape(text));
+        self.on(&pattern, "Reason: scripted\nDecision: IGNORE")
+    }
+
+    /// Planner answers inputs containing `text` with `plan`
+    /// (`{"reasoning": ..., "steps": [{"tool": ..., "dependencies": []}]}`)
+    pub fn plan(self, text: &str, plan: Value) -> Self {
+        let pattern = format!("(?s)(?:{}).*Input: .*{}", prompts::PLANNER, regex::escape(text));
+        self.on(&pattern, plan.to_string())
+    }
+
+    /// Parameter resolver answers with `args` for `tool`
+    pub fn params(self, tool: &str, args: Value) -> Self {
+        let pattern = format!(
+            "(?s)(?:{}).*Tool Name: {}\n",
+            prompts::RESOLVER,
+            regex::escape(tool)
+        );
+        self.on(&pattern, args.to_string())
+    }
+
+    /// Synthesizer answers with `reply` when the tool results contain `finding`
+    pub fn synthesize(self, finding: &str, reply: impl Into<String>) -> Self {
+        let pattern = format!("(?s)(?:{}).*Tool results:.*{}", prompts::SYNTHESIZER, regex::escape(finding));
+        self.on(&pattern, reply)
+    }
+
+    /// Every request received so far
+    pub fn requests(&self) -> Vec<ChatRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Prompts of the requests matching `pattern`
+    pub fn prompts_matching(&self, pattern: &str) -> Vec<String> {
+        let re = Regex::new(pattern).unwrap_or_else(|e| panic!("invalid prompt pattern '{}': {}", pattern, e));
+        self.requests()
+            .iter()
+            .map(prompt_of)
+            .filter(|p| re.is_match(p))
+            .collect()
+    }
+
+    /// Prompts no rule answered
+    pub fn unmatched(&self) -> Vec<String> {
+        self.unmatched.lock().unwrap().clone()
+    }
+}
+
+fn prompt_of(req: &ChatRequest) -> String {
+    req.messages
+        .iter()
+        .map(|m| m.content.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[async_trait]
+impl LLMClient for ScriptedLlm {
+    async fn chat(&self, req: ChatRequest) -> anyhow::Result<ChatOutput> {
+        let prompt = prompt_of(&req);
+        self.requests.lock().unwrap().push(req);
+        let answer = {
+            let mut rules = self.rules.lock().unwrap();
+            rules
+                .iter_mut()
+                .filter(|r| !r.responses.is_empty() && r.pattern.is_match(&prompt))
+                .find_map(|r| {
+                    if r.repeat && r.responses.len() == 1 {
+                        r.responses.front().cloned()
+                    } else {
+                        r.responses.pop_front()
+                    }
+                })
+        };
+        match answer {
+            Some(text) => Ok(ChatOutput {
+                text,
+                thought: None,
+                raw: Value::Null,
+            }),
+            None => {
+                self.unmatched.lock().unwrap().push(prompt.clone());
+                let head: String = prompt.chars().take(200).collect();
+                Err(anyhow::anyhow!("ScriptedLlm: no scripted response for prompt: {}", head))
+            }
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! In-process MCP server stand-in with declarative tools.
+use crate::core::persona::OutputStyle;
+use crate::core::runtime::Runtime;
+use crate::mcp::client::MCPClient;
+use crate::mcp::registry::ToolMeta;
+use crate::mcp::rmcp_client::is_cancel_text;
+use crate::utils::{InputEvent, OutputEvent};
+use async_trait::async_trait;
+use serde_json::{Map, Value};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+type Handler = Arc<dyn Fn(&Value) -> anyhow::Result<Value> + Send + Sync>;
+
+/// A tool result with a single text item, shaped like `CallToolResult`
+pub fn text_result(text: impl Into<String>) -> Value {
+    serde_json::json!({
+        "content": [{"type": "text", "text": text.into()}],
+        "isError": false
+    })
+}
+
+/// How a tool asks for required fields that are missing from its args
+struct Elicitation {
+    message: String,
+    /// Scripted answers, used before asking the user
+    answers: VecDeque<Value>,
+}
+
+/// A declarative tool of [`FakeMcp`]
+pub struct FakeTool {
+    meta: ToolMeta,
+    properties: Map<String, Value>,
+    required: Vec<String>,
+    handler: Handler,
+    elicitation: Option<Elicitation>,
+}
+
+impl FakeTool {
+    pub fn new(name: &str, description: &str) -> Self {
+        Self {
+            meta: ToolMeta {
+                name: name.to_string(),
+                description: description.to_string(),
+                is_long_running: false,
+                destructive: false,
+            },
+            properties: Map::new(),
+            required: Vec::new(),
+            handler: Arc::new(|_| Ok(text_result(""))),
+            elicitation: None,
+        }
+    }
+
+    /// Declare a parameter of JSON schema type `kind`
+    pub fn param(mut self, name: &str, kind: &str, required: bool) -> Self {
+        self.properties
+            .insert(name.to_string(), serde_json::json!({"type": kind}));
+        if required {
+            self.required.push(name.to_string());
+        }
+        self
+    }
+
+    /// Annotate the tool as destructive, so calls wait for confirmation
+    pub fn destructive(mut self) -> Self {
+        self.meta.destructive = true;
+        self
+    }
+
+    /// Mark the tool as long running, so planned calls run as background tasks
+    pub fn long_running(mut self) -> Self {
+        self.meta.is_long_running = true;
+        self
+    }
+
+    /// Compute the result from the call args (without `session_id` and
+    /// `__*` keys)
+    pub fn handler(mut self, f: impl Fn(&Value) -> anyhow::Result<Value> + Send + Sync + 'static) -> Self {
+        self.handler = Arc::new(f);
+        self
+    }
+
+    /// Always return `text`
+    pub fn returns(self, text: &str) -> Self {
+        let text = text.to_string();
+        self.handler(move |_| Ok(text_result(text.clone())))
+    }
+
+    /// Ask the user for missing required fields with `message`, the way an
+    /// MCP server elicitation does
+    pub fn elicit(mut self, message: &str) -> Self {
+        self.elicitation = Some(Elicitation {
+            message: message.to_string(),
+            answers: VecDeque::new(),
+        });
+        self
+    }
+
+    /// Answer the next elicitations from a script instead of asking the user
+    pub fn elicit_answers(mut self, answers: impl IntoIterator<Item = Value>) -> Self {
+        self.elicitation
+            .get_or_insert_with(|| Elicitation {
+                message: format!("请提供 {} 的参数", self.meta.name),
+                answers: VecDeque::new(),
+            })
+            .answers
+            .extend(answers);
+        self
+    }
+
+    fn schema(&self) -> Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": self.properties,
+            "required": self.required,
+        })
+    }
+
+    fn missing(&self, args: &Value) -> Vec<String> {
+        self.required
+            .iter()
+            .filter(|f| args.get(f.as_str()).is_none_or(|v| v.is_null()))
+            .cloned()
+            .collect()
+    }
+}
+
+/// A recorded call to a [`FakeMcp`] tool
+#[derive(Clone, Debug)]
+pub struct FakeCall {
+    pub session_id: Option<String>,
+    pub tool: String,
+    /// Args after elicitation, as passed to the handler
+    pub args: Value,
+}
+
+#[derive(Default)]
+struct State {
+    tools: Vec<FakeTool>,
+    calls: Vec<FakeCall>,
+}
+
+/// `MCPClient` serving [`FakeTool`]s in process.
+///
+/// Clones share tools and the call log; [`FakeMcp::for_session`] gives the
+/// per-session client a `McpClientFactory` hands out. Elicitation goes over
+/// the runtime buses like `RmcpStdIoClient`: the prompt is sent on the output
+/// bus and the next input of the session answers it.
+#[derive(Clone, Default)]
+pub struct FakeMcp {
+    state: Arc<Mutex<State>>,
+    session: Option<(String, Runtime)>,
+}
+
+impl FakeMcp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tool(self, tool: FakeTool) -> Self {
+        self.state.lock().unwrap().tools.push(tool);
+        self
+    }
+
+    pub fn for_session(&self, session_id: &str, runtime: &Runtime) -> Self {
+        Self {
+            state: self.state.clone(),
+            session: Some((session_id.to_string(), runtime.clone())),
+        }
+    }
+
+    pub fn calls(&self) -> Vec<FakeCall> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    pub fn calls_to(&self, tool: &str) -> Vec<FakeCall> {
+        self.calls().into_iter().filter(|c| c.tool == tool).collect()
+    }
+
+    /// Fill the missing required fields of `args` for `tool`
+    async fn elicit(&self, tool: &str, args: &mut Value) -> anyhow::Result<()> {
+        let (missing, message, scripted, schema) = {
+            let mut state = self.state.lock().unwrap();
+            let t = find(&mut state.tools, tool)?;
+            let missing = t.missing(args);
+            if missing.is_empty() {
+                return Ok(());
+            }
+            let schema = t.schema();
+            let Some(elicitation) = t.elicitation.as_mut() else {
+                anyhow::bail!("tool '{}' is missing required fields: {:?}", tool, missing);
+            };
+            (missing, elicitation.message.clone(), elicitation.answers.pop_front(), schema)
+        };
+        let answer = match scripted {
+            Some(answer) => answer,
+            None => {
+                let hint = args
+                    .get("__elicitation")
+                    .and_then(|e| e.get("hint"))
+                    .and_then(|h| h.as_str())
+                    .map(|h| format!("{}\n", h))
+                    .unwrap_or_default();
+                self.ask(&format!("{}{}", hint, message), &schema, &missing).await?
+            }
+        };
+        if let (Some(obj), Some(answer)) = (args.as_object_mut(), answer.as_object()) {
+            obj.extend(answer.clone());
+        }
+        let state = self.state.lock().unwrap();
+        let still_missing = state.tools.iter().find(|t| t.meta.name == tool).map(|t| t.missing(args));
+        match still_missing {
+            Some(m) if !m.is_empty() => anyhow::bail!("tool '{}' is missing required fields: {:?}", tool, m),
+            _ => Ok(()),
+        }
+    }
+
+    async fn ask(&self, message: &str, schema: &Value, missing: &[String]) -> anyhow::Result<Value> {
+        let (sid, runtime) = self
+            .session
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("FakeMcp: elicitation needs a session client"))?;
+        // Subscribe before prompting so a quick answer is not missed
+        let mut rx = runtime.subscribe_inputs();
+        runtime.set_elicitation_active(&sid, true);
+        runtime.publish_output(OutputEvent {
+            target: "default".into(),
+            source: "mcp".into(),
+            session_id: Some(sid.clone()),
+            content: serde_json::json!({"message": message, "schema": schema}),
+            style: OutputStyle::Neutral.to_string(),
+        });
+        let input = loop {
+            match rx.recv().await {
+                Ok(ev) if ev.session_id.clone().unwrap_or_else(|| ev.source.clone()) == sid => break ev,
+                Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(e) => {
+                    runtime.set_elicitation_active(&sid, false);
+                    return Err(e.into());
+                }
+            }
+        };
+        runtime.mark_event_consumed(input.id);
+        runtime.set_elicitation_active(&sid, false);
+        parse_answer(&input, missing)
+    }
+}
+
+fn find<'a>(tools: &'a mut [FakeTool], name: &str) -> anyhow::Result<&'a mut FakeTool> {
+    tools
+        .iter_mut()
+        .find(|t| t.meta.name == name)
+        .ok_or_else(|| anyhow::anyhow!("FakeMcp: unknown tool '{}'", name))
+}
+
+/// A JSON object answers as is; plain text fills a single missing field
+fn parse_answer(input: &InputEvent, missing: &[String]) -> anyhow::Result<Value> {
+    let text = input
+        .payload
+        .get("content")
+        .and_then(|v| v.as_str())
+        .map(String::from)
+        .unwrap_or_else(|| input.payload.to_string());
+    if is_cancel_text(&text) {
+        anyhow::bail!("用户取消了本次工具调用");
+    }
+    match serde_json::from_str::<Value>(&text) {
+        Ok(v) if v.is_object() => Ok(v),
+        _ if missing.len() == 1 => {
+            let value = serde_json::from_str::<Value>(&text).unwrap_or(Value::String(text));
+            Ok(serde_json::json!({ missing[0].clone(): value }))
+        }
+        _ => anyhow::bail!("cannot map answer '{}' to fields {:?}", text, missing),
+    }
+}
+
+#[async_trait]
+impl MCPClient for FakeMcp {
+    async fn call(&self, tool: &str, args: Value) -> anyhow::Result<Value> {
+        let mut args = if args.is_object() { args } else { Value::Object(Map::new()) };
+        self.elicit(tool, &mut args).await?;
+        if let Some(obj) = args.as_object_mut() {
+            obj.retain(|k, _| k != "session_id" && !k.starts_with("__"));
+        }
+        let handler = {
+            let mut state = self.state.lock().unwrap();
+            let handler = find(&mut state.tools, tool)?.handler.clone();
+            state.calls.push(FakeCall {
+                session_id: self.session.as_ref().map(|(id, _)| id.clone()),
+                tool: tool.to_string(),
+                args: args.clone(),
+            });
+            handler
+        };
+        handler(&args)
+    }
+
+    async fn list_tools(&self) -> anyhow::Result<Vec<ToolMeta>> {
+        Ok(self.state.lock().unwrap().tools.iter().map(|t| t.meta.clone()).collect())
+    }
+
+    async fn required_fields(&self, tool: &str) -> anyhow::Result<Vec<String>> {
+        let mut state = self.state.lock().unwrap();
+        Ok(find(&mut state.tools, tool)?.required.clone())
+    }
+
+    async fn tool_schema(&self, tool: &str) -> anyhow::Result<Option<Value>> {
+        let mut state = self.state.lock().unwrap();
+        Ok(Some(find(&mut state.tools, tool)?.schema()))
+    }
+}
@@ -0,0 +1,284 @@
+//! Offline scenario tests.
+//!
+//! Wires a full `RobotCore` to a [`ScriptedLlm`], a [`FakeMcp`] and the
+//! in-memory handlers, so multi-turn conversations run without LM Studio or
+//! an MCP server:
+//!
+//! ```rust,ignore
+//! let llm = ScriptedLlm::new()
+//!     .respond_to_all()
+//!     .plan("加", json!({"reasoning": "", "steps": [{"tool": "add", "dependencies": []}]}))
+//!     .params("add", json!({"a": 1, "b": 2}))
+//!     .synthesize("3", "1 加 2 等于 3");
+//! let mcp = FakeMcp::new().tool(FakeTool::new("add", "Add two numbers").returns("3"));
+//! let robot = TestRobot::builder(llm, mcp).build().await;
+//! let mut chat = robot.conversation("alice");
+//! chat.say("1 加 2")?;
+//! assert_eq!(chat.reply_text().await?, "1 加 2 等于 3");
+//! ```
+pub mod io;
+pub mod llm;
+pub mod mcp;
+
+pub use io::{MemoryHandler, MemoryInput, MemoryInputSender, MemoryOutput};
+pub use llm::{prompts, ScriptedLlm};
+pub use mcp::{text_result, FakeCall, FakeMcp, FakeTool};
+
+use crate::core::decision_engine::LLMDecisionEngine;
+use crate::core::intent::LLMIntentModule;
+use crate::core::limits::RateLimits;
+use crate::core::perception::BasicPerceptionModule;
+use crate::core::persona::Persona;
+use crate::core::policy::Policy;
+use crate::core::router::HandlerId;
+use crate::core::runtime::Runtime;
+use crate::core::synthesis::LLMResponseSynthesizer;
+use crate::core::templates::TemplateLibrary;
+use crate::core::trace::{output_text, MemoryTraceSink};
+use crate::core::workflow_engine::WorkflowEngine;
+use crate::core::RobotCore;
+use crate::mcp::client::MCPClient;
+use crate::utils::OutputEvent;
+use crate::workflow_steps::LlmParameterResolver;
+use std::sync::Arc;
+use std::time::Duration;
+
+const MODEL: &str = "scripted";
+
+pub struct TestRobotBuilder {
+    llm: ScriptedLlm,
+    mcp: FakeMcp,
+    persona: Persona,
+    templates: Arc<TemplateLibrary>,
+    policy: Option<Policy>,
+    limits: Option<RateLimits>,
+    show_intermediate: bool,
+    timeout: Duration,
+}
+
+impl TestRobotBuilder {
+    pub fn persona(mut self, persona: Persona) -> Self {
+        self.persona = persona;
+        self
+    }
+
+    pub fn templates(mut self, templates: TemplateLibrary) -> Self {
+        self.templates = Arc::new(templates);
+        self
+    }
+
+    /// Enforce a tool policy in every session
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Rate-limit inputs, LLM requests and tool calls
+    pub fn limits(mut self, limits: RateLimits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Also collect intermediate tool results (`type: "tool_result"`)
+    pub fn show_intermediate(mut self, show: bool) -> Self {
+        self.show_intermediate = show;
+        self
+    }
+
+    /// How long [`Conversation`] waits for a reply (default 5s)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn build(self) -> TestRobot {
+        let llm = Arc::new(self.llm);
+        let workflow = WorkflowEngine::new_with_resolver(Arc::new(LlmParameterResolver {
+            llm: llm.clone(),
+            model: MODEL.to_string(),
+        }))
+        .with_synthesizer(Arc::new(LLMResponseSynthesizer {
+            llm: llm.clone(),
+            model: MODEL.to_string(),
+        }))
+        .with_templates(self.templates);
+
+        let factory_mcp = self.mcp.clone();
+        let runtime = Runtime::new();
+        let factory_runtime = runtime.clone();
+        let mut core = RobotCore::with_runtime(
+            runtime,
+            self.persona,
+            Box::new(LLMDecisionEngine::new(Box::new(llm.clone()), MODEL.to_string())),
+            workflow,
+            Box::new(BasicPerceptionModule),
+            Box::new(LLMIntentModule::new(Box::new(llm.clone()), MODEL.to_string())),
+            Box::new(move |session_id| {
+                let mcp: Arc<dyn MCPClient + Send + Sync> =
+                    Arc::new(factory_mcp.for_session(&session_id, &factory_runtime));
+                Box::pin(async move { Ok(mcp) })
+            }),
+        );
+
+        let traces = Arc::new(MemoryTraceSink::default());
+        core.set_trace_sink(traces.clone());
+        if let Some(policy) = self.policy {
+            core.set_policy(Arc::new(policy));
+        }
+        if let Some(limits) = self.limits {
+            core.set_rate_limits(limits);
+        }
+        let (input, sender) = MemoryInput::new();
+        let output = MemoryOutput::new(self.show_intermediate);
+        core.add_input_handler(Box::new(input));
+        core.add_output_handler(HandlerId::of::<MemoryHandler>(), Box::new(output.clone()))
+            .await;
+
+        let runner = tokio::spawn(async move {
+            while core.run_once().await.is_ok() {}
+        });
+
+        TestRobot {
+            llm,
+            mcp: self.mcp,
+            traces,
+            input: sender,
+            output,
+            timeout: self.timeout,
+            runner,
+        }
+    }
+}
+
+/// A running `RobotCore` on scripted clients
+pub struct TestRobot {
+    pub llm: Arc<ScriptedLlm>,
+    pub mcp: FakeMcp,
+    /// Trace of every handled input
+    pub traces: Arc<MemoryTraceSink>,
+    pub input: MemoryInputSender,
+    pub output: MemoryOutput,
+    timeout: Duration,
+    runner: tokio::task::JoinHandle<()>,
+}
+
+impl TestRobot {
+    pub fn builder(llm: ScriptedLlm, mcp: FakeMcp) -> TestRobotBuilder {
+        TestRobotBuilder {
+            llm,
+            mcp,
+            persona: Persona::default(),
+            templates: Arc::new(TemplateLibrary::default()),
+            policy: None,
+            limits: None,
+            show_intermediate: false,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Start a conversation in a new session. `name` is suffixed to keep
+    /// session ids unique across tests sharing the global buses.
+    pub fn conversation(&self, name: &str) -> Conversation<'_> {
+        Conversation {
+            robot: self,
+            session_id: format!("{}-{}", name, uuid::Uuid::new_v4().simple()),
+            cursor: 0,
+        }
+    }
+}
+
+impl Drop for TestRobot {
+    fn drop(&mut self) {
+        self.runner.abort();
+    }
+}
+
+/// One session talking to a [`TestRobot`]
+pub struct Conversation<'a> {
+    robot: &'a TestRobot,
+    pub session_id: String,
+    /// Position in the output log after the last reply read
+    cursor: usize,
+}
+
+impl Conversation<'_> {
+    pub fn say(&self, text: &str) -> anyhow::Result<()> {
+        self.robot.input.send(&self.session_id, text).map(|_| ())
+    }
+
+    /// Next output sent to this session
+    pub async fn reply(&mut self) -> anyhow::Result<OutputEvent> {
+        self.reply_matching(|_| true).await
+    }
+
+    /// Text of the next output sent to this session
+    pub async fn reply_text(&mut self) -> anyhow::Result<String> {
+        Ok(output_text(&self.reply().await?))
+    }
+
+    /// Next output of this session matching `pred`, skipping the others
+    pub async fn reply_matching(&mut self, pred: impl Fn(&OutputEvent) -> bool) -> anyhow::Result<OutputEvent> {
+        let sid = self.session_id.clone();
+        let (index, event) = self
+            .robot
+            .output
+            .wait_for(self.cursor, self.robot.timeout, |e| {
+                e.session_id.as_deref() == Some(sid.as_str()) && pred(e)
+            })
+            .await?;
+        self.cursor = index + 1;
+        Ok(event)
+    }
+
+    /// Whether nothing is sent to this session within `wait`
+    pub async fn is_silent_for(&self, wait: Duration) -> bool {
+        let sid = self.session_id.clone();
+        self.robot
+            .output
+            .wait_for(self.cursor, wait, |e| e.session_id.as_deref() == Some(sid.as_str()))
+            .await
+            .is_err()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn elicits_missing_arg_across_turns() {
+        let llm = ScriptedLlm::new()
+            .ignore("大家好")
+            .respond_to_all()
+            .plan("加", json!({"reasoning": "add", "steps": [{"tool": "add", "dependencies": []}]}))
+            .params("add", json!({"a": 1, "b": null}))
+            .synthesize("3", "1 加 2 等于 3");
+        let mcp = FakeMcp::new().tool(
+            FakeTool::new("add", "Add two numbers")
+                .param("a", "number", true)
+                .param("b", "number", true)
+                .elicit("请提供 b")
+                .handler(|args| {
+                    let sum = args["a"].as_f64().unwrap_or_default() + args["b"].as_f64().unwrap_or_default();
+                    Ok(text_result(sum.to_string()))
+                }),
+        );
+        let robot = TestRobot::builder(llm, mcp).build().await;
+        let mut chat = robot.conversation("alice");
+
+        chat.say("大家好").unwrap();
+        assert!(chat.is_silent_for(Duration::from_millis(200)).await);
+
+        chat.say("1 加一个数").unwrap();
+        assert_eq!(chat.reply_text().await.unwrap(), "请提供 b");
+        chat.say("2").unwrap();
+        assert_eq!(chat.reply_text().await.unwrap(), "1 加 2 等于 3");
+
+        let calls = robot.mcp.calls_to("add");
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].args, json!({"a": 1, "b": 2}));
+        assert!(robot.llm.unmatched().is_empty());
+        assert_eq!(robot.traces.wait_for(2).await.len(), 2);
+    }
+}
@@ -1,9 +1,7 @@
 use crate::core::persona::{OutputStyle, Persona};
+use crate::workflow_steps::expr::Condition;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashSet;
-use std::sync::{Mutex, OnceLock};
-use tokio::sync::broadcast;
 use uuid::Uuid;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -13,6 +11,26 @@ pub struct InputEvent {
     pub session_id: Option<String>,
     pub source_meta: Option<crate::core::input_handler::SourceMetadata>,
     pub payload: Value,
+    /// The authenticated user who sent the input, if the console requires login
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<crate::core::auth::Identity>,
+    /// Files sent with the input
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+}
+
+/// A file attached to an input, stored by [`crate::core::uploads::UploadStore`]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    /// `upload://<sha256>`, readable from the MCP server as a resource
+    pub uri: String,
+    pub name: String,
+    pub mime_type: String,
+    pub size: u64,
+    pub sha256: String,
+    /// Absolute path of the stored content, for tools that open files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -49,6 +67,8 @@ pub struct Context {
     pub relationships: Value,
     pub input_text: String,
     pub session_id: Option<String>,
+    /// Files attached to the input
+    pub attachments: Vec<Attachment>,
 }
 
 impl Context {
@@ -60,6 +80,7 @@ impl Context {
             relationships: Value::Null,
             input_text,
             session_id,
+            attachments: Vec::new(),
         }
     }
     pub fn touch_memory(&mut self) {
@@ -87,68 +108,50 @@ pub enum StepSpec {
     Relationship,
     Tool {
         name: String,
+        /// Literal args, or `{"$ref": path, "as": type}` bindings to earlier
+        /// results (see `workflow_steps::bindings`)
         args: Value,
         #[serde(default)]
         is_background: bool,
         #[serde(default)]
         dependencies: Vec<usize>, // Indices of steps this step depends on
+        #[serde(default)]
+        on_error: RecoveryPolicy,
+    },
+    /// Run `then` when `condition` holds over previous results, else `otherwise`
+    Branch {
+        condition: Condition,
+        #[serde(default)]
+        then: Vec<StepSpec>,
+        #[serde(default)]
+        otherwise: Vec<StepSpec>,
+    },
+    /// Run `body` once per element of the array found at the `items` path
+    /// (e.g. `steps[0].content[0].text`). Inside the body the element is
+    /// available as `item`, and Tool steps without args receive it as input.
+    ForEach {
+        items: String,
+        body: Vec<StepSpec>,
+    },
+    /// Run `steps` concurrently, each on its own copy of the context
+    Parallel {
+        steps: Vec<StepSpec>,
     },
 }
 
-static EVENT_BUS_SENDER: OnceLock<broadcast::Sender<InputEvent>> = OnceLock::new();
-static OUTPUT_BUS_SENDER: OnceLock<broadcast::Sender<OutputEvent>> = OnceLock::new();
-static CONSUMED_EVENTS: OnceLock<Mutex<HashSet<Uuid>>> = OnceLock::new();
-static ACTIVE_ELICITATIONS: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
-
-pub fn event_bus() -> broadcast::Sender<InputEvent> {
-    EVENT_BUS_SENDER
-        .get_or_init(|| {
-            let (tx, _rx) = broadcast::channel(1024);
-            tx
-        })
-        .clone()
-}
-
-pub fn output_bus() -> broadcast::Sender<OutputEvent> {
-    OUTPUT_BUS_SENDER
-        .get_or_init(|| {
-            let (tx, _rx) = broadcast::channel(1024);
-            tx
-        })
-        .clone()
-}
-
-pub fn mark_event_consumed(id: Uuid) {
-    let set = CONSUMED_EVENTS.get_or_init(|| Mutex::new(HashSet::new()));
-    if let Ok(mut guard) = set.lock() {
-        guard.insert(id);
-    }
-}
-
-pub fn check_and_remove_consumed_event(id: &Uuid) -> bool {
-    let set = CONSUMED_EVENTS.get_or_init(|| Mutex::new(HashSet::new()));
-    if let Ok(mut guard) = set.lock() {
-        return guard.remove(id);
-    }
-    false
-}
-
-pub fn set_elicitation_active(session_id: &str, active: bool) {
-    let set = ACTIVE_ELICITATIONS.get_or_init(|| Mutex::new(HashSet::new()));
-    if let Ok(mut guard) = set.lock() {
-        if active {
-            guard.insert(session_id.to_string());
-        } else {
-            guard.remove(session_id);
-        }
-    }
-}
-
-pub fn is_elicitation_active(session_id: &str) -> bool {
-    let set = ACTIVE_ELICITATIONS.get_or_init(|| Mutex::new(HashSet::new()));
-    if let Ok(guard) = set.lock() {
-        guard.contains(session_id)
-    } else {
-        false
-    }
+/// What the workflow should do when a step fails (transport error or a
+/// `tool_error` / `isError` result coming back from the MCP server).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum RecoveryPolicy {
+    /// Run the same step again, waiting `backoff_ms * 2^attempt` between tries
+    Retry { max_attempts: u32, backoff_ms: u64 },
+    /// Record the failure and continue with the next step
+    Skip,
+    /// Call another tool with the same arguments
+    Substitute { tool: String },
+    /// Ask the DecisionEngine to plan the remaining steps from the history
+    #[default]
+    Replan,
+    /// Stop the workflow and tell the user what failed
+    Abort,
 }
@@ -0,0 +1,169 @@
+//! Explicit argument bindings.
+//!
+//! A Tool step's args may bind a field to an earlier result instead of
+//! leaving it to the LLM resolver:
+//!
+//! ```json
+//! {"a": {"$ref": "steps[0].content[0].text", "as": "number"}, "b": 2}
+//! ```
+//!
+//! `$ref` is a path in the workflow scope (see [`super::expr::lookup`]). The
+//! value is coerced to `as` when given, otherwise to the type the tool's
+//! JSON schema declares for the field. `default` is used when the path does
+//! not exist.
+use super::expr::{as_number, lookup};
+use crate::core::synthesis::result_text;
+use serde_json::{Map, Value};
+
+const REF_KEY: &str = "$ref";
+
+fn as_binding(v: &Value) -> Option<(&str, &Map<String, Value>)> {
+    let obj = v.as_object()?;
+    Some((obj.get(REF_KEY)?.as_str()?, obj))
+}
+
+/// Whether `args` contains any `$ref` binding
+pub fn has_refs(args: &Value) -> bool {
+    match args {
+        Value::Object(map) => map.contains_key(REF_KEY) || map.values().any(has_refs),
+        Value::Array(items) => items.iter().any(has_refs),
+        _ => false,
+    }
+}
+
+/// Replace every binding in `args` by its value in `scope`, coerced against
+/// `schema` (the tool's input schema, or the sub-schema at this position)
+pub fn bind(args: &Value, scope: &Value, schema: Option<&Value>) -> anyhow::Result<Value> {
+    if let Some((path, binding)) = as_binding(args) {
+        let value = match lookup(scope, path)? {
+            Some(v) if !v.is_null() => v,
+            _ => binding
+                .get("default")
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("binding '{}' did not resolve to a value", path))?,
+        };
+        let target = binding
+            .get("as")
+            .and_then(|t| t.as_str())
+            .or_else(|| schema_type(schema));
+        return match target {
+            Some(kind) => coerce(&value, kind)
+                .ok_or_else(|| anyhow::anyhow!("cannot bind '{}' as {}: {}", path, kind, value)),
+            None => Ok(value),
+        };
+    }
+    Ok(match args {
+        Value::Object(map) => {
+            let mut out = Map::new();
+            for (k, v) in map {
+                let sub = schema.and_then(|s| s.get("properties")).and_then(|p| p.get(k));
+                out.insert(k.clone(), bind(v, scope, sub)?);
+            }
+            Value::Object(out)
+        }
+        Value::Array(items) => {
+            let sub = schema.and_then(|s| s.get("items"));
+            Value::Array(items.iter().map(|v| bind(v, scope, sub)).collect::<anyhow::Result<_>>()?)
+        }
+        other => other.clone(),
+    })
+}
+
+/// Required fields that are absent or null after binding
+pub fn unbound_fields(args: &Value, required: &[String]) -> Vec<String> {
+    required
+        .iter()
+        .filter(|f| args.get(f.as_str()).is_none_or(|v| v.is_null()))
+        .cloned()
+        .collect()
+}
+
+/// The `type` of a schema, taking the first non-null entry of a type list
+/// (schemars writes optional fields as `["string", "null"]`)
+fn schema_type(schema: Option<&Value>) -> Option<&str> {
+    match schema?.get("type")? {
+        Value::String(t) => Some(t),
+        Value::Array(types) => types.iter().filter_map(|t| t.as_str()).find(|t| *t != "null"),
+        _ => None,
+    }
+}
+
+fn coerce(value: &Value, kind: &str) -> Option<Value> {
+    match kind {
+        "number" => as_number(&scalar(value)).map(Value::from),
+        "integer" => {
+            let n = as_number(&scalar(value))?;
+            (n.fract() == 0.0).then(|| Value::from(n as i64))
+        }
+        "boolean" => match scalar(value) {
+            Value::Bool(b) => Some(Value::Bool(b)),
+            Value::String(s) => s.trim().parse::<bool>().ok().map(Value::Bool),
+            Value::Number(n) => n.as_f64().map(|f| Value::Bool(f != 0.0)),
+            _ => None,
+        },
+        "string" => Some(match scalar(value) {
+            Value::String(s) => Value::String(s),
+            other => Value::String(other.to_string()),
+        }),
+        "json" | "object" | "array" => {
+            let v = match scalar(value) {
+                Value::String(s) => serde_json::from_str(&s).ok()?,
+                other => other,
+            };
+            match kind {
+                "object" if !v.is_object() => None,
+                "array" if !v.is_array() => None,
+                _ => Some(v),
+            }
+        }
+        _ => Some(value.clone()),
+    }
+}
+
+/// A whole tool result (`{"content": [...]}`) stands for its text
+fn scalar(value: &Value) -> Value {
+    if value.get("content").is_some_and(|c| c.is_array()) {
+        Value::String(result_text(value).trim().to_string())
+    } else {
+        value.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binds_refs_with_schema_coercion() {
+        let scope = serde_json::json!({
+            "steps": [
+                {"content": [{"type": "text", "text": "3"}]},
+                {"content": [{"type": "text", "text": "{\"port\": \"7000\"}"}]}
+            ]
+        });
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "a": {"type": "number"},
+                "port": {"type": "integer"},
+                "label": {"type": ["string", "null"]}
+            },
+            "required": ["a", "port", "b"]
+        });
+        let args = serde_json::json!({
+            "a": {"$ref": "steps[0]"},
+            "port": {"$ref": "steps[1].content[0].text.port"},
+            "label": {"$ref": "steps[0].content[0].text", "as": "string"},
+            "b": null
+        });
+        assert!(has_refs(&args));
+        let bound = bind(&args, &scope, Some(&schema)).unwrap();
+        assert_eq!(bound, serde_json::json!({"a": 3.0, "port": 7000, "label": "3", "b": null}));
+        assert_eq!(unbound_fields(&bound, &["a".into(), "b".into()]), vec!["b".to_string()]);
+
+        let missing = serde_json::json!({"a": {"$ref": "steps[5]"}});
+        assert!(bind(&missing, &scope, Some(&schema)).is_err());
+        let bad = serde_json::json!({"a": {"$ref": "steps[1].content[0].text", "as": "number"}});
+        assert!(bind(&bad, &scope, None).is_err());
+    }
+}
@@ -0,0 +1,312 @@
+//! Small path and condition language used by control-flow steps.
+//!
+//! Paths address values in the workflow scope built by [`workflow_scope`]:
+//! `steps[0].content[0].text`, `input`, `item.name`. Conditions are either
+//! written as a one-line expression (`steps[0].content[0].text contains "rain"`)
+//! or as structured predicates, so plans stay easy to author by hand.
+use crate::utils::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CompareOp {
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = "contains")]
+    Contains,
+}
+
+impl CompareOp {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "==" => CompareOp::Eq,
+            "!=" => CompareOp::Ne,
+            ">" => CompareOp::Gt,
+            ">=" => CompareOp::Ge,
+            "<" => CompareOp::Lt,
+            "<=" => CompareOp::Le,
+            "contains" => CompareOp::Contains,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Condition {
+    /// One-line expression: `<path> <op> <literal>` or just `<path>` (truthiness)
+    Expr(String),
+    Compare { path: String, op: CompareOp, value: Value },
+    Exists { path: String },
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    pub fn evaluate(&self, scope: &Value) -> anyhow::Result<bool> {
+        Ok(match self {
+            Condition::Expr(expr) => return evaluate_expr(expr, scope),
+            Condition::Compare { path, op, value } => {
+                compare(&lookup(scope, path)?.unwrap_or(Value::Null), op, value)
+            }
+            Condition::Exists { path } => lookup(scope, path)?.is_some_and(|v| !v.is_null()),
+            Condition::All(items) => {
+                for c in items {
+                    if !c.evaluate(scope)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            Condition::Any(items) => {
+                for c in items {
+                    if c.evaluate(scope)? {
+                        return Ok(true);
+                    }
+                }
+                false
+            }
+            Condition::Not(inner) => !inner.evaluate(scope)?,
+        })
+    }
+}
+
+fn evaluate_expr(expr: &str, scope: &Value) -> anyhow::Result<bool> {
+    let expr = expr.trim();
+    let mut parts = expr.splitn(3, char::is_whitespace);
+    let path = parts.next().unwrap_or_default();
+    let value = lookup(scope, path)?.unwrap_or(Value::Null);
+    let Some(op_str) = parts.next() else {
+        return Ok(is_truthy(&value));
+    };
+    let op = CompareOp::parse(op_str)
+        .ok_or_else(|| anyhow::anyhow!("unknown operator '{}' in condition '{}'", op_str, expr))?;
+    let literal = parse_literal(parts.next().unwrap_or_default());
+    Ok(compare(&value, &op, &literal))
+}
+
+/// JSON literal if it parses, otherwise the bare (optionally quoted) word
+fn parse_literal(s: &str) -> Value {
+    let s = s.trim();
+    serde_json::from_str(s).unwrap_or_else(|_| {
+        Value::String(s.trim_matches(|c| c == '\'' || c == '"').to_string())
+    })
+}
+
+fn compare(left: &Value, op: &CompareOp, right: &Value) -> bool {
+    match op {
+        CompareOp::Eq => loosely_equal(left, right),
+        CompareOp::Ne => !loosely_equal(left, right),
+        CompareOp::Contains => match left {
+            Value::String(s) => s.contains(&value_as_string(right)),
+            Value::Array(items) => items.iter().any(|it| loosely_equal(it, right)),
+            Value::Object(map) => map.contains_key(&value_as_string(right)),
+            _ => false,
+        },
+        CompareOp::Gt | CompareOp::Ge | CompareOp::Lt | CompareOp::Le => {
+            let (Some(l), Some(r)) = (as_number(left), as_number(right)) else {
+                return false;
+            };
+            match op {
+                CompareOp::Gt => l > r,
+                CompareOp::Ge => l >= r,
+                CompareOp::Lt => l < r,
+                _ => l <= r,
+            }
+        }
+    }
+}
+
+/// Equality that treats `"42"` and `42` as the same value, since tool results
+/// usually carry numbers as text
+fn loosely_equal(a: &Value, b: &Value) -> bool {
+    if a == b {
+        return true;
+    }
+    match (as_number(a), as_number(b)) {
+        (Some(x), Some(y)) => x == y,
+        _ => match (a, b) {
+            (Value::String(_), _) | (_, Value::String(_)) => value_as_string(a) == value_as_string(b),
+            _ => false,
+        },
+    }
+}
+
+pub fn as_number(v: &Value) -> Option<f64> {
+    match v {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+fn value_as_string(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+pub fn is_truthy(v: &Value) -> bool {
+    match v {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|f| f != 0.0),
+        Value::String(s) => !s.is_empty() && s != "false",
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(o) => !o.is_empty(),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+fn parse_path(path: &str) -> anyhow::Result<Vec<Segment<'_>>> {
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let (key, mut rest) = match part.find('[') {
+            Some(pos) => (&part[..pos], &part[pos..]),
+            None => (part, ""),
+        };
+        if !key.is_empty() {
+            segments.push(Segment::Key(key));
+        }
+        while let Some(stripped) = rest.strip_prefix('[') {
+            let end = stripped
+                .find(']')
+                .ok_or_else(|| anyhow::anyhow!("unclosed '[' in path '{}'", path))?;
+            let idx = stripped[..end]
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| anyhow::anyhow!("invalid index in path '{}'", path))?;
+            segments.push(Segment::Index(idx));
+            rest = &stripped[end + 1..];
+        }
+        if !rest.is_empty() {
+            return Err(anyhow::anyhow!("unexpected '{}' in path '{}'", rest, path));
+        }
+    }
+    Ok(segments)
+}
+
+/// Resolve `path` inside `scope`. Strings holding JSON documents are parsed on
+/// the way, so `steps[0].content[0].text.temp` works when a tool returns JSON
+/// text. Returns `Ok(None)` when the path does not exist.
+pub fn lookup(scope: &Value, path: &str) -> anyhow::Result<Option<Value>> {
+    let segments = parse_path(path.trim())?;
+    let mut current = scope.clone();
+    for seg in &segments {
+        if let Value::String(s) = &current {
+            match serde_json::from_str::<Value>(s) {
+                Ok(parsed) if parsed.is_object() || parsed.is_array() => current = parsed,
+                _ => return Ok(None),
+            }
+        }
+        let next = match (seg, &current) {
+            (Segment::Key(k), Value::Object(map)) => map.get(*k).cloned(),
+            (Segment::Index(i), Value::Array(arr)) => arr.get(*i).cloned(),
+            _ => None,
+        };
+        match next {
+            Some(v) => current = v,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(current))
+}
+
+/// Values visible to conditions and paths:
+/// `steps` (result of each top-level step by index), `input` (the user text)
+/// and, inside a ForEach body, `item` / `item_index`. While a workflow
+/// template runs as a macro, `scope_prefix` holds the path of its steps and
+/// `steps[N]` addresses the template's own steps instead.
+pub fn workflow_scope(ctx: &Context) -> Value {
+    let workflow = ctx.memory.get("workflow");
+    let prefix = workflow
+        .and_then(|w| w.get("scope_prefix"))
+        .and_then(|p| p.as_str());
+    let mut steps: Vec<Value> = Vec::new();
+    if let Some(history) = workflow.and_then(|w| w.get("history")).and_then(|h| h.as_array()) {
+        for entry in history {
+            if entry.get("error").is_some() {
+                continue;
+            }
+            // Entries of steps nested in control flow carry a "path"; only
+            // steps of the current level are addressable as steps[N]
+            let path = entry.get("path").and_then(|p| p.as_str());
+            let idx = match (prefix, path) {
+                (None, None) => entry.get("step_index").and_then(|v| v.as_u64()),
+                (Some(prefix), Some(path)) => path
+                    .strip_prefix(prefix)
+                    .and_then(|rest| rest.parse::<u64>().ok()),
+                _ => None,
+            };
+            let (Some(idx), Some(result)) = (idx, entry.get("result")) else {
+                continue;
+            };
+            let idx = idx as usize;
+            if steps.len() <= idx {
+                steps.resize(idx + 1, Value::Null);
+            }
+            steps[idx] = result.clone();
+        }
+    }
+    let mut scope = serde_json::json!({
+        "steps": steps,
+        "input": ctx.input_text,
+    });
+    if let Some(w) = workflow {
+        for key in ["item", "item_index"] {
+            if let Some(v) = w.get(key) {
+                scope[key] = v.clone();
+            }
+        }
+    }
+    scope
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluates_expressions_over_tool_results() {
+        let scope = serde_json::json!({
+            "steps": [
+                {"content": [{"type": "text", "text": "light rain, 12°C"}]},
+                {"content": [{"type": "text", "text": "{\"temp\": 31, \"files\": [\"a.mp4\"]}"}]}
+            ],
+            "input": "weather?"
+        });
+        let rain = Condition::Expr("steps[0].content[0].text contains \"rain\"".into());
+        assert!(rain.evaluate(&scope).unwrap());
+
+        let hot = Condition::Compare {
+            path: "steps[1].content[0].text.temp".into(),
+            op: CompareOp::Gt,
+            value: serde_json::json!(30),
+        };
+        assert!(hot.evaluate(&scope).unwrap());
+        assert_eq!(
+            lookup(&scope, "steps[1].content[0].text.files[0]").unwrap(),
+            Some(serde_json::json!("a.mp4"))
+        );
+
+        let serialized = serde_json::to_string(&Condition::Not(Box::new(rain.clone()))).unwrap();
+        let back: Condition = serde_json::from_str(&serialized).unwrap();
+        assert!(!back.evaluate(&scope).unwrap());
+    }
+}
@@ -1,10 +1,18 @@
+use crate::core::metrics;
+use crate::core::policy::ConfirmationRequired;
+use crate::core::synthesis::TOOL_RESULT_TYPE;
+use crate::core::uploads;
 use crate::llm::adapter::{ChatMessage, ChatRequest, LLMClient};
 use crate::mcp::client::MCPClient;
-use crate::utils::{Context, OutputEvent, StepSpec};
+use crate::utils::{Attachment, Context, OutputEvent, StepSpec};
 use async_trait::async_trait;
 use serde_json::Value;
 use std::sync::Arc;
-use tracing::info;
+use tracing::{info, warn};
+
+pub mod bindings;
+pub mod expr;
+pub mod schema;
 
 #[derive(Clone, Debug)]
 pub enum StepStatus {
@@ -25,6 +33,9 @@ pub trait WorkflowStep: Send + Sync {
 }
 
 pub struct MemoryStep;
+/// Placeholder for Branch / ForEach / Parallel, which need the engine to run
+/// their children and are executed by `WorkflowEngine::run_step` instead
+pub struct ControlFlowStep;
 pub struct ProfileStep;
 pub struct RelationshipStep;
 pub struct McpToolStep {
@@ -42,6 +53,18 @@ pub trait ParameterResolver: Send + Sync {
         input: &Value,
         ctx: &Context,
     ) -> anyhow::Result<Value>;
+
+    /// Fill the fields of `bound` that are still missing after `$ref`
+    /// bindings were applied. Values already in `bound` must be kept.
+    async fn resolve_unbound(
+        &self,
+        _mcp: &dyn MCPClient,
+        _tool: &str,
+        bound: &Value,
+        _ctx: &Context,
+    ) -> anyhow::Result<Value> {
+        Ok(bound.clone())
+    }
 }
 
 pub struct NoopResolver;
@@ -74,9 +97,54 @@ impl ParameterResolver for LlmParameterResolver {
         ctx: &Context,
     ) -> anyhow::Result<Value> {
         if input.is_object() {
-            return Ok(input.clone());
+            if ctx.attachments.is_empty() {
+                return Ok(input.clone());
+            }
+            let schema = mcp.tool_schema(tool).await?;
+            let mut args = input.clone();
+            let files = bind_attachments(&args, schema.as_ref(), &ctx.attachments);
+            if let Some(obj) = args.as_object_mut() {
+                obj.extend(files);
+            }
+            return Ok(args);
         }
+        self.extract(mcp, tool, input, ctx, &Value::Null).await
+    }
+
+    async fn resolve_unbound(
+        &self,
+        mcp: &dyn MCPClient,
+        tool: &str,
+        bound: &Value,
+        ctx: &Context,
+    ) -> anyhow::Result<Value> {
+        self.extract(mcp, tool, &Value::Null, ctx, bound).await
+    }
+}
+
+impl LlmParameterResolver {
+    /// Ask the LLM for the tool args. Fields of `bound` are fixed: they are
+    /// shown to the LLM as context and win over whatever it returns.
+    async fn extract(
+        &self,
+        mcp: &dyn MCPClient,
+        tool: &str,
+        input: &Value,
+        ctx: &Context,
+        bound: &Value,
+    ) -> anyhow::Result<Value> {
         let schema = mcp.tool_schema(tool).await?;
+        // Attachments are bound to file parameters as they are, rather than
+        // copied by the LLM from the prompt
+        let files = bind_attachments(bound, schema.as_ref(), &ctx.attachments);
+        let mut bound = bound.clone();
+        if !files.is_empty() {
+            match bound.as_object_mut() {
+                Some(obj) => obj.extend(files),
+                None => bound = Value::Object(files),
+            }
+        }
+        let bound = &bound;
         let schema_json = schema
             .as_ref()
             .map(|s| serde_json::to_string(s).unwrap_or_default())
@@ -191,7 +259,13 @@ impl ParameterResolver for LlmParameterResolver {
             None
         };
 
-        let system_prompt_suffix = workflow_context.clone().unwrap_or_default();
+        let mut system_prompt_suffix = workflow_context.clone().unwrap_or_default();
+        if bound.as_object().is_some_and(|b| !b.is_empty()) {
+            system_prompt_suffix.push_str(&format!(
+                "\nAlready Bound Fields (fixed by the workflow, only extract the other fields): {}\n",
+                bound
+            ));
+        }
 
         let system = if schema_json.is_empty() {
             "Convert user's input to a JSON object of tool parameters. Respond with ONLY a valid JSON object.".to_string()
@@ -235,7 +309,10 @@ impl ParameterResolver for LlmParameterResolver {
             format!("Previous result: {}", prev)
         };
         
-        let workflow_context_user = workflow_context.clone().unwrap_or_default();
+        let mut workflow_context_user = workflow_context.clone().unwrap_or_default();
+        if let Some(files) = uploads::describe_attachments(&ctx.attachments) {
+            workflow_context_user.push_str(&files);
+        }
         
         let user = if prev_str.is_empty() {
             format!("Input: {}\n{}\nReturn JSON:", input_text, workflow_context_user)
@@ -264,7 +341,7 @@ impl ParameterResolver for LlmParameterResolver {
             "LlmParameterResolver calling LLM with input: {}",
             input_text
         );
-        let out = self.llm.chat(req).await?;
+        let out = metrics::observe_llm("resolver", self.llm.chat(req)).await?;
         let s = out.text.trim();
         tracing::info!("LlmParameterResolver LLM output: {}", s);
 
@@ -277,47 +354,51 @@ impl ParameterResolver for LlmParameterResolver {
         } else {
             s
         };
-        let v: serde_json::Value = serde_json::from_str(json_slice).map_err(|e| {
+        let mut v: serde_json::Value = serde_json::from_str(json_slice).map_err(|e| {
             anyhow::anyhow!(
                 "Failed to parse JSON from LLM output: '{}'. Error: {}",
                 json_slice,
                 e
             )
         })?;
+        settle_args(&mut v, input, bound, schema.as_ref());
 
         // --- Parameter Evaluation and Correction Module ---
-        let evaluator = ParameterEvaluator {
-            llm: self.llm.clone(),
-            model: self.model.clone(),
-        };
-        
-        let fixed_v = evaluator.evaluate_and_fix(
-            tool,
-            &schema_json,
-            &input_text,
-            &v,
-            &workflow_context_user,
-            &required_fields
-        ).await.unwrap_or_else(|e| {
-            tracing::error!("Parameter evaluation failed, using original args: {}", e);
-            v.clone()
-        });
-        
-        let mut v = fixed_v;
-        // ---------------------------------------------------
-
-        normalize_null_strings(&mut v);
-
-        // Merge with original input if it was an object (preserve Planner's explicit values)
-        if let Some(original_obj) = input.as_object() {
-            if let Some(v_obj) = v.as_object_mut() {
-                for (k, val) in original_obj {
-                    if !val.is_null() {
-                        v_obj.insert(k.clone(), val.clone());
-                    }
-                }
-            }
+        // Only invalid values are sent back to the auditor; missing required
+        // fields are left to the MCP server's elicitation
+        let errors: Vec<_> = schema
+            .as_ref()
+            .map(|s| schema::validate(&v, s))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|e| !e.missing)
+            .collect();
+        if !errors.is_empty() {
+            let evaluator = ParameterEvaluator {
+                llm: self.llm.clone(),
+                model: self.model.clone(),
+            };
+
+            let fixed_v = evaluator.evaluate_and_fix(
+                tool,
+                &schema_json,
+                &input_text,
+                &v,
+                &format!(
+                    "{}\nValidation Errors (fix exactly these fields):\n{}",
+                    workflow_context_user,
+                    schema::describe(&errors)
+                ),
+                &required_fields
+            ).await.unwrap_or_else(|e| {
+                tracing::error!("Parameter evaluation failed, using original args: {}", e);
+                v.clone()
+            });
+
+            v = fixed_v;
+            settle_args(&mut v, input, bound, schema.as_ref());
         }
+        // ---------------------------------------------------
 
         ensure_required_fields_present(&mut v, &required_fields);
         Ok(v)
@@ -378,7 +459,7 @@ impl ParameterEvaluator {
         };
 
         info!("ParameterEvaluator checking args: {}", generated_args);
-        let out = self.llm.chat(req).await?;
+        let out = metrics::observe_llm("auditor", self.llm.chat(req)).await?;
         let s = out.text.trim();
         
         let json_slice = if let Some(start) = s.find('{') {
@@ -397,6 +478,62 @@ impl ParameterEvaluator {
     }
 }
 
+/// Attachments for the file parameters of `schema` that `given` has no
+/// value for. An array parameter takes every attachment it accepts; a single
+/// one only an attachment that is the sole one it accepts, since picking
+/// among several is the LLM's job.
+fn bind_attachments(
+    given: &Value,
+    schema: Option<&Value>,
+    attachments: &[Attachment],
+) -> serde_json::Map<String, Value> {
+    let mut files = serde_json::Map::new();
+    let Some(schema) = schema else {
+        return files;
+    };
+    if attachments.is_empty() {
+        return files;
+    }
+    for param in schema::file_params(schema) {
+        if given.get(&param.name).is_some_and(|v| !v.is_null()) {
+            continue;
+        }
+        let values: Vec<Value> = attachments
+            .iter()
+            .filter(|a| param.accepts(&a.mime_type))
+            .filter_map(|a| match param.format {
+                schema::FileFormat::Path => a.path.clone(),
+                schema::FileFormat::Uri => Some(a.uri.clone()),
+            })
+            .map(Value::String)
+            .collect();
+        if param.many && !values.is_empty() {
+            files.insert(param.name, Value::Array(values));
+        } else if let [value] = values.as_slice() {
+            files.insert(param.name, value.clone());
+        }
+    }
+    files
+}
+
+/// Normalize LLM args: "null" strings, the fixed values from the planner and
+/// `$ref` bindings, then schema coercion
+fn settle_args(v: &mut Value, input: &Value, bound: &Value, tool_schema: Option<&Value>) {
+    normalize_null_strings(v);
+    for original_obj in [input.as_object(), bound.as_object()].into_iter().flatten() {
+        if let Some(v_obj) = v.as_object_mut() {
+            for (k, val) in original_obj {
+                if !val.is_null() {
+                    v_obj.insert(k.clone(), val.clone());
+                }
+            }
+        }
+    }
+    if let Some(s) = tool_schema {
+        schema::coerce(v, s);
+    }
+}
+
 fn normalize_null_strings(v: &mut Value) {
     match v {
         Value::String(s) => {
@@ -473,13 +610,23 @@ impl WorkflowStep for RelationshipStep {
     }
 }
 
+#[async_trait]
+impl WorkflowStep for ControlFlowStep {
+    async fn run(&self, _ctx: &mut Context, _mcp: &dyn MCPClient) -> anyhow::Result<StepResult> {
+        Err(anyhow::anyhow!(
+            "control-flow steps must be run through WorkflowEngine::run_step"
+        ))
+    }
+}
+
 #[async_trait]
 impl WorkflowStep for McpToolStep {
     async fn run(&self, ctx: &mut Context, mcp: &dyn MCPClient) -> anyhow::Result<StepResult> {
-        let mut resolved_args = self
-            .resolver
-            .resolve(mcp, &self.name, &self.args, ctx)
-            .await?;
+        // A call that waited for confirmation resumes with the approved args
+        let mut resolved_args = match take_pending_confirmation(ctx, &self.name) {
+            Some(args) => args,
+            None => resolve_args(&*self.resolver, mcp, &self.name, &self.args, ctx).await?,
+        };
 
         if let Some(session_id) = ctx.session_id.clone() {
             if let Some(obj) = resolved_args.as_object_mut() {
@@ -492,25 +639,80 @@ impl WorkflowStep for McpToolStep {
             }
         }
 
-        // Removed client-side validation to allow MCP server to handle elicitation
+        // Validate locally. Missing required fields are left to the MCP
+        // server's elicitation; invalid ones are cleared so it asks for them
+        // again, with the errors shown in the prompt.
+        if resolved_args.is_object()
+            && let Ok(Some(tool_schema)) = mcp.tool_schema(&self.name).await
+        {
+            schema::coerce(&mut resolved_args, &tool_schema);
+            let errors: Vec<_> = schema::validate(&resolved_args, &tool_schema)
+                .into_iter()
+                .filter(|e| !e.missing)
+                .collect();
+            if !errors.is_empty() {
+                warn!("invalid args for tool '{}':\n{}", self.name, schema::describe(&errors));
+                schema::clear_invalid(&mut resolved_args, &tool_schema, &errors);
+                set_elicitation_hint(&mut resolved_args, &errors);
+            }
+        }
 
         // Record execution history (BEFORE calling, using resolved args)
+        let step_path = current_step_path(ctx);
         if let Some(workflow) = ctx.memory.get_mut("workflow") {
             if let Some(current_idx) = workflow.get("current_step_index").and_then(|v| v.as_u64()) {
                 if let Some(workflow_obj) = workflow.as_object_mut() {
                      let history = workflow_obj.entry("history").or_insert_with(|| serde_json::Value::Array(Vec::new()));
                      if let Some(arr) = history.as_array_mut() {
-                         arr.push(serde_json::json!({
+                         let mut entry = serde_json::json!({
                              "step_index": current_idx,
                              "tool": self.name,
                              "args": resolved_args
-                         }));
+                         });
+                         if let Some(path) = step_path {
+                             entry["path"] = Value::String(path);
+                         }
+                         arr.push(entry);
                      }
                 }
             }
         }
 
-        let val = mcp.call(&self.name, resolved_args.clone()).await?;
+        let started = std::time::Instant::now();
+        let called = mcp.call(&self.name, resolved_args.clone()).await;
+        if let Err(e) = &called
+            && let Some(confirm) = e.downcast_ref::<ConfirmationRequired>()
+        {
+            info!("tool '{}' waits for confirmation", self.name);
+            // The call did not go out; it is recorded again when it does
+            if let Some(history) = ctx.memory.pointer_mut("/workflow/history").and_then(|h| h.as_array_mut()) {
+                history.pop();
+            }
+            let pending = serde_json::json!({
+                "tool": self.name,
+                "args": confirm.args,
+                "step": confirmation_key(ctx),
+            });
+            if let Some(map) = ctx.memory.as_object_mut() {
+                map.insert(PENDING_CONFIRMATION.to_string(), pending);
+            }
+            return Ok(StepResult {
+                status: StepStatus::WaitUser(confirm.prompt()),
+                output: None,
+            });
+        }
+        metrics::record_tool_call(
+            &self.name,
+            called.as_ref().is_ok_and(|v| tool_error_message(v).is_none()),
+            started.elapsed(),
+        );
+        let val = match called {
+            Ok(v) => v,
+            Err(e) => {
+                record_step_error(ctx, &e.to_string());
+                return Err(e);
+            }
+        };
         if let Some(map) = ctx.memory.as_object_mut() {
             map.insert("last_tool_result".to_string(), val.clone());
             
@@ -529,11 +731,20 @@ impl WorkflowStep for McpToolStep {
         } else {
             ctx.memory = serde_json::json!({"last_tool_result": val.clone()});
         }
+        if let Some(message) = tool_error_message(&val) {
+            record_step_error(ctx, &message);
+            return Err(anyhow::anyhow!("tool '{}' failed: {}", self.name, message));
+        }
         let o = OutputEvent {
             target: "default".into(),
             source: "system".into(),
             session_id: ctx.session_id.clone(),
-            content: val,
+            content: serde_json::json!({
+                "type": TOOL_RESULT_TYPE,
+                "tool": self.name,
+                "args": resolved_args,
+                "result": val
+            }),
             style: ctx.persona.style.clone(),
         };
         Ok(StepResult {
@@ -543,6 +754,121 @@ impl WorkflowStep for McpToolStep {
     }
 }
 
+/// Extract the error message from a `CallToolResult` that reports failure,
+/// either through `isError` or the `tool_error` text convention used by
+/// robot_mcp_server tools.
+pub fn tool_error_message(result: &Value) -> Option<String> {
+    let texts: Vec<&str> = result
+        .get("content")
+        .and_then(|c| c.as_array())
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|it| it.get("text").and_then(|t| t.as_str()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if let Some(text) = texts.iter().find(|t| t.starts_with("tool_error")) {
+        let message = text
+            .lines()
+            .find_map(|l| l.strip_prefix("message="))
+            .unwrap_or(text);
+        return Some(message.to_string());
+    }
+    if result.get("isError").and_then(|v| v.as_bool()) == Some(true) {
+        let joined = texts.join("\n");
+        return Some(if joined.is_empty() {
+            "tool reported an error".to_string()
+        } else {
+            joined
+        });
+    }
+    None
+}
+
+/// Position of a step nested in control flow, e.g. `2/item[0]/0`.
+/// `None` for top-level steps.
+pub fn current_step_path(ctx: &Context) -> Option<String> {
+    ctx.memory
+        .get("workflow")
+        .and_then(|w| w.get("current_step_path"))
+        .and_then(|p| p.as_str())
+        .map(|p| p.to_string())
+}
+
+/// Key in `ctx.memory` of the tool call waiting for the user's approval:
+/// `{tool, args, step}`, with null args for a background step confirmed
+/// before its args are resolved
+pub const PENDING_CONFIRMATION: &str = "pending_confirmation";
+
+/// The step a confirmation belongs to: its nested path or top-level index
+pub fn confirmation_key(ctx: &Context) -> String {
+    current_step_path(ctx).unwrap_or_else(|| ctx.memory["workflow"]["current_step_index"].to_string())
+}
+
+/// The approved args of `tool` if the current step waited for confirmation
+fn take_pending_confirmation(ctx: &mut Context, tool: &str) -> Option<Value> {
+    let pending = ctx.memory.get(PENDING_CONFIRMATION)?;
+    if pending["tool"] != tool || pending["step"] != confirmation_key(ctx).as_str() || pending["args"].is_null() {
+        return None;
+    }
+    ctx.memory.as_object_mut()?.remove(PENDING_CONFIRMATION).map(|p| p["args"].clone())
+}
+
+/// Args for `tool`: `$ref` bindings are resolved against earlier results and
+/// the resolver only fills the required fields they leave open. Args without
+/// bindings go to the resolver as before.
+pub async fn resolve_args(
+    resolver: &dyn ParameterResolver,
+    mcp: &dyn MCPClient,
+    tool: &str,
+    args: &Value,
+    ctx: &Context,
+) -> anyhow::Result<Value> {
+    if !bindings::has_refs(args) {
+        return resolver.resolve(mcp, tool, args, ctx).await;
+    }
+    let schema = mcp.tool_schema(tool).await.ok().flatten();
+    let bound = bindings::bind(args, &expr::workflow_scope(ctx), schema.as_ref())?;
+    let required = mcp.required_fields(tool).await.unwrap_or_default();
+    if bindings::unbound_fields(&bound, &required).is_empty() {
+        Ok(bound)
+    } else {
+        resolver.resolve_unbound(mcp, tool, &bound, ctx).await
+    }
+}
+
+/// Pass field errors to the client's elicitation prompt via `__elicitation`
+fn set_elicitation_hint(args: &mut Value, errors: &[schema::FieldError]) {
+    let Some(obj) = args.as_object_mut() else {
+        return;
+    };
+    let meta = obj
+        .entry("__elicitation")
+        .or_insert_with(|| Value::Object(Default::default()));
+    if let Some(meta) = meta.as_object_mut() {
+        meta.insert(
+            "hint".to_string(),
+            Value::String(format!("参数校验失败:\n{}", schema::describe(errors))),
+        );
+    }
+}
+
+/// Attach an error to the latest entry of `ctx.memory["workflow"]["history"]`
+fn record_step_error(ctx: &mut Context, message: &str) {
+    if let Some(last_entry) = ctx
+        .memory
+        .get_mut("workflow")
+        .and_then(|w| w.get_mut("history"))
+        .and_then(|h| h.as_array_mut())
+        .and_then(|h| h.last_mut())
+        .and_then(|e| e.as_object_mut())
+    {
+        last_entry.insert("error".to_string(), Value::String(message.to_string()));
+    }
+}
+
 pub fn build_step(
     spec: &StepSpec,
     resolver: Arc<dyn ParameterResolver + Send + Sync>,
@@ -554,12 +880,77 @@ pub fn build_step(
         StepSpec::Tool {
             name,
             args,
-            is_background: _,
-            dependencies: _,
+            ..
         } => Box::new(McpToolStep {
             name: name.clone(),
             args: args.clone(),
             resolver,
         }),
+        StepSpec::Branch { .. } | StepSpec::ForEach { .. } | StepSpec::Parallel { .. } => {
+            Box::new(ControlFlowStep)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bind_attachments, tool_error_message};
+    use crate::utils::Attachment;
+
+    #[test]
+    fn detects_tool_error_results() {
+        let division_by_zero = serde_json::json!({
+            "content": [{"type": "text", "text": "tool_error\nname=division\nmessage=除数不能为0"}],
+            "isError": false
+        });
+        assert_eq!(tool_error_message(&division_by_zero).as_deref(), Some("除数不能为0"));
+
+        let is_error = serde_json::json!({
+            "content": [{"type": "text", "text": "boom"}],
+            "isError": true
+        });
+        assert_eq!(tool_error_message(&is_error).as_deref(), Some("boom"));
+
+        let ok = serde_json::json!({"content": [{"type": "text", "text": "42"}]});
+        assert_eq!(tool_error_message(&ok), None);
+    }
+
+    fn attachment(name: &str, mime_type: &str) -> Attachment {
+        Attachment {
+            uri: format!("upload://{}", name),
+            name: name.to_string(),
+            mime_type: mime_type.to_string(),
+            size: 1,
+            sha256: name.to_string(),
+            path: Some(format!("/data/blobs/{}", name)),
+        }
+    }
+
+    #[test]
+    fn binds_attachments_to_file_parameters() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "input": { "type": "string", "format": "path", "contentMediaType": "video/*" },
+                "source": { "$ref": "#/$defs/Link" },
+                "images": { "type": "array", "items": { "type": "string", "format": "uri", "contentMediaType": "image/*" } },
+                "note": { "type": "string" }
+            },
+            "$defs": { "Link": { "type": "string", "format": "uri" } }
+        });
+        let files = [attachment("clip", "video/mp4"), attachment("a", "image/png"), attachment("b", "image/jpeg")];
+
+        let bound = bind_attachments(&serde_json::Value::Null, Some(&schema), &files);
+        assert_eq!(bound["input"], "/data/blobs/clip");
+        assert_eq!(bound["images"], serde_json::json!(["upload://a", "upload://b"]));
+        // Three attachments fit `source`; which one is meant is left to the LLM
+        assert!(!bound.contains_key("source"));
+        assert!(!bound.contains_key("note"));
+
+        // Values already given are kept
+        let given = serde_json::json!({ "input": "/tmp/other.mp4" });
+        let bound = bind_attachments(&given, Some(&schema), &files[..1]);
+        assert!(!bound.contains_key("input"));
+        assert_eq!(bound["source"], "upload://clip");
     }
 }
@@ -0,0 +1,477 @@
+//! Local validation of tool arguments against the tool's JSON Schema.
+//!
+//! Covers the subset MCP servers produce with schemars: `type` (single or
+//! list), `properties`, `required`, `additionalProperties`, `items`, `enum`,
+//! `const`, numeric and length bounds, `pattern`, `anyOf` / `oneOf` /
+//! `allOf` and local `$ref`s. [`coerce`] fixes the obvious mismatches LLMs
+//! produce (`"12"` for 12, `"null"` for null) before [`validate`] reports
+//! what is still wrong, field by field. [`file_params`] finds the parameters
+//! that take a file path or URI, so attachments can be bound to them.
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldError {
+    /// Location of the value, e.g. `port` or `files[1].name`; empty for the root
+    pub path: String,
+    pub message: String,
+    /// A required field is absent or null; the MCP server will elicit it
+    pub missing: bool,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// One error per line, for prompts
+pub fn describe(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|e| format!("- {}", e))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Fields the client adds itself and servers accept anyway
+fn is_injected(key: &str) -> bool {
+    key == "session_id" || key.starts_with("__")
+}
+
+/// Convert values in place where the schema makes the intent unambiguous
+pub fn coerce(value: &mut Value, schema: &Value) {
+    coerce_at(value, schema, schema);
+}
+
+fn coerce_at(value: &mut Value, schema: &Value, root: &Value) {
+    let schema = resolve_ref(schema, root);
+    if let Value::String(s) = value
+        && s.trim().eq_ignore_ascii_case("null")
+    {
+        *value = Value::Null;
+        return;
+    }
+
+    if let Some(branches) = schema.get("anyOf").or_else(|| schema.get("oneOf")).and_then(|b| b.as_array()) {
+        if branches.iter().any(|b| validate_at(value, b, root, "").is_empty()) {
+            return;
+        }
+        for branch in branches {
+            let mut candidate = value.clone();
+            coerce_at(&mut candidate, branch, root);
+            if validate_at(&candidate, branch, root, "").is_empty() {
+                *value = candidate;
+                return;
+            }
+        }
+        return;
+    }
+
+    let types = types_of(schema);
+    if !types.is_empty()
+        && !types.iter().any(|t| has_type(value, t))
+        && let Some(converted) = types.iter().find_map(|t| convert(value, t))
+    {
+        *value = converted;
+    }
+
+    match value {
+        Value::Object(map) => {
+            if let Some(props) = schema.get("properties").and_then(|p| p.as_object()) {
+                for (k, v) in map.iter_mut() {
+                    if let Some(sub) = props.get(k) {
+                        coerce_at(v, sub, root);
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(sub) = schema.get("items").filter(|s| s.is_object()) {
+                for v in items.iter_mut() {
+                    coerce_at(v, sub, root);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn convert(value: &Value, ty: &str) -> Option<Value> {
+    match (ty, value) {
+        ("integer", Value::String(s)) => s.trim().parse::<i64>().ok().map(Value::from),
+        ("integer", Value::Number(n)) => n
+            .as_f64()
+            .filter(|f| f.fract() == 0.0)
+            .map(|f| Value::from(f as i64)),
+        ("number", Value::String(s)) => s.trim().parse::<f64>().ok().map(Value::from),
+        ("boolean", Value::String(s)) => s.trim().to_lowercase().parse::<bool>().ok().map(Value::Bool),
+        ("string", Value::Number(n)) => Some(Value::String(n.to_string())),
+        ("string", Value::Bool(b)) => Some(Value::String(b.to_string())),
+        ("array", Value::String(s)) => serde_json::from_str::<Value>(s).ok().filter(|v| v.is_array()),
+        ("object", Value::String(s)) => serde_json::from_str::<Value>(s).ok().filter(|v| v.is_object()),
+        ("null", Value::String(s)) if s.trim().is_empty() => Some(Value::Null),
+        _ => None,
+    }
+}
+
+/// Everything wrong with `value`; empty when it is valid
+pub fn validate(value: &Value, schema: &Value) -> Vec<FieldError> {
+    validate_at(value, schema, schema, "")
+}
+
+fn validate_at(value: &Value, schema: &Value, root: &Value, path: &str) -> Vec<FieldError> {
+    let schema = resolve_ref(schema, root);
+    let mut errors = Vec::new();
+    let mut fail = |message: String| {
+        errors.push(FieldError {
+            path: path.to_string(),
+            message,
+            missing: false,
+        })
+    };
+
+    let types = types_of(schema);
+    if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+        fail(format!("expected {}, got {}", types.join(" or "), describe_value(value)));
+        return errors;
+    }
+    if let Some(options) = schema.get("enum").and_then(|e| e.as_array())
+        && !options.contains(value)
+    {
+        fail(format!("must be one of {}", Value::Array(options.clone())));
+    }
+    if let Some(c) = schema.get("const")
+        && c != value
+    {
+        fail(format!("must be {}", c));
+    }
+
+    match value {
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            let bound = |key: &str| schema.get(key).and_then(|v| v.as_f64());
+            if let Some(min) = bound("minimum")
+                && n < min
+            {
+                fail(format!("must be >= {}", min));
+            }
+            if let Some(max) = bound("maximum")
+                && n > max
+            {
+                fail(format!("must be <= {}", max));
+            }
+            if let Some(min) = bound("exclusiveMinimum")
+                && n <= min
+            {
+                fail(format!("must be > {}", min));
+            }
+            if let Some(max) = bound("exclusiveMaximum")
+                && n >= max
+            {
+                fail(format!("must be < {}", max));
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(|v| v.as_u64())
+                && len < min
+            {
+                fail(format!("must have at least {} characters", min));
+            }
+            if let Some(max) = schema.get("maxLength").and_then(|v| v.as_u64())
+                && len > max
+            {
+                fail(format!("must have at most {} characters", max));
+            }
+            if let Some(pattern) = schema.get("pattern").and_then(|v| v.as_str())
+                && let Ok(re) = Regex::new(pattern)
+                && !re.is_match(s)
+            {
+                fail(format!("must match pattern {}", pattern));
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(branches) = schema.get("anyOf").or_else(|| schema.get("oneOf")).and_then(|b| b.as_array()) {
+        let branch_errors: Vec<Vec<FieldError>> = branches
+            .iter()
+            .map(|b| validate_at(value, b, root, path))
+            .collect();
+        if !branch_errors.iter().any(|e| e.is_empty()) {
+            // Report the closest branch
+            if let Some(best) = branch_errors.into_iter().min_by_key(|e| e.len()) {
+                errors.extend(best);
+            }
+        }
+    }
+    for sub in schema.get("allOf").and_then(|b| b.as_array()).into_iter().flatten() {
+        errors.extend(validate_at(value, sub, root, path));
+    }
+
+    match value {
+        Value::Object(map) => errors.extend(validate_object(map, schema, root, path)),
+        Value::Array(items) => {
+            let count = items.len() as u64;
+            if let Some(min) = schema.get("minItems").and_then(|v| v.as_u64())
+                && count < min
+            {
+                errors.push(FieldError {
+                    path: path.to_string(),
+                    message: format!("must have at least {} items", min),
+                    missing: false,
+                });
+            }
+            if let Some(max) = schema.get("maxItems").and_then(|v| v.as_u64())
+                && count > max
+            {
+                errors.push(FieldError {
+                    path: path.to_string(),
+                    message: format!("must have at most {} items", max),
+                    missing: false,
+                });
+            }
+            match schema.get("items") {
+                Some(sub @ Value::Object(_)) => {
+                    for (i, v) in items.iter().enumerate() {
+                        errors.extend(validate_at(v, sub, root, &format!("{}[{}]", path, i)));
+                    }
+                }
+                Some(Value::Array(subs)) => {
+                    for (i, (v, sub)) in items.iter().zip(subs).enumerate() {
+                        errors.extend(validate_at(v, sub, root, &format!("{}[{}]", path, i)));
+                    }
+                }
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+    errors
+}
+
+fn validate_object(map: &Map<String, Value>, schema: &Value, root: &Value, path: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    let field_path = |k: &str| {
+        if path.is_empty() {
+            k.to_string()
+        } else {
+            format!("{}.{}", path, k)
+        }
+    };
+    for field in schema.get("required").and_then(|r| r.as_array()).into_iter().flatten() {
+        let Some(field) = field.as_str() else { continue };
+        if map.get(field).is_none_or(|v| v.is_null()) {
+            errors.push(FieldError {
+                path: field_path(field),
+                message: "is required".into(),
+                missing: true,
+            });
+        }
+    }
+    let props = schema.get("properties").and_then(|p| p.as_object());
+    for (k, v) in map {
+        match props.and_then(|p| p.get(k)) {
+            // Null means "not given"; required ones were reported above
+            Some(_) if v.is_null() => {}
+            Some(sub) => errors.extend(validate_at(v, sub, root, &field_path(k))),
+            None if is_injected(k) => {}
+            None => match schema.get("additionalProperties") {
+                Some(Value::Bool(false)) => errors.push(FieldError {
+                    path: field_path(k),
+                    message: "is not a known parameter".into(),
+                    missing: false,
+                }),
+                Some(sub @ Value::Object(_)) => errors.extend(validate_at(v, sub, root, &field_path(k))),
+                _ => {}
+            },
+        }
+    }
+    errors
+}
+
+fn is_required(schema: &Value, field: &str) -> bool {
+    schema
+        .get("required")
+        .and_then(|r| r.as_array())
+        .is_some_and(|r| r.iter().any(|f| f.as_str() == Some(field)))
+}
+
+/// Remove the top-level fields that have (non-missing) errors, so the MCP
+/// server asks the user for them again. Required fields are set to null,
+/// optional ones dropped.
+pub fn clear_invalid(args: &mut Value, schema: &Value, errors: &[FieldError]) {
+    let Some(map) = args.as_object_mut() else {
+        return;
+    };
+    for e in errors.iter().filter(|e| !e.missing) {
+        let field = e.path.split(['.', '[']).next().unwrap_or_default();
+        if field.is_empty() {
+            continue;
+        }
+        if is_required(schema, field) {
+            map.insert(field.to_string(), Value::Null);
+        } else {
+            map.remove(field);
+        }
+    }
+}
+
+/// How a parameter takes a file, from the `format` of its schema
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    /// `file` or `path`: a path on the local filesystem
+    Path,
+    /// `uri`, `uri-reference` or `iri`
+    Uri,
+}
+
+/// A top-level parameter declared to take a file
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileParam {
+    pub name: String,
+    pub format: FileFormat,
+    /// An array of files rather than one
+    pub many: bool,
+    /// `contentMediaType`, e.g. `video/*`, when the schema restricts it
+    pub media_type: Option<String>,
+}
+
+impl FileParam {
+    pub fn accepts(&self, mime_type: &str) -> bool {
+        match self.media_type.as_deref() {
+            None => true,
+            Some(wanted) => match wanted.strip_suffix("/*") {
+                Some(top) => mime_type.split('/').next() == Some(top),
+                None => wanted.eq_ignore_ascii_case(mime_type),
+            },
+        }
+    }
+}
+
+/// The parameters of `schema` whose `format` (or whose items' `format`)
+/// marks them as file paths or URIs
+pub fn file_params(schema: &Value) -> Vec<FileParam> {
+    let root = schema;
+    let Some(properties) = resolve_ref(schema, root).get("properties").and_then(|p| p.as_object()) else {
+        return Vec::new();
+    };
+    properties
+        .iter()
+        .filter_map(|(name, prop)| {
+            let prop = resolve_ref(prop, root);
+            let (target, many) = match prop.get("items") {
+                Some(items) if types_of(prop).contains(&"array") => (resolve_ref(items, root), true),
+                _ => (prop, false),
+            };
+            let format = match target.get("format").and_then(|f| f.as_str())? {
+                "file" | "path" => FileFormat::Path,
+                "uri" | "uri-reference" | "iri" => FileFormat::Uri,
+                _ => return None,
+            };
+            Some(FileParam {
+                name: name.clone(),
+                format,
+                many,
+                media_type: target
+                    .get("contentMediaType")
+                    .and_then(|m| m.as_str())
+                    .map(str::to_string),
+            })
+        })
+        .collect()
+}
+
+/// Follow a local `$ref` (`#/$defs/X`, `#/definitions/X`)
+fn resolve_ref<'a>(schema: &'a Value, root: &'a Value) -> &'a Value {
+    let mut current = schema;
+    // Bounded, so a self-referencing schema cannot loop forever
+    for _ in 0..16 {
+        match current.get("$ref").and_then(|r| r.as_str()).and_then(|r| r.strip_prefix('#')) {
+            Some(pointer) => match root.pointer(pointer) {
+                Some(target) => current = target,
+                None => break,
+            },
+            None => break,
+        }
+    }
+    current
+}
+
+fn types_of(schema: &Value) -> Vec<&str> {
+    match schema.get("type") {
+        Some(Value::String(t)) => vec![t.as_str()],
+        Some(Value::Array(list)) => list.iter().filter_map(|t| t.as_str()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn has_type(value: &Value, ty: &str) -> bool {
+    match ty {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => match value {
+            Value::Number(n) => n.is_i64() || n.is_u64() || n.as_f64().is_some_and(|f| f.fract() == 0.0),
+            _ => false,
+        },
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+fn describe_value(value: &Value) -> String {
+    match value {
+        Value::Null => "null".into(),
+        Value::Bool(_) => format!("boolean {}", value),
+        Value::Number(_) => format!("number {}", value),
+        Value::String(_) => format!("string {}", value),
+        Value::Array(_) => "array".into(),
+        Value::Object(_) => "object".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coerces_obvious_cases_and_reports_field_errors() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "port": {"type": "integer", "minimum": 1, "maximum": 65535},
+                "ip": {"type": "string", "pattern": "^[0-9.]+$"},
+                "channel": {"anyOf": [{"$ref": "#/$defs/Channel"}, {"type": "null"}]},
+                "loop": {"type": ["boolean", "null"]}
+            },
+            "required": ["port", "ip"],
+            "$defs": {"Channel": {"type": "integer", "enum": [1, 2]}}
+        });
+
+        let mut args = serde_json::json!({"port": "7000", "ip": "10.0.0.5", "channel": "2", "loop": "null"});
+        coerce(&mut args, &schema);
+        assert_eq!(args, serde_json::json!({"port": 7000, "ip": "10.0.0.5", "channel": 2, "loop": null}));
+        assert!(validate(&args, &schema).is_empty());
+
+        let mut args = serde_json::json!({"port": "seven", "ip": "localhost", "channel": 3});
+        coerce(&mut args, &schema);
+        let errors = validate(&args, &schema);
+        let paths: Vec<&str> = errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["channel", "ip", "port"]);
+        assert!(errors[2].message.contains("expected integer"));
+
+        let errors = validate(&serde_json::json!({"ip": "1.2.3.4", "session_id": "s"}), &schema);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].missing);
+        assert_eq!(errors[0].to_string(), "port: is required");
+    }
+}
@@ -230,7 +230,7 @@ impl ExternalManager {
                     description: Some(Cow::Owned(desc)),
                     input_schema: Arc::new((*t.input_schema).clone()),
                     output_schema: None,
-                    annotations: None,
+                    annotations: t.annotations.clone(),
                     icons: None,
                     meta: None,
                 };
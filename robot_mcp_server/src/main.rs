@@ -9,6 +9,7 @@ use tracing_subscriber::{self, EnvFilter, layer::SubscriberExt, util::Subscriber
 mod tools;
 use crate::tools::AppState;
 mod external;
+mod uploads;
 
 elicit_safe!();
 
@@ -32,7 +33,10 @@ impl ServerHandler for RobotService {
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
             instructions: Some("Robot MCP Server with Memory and Profile capabilities".into()),
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            capabilities: ServerCapabilities::builder()
+                .enable_tools()
+                .enable_resources()
+                .build(),
             ..Default::default()
         }
     }
@@ -58,6 +62,38 @@ impl ServerHandler for RobotService {
         })
     }
 
+    /// Files users uploaded to robot_core
+    async fn list_resources(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, ErrorData> {
+        let resources = uploads::list()
+            .await
+            .map_err(|e| ErrorData::internal_error(format!("读取上传文件失败: {}", e), None))?;
+        Ok(ListResourcesResult::with_all_items(resources))
+    }
+
+    async fn read_resource(
+        &self,
+        request: ReadResourceRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, ErrorData> {
+        match uploads::read(&request.uri).await {
+            Ok(Some(contents)) => Ok(ReadResourceResult {
+                contents: vec![contents],
+            }),
+            Ok(None) => Err(ErrorData::resource_not_found(
+                format!("未知资源: {}", request.uri),
+                None,
+            )),
+            Err(e) => Err(ErrorData::internal_error(
+                format!("读取资源失败: {}", e),
+                None,
+            )),
+        }
+    }
+
     async fn call_tool(
         &self,
         request: CallToolRequestParam,
@@ -44,7 +44,7 @@ pub fn tool() -> ToolEntry {
         title: Some("Chat".into()),
         description: Some("[Conversational] Engages in open-ended conversation, answers general questions, and handles small talk.".into()),
         input_schema: Arc::new(to_object(serde_json::to_value(schema).unwrap())),
-        output_schema: None, annotations: None, icons: None, meta: None,
+        output_schema: None, annotations: crate::tools::read_only(true), icons: None, meta: None,
     };
     ToolEntry {
         name: "chat",
@@ -39,7 +39,7 @@ pub fn tool() -> ToolEntry {
         description: Some("[Utility] Calculate the division of two numbers".into()),
         input_schema: Arc::new(to_object(serde_json::to_value(schema).unwrap())),
         output_schema: None,
-        annotations: None,
+        annotations: crate::tools::read_only(false),
         icons: None,
         meta: None,
     };
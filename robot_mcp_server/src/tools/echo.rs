@@ -33,7 +33,7 @@ pub fn tool() -> ToolEntry {
         title: Some("Echo".into()),
         description: Some("[Utility] Returns the exact input string provided. Useful for testing connectivity or verification.".into()),
         input_schema: Arc::new(to_object(serde_json::to_value(schema).unwrap())),
-        output_schema: None, annotations: None, icons: None, meta: None,
+        output_schema: None, annotations: crate::tools::read_only(false), icons: None, meta: None,
     };
     ToolEntry {
         name: "echo",
@@ -15,7 +15,10 @@ use tokio::process::Command;
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[schemars(description = "ffprobe 分析工具")]
 pub struct FFProbeRequest {
-    #[schemars(description = "输入文件路径或 URI（例如 file:///path/to/video.mp4）")]
+    #[schemars(
+        description = "输入文件的本地路径、远程 URL 或上传文件 URI（upload://...）",
+        extend("format" = "path", "contentMediaType" = "video/*")
+    )]
     pub input: String,
 }
 impl rmcp::service::ElicitationSafe for FFProbeRequest {}
@@ -23,19 +26,11 @@ impl rmcp::service::ElicitationSafe for FFProbeRequest {}
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[schemars(description = "ffprobe 引导参数")]
 pub struct FFProbeElicitation {
-    #[schemars(description = "输入文件路径或 URI（例如 file:///path/to/video.mp4）")]
+    #[schemars(description = "输入文件的本地路径、远程 URL 或上传文件 URI（upload://...）")]
     pub input: Option<String>,
 }
 impl rmcp::service::ElicitationSafe for FFProbeElicitation {}
 
-fn clean_path(path: &str) -> String {
-    if let Some(stripped) = path.strip_prefix("file://") {
-        stripped.to_string()
-    } else {
-        path.to_string()
-    }
-}
-
 pub fn tool() -> ToolEntry {
     let schema = schemars::schema_for!(FFProbeRequest);
     let tool = Tool {
@@ -44,7 +39,7 @@ pub fn tool() -> ToolEntry {
         description: Some("使用 ffprobe 分析媒体文件信息".into()),
         input_schema: Arc::new(to_object(serde_json::to_value(schema).unwrap())),
         output_schema: None,
-        annotations: None,
+        annotations: crate::tools::read_only(false),
         icons: None,
         meta: None,
     };
@@ -89,13 +84,13 @@ pub async fn handle(
         }
         input = normalize_input(input);
         if let Some(ref s) = input {
-            let cleaned = clean_path(s);
-            let looks_like_remote_uri =
-                cleaned.contains("://") && !cleaned.to_ascii_lowercase().starts_with("file://");
-            if looks_like_remote_uri || Path::new(&cleaned).exists() {
-                return analyze_media(&cleaned).await;
+            if let Some(path) = crate::uploads::local_path(s).await {
+                return analyze_media(&path.to_string_lossy()).await;
+            }
+            if s.contains("://") || Path::new(s).exists() {
+                return analyze_media(s).await;
             }
-            prompt = format!("路径不存在或不可访问，请重新输入文件路径或 URI：{}", cleaned);
+            prompt = format!("路径不存在或不可访问，请重新输入文件路径或 URI：{}", s);
         }
 
         let elicit_result = tokio::select! {
@@ -36,7 +36,7 @@ pub fn tool() -> ToolEntry {
         description: Some("[Utility] Get the current datetime for a specific city or server local time".into()),
         input_schema: Arc::new(to_object(serde_json::to_value(schema).unwrap())),
         output_schema: None,
-        annotations: None,
+        annotations: crate::tools::read_only(false),
         icons: None,
         meta: None,
     };
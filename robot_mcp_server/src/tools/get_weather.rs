@@ -41,7 +41,7 @@ pub fn tool() -> ToolEntry {
         description: Some("[Utility] Get current weather information for a specific city.".into()),
         input_schema: Arc::new(to_object(serde_json::to_value(schema).unwrap())),
         output_schema: None,
-        annotations: None,
+        annotations: crate::tools::read_only(true),
         icons: None,
         meta: None,
     };
@@ -40,7 +40,7 @@ pub fn tool() -> ToolEntry {
         ),
         input_schema: Arc::new(to_object(serde_json::to_value(schema).unwrap())),
         output_schema: None,
-        annotations: None,
+        annotations: crate::tools::read_only(false),
         icons: None,
         meta: None,
     };
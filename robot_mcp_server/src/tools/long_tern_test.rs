@@ -44,7 +44,7 @@ pub fn tool() -> ToolEntry {
         description: Some("Simulate a long running task with progress updates".into()),
         input_schema: Arc::new(to_object(serde_json::to_value(schema).unwrap())),
         output_schema: None,
-        annotations: None,
+        annotations: crate::tools::read_only(false),
         icons: None,
         meta: Some(Meta(meta_map)), 
     };
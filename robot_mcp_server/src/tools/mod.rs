@@ -19,6 +19,12 @@ pub struct AppState {
     pub profile: serde_json::Value,
 }
 
+/// Annotations of a tool that does not modify its environment. `open_world`
+/// tools reach outside the server, e.g. a weather service.
+pub fn read_only(open_world: bool) -> Option<ToolAnnotations> {
+    Some(ToolAnnotations::new().read_only(true).open_world(open_world))
+}
+
 pub mod chat;
 pub mod echo;
 pub mod division;
@@ -32,7 +32,7 @@ pub fn update_tool() -> ToolEntry {
         description: Some("Update the user profile with a JSON object".into()),
         input_schema: Arc::new(to_object(serde_json::to_value(schema).unwrap())),
         output_schema: None,
-        annotations: None,
+        annotations: Some(ToolAnnotations::new().read_only(false).destructive(true).idempotent(true).open_world(false)),
         icons: None,
         meta: None,
     };
@@ -52,7 +52,7 @@ pub fn get_tool() -> ToolEntry {
         description: Some("[Profile] Get the current user profile".into()),
         input_schema: Arc::new(to_object(serde_json::to_value(schema).unwrap())),
         output_schema: None,
-        annotations: None,
+        annotations: crate::tools::read_only(false),
         icons: None,
         meta: None,
     };
@@ -52,7 +52,8 @@ pub fn tool() -> ToolEntry {
         description: Some("使用 rtp_pusher 推送 MP4 文件到指定 IP 和端口 (JT1078协议)".into()),
         input_schema: Arc::new(to_object(serde_json::to_value(schema).unwrap())),
         output_schema: None,
-        annotations: None,
+        // Spawns a pusher that streams to the given device
+        annotations: Some(ToolAnnotations::new().read_only(false).destructive(true).idempotent(false).open_world(true)),
         icons: None,
         meta: Some(Meta(meta_map)),
     };
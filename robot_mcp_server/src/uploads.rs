@@ -0,0 +1,97 @@
+//! Files uploaded to robot_core, served as MCP resources.
+//!
+//! robot_core keeps uploads under `ROBOT_UPLOAD_DIR` (default `uploads`) with
+//! a `registry.json` describing each blob, and refers to them as
+//! `upload://<sha256>`. Reading the same directory lets tools and clients
+//! fetch an attachment with `resources/read` instead of a local path.
+use anyhow::Result;
+use base64::Engine;
+use rmcp::model::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+const URI_SCHEME: &str = "upload://";
+
+#[derive(Deserialize)]
+struct Registry {
+    files: HashMap<String, StoredFile>,
+}
+
+#[derive(Deserialize)]
+struct StoredFile {
+    sha256: String,
+    filename: String,
+    mime_type: String,
+    size: u64,
+}
+
+fn upload_dir() -> PathBuf {
+    PathBuf::from(std::env::var("ROBOT_UPLOAD_DIR").unwrap_or_else(|_| "uploads".to_string()))
+}
+
+async fn registry() -> Result<Registry> {
+    match tokio::fs::read(upload_dir().join("registry.json")).await {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Registry {
+            files: HashMap::new(),
+        }),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn list() -> Result<Vec<Resource>> {
+    let registry = registry().await?;
+    Ok(registry
+        .files
+        .values()
+        .map(|f| {
+            let mut resource = RawResource::new(format!("{}{}", URI_SCHEME, f.sha256), f.filename.clone());
+            resource.mime_type = Some(f.mime_type.clone());
+            resource.size = u32::try_from(f.size).ok();
+            resource.no_annotation()
+        })
+        .collect())
+}
+
+/// Contents of `uri`; `None` when it is not a known upload
+pub async fn read(uri: &str) -> Result<Option<ResourceContents>> {
+    let Some(sha256) = uri.strip_prefix(URI_SCHEME) else {
+        return Ok(None);
+    };
+    let registry = registry().await?;
+    let Some(file) = registry.files.get(sha256) else {
+        return Ok(None);
+    };
+    let bytes = tokio::fs::read(upload_dir().join("blobs").join(&file.sha256)).await?;
+    let textual = file.mime_type.starts_with("text/") || file.mime_type == "application/json";
+    let contents = match String::from_utf8(bytes) {
+        Ok(text) if textual => ResourceContents::TextResourceContents {
+            uri: uri.to_string(),
+            mime_type: Some(file.mime_type.clone()),
+            text,
+            meta: None,
+        },
+        Ok(text) => blob(uri, &file.mime_type, text.as_bytes()),
+        Err(e) => blob(uri, &file.mime_type, e.as_bytes()),
+    };
+    Ok(Some(contents))
+}
+
+/// Where the content of upload `uri` is stored; `None` when it is not a
+/// known upload
+pub async fn local_path(uri: &str) -> Option<PathBuf> {
+    let sha256 = uri.strip_prefix(URI_SCHEME)?;
+    let registry = registry().await.ok()?;
+    let file = registry.files.get(sha256)?;
+    Some(upload_dir().join("blobs").join(&file.sha256))
+}
+
+fn blob(uri: &str, mime_type: &str, bytes: &[u8]) -> ResourceContents {
+    ResourceContents::BlobResourceContents {
+        uri: uri.to_string(),
+        mime_type: Some(mime_type.to_string()),
+        blob: base64::engine::general_purpose::STANDARD.encode(bytes),
+        meta: None,
+    }
+}
@@ -0,0 +1,238 @@
+//! Elicitation forms, asked one field at a time
+
+use serde_json::{Map, Value, json};
+
+/// One field of an elicitation schema
+struct Field {
+    name: String,
+    kind: String,
+    description: Option<String>,
+    options: Vec<Value>,
+    default: Option<Value>,
+    required: bool,
+}
+
+/// An elicitation being answered
+pub struct Form {
+    fields: Vec<Field>,
+    answers: Map<String, Value>,
+}
+
+/// What a line typed into a form leads to
+pub enum Step {
+    /// The value was not accepted, for this reason
+    Retry(String),
+    /// The next field is asked for
+    Next,
+    /// Every field has a value: the answer to send
+    Done(Value),
+}
+
+impl Form {
+    /// The form for an elicitation `schema`, required fields first. A schema
+    /// without properties makes a form whose answer is the next line.
+    pub fn new(schema: &Value) -> Self {
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(|r| r.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|r| r.as_str())
+            .collect();
+        let mut fields: Vec<Field> = schema
+            .get("properties")
+            .and_then(|p| p.as_object())
+            .into_iter()
+            .flatten()
+            .map(|(name, property)| Field {
+                name: name.clone(),
+                kind: property
+                    .get("type")
+                    .and_then(|t| t.as_str())
+                    .unwrap_or("string")
+                    .to_string(),
+                description: property
+                    .get("description")
+                    .or(property.get("title"))
+                    .and_then(|d| d.as_str())
+                    .map(str::to_string),
+                options: property
+                    .get("enum")
+                    .and_then(|e| e.as_array())
+                    .cloned()
+                    .unwrap_or_default(),
+                default: property.get("default").cloned(),
+                required: required.contains(&name.as_str()),
+            })
+            .collect();
+        fields.sort_by_key(|f| !f.required);
+        Self {
+            fields,
+            answers: Map::new(),
+        }
+    }
+
+    fn next_field(&self) -> Option<&Field> {
+        self.fields.iter().find(|f| !self.answers.contains_key(&f.name))
+    }
+
+    /// The prompt for the field asked for next
+    pub fn prompt(&self) -> String {
+        let Some(field) = self.next_field() else {
+            return "answer> ".to_string();
+        };
+        let mut prompt = field.name.clone();
+        if let Some(description) = &field.description {
+            prompt.push_str(&format!(" ({})", description));
+        }
+        if !field.options.is_empty() {
+            let options: Vec<String> = field.options.iter().map(shown).collect();
+            prompt.push_str(&format!(" [{}]", options.join("/")));
+        } else if field.kind == "boolean" {
+            prompt.push_str(" [y/n]");
+        }
+        if let Some(default) = &field.default {
+            prompt.push_str(&format!(" = {}", shown(default)));
+        } else if !field.required {
+            prompt.push_str(" (optional)");
+        }
+        format!("{}> ", prompt)
+    }
+
+    /// Take `line` as the value of the field asked for
+    pub fn answer(&mut self, line: &str) -> Step {
+        let Some(field) = self.next_field() else {
+            return Step::Done(json!(line.trim()));
+        };
+        match parse(field, line) {
+            Err(problem) => Step::Retry(problem),
+            Ok(value) => {
+                let name = field.name.clone();
+                self.answers.insert(name, value.unwrap_or(Value::Null));
+                if self.next_field().is_some() {
+                    return Step::Next;
+                }
+                // Fields left empty are left out
+                let answers = std::mem::take(&mut self.answers)
+                    .into_iter()
+                    .filter(|(_, v)| !v.is_null())
+                    .collect();
+                Step::Done(Value::Object(answers))
+            }
+        }
+    }
+}
+
+fn shown(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// The value typed for `field`; `None` for an optional field left empty
+fn parse(field: &Field, text: &str) -> Result<Option<Value>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return match (&field.default, field.required) {
+            (Some(default), _) => Ok(Some(default.clone())),
+            (None, false) => Ok(None),
+            (None, true) => Err(format!("{} is required", field.name)),
+        };
+    }
+    if !field.options.is_empty() {
+        // The option itself, or its number in the list
+        let chosen = field
+            .options
+            .iter()
+            .find(|o| shown(o).eq_ignore_ascii_case(text))
+            .or_else(|| text.parse::<usize>().ok().and_then(|n| field.options.get(n.wrapping_sub(1))));
+        return chosen
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| format!("{} must be one of the options", field.name));
+    }
+    match field.kind.as_str() {
+        "boolean" => match text.to_ascii_lowercase().as_str() {
+            "y" | "yes" | "true" | "1" => Ok(Some(json!(true))),
+            "n" | "no" | "false" | "0" => Ok(Some(json!(false))),
+            _ => Err(format!("{} is yes or no", field.name)),
+        },
+        "integer" => text
+            .parse::<i64>()
+            .map(|n| Some(json!(n)))
+            .map_err(|_| format!("{} must be a whole number", field.name)),
+        "number" => text
+            .parse::<f64>()
+            .map(|n| Some(json!(n)))
+            .map_err(|_| format!("{} must be a number", field.name)),
+        "array" => Ok(Some(json!(
+            text.split(',').map(str::trim).filter(|s| !s.is_empty()).collect::<Vec<_>>()
+        ))),
+        "object" => serde_json::from_str::<Value>(text)
+            .ok()
+            .filter(Value::is_object)
+            .map(Some)
+            .ok_or_else(|| format!("{} must be a JSON object", field.name)),
+        _ => Ok(Some(json!(text))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answers(form: &mut Form, lines: &[&str]) -> Value {
+        for line in lines {
+            if let Step::Done(answer) = form.answer(line) {
+                return answer;
+            }
+        }
+        panic!("the form is not done after {:?}", lines);
+    }
+
+    #[test]
+    fn asks_required_fields_first_and_parses_their_types() {
+        let mut form = Form::new(&json!({
+            "properties": {
+                "quality": {"type": "string", "enum": ["low", "high"], "default": "low"},
+                "loop": {"type": "boolean", "title": "Repeat"},
+                "port": {"type": "integer", "description": "Target port"}
+            },
+            "required": ["port", "loop"]
+        }));
+        assert_eq!(form.prompt(), "loop (Repeat) [y/n]> ");
+        assert!(matches!(form.answer("maybe"), Step::Retry(p) if p == "loop is yes or no"));
+        assert!(matches!(form.answer(""), Step::Retry(p) if p == "loop is required"));
+        assert!(matches!(form.answer("Yes"), Step::Next));
+        assert_eq!(form.prompt(), "port (Target port)> ");
+        assert!(matches!(form.answer("70.5"), Step::Retry(_)));
+        assert!(matches!(form.answer("7000"), Step::Next));
+        assert_eq!(form.prompt(), "quality [low/high] = low> ");
+        assert_eq!(answers(&mut form, &["2"]), json!({"loop": true, "port": 7000, "quality": "high"}));
+    }
+
+    #[test]
+    fn fills_defaults_and_leaves_out_empty_optional_fields() {
+        let schema = json!({"properties": {
+            "tags": {"type": "array"},
+            "quality": {"type": "string", "enum": ["low", "high"], "default": "low"},
+            "extra": {"type": "object"}
+        }});
+        let mut form = Form::new(&schema);
+        assert_eq!(form.prompt(), "extra (optional)> ");
+        assert_eq!(answers(&mut form, &["", "", "a, b,"]), json!({"quality": "low", "tags": ["a", "b"]}));
+
+        let mut form = Form::new(&schema);
+        assert!(matches!(form.answer("[1]"), Step::Retry(p) if p == "extra must be a JSON object"));
+        assert!(matches!(form.answer("{\"a\": 1}"), Step::Next));
+        assert!(matches!(form.answer("medium"), Step::Retry(p) if p == "quality must be one of the options"));
+        assert!(matches!(form.answer("HIGH"), Step::Next));
+        assert_eq!(answers(&mut form, &[""]), json!({"extra": {"a": 1}, "quality": "high"}));
+
+        // Without properties the whole line is the answer
+        let mut form = Form::new(&json!({}));
+        assert_eq!(form.prompt(), "answer> ");
+        assert_eq!(answers(&mut form, &[" fine "]), json!("fine"));
+    }
+}
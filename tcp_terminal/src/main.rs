@@ -1,8 +1,31 @@
+mod form;
+mod screen;
+
 use anyhow::{Context, Result};
 use clap::Parser;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use form::{Form, Step};
+use rustyline::error::ReadlineError;
+use rustyline::{DefaultEditor, ExternalPrinter};
+use screen::Screen;
+use serde_json::{Value, json};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
+use tokio::net::tcp::OwnedWriteHalf;
 use tokio::signal;
+use tokio::sync::mpsc;
+
+/// JSON lines protocol of the robot's TCP console
+const PROTOCOL: &str = "jsonl/1";
+
+const PROMPT: &str = "> ";
+
+const HELP: &str = "Commands:
+  /cancel  cancel the question being answered, or the running task
+  /quit    disconnect
+  /help    this help
+Ctrl-C cancels a question, Ctrl-D disconnects.";
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -14,6 +37,18 @@ struct Args {
     /// Port to connect to
     #[arg(short, long, default_value_t = 9000)]
     port: u16,
+
+    /// Copy bytes to and from the server as they are, without the JSON protocol
+    #[arg(long)]
+    raw: bool,
+
+    /// Token to log in with when the server asks for one
+    #[arg(short, long)]
+    token: Option<String>,
+
+    /// File keeping the line history, `~/.tcp_terminal_history` by default
+    #[arg(long)]
+    history: Option<PathBuf>,
 }
 
 #[tokio::main]
@@ -27,6 +62,252 @@ async fn main() -> Result<()> {
         .context("Failed to connect to server")?;
     println!("Connected to server!");
 
+    match args.raw {
+        true => run_raw(stream).await,
+        false => run(stream, args).await,
+    }
+}
+
+/// A line asked for on the terminal
+struct Prompt {
+    text: String,
+    /// Whether the line goes into the history; tokens do not
+    history: bool,
+}
+
+/// What came of a prompt
+enum Line {
+    Text(String),
+    Interrupted,
+    Eof,
+}
+
+/// The line editor, reading on a thread of its own since it blocks
+struct Editor {
+    prompts: std::sync::mpsc::Sender<Prompt>,
+    lines: mpsc::UnboundedReceiver<Line>,
+    thread: std::thread::JoinHandle<()>,
+    /// Whether a line is being edited
+    asking: bool,
+}
+
+impl Editor {
+    /// Start the editor; the printer writes above the line being edited
+    fn start(history: Option<PathBuf>) -> Result<(Self, Box<dyn ExternalPrinter + Send>)> {
+        let mut editor = DefaultEditor::new()?;
+        if let Some(path) = &history {
+            let _ = editor.load_history(path);
+        }
+        let printer: Box<dyn ExternalPrinter + Send> = match std::io::stdin().is_terminal() {
+            true => Box::new(editor.create_external_printer()?),
+            false => Box::new(screen::Stdout),
+        };
+        let (prompts, asked) = std::sync::mpsc::channel::<Prompt>();
+        let (sender, lines) = mpsc::unbounded_channel();
+        let thread = std::thread::spawn(move || {
+            while let Ok(prompt) = asked.recv() {
+                let line = match editor.readline(&prompt.text) {
+                    Ok(text) => {
+                        if prompt.history && !text.trim().is_empty() {
+                            let _ = editor.add_history_entry(text.as_str());
+                        }
+                        Line::Text(text)
+                    }
+                    Err(ReadlineError::Interrupted) => Line::Interrupted,
+                    Err(_) => Line::Eof,
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+            if let Some(path) = &history {
+                let _ = editor.save_history(path);
+            }
+        });
+        Ok((
+            Self {
+                prompts,
+                lines,
+                thread,
+                asking: false,
+            },
+            printer,
+        ))
+    }
+
+    fn ask(&mut self, text: &str, history: bool) {
+        self.asking = true;
+        let _ = self.prompts.send(Prompt {
+            text: text.to_string(),
+            history,
+        });
+    }
+
+    /// Wait for the line being edited, if any, and save the history
+    fn finish(self) {
+        drop(self.prompts);
+        let _ = self.thread.join();
+    }
+}
+
+async fn send(writer: &mut OwnedWriteHalf, frame: Value) -> Result<()> {
+    writer.write_all(format!("{}\n", frame).as_bytes()).await?;
+    Ok(())
+}
+
+/// What the terminal does in answer to a line
+#[derive(Debug, PartialEq)]
+enum Action {
+    Send(Value),
+    Note(String),
+    Error(String),
+    /// Ask for the next line, kept in the history or not
+    Ask(String, bool),
+    Quit,
+}
+
+/// The state of the conversation with the console
+#[derive(Default)]
+struct Client {
+    logged_in: bool,
+    chatting: bool,
+    /// The elicitation being answered
+    form: Option<Form>,
+}
+
+impl Client {
+    /// Handle what the editor returned
+    fn line(&mut self, line: Option<Line>) -> Vec<Action> {
+        let text = match line {
+            None | Some(Line::Eof) => return vec![Action::Quit],
+            Some(Line::Interrupted) if !self.chatting => return vec![Action::Quit],
+            // Ctrl-C cancels the question being answered
+            Some(Line::Interrupted) => {
+                let mut actions = Vec::new();
+                if self.form.take().is_some() {
+                    actions.push(Action::Send(json!({"type": "cancel"})));
+                }
+                actions.push(Action::Ask(PROMPT.to_string(), true));
+                return actions;
+            }
+            Some(Line::Text(text)) => text,
+        };
+        // Until the session starts the line is a token
+        if !self.chatting {
+            return match self.logged_in {
+                true => Vec::new(),
+                false => vec![Action::Send(json!({"type": "login", "token": text.trim()}))],
+            };
+        }
+        let mut actions = Vec::new();
+        match text.trim() {
+            "/quit" => return vec![Action::Quit],
+            "/help" => actions.push(Action::Note(HELP.to_string())),
+            "/cancel" => {
+                self.form = None;
+                actions.push(Action::Send(json!({"type": "cancel"})));
+            }
+            "" if self.form.is_none() => {}
+            line => match self.form.as_mut().map(|f| f.answer(line)) {
+                None => actions.push(Action::Send(json!({"type": "message", "content": line}))),
+                Some(Step::Retry(problem)) => actions.push(Action::Error(problem)),
+                Some(Step::Next) => {}
+                Some(Step::Done(answer)) => {
+                    self.form = None;
+                    actions.push(Action::Send(json!({"type": "answer", "content": answer})));
+                }
+            },
+        }
+        // Answers to a form stay out of the history
+        let prompt = self.form.as_ref().map_or(PROMPT.to_string(), |f| f.prompt());
+        actions.push(Action::Ask(prompt, self.form.is_none()));
+        actions
+    }
+}
+
+/// Talk to the console over its JSON lines protocol
+async fn run(stream: TcpStream, args: Args) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    send(&mut writer, json!({"type": "hello", "protocol": PROTOCOL})).await?;
+    let mut frames = BufReader::new(reader).lines();
+
+    let history = args
+        .history
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".tcp_terminal_history")));
+    let (mut editor, printer) = Editor::start(history)?;
+    let mut screen = Screen::new(printer, std::io::stdout().is_terminal());
+    let mut token = args.token;
+    let mut client = Client::default();
+
+    'session: loop {
+        tokio::select! {
+            frame = frames.next_line() => {
+                let Some(frame) = frame? else {
+                    screen.error("Server closed connection.");
+                    if editor.asking {
+                        screen.note("Press Enter to exit.");
+                    }
+                    break;
+                };
+                // The lines sent before the server switched are plain text
+                let Ok(frame) = serde_json::from_str::<Value>(&frame) else {
+                    continue;
+                };
+                let text = |name: &str| frame[name].as_str().unwrap_or_default().to_string();
+                match frame["type"].as_str() {
+                    Some("login_required") => match token.take() {
+                        Some(token) => send(&mut writer, json!({"type": "login", "token": token})).await?,
+                        None => editor.ask("token> ", false),
+                    },
+                    Some("logged_in") => {
+                        client.logged_in = true;
+                        screen.note(&format!("Logged in as {}", text("user_id")));
+                    }
+                    Some("session") => {
+                        let session_id = text("session_id");
+                        screen.set_session(&session_id);
+                        screen.note(&format!("Session {}. Type /help for commands.", session_id));
+                        client.chatting = true;
+                        editor.ask(PROMPT, true);
+                    }
+                    Some("error") => screen.error(&text("message")),
+                    Some("output") => {
+                        if let Some((message, schema)) = screen.output(&frame) {
+                            let question = screen.paint("33", &format!("? {}", message));
+                            screen.print(&question);
+                            let next = Form::new(&schema);
+                            let field = next.prompt();
+                            screen.note(&format!("  {} (/cancel to cancel)", field.trim_end_matches("> ")));
+                            client.form = Some(next);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            line = editor.lines.recv() => {
+                editor.asking = false;
+                screen.line_entered();
+                for action in client.line(line) {
+                    match action {
+                        Action::Send(frame) => send(&mut writer, frame).await?,
+                        Action::Note(text) => screen.note(&text),
+                        Action::Error(text) => screen.error(&text),
+                        Action::Ask(prompt, history) => editor.ask(&prompt, history),
+                        Action::Quit => break 'session,
+                    }
+                }
+            }
+            _ = signal::ctrl_c() => break,
+        }
+    }
+
+    drop(writer);
+    editor.finish();
+    Ok(())
+}
+
+/// Copy bytes between the terminal and the server as they are
+async fn run_raw(stream: TcpStream) -> Result<()> {
     let (reader, mut writer) = stream.into_split();
 
     // Task for reading from server and printing to stdout
@@ -58,7 +339,7 @@ async fn main() -> Result<()> {
     let write_handle = tokio::spawn(async move {
         let mut stdin = tokio::io::BufReader::new(tokio::io::stdin());
         let mut line = String::new();
-        
+
         loop {
             line.clear();
             match tokio::io::AsyncBufReadExt::read_line(&mut stdin, &mut line).await {
@@ -88,3 +369,69 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chatting() -> Client {
+        Client {
+            logged_in: true,
+            chatting: true,
+            form: None,
+        }
+    }
+
+    fn text(line: &str) -> Option<Line> {
+        Some(Line::Text(line.to_string()))
+    }
+
+    #[test]
+    fn keys_cancel_questions_and_quit() {
+        let mut client = Client::default();
+        assert_eq!(client.line(Some(Line::Interrupted)), [Action::Quit]);
+        assert_eq!(chatting().line(Some(Line::Eof)), [Action::Quit]);
+        assert_eq!(chatting().line(None), [Action::Quit]);
+        assert_eq!(chatting().line(text(" /quit ")), [Action::Quit]);
+
+        let mut client = chatting();
+        assert_eq!(client.line(Some(Line::Interrupted)), [Action::Ask(PROMPT.into(), true)]);
+        client.form = Some(Form::new(&json!({"properties": {"name": {"type": "string"}}})));
+        assert_eq!(
+            client.line(Some(Line::Interrupted)),
+            [Action::Send(json!({"type": "cancel"})), Action::Ask(PROMPT.into(), true)]
+        );
+        assert!(client.form.is_none());
+    }
+
+    #[test]
+    fn only_chat_lines_go_into_the_history() {
+        // The token is sent, not remembered, and the server answers it
+        let mut client = Client::default();
+        assert_eq!(
+            client.line(text(" secret ")),
+            [Action::Send(json!({"type": "login", "token": "secret"}))]
+        );
+
+        let mut client = chatting();
+        assert_eq!(
+            client.line(text("hello")),
+            [Action::Send(json!({"type": "message", "content": "hello"})), Action::Ask(PROMPT.into(), true)]
+        );
+        assert_eq!(client.line(text("  ")), [Action::Ask(PROMPT.into(), true)]);
+
+        client.form = Some(Form::new(&json!({
+            "properties": {"count": {"type": "integer"}, "note": {"type": "string"}},
+            "required": ["count"]
+        })));
+        assert_eq!(
+            client.line(text("many")),
+            [Action::Error("count must be a whole number".into()), Action::Ask("count> ".into(), false)]
+        );
+        assert_eq!(client.line(text("3")), [Action::Ask("note (optional)> ".into(), false)]);
+        assert_eq!(
+            client.line(text("")),
+            [Action::Send(json!({"type": "answer", "content": {"count": 3}})), Action::Ask(PROMPT.into(), true)]
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! Rendering of server frames above the prompt
+
+use rustyline::ExternalPrinter;
+use serde_json::Value;
+
+const PROGRESS_WIDTH: usize = 30;
+/// Tool results are cut to this many characters
+const MAX_RESULT_CHARS: usize = 300;
+
+/// Prints without a line being edited, when stdin is not a terminal
+pub struct Stdout;
+
+impl ExternalPrinter for Stdout {
+    fn print(&mut self, msg: String) -> rustyline::Result<()> {
+        use std::io::Write;
+        let mut stdout = std::io::stdout();
+        stdout.write_all(msg.as_bytes())?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+pub struct Screen {
+    printer: Box<dyn ExternalPrinter + Send>,
+    color: bool,
+    session_id: Option<String>,
+    /// Token of the progress bar on the last printed line, redrawn in place
+    open_bar: Option<String>,
+}
+
+impl Screen {
+    pub fn new(printer: Box<dyn ExternalPrinter + Send>, color: bool) -> Self {
+        Self {
+            printer,
+            color,
+            session_id: None,
+            open_bar: None,
+        }
+    }
+
+    pub fn set_session(&mut self, session_id: &str) {
+        self.session_id = Some(session_id.to_string());
+    }
+
+    pub fn paint(&self, code: &str, text: &str) -> String {
+        match self.color {
+            true => format!("\x1b[{}m{}\x1b[0m", code, text),
+            false => text.to_string(),
+        }
+    }
+
+    pub fn print(&mut self, text: &str) {
+        self.open_bar = None;
+        let _ = self.printer.print(format!("{}\n", text));
+    }
+
+    pub fn note(&mut self, text: &str) {
+        let text = self.paint("2", text);
+        self.print(&text);
+    }
+
+    pub fn error(&mut self, text: &str) {
+        let text = self.paint("31", text);
+        self.print(&text);
+    }
+
+    /// A line was entered below whatever was printed last
+    pub fn line_entered(&mut self) {
+        self.open_bar = None;
+    }
+
+    /// Show an `output` frame. Elicitations are not shown but handed back as
+    /// their message and schema.
+    pub fn output(&mut self, frame: &Value) -> Option<(String, Value)> {
+        let content = &frame["content"];
+        let session_id = frame["session_id"].as_str();
+        // Broadcasts from the other sessions of the same user are tagged
+        let other = session_id.is_some_and(|s| Some(s) != self.session_id.as_deref());
+        let tag = match (other, session_id) {
+            (true, Some(s)) => format!("[{}] ", s.chars().take(8).collect::<String>()),
+            _ => String::new(),
+        };
+        let field = |name: &str| content.get(name).and_then(|v| v.as_str()).unwrap_or_default().trim().to_string();
+        match content.get("type").and_then(|t| t.as_str()) {
+            // Our own lines are already on screen
+            Some("user_message") if !other => {}
+            Some("user_message") => {
+                let line = format!("{}{} {}", tag, self.paint("32", "you>"), field("content"));
+                self.print(&line);
+            }
+            Some("progress") => self.progress(&tag, content),
+            Some("think") => {
+                let line = self.paint("2;35", &format!("{}💭 {}", tag, field("content")));
+                self.print(&line);
+            }
+            Some("tool_result") => {
+                let result = content.get("result").map(result_text).unwrap_or_default();
+                let line = self.paint("34", &format!("{}🔧 {} → {}", tag, field("tool"), truncate(&result)));
+                self.print(&line);
+            }
+            Some("tool_cancel") => {
+                let message = Some(field("message")).filter(|m| !m.is_empty());
+                let line = self.paint("33", &format!("{}{}", tag, message.as_deref().unwrap_or("Cancelled")));
+                self.print(&line);
+            }
+            Some("elicitation") | None if content.get("schema").is_some() => {
+                let message = Some(field("message"))
+                    .filter(|m| !m.is_empty())
+                    .unwrap_or_else(|| "Please provide the missing input".to_string());
+                return Some((message, content["schema"].clone()));
+            }
+            _ => {
+                let text = ["text", "content", "message"]
+                    .iter()
+                    .find_map(|k| content.get(*k).and_then(|v| v.as_str()))
+                    .map(String::from)
+                    .unwrap_or_else(|| shown(content));
+                let line = format!("{}{} {}", tag, self.paint("36", "robot>"), text);
+                self.print(&line);
+            }
+        }
+        None
+    }
+
+    /// Draw a progress bar, over the previous one of the same token
+    fn progress(&mut self, tag: &str, content: &Value) {
+        let token = content.get("token").map(|t| t.to_string()).unwrap_or_default();
+        let message = content.get("message").and_then(|m| m.as_str()).unwrap_or("Working");
+        let progress = content.get("progress").and_then(|p| p.as_f64()).unwrap_or(0.0);
+        let bar = match content.get("total").and_then(|t| t.as_f64()) {
+            Some(total) if total > 0.0 => {
+                let ratio = (progress / total).clamp(0.0, 1.0);
+                let filled = (ratio * PROGRESS_WIDTH as f64).round() as usize;
+                format!(
+                    "[{}{}] {:>3.0}%",
+                    "#".repeat(filled),
+                    "-".repeat(PROGRESS_WIDTH - filled),
+                    ratio * 100.0
+                )
+            }
+            _ => format!("[{}]", progress),
+        };
+        let line = format!("{}{} {}", tag, self.paint("33", &bar), message);
+        // Without a terminal every update is a line of its own
+        let redraw = self.color && self.open_bar.as_ref() == Some(&token);
+        let line = match redraw {
+            true => format!("\x1b[1A\x1b[2K{}\n", line),
+            false => format!("{}\n", line),
+        };
+        let _ = self.printer.print(line);
+        self.open_bar = Some(token);
+    }
+}
+
+/// The text parts of a tool result, or its JSON
+fn result_text(result: &Value) -> String {
+    let texts: Vec<&str> = result
+        .get("content")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|part| part.get("text").and_then(|t| t.as_str()))
+        .collect();
+    match texts.is_empty() {
+        true => shown(result),
+        false => texts.join(" "),
+    }
+}
+
+fn shown(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn truncate(text: &str) -> String {
+    let text = text.replace('\n', " ");
+    match text.char_indices().nth(MAX_RESULT_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::form::Form;
+    use std::sync::{Arc, Mutex};
+
+    /// Keeps what is printed
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl ExternalPrinter for Recorder {
+        fn print(&mut self, msg: String) -> rustyline::Result<()> {
+            self.0.lock().unwrap().push(msg);
+            Ok(())
+        }
+    }
+
+    impl Recorder {
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    fn screen(color: bool) -> (Screen, Recorder) {
+        let recorder = Recorder::default();
+        let mut screen = Screen::new(Box::new(recorder.clone()), color);
+        screen.set_session("s1");
+        (screen, recorder)
+    }
+
+    /// Show a frame as it comes over the wire
+    fn show(screen: &mut Screen, line: &str) -> Option<(String, Value)> {
+        screen.output(&serde_json::from_str(line).unwrap())
+    }
+
+    #[test]
+    fn renders_output_frames() {
+        let (mut screen, printed) = screen(false);
+        show(&mut screen, r#"{"type":"output","session_id":"s1","content":{"type":"text","text":"hi"}}"#);
+        show(&mut screen, r#"{"type":"output","session_id":"s1","content":{"type":"user_message","content":"mine"}}"#);
+        show(&mut screen, r#"{"type":"output","session_id":"s2-long-id","content":{"type":"user_message","content":"theirs"}}"#);
+        show(
+            &mut screen,
+            r#"{"type":"output","session_id":"s1","content":{"type":"tool_result","tool":"echo","result":{"content":[{"type":"text","text":"a\nb"}]}}}"#,
+        );
+        show(&mut screen, r#"{"type":"output","session_id":"s1","content":{"type":"tool_cancel"}}"#);
+        show(&mut screen, r#"{"type":"output","content":{"status":"ok"}}"#);
+        assert_eq!(
+            printed.take(),
+            [
+                "robot> hi\n",
+                "[s2-long-] you> theirs\n",
+                "🔧 echo → a b\n",
+                "Cancelled\n",
+                "robot> {\"status\":\"ok\"}\n",
+            ]
+        );
+
+        let long = "x".repeat(MAX_RESULT_CHARS + 10);
+        assert_eq!(truncate(&long).chars().count(), MAX_RESULT_CHARS + 1);
+        assert!(truncate(&long).ends_with('…'));
+    }
+
+    #[test]
+    fn redraws_progress_of_the_same_token_in_place() {
+        let (mut screen, printed) = screen(true);
+        let progress = |p: u32| {
+            format!(
+                r#"{{"type":"output","session_id":"s1","content":{{"type":"progress","token":7,"progress":{},"total":4,"message":"Rendering"}}}}"#,
+                p
+            )
+        };
+        show(&mut screen, &progress(1));
+        show(&mut screen, &progress(4));
+        screen.line_entered();
+        show(&mut screen, &progress(4));
+        let lines = printed.take();
+        assert!(!lines[0].starts_with("\x1b[1A") && lines[0].contains(" 25%"), "{:?}", lines[0]);
+        assert!(lines[1].starts_with("\x1b[1A\x1b[2K") && lines[1].contains("100%"), "{:?}", lines[1]);
+        assert!(!lines[2].starts_with("\x1b[1A"), "{:?}", lines[2]);
+    }
+
+    #[test]
+    fn hands_back_elicitations_as_forms() {
+        let (mut screen, printed) = screen(false);
+        let frame = r#"{"type":"output","session_id":"s1","content":{"type":"elicitation","message":"Where to push?","schema":{"properties":{"ip":{"type":"string","description":"Target address"},"port":{"type":"integer","default":7000}},"required":["ip"]}}}"#;
+        let (message, schema) = show(&mut screen, frame).expect("an elicitation");
+        assert_eq!(message, "Where to push?");
+        assert!(printed.take().is_empty());
+
+        let mut form = Form::new(&schema);
+        assert_eq!(form.prompt(), "ip (Target address)> ");
+        assert!(matches!(form.answer("10.0.0.5"), crate::form::Step::Next));
+        assert_eq!(form.prompt(), "port = 7000> ");
+
+        // A schema without a message still asks
+        let (message, _) = show(&mut screen, r#"{"type":"output","content":{"schema":{}}}"#).unwrap();
+        assert_eq!(message, "Please provide the missing input");
+    }
+}
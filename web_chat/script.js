@@ -3,9 +3,17 @@ class ChatClient {
         this.inputPort = 8080;
         this.outputPort = 8081;
         this.serverHost = 'localhost';
+        this.scheme = 'http';
+        this.authMode = null; // 'none' or 'token', from /api/config
+        this.apiToken = '';
+        this.authToken = null; // Session token from /api/login
         this.isConnected = false;
         this.outputPollingInterval = null;
         this.eventSource = null;
+        this.socket = null;
+        this.socketFailures = 0;
+        this.useSocket = 'WebSocket' in window; // Falls back to SSE + POST
+        this.lastTypingSent = 0;
 
         this.sessionId = null;
         this.isBroadcastMode = false;
@@ -13,12 +21,41 @@ class ChatClient {
         this.sessions = [];
         this.sessionMessages = {};
         this.activeProgressBars = {};
+        this.lastSeq = {}; // Last output number seen per session, for resuming
 
         this.initializeElements();
         this.bindEvents();
         this.loadSettings();
         this.loadState();
-        this.connect();
+        this.loadConfig().then(() => this.connect());
+    }
+
+    // When the page is served by robot_core itself, take host and ports from
+    // the server instead of the saved settings
+    async loadConfig() {
+        if (!location.protocol.startsWith('http')) return;
+        try {
+            const res = await fetch('/api/config');
+            if (!res.ok) return;
+            const config = await res.json();
+            this.scheme = location.protocol.replace(':', '');
+            this.serverHost = location.hostname;
+            this.inputPort = config.input_port;
+            this.outputPort = config.output_port || config.input_port;
+            this.authMode = config.auth;
+            if (!(config.features || []).includes('websocket')) {
+                this.useSocket = false;
+            }
+            this.inputPortInput.value = this.inputPort;
+            this.outputPortInput.value = this.outputPort;
+            this.serverHostInput.value = this.serverHost;
+            if (this.authMode === 'token' && !this.apiToken) {
+                this.displaySystemMessage('This server requires an API token. Enter it in Settings.');
+            }
+        } catch (e) {
+            // Served from elsewhere; keep the saved settings
+            console.log('No /api/config, using saved settings');
+        }
     }
 
     initializeElements() {
@@ -41,6 +78,7 @@ class ChatClient {
         this.inputPortInput = document.getElementById('inputPort');
         this.outputPortInput = document.getElementById('outputPort');
         this.serverHostInput = document.getElementById('serverHost');
+        this.apiTokenInput = document.getElementById('apiToken');
         this.saveSettingsBtn = document.getElementById('saveSettings');
         this.broadcastModeInput = document.getElementById('broadcastMode');
         this.showThinkingInput = document.getElementById('showThinking');
@@ -65,6 +103,7 @@ class ChatClient {
         this.messageInput.addEventListener('input', () => {
             this.messageInput.style.height = 'auto';
             this.messageInput.style.height = (this.messageInput.scrollHeight) + 'px';
+            this.sendTyping();
             if (this.messageInput.value === '') {
                 this.messageInput.style.height = 'auto'; // Reset when empty
             }
@@ -176,15 +215,15 @@ class ChatClient {
         const md5 = await this.calculateMD5(file);
         
         // 2. Check Exists
-        const checkRes = await fetch(`http://${this.serverHost}:${this.inputPort}/api/check_file`, {
+        const checkRes = await fetch(`${this.scheme}://${this.serverHost}:${this.inputPort}/api/check_file`, {
             method: 'POST',
-            headers: { 'Content-Type': 'application/json' },
+            headers: this.authHeaders({ 'Content-Type': 'application/json' }),
             body: JSON.stringify({ md5, filename: file.name })
         });
         const checkData = await checkRes.json();
         if (checkData.exists) {
             onProgress(100);
-            return checkData.file.path;
+            return checkData.file.uri;
         }
 
         // 3. Upload
@@ -211,7 +250,10 @@ class ChatClient {
 
             xhr.addEventListener('error', () => reject(new Error('Network error')));
             
-            xhr.open('POST', `http://${this.serverHost}:${this.inputPort}/api/upload`);
+            xhr.open('POST', `${this.scheme}://${this.serverHost}:${this.inputPort}/api/upload`);
+            if (this.authToken) {
+                xhr.setRequestHeader('Authorization', `Bearer ${this.authToken}`);
+            }
             xhr.send(formData);
         });
     }
@@ -248,6 +290,7 @@ class ChatClient {
              this.sessions = parsed.sessions || [];
              this.sessionMessages = parsed.sessionMessages || {};
              this.sessionId = parsed.currentSessionId;
+             this.lastSeq = parsed.lastSeq || {};
          }
     }
 
@@ -255,7 +298,8 @@ class ChatClient {
         const state = {
             sessions: this.sessions,
             sessionMessages: this.sessionMessages,
-            currentSessionId: this.sessionId
+            currentSessionId: this.sessionId,
+            lastSeq: this.lastSeq
         };
         localStorage.setItem('chatState', JSON.stringify(state));
     }
@@ -264,11 +308,14 @@ class ChatClient {
         try {
             if (this.sessions.length >= 10) {
                 const removed = this.sessions.pop();
-                if (removed) delete this.sessionMessages[removed.id];
+                if (removed) {
+                    delete this.sessionMessages[removed.id];
+                    delete this.lastSeq[removed.id];
+                }
             }
 
-            const sessionUrl = `http://${this.serverHost}:${this.inputPort}/api/session`;
-            const res = await fetch(sessionUrl, { method: 'POST' });
+            const sessionUrl = `${this.scheme}://${this.serverHost}:${this.inputPort}/api/session`;
+            const res = await fetch(sessionUrl, { method: 'POST', headers: this.authHeaders() });
             if (!res.ok) throw new Error('Failed to create session');
             const data = await res.json();
             const newId = data.session_id;
@@ -295,6 +342,7 @@ class ChatClient {
 
         this.sessions.splice(index, 1);
         delete this.sessionMessages[id];
+        delete this.lastSeq[id];
 
         if (this.sessionId === id) {
             if (this.sessions.length > 0) {
@@ -328,6 +376,8 @@ class ChatClient {
                  if (this.showThinking) {
                      this.displayThinkMessage(msg.content, false);
                  }
+            } else if (msg.type === 'tool') {
+                 this.displayToolResult(msg.content, false);
             } else {
                  this.displayBotMessage(msg.content, false);
             }
@@ -371,6 +421,7 @@ class ChatClient {
             this.inputPort = parsed.inputPort || 8080;
             this.outputPort = parsed.outputPort || 8081;
             this.serverHost = parsed.serverHost || 'localhost';
+            this.apiToken = parsed.apiToken || '';
             this.isBroadcastMode = parsed.isBroadcastMode || false;
             this.showThinking = parsed.showThinking !== undefined ? parsed.showThinking : true;
             // if (parsed.sessionId) {
@@ -382,6 +433,7 @@ class ChatClient {
         this.inputPortInput.value = this.inputPort;
         this.outputPortInput.value = this.outputPort;
         this.serverHostInput.value = this.serverHost;
+        this.apiTokenInput.value = this.apiToken;
         this.broadcastModeInput.checked = this.isBroadcastMode;
         this.showThinkingInput.checked = this.showThinking;
         if (this.sessionId) {
@@ -393,6 +445,8 @@ class ChatClient {
         this.inputPort = parseInt(this.inputPortInput.value) || 8080;
         this.outputPort = parseInt(this.outputPortInput.value) || 8081;
         this.serverHost = this.serverHostInput.value || 'localhost';
+        this.apiToken = this.apiTokenInput.value.trim();
+        this.authToken = null;
         this.isBroadcastMode = this.broadcastModeInput.checked;
         this.showThinking = this.showThinkingInput.checked;
 
@@ -400,6 +454,7 @@ class ChatClient {
             inputPort: this.inputPort,
             outputPort: this.outputPort,
             serverHost: this.serverHost,
+            apiToken: this.apiToken,
             isBroadcastMode: this.isBroadcastMode,
             showThinking: this.showThinking,
             // sessionId: this.sessionId // Don't save session ID to ensure fresh one on reload
@@ -407,6 +462,29 @@ class ChatClient {
         localStorage.setItem('chatSettings', JSON.stringify(settings));
     }
 
+    // Headers for the input API, with the session token once logged in
+    authHeaders(headers = {}) {
+        if (this.authToken) {
+            headers['Authorization'] = `Bearer ${this.authToken}`;
+        }
+        return headers;
+    }
+
+    // Exchange the API token for a session token. Servers without
+    // authentication accept any login and return no token.
+    async login() {
+        this.authToken = null;
+        if (!this.apiToken) return;
+        const res = await fetch(`${this.scheme}://${this.serverHost}:${this.inputPort}/api/login`, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ token: this.apiToken })
+        });
+        if (!res.ok) throw new Error('Login failed');
+        const data = await res.json();
+        this.authToken = data.data ? data.data.token : null;
+    }
+
     updateConnectionStatus(status) {
         // Update the dot color
         this.connectionDot.className = 'connection-dot'; // Reset
@@ -432,7 +510,7 @@ class ChatClient {
 
         try {
             // Test input server connection
-            const inputUrl = `http://${this.serverHost}:${this.inputPort}/health`;
+            const inputUrl = `${this.scheme}://${this.serverHost}:${this.inputPort}/health`;
             const controller = new AbortController();
             const timeoutId = setTimeout(() => controller.abort(), 2000);
             
@@ -446,7 +524,7 @@ class ChatClient {
             }
 
             // Test output server connection
-            const outputUrl = `http://${this.serverHost}:${this.outputPort}/health`;
+            const outputUrl = `${this.scheme}://${this.serverHost}:${this.outputPort}/health`;
              const controller2 = new AbortController();
             const timeoutId2 = setTimeout(() => controller2.abort(), 2000);
             
@@ -459,6 +537,10 @@ class ChatClient {
                  throw e;
             }
 
+            if (!this.authToken) {
+                await this.login();
+            }
+
             // Create new session from backend
             let needNewSession = !this.sessionId;
             if (this.sessionId && !this.sessions.some(s => s.id === this.sessionId)) {
@@ -466,8 +548,8 @@ class ChatClient {
             }
 
             if (needNewSession) {
-                const sessionUrl = `http://${this.serverHost}:${this.inputPort}/api/session`;
-                const sessionRes = await fetch(sessionUrl, { method: 'POST' });
+                const sessionUrl = `${this.scheme}://${this.serverHost}:${this.inputPort}/api/session`;
+                const sessionRes = await fetch(sessionUrl, { method: 'POST', headers: this.authHeaders() });
                 if (!sessionRes.ok) throw new Error('Failed to create session');
                 const sessionData = await sessionRes.json();
                 const newId = sessionData.session_id;
@@ -494,6 +576,7 @@ class ChatClient {
 
         } catch (error) {
             console.error('Connection failed:', error);
+            this.authToken = null; // Log in again, the session token may have expired
             this.updateConnectionStatus('error');
             // Retry connection after 5 seconds
             setTimeout(() => this.connect(), 5000);
@@ -502,18 +585,117 @@ class ChatClient {
 
     disconnect() {
         this.updateConnectionStatus('disconnected');
+        this.closeStreams();
+    }
+
+    closeStreams() {
         if (this.eventSource) {
             this.eventSource.close();
             this.eventSource = null;
         }
+        if (this.socket) {
+            const socket = this.socket;
+            this.socket = null; // Keeps onclose from reconnecting
+            socket.close();
+        }
     }
 
+    // Receive outputs over the WebSocket, or over SSE when it is unavailable
     startOutputPolling() {
+        this.closeStreams();
+        if (this.useSocket) {
+            this.openSocket();
+        } else {
+            this.startEventSource();
+        }
+    }
+
+    // One socket per session carries messages both ways. Outputs carry their
+    // history id; reconnecting with `since` replays the ones missed meanwhile.
+    openSocket() {
+        const sessionId = this.sessionId;
+        let url = `${this.scheme === 'https' ? 'wss' : 'ws'}://${this.serverHost}:${this.inputPort}/api/ws/${sessionId}`;
+        const params = [];
+        if (this.lastSeq[sessionId] !== undefined) params.push(`since=${this.lastSeq[sessionId]}`);
+        if (this.authToken) params.push(`token=${encodeURIComponent(this.authToken)}`);
+        if (params.length > 0) url += `?${params.join('&')}`;
+
+        const socket = new WebSocket(url);
+        let opened = false;
+        this.socket = socket;
+
+        socket.onopen = () => {
+            opened = true;
+            this.socketFailures = 0;
+            console.log('WebSocket connection established');
+            this.updateConnectionStatus('connected');
+        };
+
+        socket.onmessage = (event) => {
+            let frame;
+            try {
+                frame = JSON.parse(event.data);
+            } catch (error) {
+                console.error('Failed to parse frame:', error);
+                return;
+            }
+            if (frame.type === 'output') {
+                if (frame.id <= (this.lastSeq[sessionId] || 0)) return;
+                this.lastSeq[sessionId] = frame.id;
+                this.handleIncomingMessage(frame.event);
+                this.saveState();
+                socket.send(JSON.stringify({ type: 'ack', id: frame.id }));
+            } else if (frame.type === 'error') {
+                this.displaySystemMessage(`Send failed: ${frame.message}`);
+            }
+        };
+
+        socket.onclose = () => {
+            if (this.socket !== socket) return; // Replaced or closed on purpose
+            this.socket = null;
+            this.updateConnectionStatus('error');
+            this.socketFailures += 1;
+            if (!opened && this.socketFailures >= 3) {
+                console.warn('WebSocket unavailable, falling back to SSE');
+                this.useSocket = false;
+                this.startEventSource();
+                return;
+            }
+            const delay = Math.min(30000, 1000 * 2 ** (this.socketFailures - 1));
+            setTimeout(() => {
+                if (this.socket || this.eventSource || this.sessionId !== sessionId) return;
+                console.log('Attempting to reconnect WebSocket...');
+                this.openSocket();
+            }, delay);
+        };
+    }
+
+    socketOpen() {
+        return this.socket && this.socket.readyState === WebSocket.OPEN;
+    }
+
+    // Tell the server the user is typing, at most every few seconds
+    sendTyping() {
+        const now = Date.now();
+        if (!this.socketOpen() || now - this.lastTypingSent < 3000) return;
+        this.lastTypingSent = now;
+        this.socket.send(JSON.stringify({ type: 'typing' }));
+    }
+
+    startEventSource() {
         if (this.eventSource) {
             this.eventSource.close();
         }
 
-        const url = `http://${this.serverHost}:${this.outputPort}/api/subscribe?session_id=${this.sessionId}`;
+        const sessionId = this.sessionId;
+        let url = `${this.scheme}://${this.serverHost}:${this.outputPort}/api/subscribe?session_id=${sessionId}`;
+        if (this.authToken) {
+            url += `&token=${encodeURIComponent(this.authToken)}`;
+        }
+        // A new EventSource does not send Last-Event-ID, so pass it along
+        if (this.lastSeq[sessionId] !== undefined) {
+            url += `&last_event_id=${this.lastSeq[sessionId]}`;
+        }
         this.eventSource = new EventSource(url);
 
         this.eventSource.onopen = () => {
@@ -524,7 +706,13 @@ class ChatClient {
         this.eventSource.onmessage = (event) => {
             try {
                 const message = JSON.parse(event.data);
+                const id = parseInt(event.lastEventId);
+                if (!isNaN(id)) {
+                    if (id <= (this.lastSeq[sessionId] || 0)) return;
+                    this.lastSeq[sessionId] = id;
+                }
                 this.handleIncomingMessage(message);
+                this.saveState();
             } catch (error) {
                 console.error('Failed to parse message:', error);
             }
@@ -540,7 +728,7 @@ class ChatClient {
             if (this.isConnected) { // Only reconnect if we think we should be connected
                 setTimeout(() => {
                     console.log('Attempting to reconnect to EventSource...');
-                    this.startOutputPolling();
+                    this.startEventSource();
                 }, 3000);
             }
         };
@@ -577,6 +765,8 @@ class ChatClient {
                 if (this.showThinking) {
                     this.displayThinkMessage(message.content.content);
                 }
+            } else if (message.content && message.content.type === 'tool_result') {
+                this.displayToolResult(message.content);
             } else {
                 this.displayBotMessage(message);
             }
@@ -627,6 +817,50 @@ class ChatClient {
         this.scrollToBottom();
     }
 
+    // data: { type: 'tool_result', tool, args, result }
+    displayToolResult(data, save = true) {
+        if (!data) return;
+
+        if (save) {
+            if (!this.sessionMessages[this.sessionId]) this.sessionMessages[this.sessionId] = [];
+            this.sessionMessages[this.sessionId].push({
+                type: 'tool', content: data, timestamp: Date.now()
+            });
+            this.saveState();
+        }
+
+        const msgDiv = document.createElement('div');
+        msgDiv.className = 'message bot-message';
+
+        const innerDiv = document.createElement('div');
+        innerDiv.className = 'message-inner';
+
+        const avatarDiv = document.createElement('div');
+        avatarDiv.className = 'message-avatar';
+
+        const contentDiv = document.createElement('div');
+        contentDiv.className = 'message-content';
+
+        const details = document.createElement('details');
+        details.className = 'tool-details';
+        const summary = document.createElement('summary');
+        summary.textContent = `🔧 ${data.tool || 'tool'}`;
+        const body = document.createElement('pre');
+        const code = document.createElement('code');
+        code.textContent = JSON.stringify({ args: data.args, result: data.result }, null, 2);
+        body.appendChild(code);
+        details.appendChild(summary);
+        details.appendChild(body);
+
+        contentDiv.appendChild(details);
+        innerDiv.appendChild(avatarDiv);
+        innerDiv.appendChild(contentDiv);
+        msgDiv.appendChild(innerDiv);
+
+        this.chatMessages.appendChild(msgDiv);
+        this.scrollToBottom();
+    }
+
     updateProgress(data) {
         console.log('updateProgress called with:', data);
         // data: { message, progress, total, token, type: 'progress' }
@@ -730,17 +964,28 @@ class ChatClient {
             this.selectedFiles = [];
             this.renderFilePreview();
 
-            const url = `http://${this.serverHost}:${this.inputPort}/api/send/${this.sessionId}`;
+            const files = uploadedFiles.length > 0 ? uploadedFiles : undefined;
+            if (this.socketOpen()) {
+                this.socket.send(JSON.stringify({
+                    type: 'message',
+                    content: content,
+                    timestamp: Date.now(),
+                    files: files
+                }));
+                return;
+            }
+
+            const url = `${this.scheme}://${this.serverHost}:${this.inputPort}/api/send/${this.sessionId}`;
             const response = await fetch(url, {
                 method: 'POST',
-                headers: {
+                headers: this.authHeaders({
                     'Content-Type': 'application/json',
-                },
+                }),
                 body: JSON.stringify({
                     content: content,
                     timestamp: Date.now(),
                     session_id: this.sessionId,
-                    files: uploadedFiles.length > 0 ? uploadedFiles : undefined
+                    files: files
                 })
             });
 
@@ -830,7 +1075,10 @@ class ChatClient {
             const filesDiv = document.createElement('div');
             filesDiv.className = 'message-files';
             files.forEach(file => {
-                 const name = file.split('/').pop().replace(/^[0-9a-f-]+_/, ''); // Try to clean uuid?
+                 // Echoed attachments are resource links; older ones are paths
+                 const name = typeof file === 'string'
+                     ? file.split('/').pop().replace(/^[0-9a-f-]+_/, '')
+                     : file.name;
                  const fileEl = document.createElement('div');
                  fileEl.textContent = `📎 ${name}`;
                  fileEl.className = 'file-attachment';